@@ -1,4 +1,7 @@
 fn main() {
+    #[cfg(feature = "uniffi-bindings")]
+    uniffi::generate_scaffolding("src/btleplug.udl").unwrap();
+
     #[cfg(target_os = "windows")]
     windows::build!(
         Windows::Devices::Bluetooth::GenericAttributeProfile::{
@@ -7,25 +10,64 @@ fn main() {
             GattCharacteristicsResult,
             GattClientCharacteristicConfigurationDescriptorValue,
             GattCommunicationStatus,
+            GattDescriptor,
+            GattDescriptorsResult,
             GattDeviceService,
             GattDeviceServicesResult,
+            GattLocalCharacteristic,
+            GattLocalCharacteristicParameters,
+            GattLocalCharacteristicResult,
+            GattLocalService,
+            GattReadRequest,
+            GattReadRequestedEventArgs,
             GattReadResult,
+            GattServiceProvider,
+            GattServiceProviderAdvertisingParameters,
+            GattServiceProviderResult,
+            GattSession,
+            GattSubscribedClient,
             GattValueChangedEventArgs,
             GattWriteOption,
+            GattWriteRequest,
+            GattWriteRequestedEventArgs,
+            GattWriteResult,
         },
         Windows::Devices::Bluetooth::Advertisement::*,
         Windows::Devices::Bluetooth::{
+            BluetoothAddressType,
             BluetoothConnectionStatus,
+            BluetoothDeviceId,
             BluetoothLEDevice,
             BluetoothCacheMode,
+            BluetoothError,
+        },
+        Windows::Devices::Enumeration::{
+            DeviceInformation,
+            DeviceInformationCollection,
+            DeviceInformationCustomPairing,
+            DeviceInformationPairing,
+            DeviceInformationUpdate,
+            DevicePairingKinds,
+            DevicePairingProtectionLevel,
+            DevicePairingRequestedEventArgs,
+            DevicePairingResult,
+            DevicePairingResultStatus,
+            DeviceWatcher,
+            DeviceWatcherStatus,
         },
         Windows::Devices::Radios::{
             Radio,
-            RadioKind
+            RadioAccessStatus,
+            RadioKind,
+            RadioState,
         },
         Windows::Foundation::{
+            AsyncOperationCompletedHandler,
+            AsyncStatus,
             DateTime,
+            Deferral,
             EventRegistrationToken,
+            IClosable,
             IAsyncOperation,
             IReference,
             TimeSpan,
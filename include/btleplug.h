@@ -0,0 +1,298 @@
+#ifndef BTLEPLUG_H
+#define BTLEPLUG_H
+
+/* Generated by cbindgen from src/capi. Don't edit this file by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The kind of a [`BtleplugEvent`].
+typedef enum BtleplugEventKind {
+  BTLEPLUG_EVENT_KIND_DEVICE_DISCOVERED,
+  BTLEPLUG_EVENT_KIND_DEVICE_UPDATED,
+  BTLEPLUG_EVENT_KIND_DEVICE_CONNECTED,
+  BTLEPLUG_EVENT_KIND_DEVICE_DISCONNECTED,
+  BTLEPLUG_EVENT_KIND_DEVICE_LOST,
+  // A device's signal strength was measured, in `rssi`.
+  BTLEPLUG_EVENT_KIND_RSSI_UPDATE,
+} BtleplugEventKind;
+
+// The result of a call to the C API.
+typedef enum BtleplugResult {
+  BTLEPLUG_RESULT_OK = 0,
+  // A handle or argument was null or invalid, such as the UUID of a characteristic which hasn't
+  // been discovered.
+  BTLEPLUG_RESULT_INVALID_ARGUMENT,
+  // A buffer was too small for the result, whose length has been returned.
+  BTLEPLUG_RESULT_BUFFER_TOO_SMALL,
+  BTLEPLUG_RESULT_PERMISSION_DENIED,
+  BTLEPLUG_RESULT_DEVICE_NOT_FOUND,
+  BTLEPLUG_RESULT_NOT_CONNECTED,
+  BTLEPLUG_RESULT_NOT_SUPPORTED,
+  BTLEPLUG_RESULT_TIMED_OUT,
+  // The device returned an ATT error.
+  BTLEPLUG_RESULT_ATT,
+  // The link needs to be paired, or pairing failed.
+  BTLEPLUG_RESULT_INSUFFICIENT_AUTHENTICATION,
+  BTLEPLUG_RESULT_DEVICE_UNREACHABLE,
+  BTLEPLUG_RESULT_OTHER,
+} BtleplugResult;
+
+// A Bluetooth adapter, through which peripherals are scanned for.
+typedef struct BtleplugAdapter BtleplugAdapter;
+
+// The entry point of the C API, from which adapters are found.
+typedef struct BtleplugManager BtleplugManager;
+
+// A peripheral found by an adapter.
+typedef struct BtleplugPeripheral BtleplugPeripheral;
+
+// A Bluetooth device address, most significant byte first.
+typedef struct BtleplugAddress {
+  uint8_t bytes[6];
+} BtleplugAddress;
+
+// An event from an adapter, about the peripheral with the given address.
+typedef struct BtleplugEvent {
+  BtleplugEventKind kind;
+  BtleplugAddress address;
+  // The signal strength in dBm, for [`BtleplugEventKind::RssiUpdate`] events, or 0.
+  int16_t rssi;
+} BtleplugEvent;
+
+// Called with each event from an adapter, and the `user_data` it was registered with. The event
+// is only valid for the duration of the call.
+typedef void (*BtleplugEventCallback)(const BtleplugEvent *event, void *user_data);
+
+// A UUID, in big-endian byte order.
+typedef struct BtleplugUuid {
+  uint8_t bytes[16];
+} BtleplugUuid;
+
+// Called with each notification or indication from a peripheral, with the UUID of the
+// characteristic it came from, its value and the `user_data` it was registered with. The
+// arguments are only valid for the duration of the call.
+typedef void (*BtleplugNotificationCallback)(const BtleplugUuid *characteristic,
+                                             const uint8_t *value,
+                                             size_t value_len,
+                                             void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Frees an adapter, stopping its event callback if one was set.
+//
+// # Safety
+//
+// `adapter` must be null or a handle which hasn't been freed.
+void btleplug_adapter_free(BtleplugAdapter *adapter);
+
+// Writes a handle for the peripheral with the given address to `peripheral`, if the adapter has
+// discovered it.
+//
+// # Safety
+//
+// `adapter` must be a valid handle, `address` must be valid for reads, and `peripheral` must be
+// valid for writes.
+BtleplugResult btleplug_adapter_peripheral(const BtleplugAdapter *adapter,
+                                           const BtleplugAddress *address,
+                                           BtleplugPeripheral **peripheral);
+
+// Writes handles for up to `capacity` of the peripherals which the adapter has discovered to
+// `peripherals`, and the number of them to `count`. If `count` is larger than `capacity`, the
+// rest are left out, and the call can be repeated with a larger array.
+//
+// # Safety
+//
+// `adapter` must be a valid handle, `peripherals` must be valid for writes of `capacity` handles,
+// and `count` must be valid for writes.
+BtleplugResult btleplug_adapter_peripherals(const BtleplugAdapter *adapter,
+                                            BtleplugPeripheral **peripherals,
+                                            size_t capacity,
+                                            size_t *count);
+
+// Sets the function to call with each event from the adapter, replacing any set before, or stops
+// calling one if `callback` is null.
+//
+// # Safety
+//
+// `adapter` must be a valid handle, and `callback` must be safe to call with `user_data` from
+// any thread until it is replaced or the adapter is freed.
+BtleplugResult btleplug_adapter_set_event_callback(const BtleplugAdapter *adapter,
+                                                   BtleplugEventCallback callback,
+                                                   void *user_data);
+
+// Starts scanning for peripherals. If `service_count` isn't 0, only peripherals advertising at
+// least one of the given services are reported.
+//
+// # Safety
+//
+// `adapter` must be a valid handle, and `services` must be valid for reads of `service_count`
+// UUIDs.
+BtleplugResult btleplug_adapter_start_scan(const BtleplugAdapter *adapter,
+                                           const BtleplugUuid *services,
+                                           size_t service_count);
+
+// Stops scanning for peripherals.
+//
+// # Safety
+//
+// `adapter` must be a valid handle.
+BtleplugResult btleplug_adapter_stop_scan(const BtleplugAdapter *adapter);
+
+// Returns a description of the last error on this thread, or null if the last call succeeded.
+// The string remains valid until the next call to the C API on this thread.
+const char *btleplug_last_error_message(void);
+
+// Finds the Bluetooth adapters on the system, writing handles for up to `capacity` of them to
+// `adapters`, and the number found to `count`. If `count` is larger than `capacity`, the rest
+// are left out, and the call can be repeated with a larger array.
+//
+// # Safety
+//
+// `manager` must be a valid handle, `adapters` must be valid for writes of `capacity` handles,
+// and `count` must be valid for writes.
+BtleplugResult btleplug_manager_adapters(const BtleplugManager *manager,
+                                         BtleplugAdapter **adapters,
+                                         size_t capacity,
+                                         size_t *count);
+
+// Frees a manager. Adapters and peripherals found through it remain valid until they are freed.
+//
+// # Safety
+//
+// `manager` must be null or a handle from [`btleplug_manager_new`] which hasn't been freed.
+void btleplug_manager_free(BtleplugManager *manager);
+
+// Creates a manager, along with the runtime on which btleplug's operations are run, and writes
+// its handle to `manager`.
+//
+// # Safety
+//
+// `manager` must be valid for writes.
+BtleplugResult btleplug_manager_new(BtleplugManager **manager);
+
+// Writes the address of the peripheral to `address`.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle, and `address` must be valid for writes.
+BtleplugResult btleplug_peripheral_address(const BtleplugPeripheral *peripheral,
+                                           BtleplugAddress *address);
+
+// Connects to the peripheral.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle.
+BtleplugResult btleplug_peripheral_connect(const BtleplugPeripheral *peripheral);
+
+// Disconnects from the peripheral.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle.
+BtleplugResult btleplug_peripheral_disconnect(const BtleplugPeripheral *peripheral);
+
+// Discovers the services and characteristics of the peripheral, which must be done after
+// connecting before its characteristics can be used.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle.
+BtleplugResult btleplug_peripheral_discover_services(const BtleplugPeripheral *peripheral);
+
+// Frees a peripheral, stopping its notification callback if one was set. This doesn't disconnect
+// from it.
+//
+// # Safety
+//
+// `peripheral` must be null or a handle which hasn't been freed.
+void btleplug_peripheral_free(BtleplugPeripheral *peripheral);
+
+// Writes whether the peripheral is connected to `connected`.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle, and `connected` must be valid for writes.
+BtleplugResult btleplug_peripheral_is_connected(const BtleplugPeripheral *peripheral,
+                                                bool *connected);
+
+// Copies the advertised name of the peripheral to `name`, as a NUL-terminated UTF-8 string, and
+// its length, including the terminator, to `len`. If the peripheral hasn't advertised a name, an
+// empty string is copied. If the name doesn't fit in `capacity` bytes,
+// [`BtleplugResult::BufferTooSmall`] is returned and nothing is copied.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle, `name` must be valid for writes of `capacity` bytes, and
+// `len` must be valid for writes.
+BtleplugResult btleplug_peripheral_local_name(const BtleplugPeripheral *peripheral,
+                                              char *name,
+                                              size_t capacity,
+                                              size_t *len);
+
+// Reads the value of the characteristic with the given UUID, copying it to `value` and its
+// length to `len`. If it doesn't fit in `capacity` bytes, [`BtleplugResult::BufferTooSmall`] is
+// returned and nothing is copied.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle, `characteristic` must be valid for reads, `value` must be
+// valid for writes of `capacity` bytes, and `len` must be valid for writes.
+BtleplugResult btleplug_peripheral_read(const BtleplugPeripheral *peripheral,
+                                        const BtleplugUuid *characteristic,
+                                        uint8_t *value,
+                                        size_t capacity,
+                                        size_t *len);
+
+// Sets the function to call with each notification or indication from the peripheral, replacing
+// any set before, or stops calling one if `callback` is null.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle, and `callback` must be safe to call with `user_data` from
+// any thread until it is replaced or the peripheral is freed.
+BtleplugResult btleplug_peripheral_set_notification_callback(const BtleplugPeripheral *peripheral,
+                                                             BtleplugNotificationCallback callback,
+                                                             void *user_data);
+
+// Enables notifications or indications from the characteristic with the given UUID, which are
+// passed to the notification callback.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle, and `characteristic` must be valid for reads.
+BtleplugResult btleplug_peripheral_subscribe(const BtleplugPeripheral *peripheral,
+                                             const BtleplugUuid *characteristic);
+
+// Disables notifications or indications from the characteristic with the given UUID.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle, and `characteristic` must be valid for reads.
+BtleplugResult btleplug_peripheral_unsubscribe(const BtleplugPeripheral *peripheral,
+                                               const BtleplugUuid *characteristic);
+
+// Writes `value_len` bytes from `value` to the characteristic with the given UUID, waiting for
+// the peripheral to acknowledge them if `with_response` is true.
+//
+// # Safety
+//
+// `peripheral` must be a valid handle, `characteristic` must be valid for reads, and `value` must
+// be valid for reads of `value_len` bytes.
+BtleplugResult btleplug_peripheral_write(const BtleplugPeripheral *peripheral,
+                                         const BtleplugUuid *characteristic,
+                                         const uint8_t *value,
+                                         size_t value_len,
+                                         bool with_response);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BTLEPLUG_H */
@@ -4,12 +4,35 @@ use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Debug, Display, Formatter, LowerHex, UpperHex};
 use std::str::FromStr;
 
+#[cfg(feature = "serde")]
+use serde_cr::{Deserialize, Serialize};
+
 /// Stores the 6 byte address used to identify Bluetooth devices.
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Default)]
 pub struct BDAddr {
     address: [u8; 6],
 }
 
+/// The sub-type of a random Bluetooth LE address, as encoded in its two most significant bits.
+///
+/// Only meaningful for addresses whose [`AddressType`](crate::api::AddressType) is `Random`; a
+/// public address can have any bit pattern.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum RandomAddressKind {
+    /// A static random address, which stays the same at least until the device power cycles.
+    Static,
+    /// A resolvable private address, which rotates periodically (typically every 15 minutes) and
+    /// can only be linked to its device with the device's Identity Resolving Key.
+    ResolvablePrivate,
+    /// A non-resolvable private address, which rotates and cannot be linked to its device at all.
+    NonResolvablePrivate,
+}
+
 /// An error parsing a [`BDAddr`] from a string.
 #[derive(Debug, thiserror::Error, Clone, PartialEq)]
 pub enum ParseBDAddrError {
@@ -133,9 +156,89 @@ impl BDAddr {
         self.address
     }
 
-    /// Check if this address is a randomly generated.
+    /// Build an address from bytes in most-significant-byte-first order.
+    ///
+    /// This is the order addresses are usually written in, and is equivalent
+    /// to `BDAddr::from(bytes)`.
+    pub const fn from_be_bytes(bytes: [u8; 6]) -> Self {
+        Self { address: bytes }
+    }
+
+    /// Build an address from bytes in least-significant-byte-first order, as
+    /// used on the wire by HCI and in many advertising payloads.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use btleplug::api::BDAddr;
+    /// let addr = BDAddr::from_le_bytes([0x00, 0xFA, 0x34, 0x00, 0xCC, 0x2A]);
+    /// assert_eq!("2A:CC:00:34:FA:00", addr.to_string());
+    /// ```
+    pub fn from_le_bytes(mut bytes: [u8; 6]) -> Self {
+        bytes.reverse();
+        Self { address: bytes }
+    }
+
+    /// Return the address bytes in most-significant-byte-first order.
+    pub fn to_be_bytes(self) -> [u8; 6] {
+        self.address
+    }
+
+    /// Return the address bytes in least-significant-byte-first order.
+    pub fn to_le_bytes(self) -> [u8; 6] {
+        let mut bytes = self.address;
+        bytes.reverse();
+        bytes
+    }
+
+    /// Build an address from the lower 48 bits of an integer whose bytes are
+    /// in least-significant-byte-first order, i.e. byte-swapped compared to
+    /// `BDAddr::try_from(u64)`.
+    pub fn from_le_u64(int: u64) -> Result<Self, ParseBDAddrError> {
+        let slice = int.to_le_bytes();
+        if slice[6..] == [0, 0] {
+            Ok(Self::from_be_bytes(slice[..6].try_into().unwrap()))
+        } else {
+            Err(ParseBDAddrError::IncorrectByteCount)
+        }
+    }
+
+    /// Convert the address to an integer whose bytes are in
+    /// least-significant-byte-first order, the inverse of
+    /// [`BDAddr::from_le_u64`].
+    pub fn to_le_u64(self) -> u64 {
+        let mut slice = [0; 8];
+        (&mut slice[..6]).copy_from_slice(&self.address);
+        u64::from_le_bytes(slice)
+    }
+
+    /// Classify this address by its two most significant bits, assuming it is a random address.
+    ///
+    /// Returns `None` for the reserved `0b10` pattern. The result is meaningless for public
+    /// addresses, so check the address type first, or use
+    /// [`PeripheralProperties::random_address_kind`](crate::api::PeripheralProperties::random_address_kind).
+    pub fn random_address_kind(&self) -> Option<RandomAddressKind> {
+        match self.address[0] >> 6 {
+            0b11 => Some(RandomAddressKind::Static),
+            0b01 => Some(RandomAddressKind::ResolvablePrivate),
+            0b00 => Some(RandomAddressKind::NonResolvablePrivate),
+            _ => None,
+        }
+    }
+
+    /// Check if this address is a static random address, assuming it is a random address.
     pub fn is_random_static(&self) -> bool {
-        self.address[5] & 0b11 == 0b11
+        self.random_address_kind() == Some(RandomAddressKind::Static)
+    }
+
+    /// Check if this address is a resolvable private address, assuming it is a random address.
+    pub fn is_resolvable_private(&self) -> bool {
+        self.random_address_kind() == Some(RandomAddressKind::ResolvablePrivate)
+    }
+
+    /// Check if this address is a non-resolvable private address, assuming it is a random address.
+    pub fn is_non_resolvable_private(&self) -> bool {
+        self.random_address_kind() == Some(RandomAddressKind::NonResolvablePrivate)
     }
 
     /// Parses a Bluetooth address with colons `:` as delimiters.
@@ -165,11 +268,12 @@ impl BDAddr {
         }
 
         let mut address = [0; 6];
-        let mut cur = s;
-        for byte in address.iter_mut() {
-            let (part, rest) = cur.split_at(2);
+        for (i, byte) in address.iter_mut().enumerate() {
+            // A multi-byte character can straddle a pair of digits; parsing the
+            // whole string then reports it as an invalid digit instead of
+            // panicking on the slice.
+            let part = s.get(i * 2..i * 2 + 2).unwrap_or(s);
             *byte = u8::from_str_radix(part, 16)?;
-            cur = rest;
         }
         Ok(Self { address })
     }
@@ -418,6 +522,56 @@ mod tests {
         assert!(matches!(result, Err(ParseBDAddrError::InvalidDigit(_))));
     }
 
+    #[test]
+    fn parse_addr_non_ascii() {
+        let result: Result<BDAddr, _> = "2a00aabbccé".parse();
+        assert!(matches!(result, Err(ParseBDAddrError::InvalidDigit(_))));
+        let result: Result<BDAddr, _> = "2a00aabbcé0".parse();
+        assert!(matches!(result, Err(ParseBDAddrError::InvalidDigit(_))));
+    }
+
+    #[test]
+    fn addr_bytes_endianness() {
+        let be = [0x1f, 0x2a, 0x00, 0xcc, 0x22, 0xf1];
+        let le = [0xf1, 0x22, 0xcc, 0x00, 0x2a, 0x1f];
+        assert_eq!(BDAddr::from_be_bytes(be), ADDR);
+        assert_eq!(BDAddr::from_le_bytes(le), ADDR);
+        assert_eq!(ADDR.to_be_bytes(), be);
+        assert_eq!(ADDR.to_le_bytes(), le);
+    }
+
+    #[test]
+    fn addr_le_u64() {
+        const HEX_LE: u64 = 0x00_00_f1_22_cc_00_2a_1f;
+        assert_eq!(BDAddr::from_le_u64(HEX_LE), Ok(ADDR));
+        assert_eq!(ADDR.to_le_u64(), HEX_LE);
+        assert_eq!(
+            BDAddr::from_le_u64(0x1122334455667788),
+            Err(ParseBDAddrError::IncorrectByteCount)
+        );
+    }
+
+    #[test]
+    fn classify_random_addr() {
+        let addr = BDAddr::from([0xc1, 0x2a, 0x00, 0xcc, 0x22, 0xf0]);
+        assert_eq!(addr.random_address_kind(), Some(RandomAddressKind::Static));
+        assert!(addr.is_random_static());
+        let addr = BDAddr::from([0x4f, 0x2a, 0x00, 0xcc, 0x22, 0xf3]);
+        assert_eq!(
+            addr.random_address_kind(),
+            Some(RandomAddressKind::ResolvablePrivate)
+        );
+        assert!(addr.is_resolvable_private());
+        assert!(!addr.is_random_static());
+        assert_eq!(
+            ADDR.random_address_kind(),
+            Some(RandomAddressKind::NonResolvablePrivate)
+        );
+        assert!(ADDR.is_non_resolvable_private());
+        let addr = BDAddr::from([0x80, 0, 0, 0, 0, 0]);
+        assert_eq!(addr.random_address_kind(), None);
+    }
+
     #[test]
     fn display_addr() {
         assert_eq!(format!("{}", ADDR), "1F:2A:00:CC:22:F1");
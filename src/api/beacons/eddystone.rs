@@ -0,0 +1,234 @@
+//! Google's Eddystone format, which is carried in the service data of an advertisement.
+
+use crate::api::bleuuid::uuid_from_u16;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The UUID of the service under which Eddystone frames are advertised as service data.
+pub const EDDYSTONE_SERVICE_UUID: Uuid = uuid_from_u16(0xfeaa);
+
+const FRAME_TYPE_UID: u8 = 0x00;
+const FRAME_TYPE_URL: u8 = 0x10;
+const FRAME_TYPE_TLM: u8 = 0x20;
+const FRAME_TYPE_EID: u8 = 0x30;
+
+const URL_SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+const URL_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net",
+    ".info", ".biz", ".gov",
+];
+
+/// An Eddystone-UID frame, which identifies a beacon by a fixed namespace and instance.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct EddystoneUid {
+    /// The transmission power at 0 metres, in dBm.
+    pub tx_power: i8,
+    /// Identifies the organisation which deployed the beacon.
+    pub namespace: [u8; 10],
+    /// Identifies an individual beacon within the namespace.
+    pub instance: [u8; 6],
+}
+
+/// An Eddystone-URL frame, which broadcasts a URL.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct EddystoneUrl {
+    /// The transmission power at 0 metres, in dBm.
+    pub tx_power: i8,
+    /// The decoded URL.
+    pub url: String,
+}
+
+/// An unencrypted Eddystone-TLM frame, which reports telemetry about the beacon itself.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EddystoneTlm {
+    /// The battery voltage in millivolts, if the beacon reports it.
+    pub battery_voltage: Option<u16>,
+    /// The temperature of the beacon in degrees Celsius, if it reports it.
+    pub temperature: Option<f32>,
+    /// The number of advertisements sent by the beacon since it was powered on.
+    pub advertisement_count: u32,
+    /// The time since the beacon was powered on.
+    pub uptime: Duration,
+}
+
+/// An Eddystone-EID frame, which identifies a beacon by a periodically rotating identifier.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct EddystoneEid {
+    /// The transmission power at 0 metres, in dBm.
+    pub tx_power: i8,
+    /// The current ephemeral identifier.
+    pub eid: [u8; 8],
+}
+
+/// A decoded Eddystone frame.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum EddystoneFrame {
+    Uid(EddystoneUid),
+    Url(EddystoneUrl),
+    Tlm(EddystoneTlm),
+    Eid(EddystoneEid),
+}
+
+impl EddystoneFrame {
+    /// Parses the service data advertised under [`EDDYSTONE_SERVICE_UUID`], returning `None` if it
+    /// isn't a valid Eddystone frame of a known type.
+    pub fn parse(data: &[u8]) -> Option<EddystoneFrame> {
+        let (&frame_type, data) = data.split_first()?;
+        match frame_type {
+            FRAME_TYPE_UID if data.len() >= 17 => Some(EddystoneFrame::Uid(EddystoneUid {
+                tx_power: data[0] as i8,
+                namespace: data[1..11].try_into().ok()?,
+                instance: data[11..17].try_into().ok()?,
+            })),
+            FRAME_TYPE_URL if data.len() >= 2 => Some(EddystoneFrame::Url(EddystoneUrl {
+                tx_power: data[0] as i8,
+                url: decode_url(data[1], &data[2..])?,
+            })),
+            // Only version 0 TLM frames are unencrypted.
+            FRAME_TYPE_TLM if data.len() >= 13 && data[0] == 0 => {
+                let battery_voltage = u16::from_be_bytes(data[1..3].try_into().ok()?);
+                let temperature = i16::from_be_bytes(data[3..5].try_into().ok()?);
+                let uptime = u32::from_be_bytes(data[9..13].try_into().ok()?);
+                Some(EddystoneFrame::Tlm(EddystoneTlm {
+                    battery_voltage: Some(battery_voltage).filter(|voltage| *voltage != 0),
+                    // The temperature is in 8.8 fixed point, with 0x8000 meaning it's not
+                    // supported.
+                    temperature: Some(temperature)
+                        .filter(|temperature| *temperature != i16::MIN)
+                        .map(|temperature| f32::from(temperature) / 256.0),
+                    advertisement_count: u32::from_be_bytes(data[5..9].try_into().ok()?),
+                    uptime: Duration::from_millis(u64::from(uptime) * 100),
+                }))
+            }
+            FRAME_TYPE_EID if data.len() >= 9 => Some(EddystoneFrame::Eid(EddystoneEid {
+                tx_power: data[0] as i8,
+                eid: data[1..9].try_into().ok()?,
+            })),
+            _ => None,
+        }
+    }
+
+    /// Looks for an Eddystone frame in the service data of a device, as found in
+    /// [`PeripheralProperties::service_data`](crate::api::PeripheralProperties::service_data) or
+    /// [`CentralEvent::ServiceDataAdvertisement`](crate::api::CentralEvent::ServiceDataAdvertisement).
+    pub fn from_service_data(service_data: &HashMap<Uuid, Vec<u8>>) -> Option<EddystoneFrame> {
+        service_data
+            .get(&EDDYSTONE_SERVICE_UUID)
+            .and_then(|data| EddystoneFrame::parse(data))
+    }
+}
+
+fn decode_url(scheme: u8, encoded: &[u8]) -> Option<String> {
+    let mut url = URL_SCHEMES.get(scheme as usize)?.to_string();
+    for &byte in encoded {
+        match byte {
+            0x21..=0x7e => url.push(byte as char),
+            _ => url.push_str(URL_EXPANSIONS.get(byte as usize)?),
+        }
+    }
+    Some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_uid() {
+        let data = [
+            0x00, 0xe7, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x11, 0x12,
+            0x13, 0x14, 0x15, 0x16, 0x00, 0x00,
+        ];
+        assert_eq!(
+            EddystoneFrame::parse(&data),
+            Some(EddystoneFrame::Uid(EddystoneUid {
+                tx_power: -25,
+                namespace: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a],
+                instance: [0x11, 0x12, 0x13, 0x14, 0x15, 0x16],
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_url() {
+        let data = [
+            0x10, 0xeb, 0x03, b'g', b'o', b'o', b'.', b'g', b'l', 0x2f, b'a', 0x07, 0x01,
+        ];
+        assert_eq!(
+            EddystoneFrame::parse(&data),
+            Some(EddystoneFrame::Url(EddystoneUrl {
+                tx_power: -21,
+                url: "https://goo.gl/a.com.org/".to_string(),
+            }))
+        );
+        assert_eq!(EddystoneFrame::parse(&[0x10, 0xeb, 0x04, b'a']), None);
+        assert_eq!(EddystoneFrame::parse(&[0x10, 0xeb, 0x00, 0x20]), None);
+    }
+
+    #[test]
+    fn parse_tlm() {
+        let data = [
+            0x20, 0x00, 0x0b, 0xb8, 0x17, 0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x64,
+        ];
+        assert_eq!(
+            EddystoneFrame::parse(&data),
+            Some(EddystoneFrame::Tlm(EddystoneTlm {
+                battery_voltage: Some(3000),
+                temperature: Some(23.5),
+                advertisement_count: 256,
+                uptime: Duration::from_secs(10),
+            }))
+        );
+
+        let mut encrypted = data;
+        encrypted[1] = 0x01;
+        assert_eq!(EddystoneFrame::parse(&encrypted), None);
+    }
+
+    #[test]
+    fn parse_eid() {
+        let mut service_data = HashMap::new();
+        service_data.insert(
+            EDDYSTONE_SERVICE_UUID,
+            vec![0x30, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+        );
+        assert_eq!(
+            EddystoneFrame::from_service_data(&service_data),
+            Some(EddystoneFrame::Eid(EddystoneEid {
+                tx_power: 0,
+                eid: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+            }))
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Apple's iBeacon format, which is carried in the manufacturer data of an advertisement.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use uuid::Uuid;
+
+/// The company ID under which iBeacons advertise their manufacturer data.
+pub const APPLE_COMPANY_ID: u16 = 0x004c;
+
+/// The type and length which start the manufacturer data of an iBeacon.
+const IBEACON_PREFIX: [u8; 2] = [0x02, 0x15];
+
+/// The contents of an iBeacon advertisement.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct IBeacon {
+    /// Identifies the organisation or application which deployed the beacon.
+    pub uuid: Uuid,
+    /// Identifies a group of beacons, such as those in one building.
+    pub major: u16,
+    /// Identifies an individual beacon within its group.
+    pub minor: u16,
+    /// The signal strength expected at a distance of 1 metre from the beacon, in dBm.
+    pub measured_power: i8,
+}
+
+impl IBeacon {
+    /// Parses the manufacturer data advertised under [`APPLE_COMPANY_ID`], returning `None` if it
+    /// isn't an iBeacon.
+    pub fn parse(data: &[u8]) -> Option<IBeacon> {
+        if data.len() != 23 || data[0..2] != IBEACON_PREFIX {
+            return None;
+        }
+        Some(IBeacon {
+            uuid: Uuid::from_slice(&data[2..18]).ok()?,
+            major: u16::from_be_bytes(data[18..20].try_into().ok()?),
+            minor: u16::from_be_bytes(data[20..22].try_into().ok()?),
+            measured_power: data[22] as i8,
+        })
+    }
+
+    /// Looks for an iBeacon in the manufacturer data of a device, as found in
+    /// [`PeripheralProperties::manufacturer_data`](crate::api::PeripheralProperties::manufacturer_data)
+    /// or [`CentralEvent::ManufacturerDataAdvertisement`](crate::api::CentralEvent::ManufacturerDataAdvertisement).
+    pub fn from_manufacturer_data(manufacturer_data: &HashMap<u16, Vec<u8>>) -> Option<IBeacon> {
+        manufacturer_data
+            .get(&APPLE_COMPANY_ID)
+            .and_then(|data| IBeacon::parse(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: [u8; 23] = [
+        0x02, 0x15, 0xe2, 0xc5, 0x6d, 0xb5, 0xdf, 0xfb, 0x48, 0xd2, 0xb0, 0x60, 0xd0, 0xf5, 0xa7,
+        0x10, 0x96, 0xe0, 0x00, 0x01, 0x01, 0x02, 0xc5,
+    ];
+
+    #[test]
+    fn parse_ibeacon() {
+        assert_eq!(
+            IBeacon::parse(&DATA),
+            Some(IBeacon {
+                uuid: Uuid::parse_str("e2c56db5-dffb-48d2-b060-d0f5a71096e0").unwrap(),
+                major: 1,
+                minor: 0x0102,
+                measured_power: -59,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_other_manufacturer_data() {
+        assert_eq!(IBeacon::parse(&DATA[..22]), None);
+        assert_eq!(
+            IBeacon::parse(&[0x10, 0x05, 0x01, 0x18, 0x44, 0x1c, 0x2a]),
+            None
+        );
+
+        let mut manufacturer_data = HashMap::new();
+        manufacturer_data.insert(0x0059, DATA.to_vec());
+        assert_eq!(IBeacon::from_manufacturer_data(&manufacturer_data), None);
+        manufacturer_data.insert(APPLE_COMPANY_ID, DATA.to_vec());
+        assert!(IBeacon::from_manufacturer_data(&manufacturer_data).is_some());
+    }
+}
@@ -0,0 +1,5 @@
+//! Decoders for well-known beacon formats, which are built on top of the manufacturer and service
+//! data found in advertisements.
+
+pub mod eddystone;
+pub mod ibeacon;
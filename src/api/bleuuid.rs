@@ -2,6 +2,9 @@
 
 use uuid::Uuid;
 
+#[cfg(feature = "sig-names")]
+mod sig_names;
+
 const BLUETOOTH_BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805f9b34fb;
 const BLUETOOTH_BASE_MASK: u128 = 0x00000000_ffff_ffff_ffff_ffffffffffff;
 const BLUETOOTH_BASE_MASK_16: u128 = 0xffff0000_ffff_ffff_ffff_ffffffffffff;
@@ -19,6 +22,118 @@ pub const fn uuid_from_u16(short: u16) -> Uuid {
     uuid_from_u32(short as u32)
 }
 
+/// Parse a BLE UUID from a string: either a 16-bit or 32-bit short UUID written as 4 or 8 hex
+/// digits, or a full 128-bit UUID in its usual hyphenated form. Short UUIDs are filled in with
+/// the standard Bluetooth Base UUID.
+///
+/// This is a `const fn` so that UUID constants can be defined without parsing them at runtime;
+/// see [`ble_uuid!`](crate::ble_uuid), which makes sure that it is evaluated at compile time.
+///
+/// # Panics
+///
+/// Panics if the string isn't a UUID in one of these forms. In a const context this is a
+/// compile error instead.
+pub const fn uuid_from_str(s: &str) -> Uuid {
+    let bytes = s.as_bytes();
+    match bytes.len() {
+        4 => uuid_from_u16(parse_hex(bytes, 0, 4) as u16),
+        8 => uuid_from_u32(parse_hex(bytes, 0, 8) as u32),
+        36 => {
+            if bytes[8] != b'-' || bytes[13] != b'-' || bytes[18] != b'-' || bytes[23] != b'-' {
+                panic!(
+                    "A 128-bit UUID must be hyphenated like 0000180f-0000-1000-8000-00805f9b34fb"
+                );
+            }
+            Uuid::from_u128(
+                parse_hex(bytes, 0, 8) << 96
+                    | parse_hex(bytes, 9, 13) << 80
+                    | parse_hex(bytes, 14, 18) << 64
+                    | parse_hex(bytes, 19, 23) << 48
+                    | parse_hex(bytes, 24, 36),
+            )
+        }
+        _ => panic!("A BLE UUID must be 4 or 8 hex digits, or a hyphenated 128-bit UUID"),
+    }
+}
+
+/// Parse the hex digits in `bytes[start..end]` as a number.
+const fn parse_hex(bytes: &[u8], start: usize, end: usize) -> u128 {
+    let mut value = 0;
+    let mut i = start;
+    while i < end {
+        let digit = match bytes[i] {
+            b'0'..=b'9' => bytes[i] - b'0',
+            b'a'..=b'f' => bytes[i] - b'a' + 10,
+            b'A'..=b'F' => bytes[i] - b'A' + 10,
+            _ => panic!("Invalid hex digit in UUID"),
+        };
+        value = value << 4 | digit as u128;
+        i += 1;
+    }
+    value
+}
+
+/// Build a BLE UUID from a string literal at compile time, with [`uuid_from_str`]. Invalid UUIDs
+/// are a compile error.
+///
+/// # Example
+///
+/// ```
+/// use btleplug::ble_uuid;
+/// use btleplug::api::bleuuid::uuid_from_u16;
+/// use uuid::Uuid;
+///
+/// const BATTERY_LEVEL: Uuid = ble_uuid!("2a19");
+/// const NORDIC_UART: Uuid = ble_uuid!("6e400001-b5a3-f393-e0a9-e50e24dcca9e");
+///
+/// assert_eq!(BATTERY_LEVEL, uuid_from_u16(0x2a19));
+/// ```
+#[macro_export]
+macro_rules! ble_uuid {
+    ($uuid:literal) => {{
+        const UUID: $crate::api::bleuuid::__Uuid = $crate::api::bleuuid::uuid_from_str($uuid);
+        UUID
+    }};
+}
+
+#[doc(hidden)]
+pub use uuid::Uuid as __Uuid;
+
+/// Look up the name of a Bluetooth SIG assigned 16-bit UUID, such as a standard GATT service,
+/// characteristic or descriptor, or a member service UUID.
+///
+/// The table embedded in btleplug covers the commonly used assigned numbers rather than the full
+/// registry, so `None` doesn't mean that the UUID is unassigned.
+///
+/// # Example
+///
+/// ```
+/// use btleplug::api::bleuuid::{name_of, uuid_from_u16};
+///
+/// assert_eq!(name_of(uuid_from_u16(0x180F)), Some("Battery"));
+/// ```
+#[cfg(feature = "sig-names")]
+pub fn name_of(uuid: Uuid) -> Option<&'static str> {
+    lookup(sig_names::UUID16_NAMES, uuid.to_ble_u16()?)
+}
+
+/// Look up the name of a Bluetooth SIG company identifier, as used as the keys of
+/// [`PeripheralProperties::manufacturer_data`](crate::api::PeripheralProperties::manufacturer_data).
+///
+/// As with [`name_of`], only commonly seen identifiers are included.
+#[cfg(feature = "sig-names")]
+pub fn company_name(company_id: u16) -> Option<&'static str> {
+    lookup(sig_names::COMPANY_NAMES, company_id)
+}
+
+#[cfg(feature = "sig-names")]
+fn lookup(table: &[(u16, &'static str)], number: u16) -> Option<&'static str> {
+    table
+        .binary_search_by_key(&number, |&(number, _)| number)
+        .ok()
+        .map(|index| table[index].1)
+}
+
 /// An extension trait for `Uuid` which provides BLE-specific methods.
 pub trait BleUuid {
     /// If the UUID is a valid BLE short UUID then return its short form, otherwise return `None`.
@@ -122,6 +237,29 @@ mod tests {
         assert_eq!(Uuid::nil().to_ble_u32(), None);
     }
 
+    #[test]
+    fn uuid_from_str_test() {
+        assert_eq!(uuid_from_str("1122"), uuid_from_u16(0x1122));
+        assert_eq!(uuid_from_str("11223344"), uuid_from_u32(0x11223344));
+        assert_eq!(
+            uuid_from_str("6E400001-b5a3-f393-e0a9-e50e24dcca9e"),
+            Uuid::parse_str("6e400001-b5a3-f393-e0a9-e50e24dcca9e").unwrap()
+        );
+        assert_eq!(ble_uuid!("aBcD"), uuid_from_u16(0xabcd));
+    }
+
+    #[test]
+    #[should_panic]
+    fn uuid_from_str_invalid_digit() {
+        uuid_from_str("11g2");
+    }
+
+    #[test]
+    #[should_panic]
+    fn uuid_from_str_invalid_length() {
+        uuid_from_str("112");
+    }
+
     #[test]
     fn to_short_string_u16() {
         let uuid = uuid_from_u16(0x1122);
@@ -134,6 +272,24 @@ mod tests {
         assert_eq!(uuid.to_short_string(), "0x11223344");
     }
 
+    #[cfg(feature = "sig-names")]
+    #[test]
+    fn sig_name_tables_sorted() {
+        for table in &[sig_names::UUID16_NAMES, sig_names::COMPANY_NAMES] {
+            assert!(table.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+    }
+
+    #[cfg(feature = "sig-names")]
+    #[test]
+    fn sig_name_lookup() {
+        assert_eq!(name_of(uuid_from_u16(0x2A19)), Some("Battery Level"));
+        assert_eq!(name_of(uuid_from_u16(0xFFFF)), None);
+        assert_eq!(name_of(uuid_from_u32(0x1234180F)), None);
+        assert_eq!(company_name(0x004C), Some("Apple, Inc."));
+        assert_eq!(company_name(0xFFFF), None);
+    }
+
     #[test]
     fn to_short_string_long() {
         let uuid_str = "12345678-9000-1000-8000-00805f9b34fb";
@@ -0,0 +1,168 @@
+//! Standard descriptors which describe the value of a characteristic.
+
+use super::{bleuuid::uuid_from_u16, CharPropFlags};
+#[cfg(feature = "serde")]
+use serde_cr::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The UUID of the Characteristic Extended Properties descriptor, which holds the properties
+/// which didn't fit in the characteristic declaration. These are included in
+/// [`Characteristic::properties`].
+pub const EXTENDED_PROPERTIES: Uuid = uuid_from_u16(0x2900);
+
+/// The UUID of the Characteristic User Description descriptor, which holds a human-readable name
+/// for the characteristic.
+pub const USER_DESCRIPTION: Uuid = uuid_from_u16(0x2901);
+
+/// The UUID of the Characteristic Presentation Format descriptor, which says how the value of the
+/// characteristic is formatted. See [`PresentationFormat`].
+pub const PRESENTATION_FORMAT: Uuid = uuid_from_u16(0x2904);
+
+/// The contents of a Characteristic Presentation Format descriptor.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct PresentationFormat {
+    /// The format of the value, as assigned by the Bluetooth SIG, such as 0x04 for an unsigned
+    /// 8-bit integer or 0x19 for a UTF-8 string.
+    pub format: u8,
+    /// The power of 10 which integer values are multiplied by to get the actual value.
+    pub exponent: i8,
+    /// The 16-bit UUID of the unit of the value, such as 0x272F for degrees Celsius.
+    pub unit: u16,
+    /// The organisation which assigned `description`, 0x01 for the Bluetooth SIG.
+    pub namespace: u8,
+    /// Distinguishes between characteristics with the same format and unit, such as the left and
+    /// right sensors of a device.
+    pub description: u16,
+}
+
+impl PresentationFormat {
+    /// Parses the value of a Characteristic Presentation Format descriptor, returning `None` if it
+    /// is too short.
+    pub fn from_bytes(value: &[u8]) -> Option<Self> {
+        if value.len() < 7 {
+            return None;
+        }
+        Some(PresentationFormat {
+            format: value[0],
+            exponent: value[1] as i8,
+            unit: u16::from_le_bytes([value[2], value[3]]),
+            namespace: value[4],
+            description: u16::from_le_bytes([value[5], value[6]]),
+        })
+    }
+
+    /// Returns the full UUID of the unit of the value.
+    pub fn unit_uuid(&self) -> Uuid {
+        uuid_from_u16(self.unit)
+    }
+
+    /// Applies the exponent to a raw integer value read from the characteristic.
+    pub fn scale(&self, raw: i64) -> f64 {
+        raw as f64 * 10f64.powi(self.exponent.into())
+    }
+}
+
+/// Converts the value of a Characteristic Extended Properties descriptor to the corresponding
+/// properties.
+pub(crate) fn extended_properties(value: &[u8]) -> CharPropFlags {
+    let bits = value.first().copied().unwrap_or_default();
+    let mut properties = CharPropFlags::default();
+    if bits & 0x01 != 0 {
+        properties |= CharPropFlags::RELIABLE_WRITE;
+    }
+    if bits & 0x02 != 0 {
+        properties |= CharPropFlags::WRITABLE_AUXILIARIES;
+    }
+    properties
+}
+
+/// What the optional descriptors of a characteristic say about its value, as returned by
+/// [`Peripheral::read_characteristic_metadata`](super::Peripheral::read_characteristic_metadata).
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash)]
+pub struct CharacteristicMetadata {
+    /// The human-readable name of the characteristic, from its Characteristic User Description
+    /// descriptor if it has one.
+    pub user_description: Option<String>,
+    /// How the value of the characteristic is formatted, from its Characteristic Presentation
+    /// Format descriptor if it has one.
+    pub presentation_format: Option<PresentationFormat>,
+}
+
+impl CharacteristicMetadata {
+    /// Fills in the field which corresponds to the descriptor with the given UUID, from the value
+    /// read from it.
+    pub(crate) fn apply(&mut self, uuid: &Uuid, value: &[u8]) {
+        if *uuid == USER_DESCRIPTION {
+            let description = String::from_utf8_lossy(value);
+            self.user_description = Some(description.trim_end_matches('\0').to_owned());
+        } else if *uuid == PRESENTATION_FORMAT {
+            self.presentation_format = PresentationFormat::from_bytes(value);
+        }
+    }
+}
+
+/// Returns whether the descriptor with the given UUID is read to fill in the fields of
+/// [`CharacteristicMetadata`].
+pub(crate) fn is_metadata(uuid: &Uuid) -> bool {
+    *uuid == USER_DESCRIPTION || *uuid == PRESENTATION_FORMAT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_presentation_format() {
+        // A signed 16-bit temperature in hundredths of a degree Celsius.
+        let format =
+            PresentationFormat::from_bytes(&[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x00]).unwrap();
+        assert_eq!(
+            format,
+            PresentationFormat {
+                format: 0x0e,
+                exponent: -2,
+                unit: 0x272f,
+                namespace: 0x01,
+                description: 0x0000,
+            }
+        );
+        assert_eq!(format.unit_uuid(), uuid_from_u16(0x272f));
+        assert!((format.scale(2150) - 21.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parse_short_presentation_format() {
+        assert_eq!(PresentationFormat::from_bytes(&[0x0e, 0xfe, 0x2f]), None);
+    }
+
+    #[test]
+    fn parse_extended_properties() {
+        assert_eq!(
+            extended_properties(&[0x03, 0x00]),
+            CharPropFlags::RELIABLE_WRITE | CharPropFlags::WRITABLE_AUXILIARIES
+        );
+        assert_eq!(
+            extended_properties(&[0x02, 0x00]),
+            CharPropFlags::WRITABLE_AUXILIARIES
+        );
+        assert_eq!(extended_properties(&[]), CharPropFlags::default());
+    }
+
+    #[test]
+    fn apply_user_description() {
+        let mut metadata = CharacteristicMetadata::default();
+        metadata.apply(&USER_DESCRIPTION, b"Left sensor\0");
+        assert_eq!(metadata.user_description.as_deref(), Some("Left sensor"));
+        assert_eq!(metadata.presentation_format, None);
+    }
+}
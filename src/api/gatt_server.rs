@@ -0,0 +1,74 @@
+//! Types and traits for acting as a GATT server (the peripheral role), exposing local services to
+//! remote centrals.
+
+use super::CharPropFlags;
+use crate::Result;
+use async_trait::async_trait;
+use futures::stream::Stream;
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// A characteristic to be hosted by a local [`GattServer`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LocalCharacteristic {
+    /// The UUID for this characteristic.
+    pub uuid: Uuid,
+    /// The operations remote centrals may perform on this characteristic.
+    pub properties: CharPropFlags,
+    /// The initial value of the characteristic. Reads from remote centrals are answered with the
+    /// current value, which can be changed with [`GattServer::set_value`] or
+    /// [`GattServer::notify`].
+    pub value: Vec<u8>,
+}
+
+/// A service to be hosted by a local [`GattServer`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LocalService {
+    /// The UUID for this service.
+    pub uuid: Uuid,
+    /// Whether this is a primary service.
+    pub primary: bool,
+    /// The characteristics that make up this service.
+    pub characteristics: Vec<LocalCharacteristic>,
+}
+
+/// An event raised by a local [`GattServer`] in response to requests from remote centrals.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum GattServerEvent {
+    /// A remote central read the value of a characteristic.
+    CharacteristicRead { service: Uuid, characteristic: Uuid },
+    /// A remote central wrote a new value to a characteristic. The stored value of the
+    /// characteristic has already been updated when this is emitted.
+    CharacteristicWritten {
+        service: Uuid,
+        characteristic: Uuid,
+        value: Vec<u8>,
+    },
+    /// A remote central enabled notifications or indications for a characteristic.
+    Subscribed { service: Uuid, characteristic: Uuid },
+    /// A remote central disabled notifications or indications for a characteristic.
+    Unsubscribed { service: Uuid, characteristic: Uuid },
+}
+
+/// GattServer lets an adapter act in the peripheral role, hosting services which remote centrals
+/// (such as phones) can connect to, read from, write to and subscribe to.
+#[async_trait]
+pub trait GattServer: Send + Sync + Clone {
+    /// Registers a service with the local GATT database and starts advertising it.
+    async fn add_service(&self, service: &LocalService) -> Result<()>;
+
+    /// Stops advertising a previously registered service and removes it from the local GATT
+    /// database.
+    async fn remove_service(&self, service: Uuid) -> Result<()>;
+
+    /// Sets the value returned to remote centrals when they read the given characteristic, without
+    /// notifying subscribers.
+    async fn set_value(&self, service: Uuid, characteristic: Uuid, value: &[u8]) -> Result<()>;
+
+    /// Sets the value of the given characteristic and sends a notification or indication with the
+    /// new value to all subscribed centrals.
+    async fn notify(&self, service: Uuid, characteristic: Uuid, value: &[u8]) -> Result<()>;
+
+    /// Returns a stream of [`GattServerEvent`]s describing requests made by remote centrals.
+    async fn server_events(&self) -> Result<Pin<Box<dyn Stream<Item = GattServerEvent> + Send>>>;
+}
@@ -0,0 +1,107 @@
+//! Identity Resolving Keys, used to recognise devices which advertise with resolvable private
+//! addresses.
+
+use super::BDAddr;
+use aes::cipher::{BlockEncrypt, NewBlockCipher};
+use aes::{Aes128, Block};
+use std::fmt::{self, Debug, Formatter};
+
+/// A 128-bit Identity Resolving Key (IRK), which a device distributes when bonding so that its
+/// resolvable private addresses can be linked back to its identity address.
+#[derive(Copy, Clone, Hash, Eq, PartialEq)]
+pub struct IdentityResolvingKey {
+    key: [u8; 16],
+}
+
+impl IdentityResolvingKey {
+    /// Build a key from bytes in most-significant-byte-first order, as keys are written in the
+    /// Bluetooth Core Specification.
+    pub fn from_be_bytes(key: [u8; 16]) -> Self {
+        Self { key }
+    }
+
+    /// Build a key from bytes in least-significant-byte-first order, as keys are sent over HCI
+    /// and the Security Manager Protocol.
+    pub fn from_le_bytes(mut key: [u8; 16]) -> Self {
+        key.reverse();
+        Self { key }
+    }
+
+    /// Return the key bytes in most-significant-byte-first order.
+    pub fn to_be_bytes(self) -> [u8; 16] {
+        self.key
+    }
+
+    /// Check whether the given address is a resolvable private address generated from this key,
+    /// using the `ah` random address hash function from the Bluetooth Core Specification.
+    pub fn resolves(&self, address: &BDAddr) -> bool {
+        if !address.is_resolvable_private() {
+            return false;
+        }
+        let address = address.into_inner();
+        let (prand, hash) = address.split_at(3);
+        let mut block = Block::default();
+        block[13..].copy_from_slice(prand);
+        Aes128::new(&self.key.into()).encrypt_block(&mut block);
+        block[13..] == *hash
+    }
+}
+
+impl From<[u8; 16]> for IdentityResolvingKey {
+    /// Build a key from an array in most-significant-byte-first order.
+    fn from(key: [u8; 16]) -> Self {
+        Self::from_be_bytes(key)
+    }
+}
+
+impl Debug for IdentityResolvingKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Don't leak the key into logs.
+        f.write_str("IdentityResolvingKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The sample data for the `ah` function from the Bluetooth Core Specification, Vol 3, Part H,
+    /// Appendix D.7.
+    const IRK: [u8; 16] = [
+        0xec, 0x02, 0x34, 0xa3, 0x57, 0xc8, 0xad, 0x05, 0x34, 0x10, 0x10, 0xa6, 0x0a, 0x39, 0x7d,
+        0x9b,
+    ];
+    const RPA: BDAddr = BDAddr::from_be_bytes([0x70, 0x81, 0x94, 0x0d, 0xfb, 0xaa]);
+
+    #[test]
+    fn resolves_sample_address() {
+        let irk = IdentityResolvingKey::from_be_bytes(IRK);
+        assert!(irk.resolves(&RPA));
+    }
+
+    #[test]
+    fn rejects_other_addresses() {
+        let irk = IdentityResolvingKey::from_be_bytes(IRK);
+        // Wrong hash.
+        let address = BDAddr::from([0x70, 0x81, 0x94, 0x0d, 0xfb, 0xab]);
+        assert!(!irk.resolves(&address));
+        // The right hash, but not a resolvable private address.
+        let address = BDAddr::from([0xf0, 0x81, 0x94, 0x0d, 0xfb, 0xaa]);
+        assert!(!irk.resolves(&address));
+    }
+
+    #[test]
+    fn le_bytes() {
+        let mut reversed = IRK;
+        reversed.reverse();
+        let irk = IdentityResolvingKey::from_le_bytes(reversed);
+        assert_eq!(irk.to_be_bytes(), IRK);
+        assert!(irk.resolves(&RPA));
+    }
+
+    #[test]
+    fn debug_hides_key() {
+        let irk = IdentityResolvingKey::from_be_bytes(IRK);
+        assert_eq!(format!("{:?}", irk), "IdentityResolvingKey(..)");
+    }
+}
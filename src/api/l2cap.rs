@@ -0,0 +1,24 @@
+use crate::Result;
+use async_trait::async_trait;
+use std::fmt::Debug;
+
+/// An L2CAP connection-oriented channel to a peripheral, opened with
+/// [`Peripheral::open_l2cap_channel`](super::Peripheral::open_l2cap_channel). This carries a
+/// stream of data outside of GATT, which some accessories use for bulk transfers.
+///
+/// The channel is closed when it is dropped, or when the peripheral disconnects.
+#[async_trait]
+pub trait L2capChannel: Debug + Send + Sync {
+    /// Returns the PSM (Protocol/Service Multiplexer) the channel was opened on.
+    fn psm(&self) -> u16;
+
+    /// Sends data to the peripheral, waiting until it has all been written to the channel.
+    async fn write(&self, data: &[u8]) -> Result<()>;
+
+    /// Waits for the next data received from the peripheral. Returns `None` once the channel has
+    /// been closed.
+    async fn read(&self) -> Result<Option<Vec<u8>>>;
+
+    /// Closes the channel.
+    async fn close(&self) -> Result<()>;
+}
@@ -0,0 +1,111 @@
+//! A wrapper around a [`Peripheral`] which keeps it connected, reconnecting and restoring its
+//! subscriptions whenever the connection is lost.
+
+use super::{Central, CentralEvent, Characteristic, ConnectOptions, Peripheral};
+use crate::{common::runtime, Error, Result};
+use futures::stream::StreamExt;
+use log::{debug, warn};
+use std::time::Duration;
+
+/// Controls how a [`ManagedPeripheral`] retries after losing its connection.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReconnectPolicy {
+    /// The number of reconnection attempts to make before giving up, or `None` to keep trying
+    /// forever.
+    pub max_attempts: Option<u32>,
+    /// The delay before the first reconnection attempt. This doubles after each failed attempt.
+    pub initial_delay: Duration,
+    /// The longest delay between two reconnection attempts.
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: None,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Keeps a [`Peripheral`] connected according to a [`ReconnectPolicy`]. After each reconnection
+/// its characteristics are discovered again and the characteristics which were subscribed to are
+/// resubscribed, as with [`ConnectOptions::resubscribe`]. The stream from
+/// [`Peripheral::notifications`] still ends when the connection is lost, so it needs to be
+/// requested again after each reconnection.
+#[derive(Debug, Clone)]
+pub struct ManagedPeripheral<P: Peripheral> {
+    peripheral: P,
+    policy: ReconnectPolicy,
+}
+
+impl<P: Peripheral> ManagedPeripheral<P> {
+    pub fn new(peripheral: P, policy: ReconnectPolicy) -> Self {
+        ManagedPeripheral { peripheral, policy }
+    }
+
+    /// The underlying peripheral.
+    pub fn peripheral(&self) -> &P {
+        &self.peripheral
+    }
+
+    /// Subscribes to a characteristic, and remembers to subscribe to it again after reconnecting.
+    pub async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.peripheral.subscribe(characteristic).await
+    }
+
+    /// Unsubscribes from a characteristic, so it won't be subscribed to after reconnecting.
+    pub async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.peripheral.unsubscribe(characteristic).await
+    }
+
+    /// Connects to the peripheral, then keeps it connected until the reconnection policy gives
+    /// up, in which case the error from the last attempt is returned. The peripheral is only
+    /// managed while the returned future is being polled.
+    pub async fn run<C: Central>(&self, central: &C) -> Result<()> {
+        let address = self.peripheral.address();
+        let mut events = central.events().await?;
+        if !self.peripheral.is_connected().await? {
+            self.reconnect().await?;
+        }
+        while let Some(event) = events.next().await {
+            if let CentralEvent::DeviceDisconnected(disconnected, _) = event {
+                if disconnected == address {
+                    debug!("{} disconnected, reconnecting", address);
+                    self.reconnect().await?;
+                }
+            }
+        }
+        Err(Error::Other("Central event stream ended.".into()))
+    }
+
+    async fn reconnect(&self) -> Result<()> {
+        let mut delay = self.policy.initial_delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let may_retry = match self.policy.max_attempts {
+                Some(max_attempts) => attempt < max_attempts,
+                None => true,
+            };
+            match self.restore_connection().await {
+                Ok(()) => return Ok(()),
+                Err(e) if may_retry => warn!("Reconnection attempt {} failed: {}", attempt, e),
+                Err(e) => return Err(e),
+            }
+            runtime::sleep(delay).await;
+            delay = (delay * 2).min(self.policy.max_delay);
+        }
+    }
+
+    async fn restore_connection(&self) -> Result<()> {
+        let options = ConnectOptions {
+            resubscribe: true,
+            ..Default::default()
+        };
+        self.peripheral.connect_with_options(&options).await?;
+        self.peripheral.discover_characteristics().await?;
+        Ok(())
+    }
+}
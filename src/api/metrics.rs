@@ -0,0 +1,153 @@
+#[cfg(feature = "serde")]
+use serde_cr::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The upper bounds of the buckets of a [`LatencyHistogram`]. Operations which take longer than
+/// the last of these are counted in a further, unbounded bucket.
+pub const LATENCY_BUCKETS: [Duration; 10] = [
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_millis(1000),
+    Duration::from_millis(2500),
+    Duration::from_millis(5000),
+];
+
+/// A snapshot of the counters kept by an adapter, from
+/// [`Central::metrics`](super::Central::metrics).
+///
+/// The counters start from zero when the adapter is created and only ever go up, so rates such as
+/// notifications per second can be worked out from two snapshots with [`since`](Self::since).
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AdapterMetrics {
+    /// How long the counters have been running.
+    pub elapsed: Duration,
+    /// The number of advertisements received while scanning, before any scan filter is applied.
+    pub advertisements_received: u64,
+    /// The number of times a connection to a peripheral was attempted.
+    pub connect_attempts: u64,
+    /// The number of attempts to connect which failed, timed out or were cancelled.
+    pub connect_failures: u64,
+    /// The number of notifications and indications received from peripherals.
+    pub notifications_received: u64,
+    /// The number of notifications dropped because the buffer of a notification stream was full.
+    /// See [`PeripheralOptions::notification_capacity`](super::PeripheralOptions).
+    pub dropped_events: u64,
+    /// How long reads and writes of characteristics and descriptors took, whether or not they
+    /// succeeded.
+    pub gatt_latency: LatencyHistogram,
+}
+
+impl AdapterMetrics {
+    /// Returns what was counted between an earlier snapshot and this one.
+    pub fn since(&self, earlier: &AdapterMetrics) -> AdapterMetrics {
+        AdapterMetrics {
+            elapsed: self.elapsed.saturating_sub(earlier.elapsed),
+            advertisements_received: self
+                .advertisements_received
+                .saturating_sub(earlier.advertisements_received),
+            connect_attempts: self
+                .connect_attempts
+                .saturating_sub(earlier.connect_attempts),
+            connect_failures: self
+                .connect_failures
+                .saturating_sub(earlier.connect_failures),
+            notifications_received: self
+                .notifications_received
+                .saturating_sub(earlier.notifications_received),
+            dropped_events: self.dropped_events.saturating_sub(earlier.dropped_events),
+            gatt_latency: self.gatt_latency.since(&earlier.gatt_latency),
+        }
+    }
+
+    /// Returns the mean number of advertisements received per second.
+    pub fn advertisements_per_second(&self) -> f64 {
+        per_second(self.advertisements_received, self.elapsed)
+    }
+
+    /// Returns the mean number of notifications received per second.
+    pub fn notifications_per_second(&self) -> f64 {
+        per_second(self.notifications_received, self.elapsed)
+    }
+}
+
+fn per_second(count: u64, elapsed: Duration) -> f64 {
+    if elapsed.as_secs_f64() > 0.0 {
+        count as f64 / elapsed.as_secs_f64()
+    } else {
+        0.0
+    }
+}
+
+/// A histogram of how long operations took, with the buckets in [`LATENCY_BUCKETS`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    total: Duration,
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&mut self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.total += latency;
+    }
+
+    /// Returns the number of operations in each bucket, along with the upper bound of the bucket,
+    /// or `None` for the last one.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        LATENCY_BUCKETS
+            .iter()
+            .copied()
+            .map(Some)
+            .chain(std::iter::once(None))
+            .zip(self.counts.iter().copied())
+    }
+
+    /// Returns the number of operations recorded.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the total time taken by the operations recorded.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Returns the mean time taken by an operation, or `None` if none have been recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(self.total.div_f64(count as f64)),
+        }
+    }
+
+    /// Returns the histogram of the operations recorded since an earlier snapshot of it.
+    pub fn since(&self, earlier: &LatencyHistogram) -> LatencyHistogram {
+        let mut counts = self.counts;
+        for (count, earlier) in counts.iter_mut().zip(earlier.counts.iter()) {
+            *count = count.saturating_sub(*earlier);
+        }
+        LatencyHistogram {
+            counts,
+            total: self.total.saturating_sub(earlier.total),
+        }
+    }
+}
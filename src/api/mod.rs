@@ -22,11 +22,21 @@
 //! ```
 
 pub(crate) mod bdaddr;
+pub mod beacons;
 pub mod bleuuid;
+pub mod descriptors;
+mod gatt_server;
+mod irk;
+mod l2cap;
+pub mod managed;
+mod metrics;
+mod pairing;
+pub mod profiles;
 
-use crate::Result;
+use crate::{Error, Result};
 use async_trait::async_trait;
 use bitflags::bitflags;
+use bytes::Bytes;
 use futures::stream::Stream;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -35,11 +45,21 @@ use serde_cr as serde;
 use std::{
     collections::{BTreeSet, HashMap},
     fmt::{self, Debug, Display, Formatter},
+    num::NonZeroUsize,
     pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 use uuid::Uuid;
 
-pub use self::bdaddr::{BDAddr, ParseBDAddrError};
+pub use self::bdaddr::{BDAddr, ParseBDAddrError, RandomAddressKind};
+use self::beacons::{eddystone::EddystoneFrame, ibeacon::IBeacon};
+pub use self::descriptors::{CharacteristicMetadata, PresentationFormat};
+pub use self::gatt_server::{GattServer, GattServerEvent, LocalCharacteristic, LocalService};
+pub use self::irk::IdentityResolvingKey;
+pub use self::l2cap::L2capChannel;
+pub use self::metrics::{AdapterMetrics, LatencyHistogram, LATENCY_BUCKETS};
+pub use self::pairing::PairingAgent;
 
 #[cfg_attr(
     feature = "serde",
@@ -88,13 +108,25 @@ impl AddressType {
 pub struct ValueNotification {
     /// UUID of the characteristic that fired the notification.
     pub uuid: Uuid,
-    /// The new value of the characteristic.
-    pub value: Vec<u8>,
+    /// UUID of the service containing the characteristic.
+    pub service_uuid: Uuid,
+    /// The attribute handle of the characteristic, as in [`Characteristic::handle`].
+    pub handle: u16,
+    /// The new value of the characteristic. This is shared rather than copied when the notification
+    /// is sent to more than one stream.
+    pub value: Bytes,
 }
 
 bitflags! {
     /// A set of properties that indicate what operations are supported by a Characteristic.
-    pub struct CharPropFlags: u8 {
+    /// `RELIABLE_WRITE` and `WRITABLE_AUXILIARIES` come from the Characteristic Extended
+    /// Properties descriptor of characteristics which have `EXTENDED_PROPERTIES` set.
+    #[cfg_attr(
+        feature = "serde",
+        derive(Serialize, Deserialize),
+        serde(crate = "serde_cr")
+    )]
+    pub struct CharPropFlags: u16 {
         const BROADCAST = 0x01;
         const READ = 0x02;
         const WRITE_WITHOUT_RESPONSE = 0x04;
@@ -103,6 +135,11 @@ bitflags! {
         const INDICATE = 0x20;
         const AUTHENTICATED_SIGNED_WRITES = 0x40;
         const EXTENDED_PROPERTIES = 0x80;
+        /// Queued writes of the value may be committed together with those of other
+        /// characteristics, as a reliable write.
+        const RELIABLE_WRITE = 0x100;
+        /// The Characteristic User Description descriptor may be written.
+        const WRITABLE_AUXILIARIES = 0x200;
     }
 }
 
@@ -120,10 +157,22 @@ impl Default for CharPropFlags {
 ///
 /// A characteristic may be interacted with in various ways depending on its properties. You may be
 /// able to write to it, read from it, set its notify or indicate status, or send a command to it.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
 pub struct Characteristic {
     /// The UUID for this characteristic. This uniquely identifies its behavior.
     pub uuid: Uuid,
+    /// The UUID of the service this characteristic belongs to. Different services may contain
+    /// characteristics with the same UUID.
+    pub service_uuid: Uuid,
+    /// The attribute handle of this characteristic on the device, which tells apart
+    /// characteristics with the same UUID in the same service, such as those of a service which
+    /// is included more than once. CoreBluetooth doesn't expose handles, so there this is always 0.
+    pub handle: u16,
     /// The set of properties for this characteristic, which indicate what functionality it
     /// supports. If you attempt an operation that is not supported by the characteristics (for
     /// example setting notify on one without the NOTIFY flag), that operation will fail.
@@ -134,12 +183,86 @@ impl Display for Characteristic {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
             f,
-            "uuid: {:?}, char properties: {:?}",
-            self.uuid, self.properties
+            "uuid: {:?}, service: {:?}, char properties: {:?}",
+            self.uuid, self.service_uuid, self.properties
+        )
+    }
+}
+
+/// A Bluetooth GATT service. Services group together the characteristics which implement a
+/// particular feature of a device, such as reporting its battery level. Different services may
+/// contain characteristics with the same UUID.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+pub struct Service {
+    /// The UUID for this service. This uniquely identifies its behavior.
+    pub uuid: Uuid,
+    /// Whether this is a primary service. Secondary services are only meant to be used through the
+    /// services which include them.
+    pub primary: bool,
+    /// The characteristics belonging to this service.
+    pub characteristics: BTreeSet<Characteristic>,
+    /// The UUIDs of the services which this service includes, such as the Battery service
+    /// included by a HID service. Included services are listed among the peripheral's services
+    /// too.
+    pub included_services: BTreeSet<Uuid>,
+}
+
+impl Display for Service {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "uuid: {:?}, primary: {}, characteristics: {}, included services: {}",
+            self.uuid,
+            self.primary,
+            self.characteristics.len(),
+            self.included_services.len()
+        )
+    }
+}
+
+/// A Bluetooth characteristic descriptor. Descriptors hold additional information about the value
+/// of a characteristic, such as its Client Characteristic Configuration (0x2902) or a
+/// human-readable description, as well as vendor specific data.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+pub struct Descriptor {
+    /// The UUID for this descriptor. This uniquely identifies its behavior.
+    pub uuid: Uuid,
+    /// The UUID of the characteristic this descriptor belongs to.
+    pub characteristic_uuid: Uuid,
+    /// The attribute handle of the characteristic this descriptor belongs to, as in
+    /// [`Characteristic::handle`].
+    pub characteristic_handle: u16,
+}
+
+impl Display for Descriptor {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "uuid: {:?}, characteristic: {:?}",
+            self.uuid, self.characteristic_uuid
         )
     }
 }
 
+/// A single AD structure from an advertisement, as defined in the Core Specification Supplement.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AdStructure {
+    /// The AD type, which identifies how `data` should be interpreted.
+    pub ad_type: u8,
+    /// The data following the AD type.
+    pub data: Vec<u8>,
+}
+
 /// The properties of this peripheral, as determined by the advertising reports we've received for
 /// it.
 #[derive(Debug, Default, Clone)]
@@ -160,11 +283,471 @@ pub struct PeripheralProperties {
     pub service_data: HashMap<Uuid, Vec<u8>>,
     /// Advertised services for this device
     pub services: Vec<Uuid>,
+    /// The signal strength of the last advertisement received from this device, in dBm.
+    pub rssi: Option<i16>,
     /// Number of times we've seen advertising reports for this device
     pub discovery_count: u32,
+    /// The raw AD structures of the last advertisement received from this device, including any
+    /// which btleplug doesn't parse itself. This is empty on platforms which don't expose them.
+    pub ad_structures: Vec<AdStructure>,
+    /// When the last advertisement from this device was received, on platforms which track it.
+    pub last_seen: Option<SystemTime>,
+}
+
+/// A snapshot of the state of a peripheral, as returned by [`Peripheral::summary`], for logs and
+/// device lists. Its `Display` implementation gives a one-line description.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PeripheralSummary {
+    pub address: BDAddr,
+    /// The advertised name, if one has been seen.
+    pub local_name: Option<String>,
+    /// The signal strength of the last advertisement received, in dBm.
+    pub rssi: Option<i16>,
+    pub connected: bool,
+    /// The number of services with discovered characteristics, which is 0 until services have
+    /// been discovered.
+    pub service_count: usize,
+}
+
+impl Display for PeripheralSummary {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.address,
+            self.local_name.as_deref().unwrap_or("(unknown)")
+        )?;
+        if let Some(rssi) = self.rssi {
+            write!(f, " {} dBm", rssi)?;
+        }
+        if self.connected {
+            write!(f, " connected, {} services", self.service_count)?;
+        }
+        Ok(())
+    }
+}
+
+impl PeripheralProperties {
+    /// The kind of random address this peripheral is using, or `None` if its address is public,
+    /// its address type is unknown, or the address uses the reserved bit pattern.
+    ///
+    /// Phones and other personal devices usually advertise with a
+    /// [`RandomAddressKind::ResolvablePrivate`] address which rotates every few minutes, so
+    /// applications tracking device presence may want to ignore them.
+    pub fn random_address_kind(&self) -> Option<RandomAddressKind> {
+        match self.address_type {
+            Some(AddressType::Random) => self.address.random_address_kind(),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    /// The properties of a peripheral which changed, as reported by
+    /// [`CentralEvent::DeviceUpdated`].
+    #[cfg_attr(
+        feature = "serde",
+        derive(Serialize, Deserialize),
+        serde(crate = "serde_cr")
+    )]
+    pub struct PropertyChanges: u8 {
+        const RSSI = 0x01;
+        const LOCAL_NAME = 0x02;
+        const MANUFACTURER_DATA = 0x04;
+        const SERVICE_DATA = 0x08;
+        const SERVICES = 0x10;
+        const TX_POWER_LEVEL = 0x20;
+    }
+}
+
+impl Default for PropertyChanges {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl PropertyChanges {
+    /// Returns the properties which differ between two snapshots of the properties of a
+    /// peripheral.
+    pub fn between(old: &PeripheralProperties, new: &PeripheralProperties) -> Self {
+        let mut changes = PropertyChanges::empty();
+        changes.set(PropertyChanges::RSSI, old.rssi != new.rssi);
+        changes.set(
+            PropertyChanges::LOCAL_NAME,
+            old.local_name != new.local_name,
+        );
+        changes.set(
+            PropertyChanges::MANUFACTURER_DATA,
+            old.manufacturer_data != new.manufacturer_data,
+        );
+        changes.set(
+            PropertyChanges::SERVICE_DATA,
+            old.service_data != new.service_data,
+        );
+        changes.set(PropertyChanges::SERVICES, old.services != new.services);
+        changes.set(
+            PropertyChanges::TX_POWER_LEVEL,
+            old.tx_power_level != new.tx_power_level,
+        );
+        changes
+    }
+}
+
+/// Options for [`Peripheral::connect_with_options`].
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ConnectOptions {
+    /// Asks the operating system to keep the connection open while no GATT operations are in
+    /// progress. Windows otherwise closes idle connections after a few seconds. Other platforms
+    /// always keep the connection open, so this has no effect there.
+    pub maintain_connection: bool,
+    /// Discovers services and characteristics from the device itself rather than the operating
+    /// system's cache. Windows caches the GATT database aggressively, so without this devices which
+    /// change their services, such as when rebooting into a DFU bootloader, show stale
+    /// characteristics. Only supported on Windows, and ignored elsewhere.
+    pub uncached_discovery: bool,
+    /// Once connected, discovers characteristics and enables notifications again for those which
+    /// were still [subscribed](Peripheral::subscribe) to when the previous connection was lost.
+    /// Otherwise those subscriptions are forgotten. Notification streams end with the connection,
+    /// so [`Peripheral::notifications`] needs to be called again after reconnecting.
+    pub resubscribe: bool,
+    /// How many reads, writes and subscriptions may be in progress on the connection at once.
+    /// Others wait, and are started in the order they were requested. Defaults to one, as some
+    /// platforms fail GATT requests which overlap with others.
+    pub concurrent_operations: Option<NonZeroUsize>,
+}
+
+/// Timeouts and retries for the operations on a peripheral, set with [`Peripheral::set_options`].
+/// An operation which doesn't complete in time fails with [`Error::TimedOut`] rather than waiting
+/// forever for a device which has stopped responding. Each timeout defaults to `None`, for no
+/// timeout.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PeripheralOptions {
+    /// The timeout for reading a characteristic or descriptor, including any time spent waiting
+    /// for earlier operations on the connection to finish.
+    pub read_timeout: Option<Duration>,
+    /// The timeout for writing to a characteristic or descriptor, including any time spent waiting
+    /// for earlier operations on the connection to finish.
+    pub write_timeout: Option<Duration>,
+    /// The timeout for enabling or disabling notifications or indications on a characteristic.
+    pub subscribe_timeout: Option<Duration>,
+    /// The timeout for discovering services, characteristics or descriptors.
+    pub discovery_timeout: Option<Duration>,
+    /// Retries connecting and reads, writes and subscriptions which fail in a way that might
+    /// succeed if tried again. Defaults to `None`, so every failure is returned straight away.
+    pub retry: Option<RetryPolicy>,
+    /// The number of notifications buffered for each stream returned by
+    /// [`Peripheral::notifications`]. Once a stream's buffer is full, further notifications for it
+    /// are dropped and counted by [`Peripheral::dropped_notifications`] until it is read from.
+    /// Defaults to `None`, so notifications are buffered without limit and never dropped. Only
+    /// affects streams requested after it is set.
+    pub notification_capacity: Option<usize>,
+}
+
+bitflags! {
+    /// The kinds of error which a [`RetryPolicy`] retries.
+    pub struct RetryableErrors: u8 {
+        /// [`Error::TimedOut`], when a timeout is set in [`PeripheralOptions`]. The operation may
+        /// still have reached the device.
+        const TIMED_OUT = 0x01;
+        /// [`Error::NotConnected`], such as when the link dropped during the operation.
+        const NOT_CONNECTED = 0x02;
+        /// [`Error::Att`], returned by the device itself.
+        const ATT = 0x04;
+        /// [`Error::Other`] and [`Error::Platform`], which cover most failures reported by the
+        /// operating system's Bluetooth stack.
+        const OTHER = 0x08;
+        /// Any error which [`Error::is_transient`], such as [`Error::ConnectionTimeout`] or an
+        /// ATT error which the device returns when it is short of resources.
+        const TRANSIENT = 0x10;
+    }
+}
+
+/// Controls how failed operations on a peripheral are retried, set in
+/// [`PeripheralOptions::retry`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first.
+    pub max_attempts: u32,
+    /// The delay before the first retry. This doubles after each failed attempt.
+    pub initial_delay: Duration,
+    /// The longest delay between two attempts.
+    pub max_delay: Duration,
+    /// The kinds of error which are retried. Others are returned straight away.
+    pub retryable: RetryableErrors,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            retryable: RetryableErrors::TIMED_OUT
+                | RetryableErrors::OTHER
+                | RetryableErrors::TRANSIENT,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns whether the policy retries operations which fail with the given error.
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        if self.retryable.contains(RetryableErrors::TRANSIENT) && error.is_transient() {
+            return true;
+        }
+        let kind = match error {
+            Error::TimedOut(_) => RetryableErrors::TIMED_OUT,
+            Error::NotConnected => RetryableErrors::NOT_CONNECTED,
+            Error::Att(_) => RetryableErrors::ATT,
+            Error::Platform { .. } | Error::Other(_) => RetryableErrors::OTHER,
+            _ => return false,
+        };
+        self.retryable.contains(kind)
+    }
+}
+
+/// The preferred parameters for a connection to a peripheral, set with
+/// [`Peripheral::set_connection_parameters`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ConnectionParameters {
+    /// The minimum connection interval. Must be between 7.5 ms and 4 s.
+    pub min_interval: Duration,
+    /// The maximum connection interval. Must be between `min_interval` and 4 s.
+    pub max_interval: Duration,
+    /// The number of connection events the peripheral may skip when it has nothing to send.
+    pub latency: u16,
+    /// How long the connection may go without a successful connection event before it is
+    /// considered lost. Must be between 100 ms and 32 s.
+    pub supervision_timeout: Duration,
+}
+
+impl ConnectionParameters {
+    /// Checks that the parameters are within the ranges the Bluetooth specification allows, and
+    /// that the supervision timeout is long enough for the latency and maximum interval.
+    pub fn validate(&self) -> Result<()> {
+        let interval_range = Duration::from_micros(7_500)..=Duration::from_secs(4);
+        let invalid = |message: &str| Err(Error::Other(message.to_string().into()));
+        if !interval_range.contains(&self.min_interval)
+            || !interval_range.contains(&self.max_interval)
+        {
+            return invalid("The connection interval must be between 7.5 ms and 4 s");
+        }
+        if self.min_interval > self.max_interval {
+            return invalid("The minimum connection interval is above the maximum");
+        }
+        if self.latency > 499 {
+            return invalid("The peripheral latency must be at most 499");
+        }
+        if !(Duration::from_millis(100)..=Duration::from_secs(32))
+            .contains(&self.supervision_timeout)
+        {
+            return invalid("The supervision timeout must be between 100 ms and 32 s");
+        }
+        if self.supervision_timeout <= self.max_interval * 2 * (u32::from(self.latency) + 1) {
+            return invalid(
+                "The supervision timeout must be more than twice the maximum interval for each \
+                 connection event the peripheral may skip",
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A coarse request for how a connection should trade throughput and latency against power
+/// consumption, for use with [`Peripheral::request_connection_priority`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConnectionPriority {
+    /// Short connection intervals, for high throughput and low latency transfers such as firmware
+    /// updates.
+    High,
+    /// The default trade-off between throughput and power consumption.
+    Balanced,
+    /// Long connection intervals, reducing power consumption at the cost of throughput.
+    LowPower,
+}
+
+/// The minimum security a link to a peripheral should have, for use with
+/// [`Peripheral::request_security`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum SecurityLevel {
+    /// The link is encrypted, with keys which may have been exchanged without protection against
+    /// man-in-the-middle attacks ("Just Works" pairing).
+    Encrypted,
+    /// The link is encrypted with keys exchanged using a passkey or numeric comparison, which
+    /// protects against man-in-the-middle attacks.
+    Authenticated,
+}
+
+impl From<ConnectionPriority> for ConnectionParameters {
+    /// Converts the priority into the same parameters Android uses for it.
+    fn from(priority: ConnectionPriority) -> Self {
+        let (min_interval_micros, max_interval_micros, latency) = match priority {
+            ConnectionPriority::High => (11_250, 15_000, 0),
+            ConnectionPriority::Balanced => (30_000, 50_000, 0),
+            ConnectionPriority::LowPower => (100_000, 125_000, 2),
+        };
+        ConnectionParameters {
+            min_interval: Duration::from_micros(min_interval_micros),
+            max_interval: Duration::from_micros(max_interval_micros),
+            latency,
+            supervision_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A Bluetooth LE physical layer, as used by [`Peripheral::set_preferred_phy`] and
+/// [`Peripheral::read_phy`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Phy {
+    /// The original 1 Msym/s PHY, supported by all devices.
+    Le1M,
+    /// The 2 Msym/s PHY introduced in Bluetooth 5, for higher throughput.
+    Le2M,
+    /// The coded PHY introduced in Bluetooth 5, trading throughput for range.
+    LeCoded,
+}
+
+/// Whether a scan sends scan requests to the devices it discovers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScanType {
+    /// Request scan response data from each device, which may include extra information such as
+    /// its name.
+    Active,
+    /// Only listen for advertisements, without transmitting anything. This uses less power, and
+    /// doesn't change the behaviour of devices which react to being scanned.
+    Passive,
+}
+
+impl Default for ScanType {
+    fn default() -> Self {
+        ScanType::Active
+    }
+}
+
+/// How much of the time a scan spends listening, trading how quickly devices are discovered
+/// against power consumption.
+///
+/// This is only applied on Linux with the `hci` feature, where it sets the scan interval and window
+/// which the kernel uses for discovery on the whole adapter, and needs the `CAP_NET_ADMIN`
+/// capability. It doesn't affect passive scans there. On other platforms it has no effect, as
+/// WinRT and CoreBluetooth choose the scan interval and window themselves.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScanMode {
+    /// Listen for about a tenth of the time, for long-running background monitoring.
+    LowPower,
+    /// The platform's default. On Linux, the kernel's defaults are restored, which listen
+    /// continuously.
+    Balanced,
+    /// Listen continuously, to discover devices as quickly as possible. On Linux, this is the same
+    /// as `Balanced`.
+    LowLatency,
+}
+
+impl Default for ScanMode {
+    fn default() -> Self {
+        ScanMode::Balanced
+    }
+}
+
+/// Restricts which devices are reported by a scan started with [`Central::start_scan`]. A device
+/// is only reported once it matches every condition which is set. Conditions which the platform
+/// can't apply natively are checked against each device's [`PeripheralProperties`] instead, so
+/// the same devices are reported on every platform.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct ScanFilter {
+    /// If not empty, only devices advertising at least one of these services are reported.
+    pub services: Vec<Uuid>,
+    /// Only devices with a local name starting with this prefix are reported.
+    pub name_prefix: Option<String>,
+    /// Only devices advertising manufacturer data for this company ID are reported.
+    pub manufacturer_id: Option<u16>,
+    /// Only devices received with at least this signal strength, in dBm, are reported. Devices for
+    /// which the platform doesn't report a signal strength are not filtered out.
+    pub min_rssi: Option<i16>,
+    /// Whether to scan actively or passively. This doesn't restrict which devices are reported,
+    /// but passive scans may not receive scan response data such as device names.
+    pub scan_type: ScanType,
+    /// How much of the time to spend scanning. This doesn't restrict which devices are reported.
+    pub scan_mode: ScanMode,
+    /// Whether to report every advertisement received from a device, rather than only those which
+    /// change its properties. If set, a [`CentralEvent::RssiUpdate`] is emitted for each
+    /// advertisement.
+    pub allow_duplicates: bool,
+}
+
+impl ScanFilter {
+    /// Returns whether a device with the given properties passes this filter.
+    pub fn matches(&self, properties: &PeripheralProperties) -> bool {
+        if !self.services.is_empty()
+            && !self
+                .services
+                .iter()
+                .any(|service| properties.services.contains(service))
+        {
+            return false;
+        }
+        if let Some(name_prefix) = &self.name_prefix {
+            match &properties.local_name {
+                Some(local_name) if local_name.starts_with(name_prefix.as_str()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(manufacturer_id) = self.manufacturer_id {
+            if !properties.manufacturer_data.contains_key(&manufacturer_id) {
+                return false;
+            }
+        }
+        match (self.min_rssi, properties.rssi) {
+            (Some(min_rssi), Some(rssi)) => rssi >= min_rssi,
+            _ => true,
+        }
+    }
+}
+
+/// A periodic advertisement received from a device synchronized to with
+/// [`Central::sync_periodic_advertising`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PeriodicAdvertisement {
+    /// The address of the device sending the periodic advertising train.
+    pub address: BDAddr,
+    /// The advertising set ID of the periodic advertising train.
+    pub sid: u8,
+    /// The transmission power level reported for the advertisement, if any.
+    pub tx_power_level: Option<i8>,
+    /// The signal strength with which the advertisement was received, in dBm, if known.
+    pub rssi: Option<i16>,
+    /// The raw advertising data of the advertisement, reassembled if it was split across several
+    /// reports.
+    pub data: Vec<u8>,
+}
+
+/// The data to broadcast when advertising from a local adapter with [`Central::start_advertising`].
+///
+/// Windows reserves the local name and service UUID sections of advertisements for itself, so only
+/// manufacturer data can be advertised there; an advertisement with a local name or services is
+/// rejected with [`Error::NotSupported`](crate::Error::NotSupported).
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Advertisement {
+    /// The local name to advertise. Not supported on Windows.
+    pub local_name: Option<String>,
+    /// Service UUIDs to advertise. Not supported on Windows.
+    pub services: Vec<Uuid>,
+    /// Advertisement data specific to the device manufacturer. The keys of this map are
+    /// 'manufacturer IDs', while the values are arbitrary data.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
 }
 
 /// The type of write operation to use.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum WriteType {
     /// A write operation where the device is expected to respond with a confirmation or error. Also
@@ -186,6 +769,17 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// as additional advertising reports are received.
     async fn properties(&self) -> Result<Option<PeripheralProperties>>;
 
+    /// Returns a stream which yields the current properties of the peripheral, then the new
+    /// properties each time they are updated. If they are updated faster than the stream is read,
+    /// only the latest are yielded, so it can be used to keep a display of the device up to date.
+    async fn properties_stream(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeripheralProperties> + Send>>>;
+
+    /// Returns a snapshot of the peripheral's address, name, signal strength and connection state,
+    /// which is cheaper to get and log than the full properties.
+    async fn summary(&self) -> Result<PeripheralSummary>;
+
     /// The set of characteristics we've discovered for this device. This will be empty until
     /// `discover_characteristics` is called.
     fn characteristics(&self) -> BTreeSet<Characteristic>;
@@ -196,16 +790,101 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Creates a connection to the device. If this method returns Ok there has been successful
     /// connection. Note that peripherals allow only one connection at a time. Operations that
     /// attempt to communicate with a device will fail until it is connected.
+    ///
+    /// Depending on the platform this may wait indefinitely for the device to appear. A connection
+    /// attempt which is still in progress can be cancelled by calling [`Peripheral::disconnect`].
     async fn connect(&self) -> Result<()>;
 
-    /// Terminates a connection to the device.
+    /// Creates a connection to the device like [`Peripheral::connect`], but cancels the attempt
+    /// and returns [`Error::TimedOut`](crate::Error::TimedOut) if it hasn't succeeded within the
+    /// given time.
+    async fn connect_with_timeout(&self, timeout: Duration) -> Result<()>;
+
+    /// Creates a connection to the device like [`Peripheral::connect`], with the given options.
+    async fn connect_with_options(&self, options: &ConnectOptions) -> Result<()>;
+
+    /// Sets the timeouts and retry policy for later operations on this peripheral. Operations which
+    /// are already in progress aren't affected.
+    fn set_options(&self, options: &PeripheralOptions);
+
+    /// Pairs with the device, using the operating system's own prompts if any input is needed.
+    /// Not supported on CoreBluetooth, which pairs automatically when a protected characteristic is
+    /// accessed.
+    async fn pair(&self) -> Result<()>;
+
+    /// Pairs with the device, with the given agent supplying any input needed instead of the
+    /// operating system's prompts. On BlueZ, the agent is registered with the daemon for the
+    /// duration of pairing. Not supported on CoreBluetooth.
+    async fn pair_with_agent(&self, agent: Arc<dyn PairingAgent>) -> Result<()>;
+
+    /// Raises the security of the link to the device to at least the given level, pairing with it
+    /// if needed, so that characteristics which need an encrypted or authenticated link can be
+    /// accessed without first failing with an [`AttError`](crate::AttError).
+    ///
+    /// BlueZ encrypts links to bonded devices itself, so there this pairs with the device if it
+    /// isn't already bonded. It can't guarantee that the keys are authenticated, so
+    /// [`SecurityLevel::Authenticated`] returns [`Error::NotSupported`](crate::Error::NotSupported)
+    /// there. Not supported on CoreBluetooth, which pairs automatically when a protected
+    /// characteristic is accessed.
+    async fn request_security(&self, level: SecurityLevel) -> Result<()>;
+
+    /// Terminates a connection to the device, or cancels a connection attempt which is in progress.
     async fn disconnect(&self) -> Result<()>;
 
+    /// Returns the ATT MTU currently in use on the connection to the device. The largest value
+    /// which can be sent in a single write or notification is 3 bytes less than this.
+    ///
+    /// On Linux this needs BlueZ 5.62 or later, and the device's characteristics to have been
+    /// discovered.
+    async fn mtu(&self) -> Result<u16>;
+
+    /// Asks the device to use the given ATT MTU for the current connection. Most platforms
+    /// negotiate the MTU automatically when connecting, and will return
+    /// [`Error::NotSupported`](crate::Error::NotSupported) here.
+    async fn request_mtu(&self, mtu: u16) -> Result<()>;
+
+    /// Reads the current RSSI of the connection to the device, in dBm. Unlike the RSSI reported
+    /// in advertisements, this requires the device to be connected.
+    async fn read_rssi(&self) -> Result<i16>;
+
+    /// Sets the preferred connection parameters for this device. Depending on the platform these
+    /// may only be applied to the next connection, and the device may negotiate different values.
+    /// Parameters outside the ranges checked by [`ConnectionParameters::validate`] are rejected.
+    ///
+    /// On Linux, these are the defaults of the whole adapter rather than of this device, so they
+    /// apply to its next connection to any device. Setting them needs root, and returns
+    /// [`Error::PermissionDenied`](crate::Error::PermissionDenied) otherwise.
+    async fn set_connection_parameters(&self, parameters: &ConnectionParameters) -> Result<()>;
+
+    /// Requests connection parameters suited to the given priority. This is a portable
+    /// alternative to [`set_connection_parameters`](Peripheral::set_connection_parameters), and
+    /// has the same caveats.
+    async fn request_connection_priority(&self, priority: ConnectionPriority) -> Result<()>;
+
+    /// Asks for the connection to the device to use the given PHYs for transmitting and receiving.
+    /// The device may not support them, in which case the current PHYs will be kept.
+    async fn set_preferred_phy(&self, tx: Phy, rx: Phy) -> Result<()>;
+
+    /// Returns the PHYs currently used for transmitting to and receiving from the device, in that
+    /// order.
+    async fn read_phy(&self) -> Result<(Phy, Phy)>;
+
     /// Discovers all characteristics for the device.
     async fn discover_characteristics(&self) -> Result<Vec<Characteristic>>;
 
+    /// Discovers all services for the device, along with the characteristics belonging to each of
+    /// them and the services they include. Secondary services are found through the services
+    /// which include them. This also makes the characteristics available from
+    /// [`characteristics`](Peripheral::characteristics), as `discover_characteristics` does.
+    async fn discover_services(&self) -> Result<Vec<Service>>;
+
     /// Write some data to the characteristic. Returns an error if the write couldn't be sent or (in
     /// the case of a write-with-response) if the device returns an error.
+    ///
+    /// A write-without-response must fit in a single packet, so can be at most 3 bytes shorter than
+    /// the [MTU](Peripheral::mtu). Use [`write_long`](Peripheral::write_long) for longer values. It
+    /// completes once the operating system has accepted the write, waiting first until it has room
+    /// for it, so sending many in a row doesn't overrun its buffers.
     async fn write(
         &self,
         characteristic: &Characteristic,
@@ -213,20 +892,155 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
         write_type: WriteType,
     ) -> Result<()>;
 
+    /// Waits until the operating system is ready to accept another write-without-response to the
+    /// device. This allows data to be produced only as fast as it can be sent, rather than queueing
+    /// up in [`write`](Peripheral::write).
+    ///
+    /// Only CoreBluetooth reports this. On BlueZ and Windows, writes-without-response complete
+    /// once the write has been queued by the Bluetooth stack, so this returns straight away while
+    /// the device is connected.
+    async fn wait_until_ready_to_write(&self) -> Result<()>;
+
+    /// Writes some data to the characteristic starting at the given offset into its value, with
+    /// Prepare Write requests followed by an Execute Write request if it doesn't fit in a single
+    /// write request. This allows values longer than the MTU to be written to characteristics which
+    /// support it. Returns an error if the device rejects any part of the write.
+    ///
+    /// Only BlueZ supports a non-zero offset.
+    async fn write_long(
+        &self,
+        characteristic: &Characteristic,
+        offset: u16,
+        data: &[u8],
+    ) -> Result<()>;
+
     /// Sends a read request to the device. Returns either an error if the request was not accepted
     /// or the response from the device.
-    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
+    ///
+    /// Values too long to fit in a single response are returned in full, as every platform follows
+    /// up with Read Blob requests for the rest of the value itself. There is no option to turn this
+    /// off, because none of them can send a lone Read request: BlueZ's `ReadValue` always reads to
+    /// the end of the value (its `offset` option only says where to start), and WinRT's
+    /// `ReadValueAsync` and CoreBluetooth's `readValue` have no options for it at all.
+    async fn read(&self, characteristic: &Characteristic) -> Result<Bytes>;
+
+    /// Discovers the descriptors of the given characteristic. `discover_characteristics` must have
+    /// been called first.
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>>;
+
+    /// Sends a read request for a descriptor to the device. Returns either an error if the request
+    /// was not accepted or the value of the descriptor.
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
+
+    /// Writes some data to the descriptor. Returns an error if the write couldn't be sent or if the
+    /// device returns an error.
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()>;
+
+    /// Reads the Characteristic User Description and Characteristic Presentation Format
+    /// descriptors of the given characteristic, if it has them. These aren't read during discovery,
+    /// as that would take a request to the device for every characteristic.
+    async fn read_characteristic_metadata(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<CharacteristicMetadata> {
+        let found = self.discover_descriptors(characteristic).await?;
+        let mut metadata = CharacteristicMetadata::default();
+        for descriptor in found {
+            if descriptors::is_metadata(&descriptor.uuid) {
+                let value = self.read_descriptor(&descriptor).await?;
+                metadata.apply(&descriptor.uuid, &value);
+            }
+        }
+        Ok(metadata)
+    }
 
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
+    /// Subscriptions are counted, so each call should be matched by a call to
+    /// [`unsubscribe`](Peripheral::unsubscribe).
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
 
-    /// Disables either notify or indicate (depending on support) for the specified characteristic.
+    /// Disables either notify or indicate (depending on support) for the specified characteristic,
+    /// once every [`subscribe`](Peripheral::subscribe) call for it has been matched by a call to
+    /// this, so that other subscribers keep receiving notifications.
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
 
+    /// Returns whether notifications or indications are enabled for the characteristic, because
+    /// it has been subscribed to more times than it has been unsubscribed from since connecting.
+    /// Subscriptions end when the device disconnects.
+    fn is_subscribed(&self, characteristic: &Characteristic) -> bool;
+
+    /// Returns the characteristics which are currently subscribed to, as with
+    /// [`is_subscribed`](Peripheral::is_subscribed).
+    fn subscriptions(&self) -> BTreeSet<Characteristic>;
+
     /// Returns a stream of notifications for characteristic value updates. The stream will receive
     /// a notification when a value notification or indication is received from the device. This
-    /// method should only be used after a connection has been established.
+    /// method should only be used after a connection has been established, and the stream ends
+    /// when the device disconnects.
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>>;
+
+    /// Returns the number of notifications which have been dropped because a stream from
+    /// [`notifications`](Peripheral::notifications) wasn't read from quickly enough, as limited by
+    /// [`PeripheralOptions::notification_capacity`].
+    fn dropped_notifications(&self) -> u64;
+
+    /// Returns a stream of notifications like [`notifications`](Peripheral::notifications), but
+    /// only for the given characteristic. The characteristic must still be
+    /// [subscribed](Peripheral::subscribe) to for notifications to be sent.
+    async fn notifications_for(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>>;
+
+    /// Opens an L2CAP connection-oriented channel to the device on the given PSM, which the device
+    /// usually publishes in a GATT characteristic. This method should only be used after a
+    /// connection has been established. Only supported on CoreBluetooth.
+    async fn open_l2cap_channel(&self, psm: u16) -> Result<Box<dyn L2capChannel>>;
+}
+
+/// The state of a Bluetooth adapter.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AdapterState {
+    PoweredOn,
+    PoweredOff,
+    /// The adapter is being reset, and will return to another state shortly.
+    Resetting,
+    /// The adapter can't be used, for example because it was removed or disabled.
+    Unavailable,
+    /// This platform or computer doesn't support Bluetooth LE.
+    Unsupported,
+    /// This application isn't allowed to use Bluetooth. On macOS, this is the case when the user
+    /// denied the Bluetooth permission.
+    Unauthorized,
+    Unknown,
+}
+
+/// Why a connection to a peripheral ended, as far as the platform reports it.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum DisconnectReason {
+    /// The connection was closed by this application, via [`Peripheral::disconnect`].
+    UserRequest,
+    /// The peripheral stopped responding, and the link supervision timeout expired.
+    SupervisionTimeout,
+    /// The peripheral closed the connection.
+    RemoteTerminated,
+    /// The local Bluetooth adapter was turned off.
+    AdapterOff,
+    /// The platform didn't give a reason.
+    Unknown,
 }
 
 #[cfg_attr(
@@ -238,9 +1052,18 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
 pub enum CentralEvent {
     DeviceDiscovered(BDAddr),
     DeviceLost(BDAddr),
-    DeviceUpdated(BDAddr),
+    /// Emitted when an advertisement or other update is received for a device, with the
+    /// properties which it changed. These may be empty if it only repeated what was already known.
+    DeviceUpdated {
+        address: BDAddr,
+        changes: PropertyChanges,
+    },
     DeviceConnected(BDAddr),
-    DeviceDisconnected(BDAddr),
+    DeviceDisconnected(BDAddr, DisconnectReason),
+    /// Emitted when a connected device indicates that its services have changed, for example when
+    /// it switches into a firmware update mode. Characteristics discovered before this may no
+    /// longer be valid, so they should be discovered again.
+    ServicesChanged(BDAddr),
     /// Emitted when a Manufacturer Data advertisement has been received from a device
     ManufacturerDataAdvertisement {
         address: BDAddr,
@@ -256,6 +1079,158 @@ pub enum CentralEvent {
         address: BDAddr,
         services: Vec<Uuid>,
     },
+    /// Emitted for each advertisement received from a device, with the signal strength it was
+    /// received at in dBm. Only emitted if [`ScanFilter::allow_duplicates`] is set.
+    RssiUpdate {
+        address: BDAddr,
+        rssi: i16,
+    },
+    /// Emitted along with a `ManufacturerDataAdvertisement` which contains an iBeacon.
+    IBeaconDiscovered {
+        address: BDAddr,
+        beacon: IBeacon,
+    },
+    /// Emitted along with a `ServiceDataAdvertisement` which contains an Eddystone frame.
+    EddystoneDiscovered {
+        address: BDAddr,
+        frame: EddystoneFrame,
+    },
+    /// Emitted when a section of an advertisement from a device is too short for its AD type, and
+    /// was skipped. Only emitted on Windows, where btleplug parses the raw advertisement data.
+    MalformedAdvertisement {
+        address: BDAddr,
+        ad_type: u8,
+    },
+    /// Emitted when the adapter is turned on or off, or otherwise changes state.
+    AdapterStateChanged(AdapterState),
+    /// Emitted when the adapter starts scanning.
+    ScanStarted,
+    /// Emitted when the adapter stops scanning, whether because of [`Central::stop_scan`] or
+    /// because the operating system stopped it.
+    ScanStopped,
+}
+
+impl CentralEvent {
+    /// Returns the address of the device which the event is about, if any.
+    pub fn address(&self) -> Option<BDAddr> {
+        match self {
+            CentralEvent::DeviceDiscovered(address)
+            | CentralEvent::DeviceLost(address)
+            | CentralEvent::DeviceUpdated { address, .. }
+            | CentralEvent::DeviceConnected(address)
+            | CentralEvent::DeviceDisconnected(address, _)
+            | CentralEvent::ServicesChanged(address)
+            | CentralEvent::ManufacturerDataAdvertisement { address, .. }
+            | CentralEvent::ServiceDataAdvertisement { address, .. }
+            | CentralEvent::ServicesAdvertisement { address, .. }
+            | CentralEvent::RssiUpdate { address, .. }
+            | CentralEvent::IBeaconDiscovered { address, .. }
+            | CentralEvent::EddystoneDiscovered { address, .. }
+            | CentralEvent::MalformedAdvertisement { address, .. } => Some(*address),
+            CentralEvent::AdapterStateChanged(_)
+            | CentralEvent::ScanStarted
+            | CentralEvent::ScanStopped => None,
+        }
+    }
+
+    /// Returns a mutable reference to the address of the device the event is about, if any.
+    pub(crate) fn address_mut(&mut self) -> Option<&mut BDAddr> {
+        match self {
+            CentralEvent::DeviceDiscovered(address)
+            | CentralEvent::DeviceLost(address)
+            | CentralEvent::DeviceUpdated { address, .. }
+            | CentralEvent::DeviceConnected(address)
+            | CentralEvent::DeviceDisconnected(address, _)
+            | CentralEvent::ServicesChanged(address)
+            | CentralEvent::ManufacturerDataAdvertisement { address, .. }
+            | CentralEvent::ServiceDataAdvertisement { address, .. }
+            | CentralEvent::ServicesAdvertisement { address, .. }
+            | CentralEvent::RssiUpdate { address, .. }
+            | CentralEvent::IBeaconDiscovered { address, .. }
+            | CentralEvent::EddystoneDiscovered { address, .. }
+            | CentralEvent::MalformedAdvertisement { address, .. } => Some(address),
+            CentralEvent::AdapterStateChanged(_)
+            | CentralEvent::ScanStarted
+            | CentralEvent::ScanStopped => None,
+        }
+    }
+
+    /// Returns the kind of the event, for use in an [`EventFilter`].
+    pub fn kind(&self) -> EventKinds {
+        match self {
+            CentralEvent::DeviceDiscovered(_) => EventKinds::DEVICE_DISCOVERED,
+            CentralEvent::DeviceLost(_) => EventKinds::DEVICE_LOST,
+            CentralEvent::DeviceUpdated { .. } => EventKinds::DEVICE_UPDATED,
+            CentralEvent::DeviceConnected(_) => EventKinds::DEVICE_CONNECTED,
+            CentralEvent::DeviceDisconnected(_, _) => EventKinds::DEVICE_DISCONNECTED,
+            CentralEvent::ServicesChanged(_) => EventKinds::SERVICES_CHANGED,
+            CentralEvent::ManufacturerDataAdvertisement { .. } => EventKinds::MANUFACTURER_DATA,
+            CentralEvent::ServiceDataAdvertisement { .. } => EventKinds::SERVICE_DATA,
+            CentralEvent::ServicesAdvertisement { .. } => EventKinds::SERVICES,
+            CentralEvent::RssiUpdate { .. } => EventKinds::RSSI,
+            CentralEvent::IBeaconDiscovered { .. } => EventKinds::IBEACON,
+            CentralEvent::EddystoneDiscovered { .. } => EventKinds::EDDYSTONE,
+            CentralEvent::MalformedAdvertisement { .. } => EventKinds::MALFORMED_ADVERTISEMENT,
+            CentralEvent::AdapterStateChanged(_) => EventKinds::ADAPTER_STATE,
+            CentralEvent::ScanStarted | CentralEvent::ScanStopped => EventKinds::SCAN,
+        }
+    }
+}
+
+bitflags! {
+    /// Kinds of [`CentralEvent`], as selected by an [`EventFilter`].
+    pub struct EventKinds: u16 {
+        const DEVICE_DISCOVERED = 0x0001;
+        const DEVICE_LOST = 0x0002;
+        const DEVICE_UPDATED = 0x0004;
+        const DEVICE_CONNECTED = 0x0008;
+        const DEVICE_DISCONNECTED = 0x0010;
+        const SERVICES_CHANGED = 0x0020;
+        const MANUFACTURER_DATA = 0x0040;
+        const SERVICE_DATA = 0x0080;
+        const SERVICES = 0x0100;
+        const RSSI = 0x0200;
+        const IBEACON = 0x0400;
+        const EDDYSTONE = 0x0800;
+        const ADAPTER_STATE = 0x1000;
+        /// Both `ScanStarted` and `ScanStopped`.
+        const SCAN = 0x2000;
+        const MALFORMED_ADVERTISEMENT = 0x4000;
+    }
+}
+
+/// Selects which events are sent to a stream from [`Central::events_filtered`]. Events which
+/// don't match are never queued for the stream, so it can be used to follow a few devices without
+/// having to keep up with every advertisement received.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EventFilter {
+    /// The kinds of event to include. Defaults to all of them.
+    pub kinds: EventKinds,
+    /// If not empty, only events about these devices are included. Events which aren't about any
+    /// particular device, such as `AdapterStateChanged`, are still included if their kind is.
+    pub addresses: Vec<BDAddr>,
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        EventFilter {
+            kinds: EventKinds::all(),
+            addresses: Vec::new(),
+        }
+    }
+}
+
+impl EventFilter {
+    /// Returns whether the event passes this filter.
+    pub fn matches(&self, event: &CentralEvent) -> bool {
+        if !self.kinds.contains(event.kind()) {
+            return false;
+        }
+        match event.address() {
+            Some(address) => self.addresses.is_empty() || self.addresses.contains(&address),
+            None => true,
+        }
+    }
 }
 
 /// Central is the "client" of BLE. It's able to scan for and establish connections to peripherals.
@@ -268,14 +1243,35 @@ pub trait Central: Send + Sync + Clone {
     /// occur for this Central module. See [`CentralEvent`] for the full set of possible events.
     async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>>;
 
+    /// Retrieve a stream of the `CentralEvent`s which match the given filter, such as those about
+    /// a particular device.
+    async fn events_filtered(
+        &self,
+        filter: EventFilter,
+    ) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>>;
+
     /// Starts a scan for BLE devices. This scan will generally continue until explicitly stopped,
-    /// although this may depend on your Bluetooth adapter. Discovered devices will be announced
-    /// to subscribers of `events` and will be available via `peripherals()`.
-    async fn start_scan(&self) -> Result<()>;
+    /// although this may depend on your Bluetooth adapter. Discovered devices matching the given
+    /// filter will be announced to subscribers of `events` and will be available via
+    /// `peripherals()`. On Windows, LE devices which are paired with the computer are announced
+    /// too, even if they aren't advertising.
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()>;
 
     /// Stops scanning for BLE devices.
     async fn stop_scan(&self) -> Result<()>;
 
+    /// Returns whether the adapter is currently scanning.
+    async fn is_scanning(&self) -> Result<bool>;
+
+    /// Turns the Bluetooth adapter on or off. Windows may ask the user for permission, and
+    /// returns [`Error::PermissionDenied`](crate::Error::PermissionDenied) if it isn't granted.
+    /// Not supported on CoreBluetooth, where only the user can turn Bluetooth on or off.
+    async fn set_powered(&self, powered: bool) -> Result<()>;
+
+    /// Returns the current state of the adapter. Changes are also reported by
+    /// [`CentralEvent::AdapterStateChanged`].
+    async fn adapter_state(&self) -> Result<AdapterState>;
+
     /// Returns the list of [`Peripheral`]s that have been discovered so far. Note that this list
     /// may contain peripherals that are no longer available.
     async fn peripherals(&self) -> Result<Vec<Self::Peripheral>>;
@@ -283,8 +1279,101 @@ pub trait Central: Send + Sync + Clone {
     /// Returns a particular [`Peripheral`] by its address if it has been discovered.
     async fn peripheral(&self, address: BDAddr) -> Result<Self::Peripheral>;
 
-    /// Add a [`Peripheral`] from a MAC address without a scan result. Not supported on all Bluetooth systems.
+    /// Sets whether peripherals are kept by this adapter after they disconnect, so that the same
+    /// [`Peripheral`] can be connected to again without scanning for it first. By default they are
+    /// forgotten, and must be rediscovered. BlueZ always keeps peripherals, so this has no effect
+    /// there.
+    async fn set_retain_peripherals(&self, retain: bool) -> Result<()>;
+
+    /// Sets how long a peripheral may go without advertising before it is considered gone, at
+    /// which point a [`CentralEvent::DeviceLost`] event is emitted for it and it is removed from
+    /// [`peripherals()`](Self::peripherals). Connected peripherals are never removed. `None`, the
+    /// default, keeps peripherals until the adapter is dropped.
+    ///
+    /// On Linux, this removes the device from BlueZ, unless it is paired, as that would also
+    /// delete its keys.
+    async fn set_peripheral_ttl(&self, ttl: Option<Duration>) -> Result<()>;
+
+    /// Limits how many peripherals are kept at once. When a new peripheral is found and there are
+    /// already this many, the one which advertised least recently is removed from
+    /// [`peripherals()`](Self::peripherals) and a [`CentralEvent::DeviceLost`] event is emitted for
+    /// it, so that scanning in a crowded place uses bounded memory. Connected peripherals are
+    /// never removed. `None`, the default, keeps any number of peripherals.
+    ///
+    /// On Linux, this removes the device from BlueZ. Paired devices are never removed, as that
+    /// would also delete their keys, but they still count towards the limit.
+    async fn set_max_peripherals(&self, max: Option<usize>) -> Result<()>;
+
+    /// Registers the Identity Resolving Key of a device which advertises with resolvable private
+    /// addresses, so that its events and [`Peripheral`] are reported under the given identity
+    /// address rather than under a new address each time its private address rotates. Any
+    /// previous key for the identity is replaced.
+    ///
+    /// Only supported on BlueZ, which already does this itself for devices it has bonded with.
+    async fn add_identity_resolving_key(
+        &self,
+        identity: BDAddr,
+        irk: IdentityResolvingKey,
+    ) -> Result<()>;
+
+    /// Removes the Identity Resolving Key registered for the given identity address with
+    /// [`add_identity_resolving_key`](Self::add_identity_resolving_key).
+    async fn remove_identity_resolving_key(&self, identity: BDAddr) -> Result<()>;
+
+    /// Add a [`Peripheral`] from a MAC address without a scan result, so that it can be connected
+    /// to straight away. If the peripheral is already known it is returned as is.
+    ///
+    /// On Windows any address may be used. BlueZ can only connect to devices it already knows
+    /// about, such as bonded devices, and returns
+    /// [`Error::DeviceNotFound`](crate::Error::DeviceNotFound) for others; the BlueZ
+    /// `Adapter::connect_with_address_type` can connect to those given their address type. Not supported on CoreBluetooth, which doesn't expose MAC addresses.
     async fn add_peripheral(&self, address: BDAddr) -> Result<Self::Peripheral>;
+
+    /// Returns the peripherals which the operating system has already bonded (paired) with, so
+    /// that they can be reconnected to without scanning for them first. Not supported on all
+    /// Bluetooth systems.
+    async fn bonded_peripherals(&self) -> Result<Vec<Self::Peripheral>>;
+
+    /// Starts broadcasting the given advertisement from this adapter, replacing any advertisement
+    /// previously started with this method. Not all platforms allow every field of an
+    /// [`Advertisement`] to be set; an error is returned if the advertisement can't be broadcast as
+    /// given. On Linux the advertisement is connectable, and is registered with BlueZ, which
+    /// limits how many may be broadcast at once. Not supported on macOS or iOS.
+    async fn start_advertising(&self, advertisement: &Advertisement) -> Result<()>;
+
+    /// Stops broadcasting the advertisement started with `start_advertising`.
+    async fn stop_advertising(&self) -> Result<()>;
+
+    /// Synchronizes to the periodic advertising train with the given advertising set ID from the
+    /// given device, and returns a stream of the advertisements received on it. The adapter must
+    /// be scanning until the sync is established. The stream ends if the sync is lost, and the
+    /// sync is terminated when the stream is dropped.
+    ///
+    /// Only supported on Linux with the `hci` feature, as neither BlueZ, WinRT nor CoreBluetooth
+    /// have an API for it. There it needs the `CAP_NET_RAW` capability, a controller which supports
+    /// periodic advertising, and a kernel which has enabled the controller's periodic advertising
+    /// events; otherwise this times out.
+    async fn sync_periodic_advertising(
+        &self,
+        address: BDAddr,
+        sid: u8,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeriodicAdvertisement> + Send>>>;
+
+    /// Returns a snapshot of the counters kept by this adapter and its peripherals, such as the
+    /// number of advertisements received and how long GATT operations take, for monitoring.
+    ///
+    /// BlueZ reports changes to devices rather than each advertisement and notification, so there
+    /// these are counted as they are read from the streams returned by [`events`](Self::events)
+    /// and [`Peripheral::notifications`], once for each stream.
+    async fn metrics(&self) -> Result<AdapterMetrics>;
+}
+
+/// Events emitted by a [`Manager`] as Bluetooth adapters are added to or removed from the system.
+#[derive(Debug, Clone)]
+pub enum ManagerEvent<A> {
+    AdapterAdded(A),
+    /// The adapter has been removed, and can no longer be used.
+    AdapterRemoved(A),
 }
 
 /// The Manager is the entry point to the library, providing access to all the Bluetooth adapters on
@@ -312,4 +1401,12 @@ pub trait Manager {
 
     /// Get a list of all Bluetooth adapters on the system. Each adapter implements [`Central`].
     async fn adapters(&self) -> Result<Vec<Self::Adapter>>;
+
+    /// Retrieve a stream of [`ManagerEvent`]s, announcing adapters as they are added or removed,
+    /// for example when a USB dongle is plugged in or pulled out. Adapters which are already
+    /// present when the stream is created aren't announced. Not supported on CoreBluetooth, which
+    /// only ever has the one built-in adapter.
+    async fn adapter_events(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = ManagerEvent<Self::Adapter>> + Send>>>;
 }
@@ -0,0 +1,39 @@
+use super::BDAddr;
+use std::fmt::Debug;
+
+/// Takes part in pairing on behalf of the user, for applications which can't rely on the operating
+/// system's own pairing prompts, such as headless services. Pass one to
+/// [`Peripheral::pair_with_agent`](super::Peripheral::pair_with_agent).
+///
+/// Which method is called depends on the input and output capabilities of the peripheral. Each
+/// method rejects the request by default, so only those which the application supports need to be
+/// implemented. Methods are called from a platform thread, and should return promptly.
+pub trait PairingAgent: Debug + Send + Sync {
+    /// Asks whether to pair with a peripheral which doesn't require any further input ("Just
+    /// Works" pairing).
+    fn confirm(&self, _address: BDAddr) -> bool {
+        false
+    }
+
+    /// Asks whether the given passkey matches the one shown by the peripheral (numeric
+    /// comparison).
+    fn confirm_passkey(&self, _address: BDAddr, _passkey: u32) -> bool {
+        false
+    }
+
+    /// Shows the given passkey, which must be entered on the peripheral. Returns whether to go
+    /// ahead with pairing.
+    fn display_passkey(&self, _address: BDAddr, _passkey: u32) -> bool {
+        false
+    }
+
+    /// Asks for the passkey shown by the peripheral, or `None` to reject pairing.
+    fn request_passkey(&self, _address: BDAddr) -> Option<u32> {
+        None
+    }
+
+    /// Asks for a PIN code, as used by legacy pairing, or `None` to reject pairing.
+    fn request_pin(&self, _address: BDAddr) -> Option<String> {
+        None
+    }
+}
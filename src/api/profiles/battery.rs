@@ -0,0 +1,103 @@
+//! The Battery Service, through which a device reports how charged its battery is.
+
+use super::{find_characteristic, CharacteristicSpec, CharacteristicValue, Profile};
+use crate::api::{bleuuid::uuid_from_u16, Characteristic, Peripheral};
+use crate::{Error, Result};
+use futures::future::ready;
+use futures::stream::{Stream, StreamExt};
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// The UUID of the Battery Service.
+pub const BATTERY_SERVICE: Uuid = uuid_from_u16(0x180F);
+/// The UUID of the Battery Level characteristic.
+pub const BATTERY_LEVEL: Uuid = uuid_from_u16(0x2A19);
+
+/// The Battery Service of a connected peripheral.
+#[derive(Debug, Clone)]
+pub struct BatteryService<P: Peripheral> {
+    peripheral: P,
+    level: Characteristic,
+}
+
+impl<P: Peripheral> BatteryService<P> {
+    /// Finds the Battery Level characteristic of a connected peripheral, discovering its
+    /// characteristics first if that hasn't been done yet.
+    pub async fn new(peripheral: P) -> Result<Self> {
+        let level = find_characteristic(&peripheral, BATTERY_SERVICE, BATTERY_LEVEL).await?;
+        Ok(BatteryService { peripheral, level })
+    }
+
+    /// The Battery Level characteristic.
+    pub fn level_characteristic(&self) -> &Characteristic {
+        &self.level
+    }
+
+    /// Reads the battery level, as a percentage.
+    pub async fn read_level(&self) -> Result<u8> {
+        let value = self.peripheral.read(&self.level).await?;
+        parse_level(&value)
+    }
+
+    /// Subscribes to the battery level, and returns a stream of it as a percentage whenever it
+    /// changes. As with [`Peripheral::notifications`], the stream ends when the peripheral
+    /// disconnects. The subscription isn't dropped along with the stream; call
+    /// [`unsubscribe`](Self::unsubscribe) to stop the notifications.
+    ///
+    /// Notifying changes of the battery level is optional, so this fails on devices which don't
+    /// support it.
+    pub async fn level_stream(&self) -> Result<Pin<Box<dyn Stream<Item = u8> + Send>>> {
+        let notifications = self.peripheral.notifications().await?;
+        self.peripheral.subscribe(&self.level).await?;
+        let level = self.level.clone();
+        Ok(Box::pin(notifications.filter_map(move |notification| {
+            ready(
+                if notification.uuid == level.uuid
+                    && notification.service_uuid == level.service_uuid
+                {
+                    parse_level(&notification.value).ok()
+                } else {
+                    None
+                },
+            )
+        })))
+    }
+
+    /// Unsubscribes from the battery level, after [`level_stream`](Self::level_stream).
+    pub async fn unsubscribe(&self) -> Result<()> {
+        self.peripheral.unsubscribe(&self.level).await
+    }
+}
+
+/// A value of the Battery Level characteristic, as a percentage, for use with
+/// [`BoundProfile`](super::BoundProfile).
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct BatteryLevel(pub u8);
+
+impl CharacteristicValue for BatteryLevel {
+    const SERVICE: Uuid = BATTERY_SERVICE;
+    const CHARACTERISTIC: Uuid = BATTERY_LEVEL;
+
+    fn parse(value: &[u8]) -> Option<Self> {
+        parse_level(value).ok().map(BatteryLevel)
+    }
+}
+
+/// The declaration of the Battery profile, for use with [`BoundProfile`](super::BoundProfile).
+#[derive(Debug, Clone, Copy)]
+pub struct BatteryProfile;
+
+impl Profile for BatteryProfile {
+    const CHARACTERISTICS: &'static [CharacteristicSpec] =
+        &[CharacteristicSpec::required(BATTERY_SERVICE, BATTERY_LEVEL)];
+}
+
+/// Parses the value of the Battery Level characteristic, a percentage from 0 to 100.
+pub fn parse_level(value: &[u8]) -> Result<u8> {
+    match value {
+        [level] if *level <= 100 => Ok(*level),
+        _ => Err(Error::Other(
+            format!("Invalid battery level {:?}", value).into(),
+        )),
+    }
+}
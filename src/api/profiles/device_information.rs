@@ -0,0 +1,80 @@
+//! The Device Information Service, through which a device describes its manufacturer, model and
+//! versions.
+
+use super::discovered_characteristics;
+use crate::api::{bleuuid::uuid_from_u16, Peripheral};
+use crate::{Error, Result};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use uuid::Uuid;
+
+/// The UUID of the Device Information Service.
+pub const DEVICE_INFORMATION_SERVICE: Uuid = uuid_from_u16(0x180A);
+/// The UUID of the Model Number String characteristic.
+pub const MODEL_NUMBER: Uuid = uuid_from_u16(0x2A24);
+/// The UUID of the Serial Number String characteristic.
+pub const SERIAL_NUMBER: Uuid = uuid_from_u16(0x2A25);
+/// The UUID of the Firmware Revision String characteristic.
+pub const FIRMWARE_REVISION: Uuid = uuid_from_u16(0x2A26);
+/// The UUID of the Hardware Revision String characteristic.
+pub const HARDWARE_REVISION: Uuid = uuid_from_u16(0x2A27);
+/// The UUID of the Software Revision String characteristic.
+pub const SOFTWARE_REVISION: Uuid = uuid_from_u16(0x2A28);
+/// The UUID of the Manufacturer Name String characteristic.
+pub const MANUFACTURER_NAME: Uuid = uuid_from_u16(0x2A29);
+
+/// The strings a device reports in its Device Information Service. Each is `None` if the device
+/// doesn't have the corresponding characteristic, or doesn't allow it to be read.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct DeviceInformation {
+    pub manufacturer_name: Option<String>,
+    pub model_number: Option<String>,
+    pub serial_number: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub hardware_revision: Option<String>,
+    pub software_revision: Option<String>,
+}
+
+impl DeviceInformation {
+    /// Reads all of the Device Information Service strings from a connected peripheral,
+    /// discovering its characteristics first if that hasn't been done yet. A device without the
+    /// service gives a `DeviceInformation` with every field `None`.
+    pub async fn read<P: Peripheral>(peripheral: &P) -> Result<DeviceInformation> {
+        let mut information = DeviceInformation::default();
+        for characteristic in discovered_characteristics(peripheral).await? {
+            if characteristic.service_uuid != DEVICE_INFORMATION_SERVICE {
+                continue;
+            }
+            let field = match characteristic.uuid {
+                MANUFACTURER_NAME => &mut information.manufacturer_name,
+                MODEL_NUMBER => &mut information.model_number,
+                SERIAL_NUMBER => &mut information.serial_number,
+                FIRMWARE_REVISION => &mut information.firmware_revision,
+                HARDWARE_REVISION => &mut information.hardware_revision,
+                SOFTWARE_REVISION => &mut information.software_revision,
+                _ => continue,
+            };
+            *field = match peripheral.read(&characteristic).await {
+                Ok(value) => Some(parse_string(&value)),
+                // Some devices only allow some of the strings to be read, such as after pairing.
+                Err(Error::Att(_)) => None,
+                Err(e) => return Err(e),
+            };
+        }
+        Ok(information)
+    }
+}
+
+/// Parses a UTF-8 string characteristic, ignoring any trailing NUL padding which some devices add.
+fn parse_string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .to_string()
+}
@@ -0,0 +1,242 @@
+//! The Heart Rate Service, through which heart rate monitors report their measurements.
+
+use super::{find_characteristic, CharacteristicSpec, CharacteristicValue, Profile};
+use crate::api::{bleuuid::uuid_from_u16, Characteristic, Peripheral, ValueNotification};
+use crate::Result;
+use futures::ready;
+use futures::stream::Stream;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_cr as serde;
+use std::convert::TryInto;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The UUID of the Heart Rate Service.
+pub const HEART_RATE_SERVICE: Uuid = uuid_from_u16(0x180D);
+/// The UUID of the Heart Rate Measurement characteristic.
+pub const HEART_RATE_MEASUREMENT: Uuid = uuid_from_u16(0x2A37);
+
+const FLAG_VALUE_U16: u8 = 0x01;
+const FLAG_CONTACT_DETECTED: u8 = 0x02;
+const FLAG_CONTACT_SUPPORTED: u8 = 0x04;
+const FLAG_ENERGY_EXPENDED: u8 = 0x08;
+const FLAG_RR_INTERVALS: u8 = 0x10;
+
+/// Whether a heart rate sensor is in contact with the body.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SensorContact {
+    /// The sensor can't tell whether it is in contact.
+    NotSupported,
+    NotDetected,
+    Detected,
+}
+
+/// A value of the Heart Rate Measurement characteristic.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct HeartRateMeasurement {
+    /// The heart rate, in beats per minute.
+    pub bpm: u16,
+    pub sensor_contact: SensorContact,
+    /// The energy expended since it was last reset, in kilojoules, if the sensor reports it.
+    pub energy_expended: Option<u16>,
+    /// The intervals between the R waves of consecutive heart beats since the last measurement,
+    /// oldest first. The sensor reports these with a resolution of 1/1024 of a second.
+    pub rr_intervals: Vec<Duration>,
+}
+
+impl HeartRateMeasurement {
+    /// Parses a value of the Heart Rate Measurement characteristic, returning `None` if it is
+    /// malformed.
+    pub fn parse(value: &[u8]) -> Option<HeartRateMeasurement> {
+        let (&flags, mut rest) = value.split_first()?;
+        let bpm = if flags & FLAG_VALUE_U16 != 0 {
+            read_u16(&mut rest)?
+        } else {
+            let (&bpm, remaining) = rest.split_first()?;
+            rest = remaining;
+            bpm.into()
+        };
+        let sensor_contact = if flags & FLAG_CONTACT_SUPPORTED == 0 {
+            SensorContact::NotSupported
+        } else if flags & FLAG_CONTACT_DETECTED != 0 {
+            SensorContact::Detected
+        } else {
+            SensorContact::NotDetected
+        };
+        let energy_expended = if flags & FLAG_ENERGY_EXPENDED != 0 {
+            Some(read_u16(&mut rest)?)
+        } else {
+            None
+        };
+        let mut rr_intervals = vec![];
+        if flags & FLAG_RR_INTERVALS != 0 {
+            while !rest.is_empty() {
+                let interval = read_u16(&mut rest)?;
+                rr_intervals.push(Duration::from_nanos(
+                    u64::from(interval) * 1_000_000_000 / 1024,
+                ));
+            }
+        }
+        Some(HeartRateMeasurement {
+            bpm,
+            sensor_contact,
+            energy_expended,
+            rr_intervals,
+        })
+    }
+}
+
+impl CharacteristicValue for HeartRateMeasurement {
+    const SERVICE: Uuid = HEART_RATE_SERVICE;
+    const CHARACTERISTIC: Uuid = HEART_RATE_MEASUREMENT;
+
+    fn parse(value: &[u8]) -> Option<Self> {
+        HeartRateMeasurement::parse(value)
+    }
+}
+
+/// The declaration of the Heart Rate profile, for use with [`BoundProfile`](super::BoundProfile).
+#[derive(Debug, Clone, Copy)]
+pub struct HeartRateProfile;
+
+impl Profile for HeartRateProfile {
+    const CHARACTERISTICS: &'static [CharacteristicSpec] = &[CharacteristicSpec::required(
+        HEART_RATE_SERVICE,
+        HEART_RATE_MEASUREMENT,
+    )];
+}
+
+/// Reads a little-endian `u16` from the start of `data`, advancing it past the value.
+fn read_u16(data: &mut &[u8]) -> Option<u16> {
+    let value = u16::from_le_bytes(data.get(..2)?.try_into().ok()?);
+    *data = &data[2..];
+    Some(value)
+}
+
+/// The Heart Rate Service of a connected peripheral.
+#[derive(Debug, Clone)]
+pub struct HeartRateService<P: Peripheral> {
+    peripheral: P,
+    measurement: Characteristic,
+}
+
+impl<P: Peripheral> HeartRateService<P> {
+    /// Finds the Heart Rate Measurement characteristic of a connected peripheral, discovering its
+    /// characteristics first if that hasn't been done yet.
+    pub async fn new(peripheral: P) -> Result<Self> {
+        let measurement =
+            find_characteristic(&peripheral, HEART_RATE_SERVICE, HEART_RATE_MEASUREMENT).await?;
+        Ok(HeartRateService {
+            peripheral,
+            measurement,
+        })
+    }
+
+    /// Subscribes to heart rate measurements, and returns a stream of them. As with
+    /// [`Peripheral::notifications`], the stream ends when the peripheral disconnects. The
+    /// subscription isn't dropped along with the stream; call [`unsubscribe`](Self::unsubscribe)
+    /// to stop the measurements.
+    pub async fn measurements(&self) -> Result<HeartRateStream> {
+        let notifications = self.peripheral.notifications().await?;
+        self.peripheral.subscribe(&self.measurement).await?;
+        Ok(HeartRateStream {
+            notifications,
+            measurement: self.measurement.clone(),
+        })
+    }
+
+    /// Unsubscribes from heart rate measurements, after [`measurements`](Self::measurements).
+    pub async fn unsubscribe(&self) -> Result<()> {
+        self.peripheral.unsubscribe(&self.measurement).await
+    }
+}
+
+/// A stream of [`HeartRateMeasurement`]s, from [`HeartRateService::measurements`]. Malformed
+/// measurements are skipped.
+pub struct HeartRateStream {
+    notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+    measurement: Characteristic,
+}
+
+impl Stream for HeartRateStream {
+    type Item = HeartRateMeasurement;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let notification = match ready!(self.notifications.as_mut().poll_next(cx)) {
+                Some(notification) => notification,
+                None => return Poll::Ready(None),
+            };
+            if notification.uuid == self.measurement.uuid
+                && notification.service_uuid == self.measurement.service_uuid
+            {
+                if let Some(measurement) = HeartRateMeasurement::parse(&notification.value) {
+                    return Poll::Ready(Some(measurement));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_u8_bpm() {
+        assert_eq!(
+            HeartRateMeasurement::parse(&[0x00, 72]),
+            Some(HeartRateMeasurement {
+                bpm: 72,
+                sensor_contact: SensorContact::NotSupported,
+                energy_expended: None,
+                rr_intervals: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_all_fields() {
+        let value = [0x1f, 0x2c, 0x01, 0x10, 0x00, 0x00, 0x04, 0x00, 0x02];
+        assert_eq!(
+            HeartRateMeasurement::parse(&value),
+            Some(HeartRateMeasurement {
+                bpm: 300,
+                sensor_contact: SensorContact::Detected,
+                energy_expended: Some(16),
+                rr_intervals: vec![Duration::from_secs(1), Duration::from_millis(500)],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_contact_not_detected() {
+        let measurement = HeartRateMeasurement::parse(&[0x04, 60]).unwrap();
+        assert_eq!(measurement.sensor_contact, SensorContact::NotDetected);
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert_eq!(HeartRateMeasurement::parse(&[]), None);
+        assert_eq!(HeartRateMeasurement::parse(&[0x01, 0x2c]), None);
+        assert_eq!(HeartRateMeasurement::parse(&[0x08, 60, 0x10]), None);
+        assert_eq!(
+            HeartRateMeasurement::parse(&[0x10, 60, 0x00, 0x04, 0x00]),
+            None
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Helpers for standard GATT profiles, which find the relevant characteristics on a connected
+//! [`Peripheral`] and convert their values to and from typed data.
+//!
+//! Drivers for other devices can be built the same way, by declaring a [`Profile`] with the
+//! characteristics it uses and a [`CharacteristicValue`] for each typed value, then binding it to
+//! a peripheral with [`BoundProfile::bind`]:
+//!
+//! ```
+//! use btleplug::api::bleuuid::uuid_from_u16;
+//! use btleplug::api::profiles::{BoundProfile, CharacteristicSpec, CharacteristicValue, Profile};
+//! use btleplug::api::Peripheral;
+//! use std::convert::TryInto;
+//! use uuid::Uuid;
+//!
+//! const THERMOMETER: Uuid = uuid_from_u16(0x181A);
+//! const TEMPERATURE: Uuid = uuid_from_u16(0x2A6E);
+//!
+//! struct Thermometer;
+//!
+//! impl Profile for Thermometer {
+//!     const CHARACTERISTICS: &'static [CharacteristicSpec] =
+//!         &[CharacteristicSpec::required(THERMOMETER, TEMPERATURE)];
+//! }
+//!
+//! /// A temperature in hundredths of a degree Celsius.
+//! struct Temperature(i16);
+//!
+//! impl CharacteristicValue for Temperature {
+//!     const SERVICE: Uuid = THERMOMETER;
+//!     const CHARACTERISTIC: Uuid = TEMPERATURE;
+//!
+//!     fn parse(value: &[u8]) -> Option<Self> {
+//!         Some(Temperature(i16::from_le_bytes(value.try_into().ok()?)))
+//!     }
+//! }
+//!
+//! async fn read_temperature<P: Peripheral>(peripheral: P) -> btleplug::Result<f32> {
+//!     let thermometer = BoundProfile::<_, Thermometer>::bind(peripheral).await?;
+//!     let temperature: Temperature = thermometer.read().await?;
+//!     Ok(f32::from(temperature.0) / 100.0)
+//! }
+//! ```
+
+pub mod battery;
+pub mod device_information;
+pub mod heart_rate;
+#[cfg(feature = "nordic-dfu")]
+pub mod nordic_dfu;
+
+use super::{Characteristic, Peripheral, WriteType};
+use crate::{Error, Result};
+use futures::future::ready;
+use futures::stream::{Stream, StreamExt};
+use std::collections::BTreeSet;
+use std::fmt::{self, Debug, Formatter};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// A characteristic used by a [`Profile`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct CharacteristicSpec {
+    /// The UUID of the service containing the characteristic.
+    pub service: Uuid,
+    /// The UUID of the characteristic.
+    pub characteristic: Uuid,
+    /// Whether the profile can't be used on a peripheral without the characteristic.
+    pub required: bool,
+}
+
+impl CharacteristicSpec {
+    /// A characteristic which a peripheral must have to be bound to the profile.
+    pub const fn required(service: Uuid, characteristic: Uuid) -> Self {
+        CharacteristicSpec {
+            service,
+            characteristic,
+            required: true,
+        }
+    }
+
+    /// A characteristic which the profile uses if the peripheral has it.
+    pub const fn optional(service: Uuid, characteristic: Uuid) -> Self {
+        CharacteristicSpec {
+            service,
+            characteristic,
+            required: false,
+        }
+    }
+}
+
+/// The declaration of a GATT profile: the characteristics a peripheral must, or may, have for
+/// the profile to be used with it.
+pub trait Profile {
+    const CHARACTERISTICS: &'static [CharacteristicSpec];
+}
+
+/// A typed value of a characteristic, which can be converted to and from its raw bytes.
+pub trait CharacteristicValue: Sized {
+    /// The UUID of the service containing the characteristic.
+    const SERVICE: Uuid;
+    /// The UUID of the characteristic.
+    const CHARACTERISTIC: Uuid;
+
+    /// Parses a raw value of the characteristic, returning `None` if it is malformed.
+    fn parse(value: &[u8]) -> Option<Self>;
+
+    /// Encodes the value to be written to the characteristic. By default values are read-only,
+    /// and this returns `None`.
+    fn encode(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// A connected [`Peripheral`] which has been checked to have the characteristics required by a
+/// [`Profile`], through which typed values of the profile's characteristics can be read, written
+/// and subscribed to.
+pub struct BoundProfile<P: Peripheral, Pr: Profile> {
+    peripheral: P,
+    /// The characteristics of the peripheral matching `Pr::CHARACTERISTICS`, in the same order.
+    characteristics: Vec<Option<Characteristic>>,
+    profile: PhantomData<Pr>,
+}
+
+impl<P: Peripheral, Pr: Profile> BoundProfile<P, Pr> {
+    /// Binds the profile to a connected peripheral, discovering its characteristics first if that
+    /// hasn't been done yet. Fails if the peripheral is missing any of the characteristics the
+    /// profile requires.
+    pub async fn bind(peripheral: P) -> Result<Self> {
+        let discovered = discovered_characteristics(&peripheral).await?;
+        let characteristics: Vec<_> = Pr::CHARACTERISTICS
+            .iter()
+            .map(|spec| {
+                discovered
+                    .iter()
+                    .find(|c| c.service_uuid == spec.service && c.uuid == spec.characteristic)
+                    .cloned()
+            })
+            .collect();
+        let missing: Vec<_> = Pr::CHARACTERISTICS
+            .iter()
+            .zip(&characteristics)
+            .filter(|(spec, found)| spec.required && found.is_none())
+            .map(|(spec, _)| format!("{} in service {}", spec.characteristic, spec.service))
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::Other(
+                format!(
+                    "{} is missing required characteristics: {}.",
+                    peripheral.address(),
+                    missing.join(", ")
+                )
+                .into(),
+            ));
+        }
+        Ok(BoundProfile {
+            peripheral,
+            characteristics,
+            profile: PhantomData,
+        })
+    }
+
+    /// The underlying peripheral.
+    pub fn peripheral(&self) -> &P {
+        &self.peripheral
+    }
+
+    /// Returns the peripheral's characteristic with the given UUIDs, if it is one of the
+    /// profile's characteristics and the peripheral has it.
+    pub fn characteristic(&self, service: Uuid, characteristic: Uuid) -> Option<&Characteristic> {
+        self.characteristics
+            .iter()
+            .flatten()
+            .find(|c| c.service_uuid == service && c.uuid == characteristic)
+    }
+
+    fn characteristic_for<V: CharacteristicValue>(&self) -> Result<&Characteristic> {
+        self.characteristic(V::SERVICE, V::CHARACTERISTIC)
+            .ok_or_else(|| {
+                Error::Other(
+                    format!(
+                        "Characteristic with UUID {} not found in service {}.",
+                        V::CHARACTERISTIC,
+                        V::SERVICE
+                    )
+                    .into(),
+                )
+            })
+    }
+
+    /// Reads and parses the value of a characteristic.
+    pub async fn read<V: CharacteristicValue>(&self) -> Result<V> {
+        let characteristic = self.characteristic_for::<V>()?;
+        let value = self.peripheral.read(characteristic).await?;
+        V::parse(&value).ok_or_else(|| {
+            Error::Other(
+                format!(
+                    "Invalid value {:?} for characteristic {}",
+                    value, characteristic.uuid
+                )
+                .into(),
+            )
+        })
+    }
+
+    /// Encodes and writes the value of a characteristic.
+    pub async fn write<V: CharacteristicValue>(
+        &self,
+        value: &V,
+        write_type: WriteType,
+    ) -> Result<()> {
+        let characteristic = self.characteristic_for::<V>()?;
+        let data = value.encode().ok_or_else(|| {
+            Error::NotSupported(format!(
+                "Writing characteristic {} is not supported by its profile",
+                characteristic.uuid
+            ))
+        })?;
+        self.peripheral
+            .write(characteristic, &data, write_type)
+            .await
+    }
+
+    /// Subscribes to a characteristic, and returns a stream of its parsed values. As with
+    /// [`Peripheral::notifications`], the stream ends when the peripheral disconnects, and
+    /// malformed values are skipped. The subscription isn't dropped along with the stream; call
+    /// [`unsubscribe`](Self::unsubscribe) to stop the notifications.
+    pub async fn subscribe<V: CharacteristicValue + Send + 'static>(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = V> + Send>>> {
+        let characteristic = self.characteristic_for::<V>()?;
+        let notifications = self.peripheral.notifications().await?;
+        self.peripheral.subscribe(characteristic).await?;
+        Ok(Box::pin(notifications.filter_map(|notification| {
+            ready(
+                if notification.uuid == V::CHARACTERISTIC && notification.service_uuid == V::SERVICE
+                {
+                    V::parse(&notification.value)
+                } else {
+                    None
+                },
+            )
+        })))
+    }
+
+    /// Unsubscribes from a characteristic, after [`subscribe`](Self::subscribe).
+    pub async fn unsubscribe<V: CharacteristicValue>(&self) -> Result<()> {
+        let characteristic = self.characteristic_for::<V>()?;
+        self.peripheral.unsubscribe(characteristic).await
+    }
+}
+
+impl<P: Peripheral, Pr: Profile> Debug for BoundProfile<P, Pr> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("BoundProfile")
+            .field("peripheral", &self.peripheral)
+            .field("characteristics", &self.characteristics)
+            .finish()
+    }
+}
+
+impl<P: Peripheral, Pr: Profile> Clone for BoundProfile<P, Pr> {
+    fn clone(&self) -> Self {
+        BoundProfile {
+            peripheral: self.peripheral.clone(),
+            characteristics: self.characteristics.clone(),
+            profile: PhantomData,
+        }
+    }
+}
+
+/// Returns the characteristics of a connected peripheral, discovering them first if that hasn't
+/// been done yet.
+async fn discovered_characteristics<P: Peripheral>(
+    peripheral: &P,
+) -> Result<BTreeSet<Characteristic>> {
+    let characteristics = peripheral.characteristics();
+    if characteristics.is_empty() {
+        peripheral.discover_characteristics().await?;
+        Ok(peripheral.characteristics())
+    } else {
+        Ok(characteristics)
+    }
+}
+
+/// Finds the characteristic with the given UUID in the given service of a connected peripheral,
+/// discovering its characteristics first if that hasn't been done yet.
+async fn find_characteristic<P: Peripheral>(
+    peripheral: &P,
+    service: Uuid,
+    characteristic: Uuid,
+) -> Result<Characteristic> {
+    discovered_characteristics(peripheral)
+        .await?
+        .into_iter()
+        .find(|c| c.service_uuid == service && c.uuid == characteristic)
+        .ok_or_else(|| {
+            Error::Other(
+                format!(
+                    "Characteristic with UUID {} not found in service {}.",
+                    characteristic, service
+                )
+                .into(),
+            )
+        })
+}
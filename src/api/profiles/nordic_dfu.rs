@@ -0,0 +1,474 @@
+//! Firmware updates for devices running Nordic Semiconductor's Secure DFU bootloader.
+//!
+//! A device in its application firmware is first switched into the bootloader with
+//! [`enter_bootloader`], if it supports the buttonless DFU service. It then reboots, and
+//! advertises the DFU service itself, usually at an address one higher than its usual one. After
+//! connecting to it there, [`update`] sends the init packet and firmware image from a DFU
+//! package: the `.dat` and `.bin` files in the zip created by `nrfutil`.
+
+use super::find_characteristic;
+use crate::api::{
+    bleuuid::uuid_from_u16, Characteristic, Peripheral, ValueNotification, WriteType,
+};
+use crate::common::util;
+use crate::{Error, Result};
+use futures::stream::{Stream, StreamExt};
+use log::debug;
+use std::convert::TryInto;
+use std::fmt::{self, Display, Formatter};
+use std::pin::Pin;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The UUID of the Secure DFU service, under which both the bootloader and the buttonless DFU
+/// characteristics of an application are found.
+pub const DFU_SERVICE: Uuid = uuid_from_u16(0xFE59);
+/// The UUID of the DFU Control Point characteristic of the bootloader.
+pub const DFU_CONTROL_POINT: Uuid = Uuid::from_u128(0x8ec90001_f315_4f60_9fb8_838830daea50);
+/// The UUID of the DFU Packet characteristic of the bootloader.
+pub const DFU_PACKET: Uuid = Uuid::from_u128(0x8ec90002_f315_4f60_9fb8_838830daea50);
+/// The UUID of the Buttonless DFU characteristic of an application which doesn't share bonds
+/// with its bootloader.
+pub const BUTTONLESS_DFU_WITHOUT_BONDS: Uuid =
+    Uuid::from_u128(0x8ec90003_f315_4f60_9fb8_838830daea50);
+/// The UUID of the Buttonless DFU characteristic of an application which shares bonds with its
+/// bootloader.
+pub const BUTTONLESS_DFU_WITH_BONDS: Uuid = Uuid::from_u128(0x8ec90004_f315_4f60_9fb8_838830daea50);
+
+/// How long to wait for the device to respond to a control point request. Executing the firmware
+/// object can take a while, as the bootloader validates the image.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(20);
+/// The largest packet which fits in the minimum ATT MTU of 23 bytes.
+const MIN_PACKET_SIZE: usize = 20;
+
+const OP_CREATE: u8 = 0x01;
+const OP_SET_PRN: u8 = 0x02;
+const OP_CALCULATE_CHECKSUM: u8 = 0x03;
+const OP_EXECUTE: u8 = 0x04;
+const OP_SELECT: u8 = 0x06;
+const OP_RESPONSE: u8 = 0x60;
+
+const BUTTONLESS_ENTER_BOOTLOADER: u8 = 0x01;
+const BUTTONLESS_RESPONSE: u8 = 0x20;
+
+const RESULT_SUCCESS: u8 = 0x01;
+const RESULT_EXTENDED_ERROR: u8 = 0x0B;
+
+/// The kinds of object transferred during a DFU.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum DfuObject {
+    /// The init packet, which describes and signs the firmware.
+    Command,
+    /// The firmware image.
+    Data,
+}
+
+impl DfuObject {
+    fn code(self) -> u8 {
+        match self {
+            DfuObject::Command => 0x01,
+            DfuObject::Data => 0x02,
+        }
+    }
+}
+
+/// The progress of an [`update`], reported as each chunk of an object is sent.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DfuProgress {
+    /// The object being sent.
+    pub object: DfuObject,
+    /// How many bytes of the object have been sent so far.
+    pub bytes_sent: usize,
+    /// The size of the object.
+    pub total_bytes: usize,
+}
+
+/// An error result from a DFU control point request.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct DfuError {
+    opcode: u8,
+    result: u8,
+    extended: Option<u8>,
+}
+
+impl Display for DfuError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let description = match self.result {
+            0x00 => "invalid opcode",
+            0x02 => "opcode not supported",
+            0x03 => "invalid parameter",
+            0x04 => "insufficient resources",
+            0x05 => "invalid object",
+            0x07 => "unsupported type",
+            0x08 => "operation not permitted",
+            0x0A => "operation failed",
+            RESULT_EXTENDED_ERROR => "extended error",
+            _ => "unknown error",
+        };
+        write!(
+            f,
+            "DFU operation {:#04x} failed: {} ({:#04x})",
+            self.opcode, description, self.result
+        )?;
+        if let Some(extended) = self.extended {
+            write!(f, ", extended error {:#04x}", extended)?;
+        }
+        Ok(())
+    }
+}
+
+/// Switches a device running its application firmware into its DFU bootloader, through its
+/// buttonless DFU characteristic. The device disconnects and reboots into the bootloader once
+/// this returns, after which it needs to be scanned for again.
+pub async fn enter_bootloader<P: Peripheral>(peripheral: &P) -> Result<()> {
+    let buttonless =
+        match find_characteristic(peripheral, DFU_SERVICE, BUTTONLESS_DFU_WITHOUT_BONDS).await {
+            Ok(characteristic) => characteristic,
+            Err(_) => {
+                find_characteristic(peripheral, DFU_SERVICE, BUTTONLESS_DFU_WITH_BONDS).await?
+            }
+        };
+    let mut responses = util::notifications_for(peripheral.notifications().await?, &buttonless);
+    peripheral.subscribe(&buttonless).await?;
+    peripheral
+        .write(
+            &buttonless,
+            &[BUTTONLESS_ENTER_BOOTLOADER],
+            WriteType::WithResponse,
+        )
+        .await?;
+    let response = next_response(&mut responses).await?;
+    match *response {
+        [BUTTONLESS_RESPONSE, BUTTONLESS_ENTER_BOOTLOADER, RESULT_SUCCESS] => Ok(()),
+        [BUTTONLESS_RESPONSE, BUTTONLESS_ENTER_BOOTLOADER, result, ..] => Err(Error::Other(
+            DfuError {
+                opcode: BUTTONLESS_ENTER_BOOTLOADER,
+                result,
+                extended: None,
+            }
+            .to_string()
+            .into(),
+        )),
+        _ => Err(unexpected_response(&response)),
+    }
+}
+
+/// Sends a firmware update to a device running the Secure DFU bootloader: first the init packet,
+/// then the firmware image. `progress` is called as each chunk of them is sent. The device
+/// validates and activates the firmware once it has all been sent, and then disconnects.
+///
+/// Interrupted transfers aren't resumed; calling this again starts the transfer over.
+pub async fn update<P: Peripheral>(
+    peripheral: &P,
+    init_packet: &[u8],
+    firmware: &[u8],
+    mut progress: impl FnMut(DfuProgress) + Send,
+) -> Result<()> {
+    let control_point = find_characteristic(peripheral, DFU_SERVICE, DFU_CONTROL_POINT).await?;
+    let packet = find_characteristic(peripheral, DFU_SERVICE, DFU_PACKET).await?;
+    let responses = util::notifications_for(peripheral.notifications().await?, &control_point);
+    peripheral.subscribe(&control_point).await?;
+    // Backends which can't report the MTU get packets which fit the minimum one.
+    let packet_size = match peripheral.mtu().await {
+        Ok(mtu) => usize::from(mtu.saturating_sub(3)).max(MIN_PACKET_SIZE),
+        Err(Error::NotSupported(_)) => MIN_PACKET_SIZE,
+        Err(error) => return Err(error),
+    };
+    let mut dfu = Dfu {
+        peripheral,
+        control_point,
+        packet,
+        responses,
+        packet_size,
+    };
+    // Flow control is left to the writes themselves rather than to packet receipt
+    // notifications, which each backend already provides.
+    dfu.request(&[OP_SET_PRN, 0, 0]).await?;
+    dfu.send_object(DfuObject::Command, init_packet, &mut progress)
+        .await?;
+    dfu.send_object(DfuObject::Data, firmware, &mut progress)
+        .await
+}
+
+struct Dfu<'a, P: Peripheral> {
+    peripheral: &'a P,
+    control_point: Characteristic,
+    packet: Characteristic,
+    responses: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+    packet_size: usize,
+}
+
+impl<'a, P: Peripheral> Dfu<'a, P> {
+    /// Sends an object in chunks no larger than the bootloader's maximum object size, checking
+    /// the CRC of everything sent so far after each one.
+    async fn send_object(
+        &mut self,
+        object: DfuObject,
+        data: &[u8],
+        progress: &mut (impl FnMut(DfuProgress) + Send),
+    ) -> Result<()> {
+        let selected = self.request(&[OP_SELECT, object.code()]).await?;
+        let max_size = read_u32(&selected, 0)? as usize;
+        if max_size == 0 {
+            return Err(unexpected_response(&selected));
+        }
+        debug!(
+            "Sending {:?} object of {} bytes in chunks of up to {}",
+            object,
+            data.len(),
+            max_size
+        );
+        let mut offset = 0;
+        let mut crc = 0;
+        for chunk in data.chunks(max_size) {
+            let mut create = vec![OP_CREATE, object.code()];
+            create.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            self.request(&create).await?;
+            for packet in chunk.chunks(self.packet_size) {
+                self.peripheral
+                    .write(&self.packet, packet, WriteType::WithoutResponse)
+                    .await?;
+            }
+            offset += chunk.len();
+            crc = crc32_update(crc, chunk);
+
+            let checksum = self.request(&[OP_CALCULATE_CHECKSUM]).await?;
+            let (device_offset, device_crc) = (read_u32(&checksum, 0)?, read_u32(&checksum, 4)?);
+            if device_offset as usize != offset || device_crc != crc {
+                return Err(Error::Other(
+                    format!(
+                        "DFU checksum mismatch: sent {} bytes with CRC {:#010x}, but device has {} \
+                         bytes with CRC {:#010x}",
+                        offset, crc, device_offset, device_crc
+                    )
+                    .into(),
+                ));
+            }
+            self.request(&[OP_EXECUTE]).await?;
+            progress(DfuProgress {
+                object,
+                bytes_sent: offset,
+                total_bytes: data.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes a request to the control point, and returns the payload of the successful response
+    /// to it.
+    async fn request(&mut self, request: &[u8]) -> Result<Vec<u8>> {
+        self.peripheral
+            .write(&self.control_point, request, WriteType::WithResponse)
+            .await?;
+        let response = next_response(&mut self.responses).await?;
+        parse_response(request[0], &response)
+    }
+}
+
+/// Waits for the next value of a characteristic, with a timeout.
+async fn next_response(
+    responses: &mut Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+) -> Result<Vec<u8>> {
+    util::with_timeout(Some(RESPONSE_TIMEOUT), async {
+        responses
+            .next()
+            .await
+            .map(|notification| notification.value.to_vec())
+            .ok_or(Error::NotConnected)
+    })
+    .await
+}
+
+/// Checks a control point response to a request with the given opcode, and returns its payload.
+fn parse_response(opcode: u8, response: &[u8]) -> Result<Vec<u8>> {
+    match *response {
+        [OP_RESPONSE, response_opcode, RESULT_SUCCESS, ref payload @ ..]
+            if response_opcode == opcode =>
+        {
+            Ok(payload.to_vec())
+        }
+        [OP_RESPONSE, response_opcode, result, ref rest @ ..] if response_opcode == opcode => {
+            let extended = if result == RESULT_EXTENDED_ERROR {
+                rest.first().copied()
+            } else {
+                None
+            };
+            Err(Error::Other(
+                DfuError {
+                    opcode,
+                    result,
+                    extended,
+                }
+                .to_string()
+                .into(),
+            ))
+        }
+        _ => Err(unexpected_response(response)),
+    }
+}
+
+fn unexpected_response(response: &[u8]) -> Error {
+    Error::Other(format!("Unexpected DFU response {:?}", response).into())
+}
+
+fn read_u32(payload: &[u8], offset: usize) -> Result<u32> {
+    payload
+        .get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or_else(|| unexpected_response(payload))
+}
+
+/// The CRC-32 used by the bootloader to check the data it has received, as used by zlib.
+#[cfg(test)]
+fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Extends the CRC-32 of some data with the data following it, so that
+/// `crc32_update(crc32(a), b)` is the CRC of `a` followed by `b`.
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(&[]), 0);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn parse_success_response() {
+        let response = [
+            OP_RESPONSE,
+            OP_SELECT,
+            RESULT_SUCCESS,
+            0x00,
+            0x10,
+            0x00,
+            0x00,
+        ];
+        assert_eq!(
+            parse_response(OP_SELECT, &response).unwrap(),
+            vec![0x00, 0x10, 0x00, 0x00]
+        );
+        assert_eq!(read_u32(&response[3..], 0).unwrap(), 0x1000);
+    }
+
+    #[test]
+    fn parse_error_response() {
+        let error = parse_response(OP_CREATE, &[OP_RESPONSE, OP_CREATE, 0x04]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "DFU operation 0x01 failed: insufficient resources (0x04)"
+        );
+        let error = parse_response(OP_EXECUTE, &[OP_RESPONSE, OP_EXECUTE, 0x0B, 0x07]).unwrap_err();
+        assert!(error.to_string().ends_with("extended error 0x07"));
+    }
+
+    #[test]
+    fn parse_unexpected_response() {
+        assert!(parse_response(OP_CREATE, &[OP_RESPONSE, OP_EXECUTE, RESULT_SUCCESS]).is_err());
+        assert!(parse_response(OP_CREATE, &[]).is_err());
+        assert!(read_u32(&[0x00, 0x01], 0).is_err());
+    }
+
+    /// Updates a mock device which can't report its MTU, acting as a bootloader which accepts
+    /// objects of up to 64 bytes.
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn update_without_mtu() {
+        use crate::api::{BDAddr, Central, CharPropFlags};
+        use crate::mock::{Adapter, MockCharacteristic, MockDevice, MockService};
+        use std::sync::{Arc, Mutex};
+
+        const ADDRESS: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let mut device = MockDevice::default();
+        device.properties.address = ADDRESS;
+        device.services.push(MockService {
+            uuid: DFU_SERVICE,
+            characteristics: vec![
+                MockCharacteristic {
+                    uuid: DFU_CONTROL_POINT,
+                    properties: CharPropFlags::WRITE | CharPropFlags::NOTIFY,
+                    ..Default::default()
+                },
+                MockCharacteristic {
+                    uuid: DFU_PACKET,
+                    properties: CharPropFlags::WRITE_WITHOUT_RESPONSE,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        });
+        let adapter = Adapter::new();
+        let simulated = adapter.add_device(device);
+        simulated.set_mtu_reported(false);
+
+        // The bytes received of the current object, and the largest packet they came in.
+        let received = Arc::new(Mutex::new((Vec::new(), 0)));
+        let packets = received.clone();
+        simulated
+            .set_write_handler(DFU_PACKET, move |_, packet| {
+                let (data, largest) = &mut *packets.lock().unwrap();
+                data.extend_from_slice(packet);
+                *largest = packet.len().max(*largest);
+                Ok(())
+            })
+            .unwrap();
+        let objects = received.clone();
+        simulated
+            .set_write_handler(DFU_CONTROL_POINT, move |device, request| {
+                let mut response = vec![OP_RESPONSE, request[0], RESULT_SUCCESS];
+                let (data, _) = &mut *objects.lock().unwrap();
+                match request[0] {
+                    OP_SELECT => {
+                        data.clear();
+                        response.extend_from_slice(&64u32.to_le_bytes());
+                        response.extend_from_slice(&[0; 8]);
+                    }
+                    OP_CALCULATE_CHECKSUM => {
+                        response.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                        response.extend_from_slice(&crc32(data).to_le_bytes());
+                    }
+                    _ => {}
+                }
+                device.notify(DFU_CONTROL_POINT, &response).unwrap();
+                Ok(())
+            })
+            .unwrap();
+
+        let peripheral = adapter.add_peripheral(ADDRESS).await.unwrap();
+        peripheral.connect().await.unwrap();
+        peripheral.discover_services().await.unwrap();
+        let firmware: Vec<u8> = (0..150).collect();
+        let mut reports = Vec::new();
+        update(&peripheral, &[0xaa; 10], &firmware, |progress| {
+            reports.push(progress)
+        })
+        .await
+        .unwrap();
+
+        let (data, largest) = &*received.lock().unwrap();
+        assert_eq!(data, &firmware);
+        assert_eq!(*largest, MIN_PACKET_SIZE);
+        assert_eq!(reports.len(), 4);
+        assert_eq!(reports[3].bytes_sent, 150);
+    }
+}
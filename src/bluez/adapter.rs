@@ -1,24 +1,160 @@
-use super::peripheral::Peripheral;
-use crate::api::{BDAddr, Central, CentralEvent};
+use super::advertising::{self, RegisteredAdvertisement};
+use super::connect;
+use super::error;
+use super::expiry::PeripheralExpiry;
+use super::gatt_server::{GattApplication, ServerEventSenders};
+use super::monitor::{self, AdvertisementMonitor};
+use super::peripheral::{device_properties, Peripheral};
+#[cfg(feature = "hci")]
+use super::{hci, periodic};
+#[cfg(feature = "hci")]
+use crate::api::ScanMode;
+use crate::api::{
+    AdapterMetrics, AdapterState, AddressType, Advertisement, BDAddr, Central, CentralEvent,
+    DisconnectReason, EventFilter, GattServer, GattServerEvent, IdentityResolvingKey, LocalService,
+    PeriodicAdvertisement, Peripheral as _, PropertyChanges, ScanFilter, ScanType,
+};
+use crate::common::{
+    identity_resolver::IdentityResolver, metrics::Metrics, runtime, scan_filter::ScanFilterState,
+    util,
+};
 use crate::{Error, Result};
 use async_trait::async_trait;
 use bluez_async::{
-    AdapterId, BluetoothError, BluetoothEvent, BluetoothSession, DeviceEvent, DiscoveryFilter,
-    Transport,
+    AdapterEvent, AdapterId, BluetoothError, BluetoothEvent, BluetoothSession, DeviceEvent,
+    DeviceInfo, DiscoveryFilter, Transport,
 };
+use dbus::Path;
+use futures::channel::mpsc;
+use futures::future::ready;
 use futures::stream::{self, Stream, StreamExt};
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
 
 /// Implementation of [api::Central](crate::api::Central).
 #[derive(Clone, Debug)]
 pub struct Adapter {
     session: BluetoothSession,
     adapter: AdapterId,
+    scan_filter: Arc<Mutex<ScanFilter>>,
+    expiry: PeripheralExpiry,
+    monitor: Arc<Mutex<Option<AdvertisementMonitor>>>,
+    advertisement: Arc<Mutex<Option<RegisteredAdvertisement>>>,
+    local_services: Arc<Mutex<HashMap<Uuid, Arc<GattApplication>>>>,
+    /// The advertisement listing the services hosted with [`GattServer::add_service`], while there
+    /// are any.
+    server_advertisement: Arc<Mutex<Option<RegisteredAdvertisement>>>,
+    server_event_senders: ServerEventSenders,
+    identities: Arc<Mutex<IdentityResolver>>,
+    metrics: Arc<Metrics>,
 }
 
 impl Adapter {
     pub(crate) fn new(session: BluetoothSession, adapter: AdapterId) -> Self {
-        Self { session, adapter }
+        Self {
+            session,
+            adapter,
+            scan_filter: Arc::new(Mutex::new(ScanFilter::default())),
+            expiry: PeripheralExpiry::default(),
+            monitor: Arc::new(Mutex::new(None)),
+            advertisement: Arc::new(Mutex::new(None)),
+            local_services: Arc::new(Mutex::new(HashMap::new())),
+            server_advertisement: Arc::new(Mutex::new(None)),
+            server_event_senders: Arc::new(Mutex::new(Vec::new())),
+            identities: Arc::new(Mutex::new(IdentityResolver::default())),
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    /// Connects to the device with the given address, and returns it. If BlueZ doesn't already
+    /// know about the device, it is created with the given address type, rather than BlueZ
+    /// assuming a public address. Devices using random addresses can't be connected to otherwise
+    /// without scanning for them first.
+    ///
+    /// Creating devices needs `bluetoothd` to be run with `--experimental`.
+    pub async fn connect_with_address_type(
+        &self,
+        address: BDAddr,
+        address_type: AddressType,
+    ) -> Result<Peripheral> {
+        match self.peripheral(address).await {
+            Ok(peripheral) => {
+                peripheral.connect().await?;
+                Ok(peripheral)
+            }
+            Err(Error::DeviceNotFound) => {
+                connect::connect_device(self.adapter.clone().into(), address, address_type).await?;
+                self.peripheral(address).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Scans passively by registering an Advertisement Monitor with BlueZ, which needs a service,
+    /// name prefix or manufacturer ID to match advertisements against. Where the controller
+    /// supports it, BlueZ offloads the matching to the controller, so unrelated advertisements
+    /// don't wake the host.
+    async fn start_passive_scan(&self, filter: ScanFilter) -> Result<()> {
+        let patterns = monitor::patterns(&filter);
+        if patterns.is_empty() {
+            return Err(Error::NotSupported(
+                "Passive scanning on BlueZ needs a service, name prefix or manufacturer ID to filter on"
+                    .to_string(),
+            ));
+        }
+        let adapter: Path<'static> = self.adapter.clone().into();
+        let advertisement_monitor =
+            runtime::spawn_blocking(move || monitor::start(adapter, patterns))
+                .await?
+                .map_err(error::from_dbus)?;
+        *self.scan_filter.lock().unwrap() = filter;
+        *self.monitor.lock().unwrap() = Some(advertisement_monitor);
+        Ok(())
+    }
+
+    /// Sets the scan interval and window the kernel uses for discovery to suit the given scan mode,
+    /// unless the previous scan already used the same ones.
+    #[cfg(feature = "hci")]
+    async fn set_scan_mode(&self, scan_mode: ScanMode) -> Result<()> {
+        let previous = self.scan_filter.lock().unwrap().scan_mode;
+        let (interval, window) = discovery_scan_parameters(scan_mode);
+        if discovery_scan_parameters(previous) == (interval, window) {
+            return Ok(());
+        }
+        let dev_id = hci::dev_id(&self.adapter.clone().into())?;
+        runtime::spawn_blocking(move || {
+            hci::set_discovery_scan_parameters(dev_id, interval, window)
+        })
+        .await?
+        .map_err(hci::to_error)
+    }
+
+    fn local_service(&self, service: Uuid) -> Result<Arc<GattApplication>> {
+        self.local_services
+            .lock()
+            .unwrap()
+            .get(&service)
+            .cloned()
+            .ok_or_else(|| Error::Other(format!("Service with UUID {} not found.", service).into()))
+    }
+
+    /// Sends a raw HCI command to the controller, and returns the return parameters of the Command
+    /// Complete event in response, which usually start with a status byte. The opcode is made up
+    /// of the OGF (opcode group field, 6 bits) and OCF (opcode command field, 10 bits).
+    ///
+    /// This bypasses BlueZ, which isn't told about any changes the command makes, so is only meant
+    /// for vendor commands and controller settings which the rest of the API doesn't cover. It
+    /// needs the `CAP_NET_RAW` capability.
+    #[cfg(feature = "hci")]
+    pub async fn hci_command(&self, ogf: u8, ocf: u16, params: &[u8]) -> Result<Vec<u8>> {
+        let dev_id = hci::dev_id(&self.adapter.clone().into())?;
+        let params = params.to_vec();
+        runtime::spawn_blocking(move || hci::hci_command(dev_id, ogf, ocf, &params))
+            .await?
+            .map_err(hci::to_error)
     }
 }
 
@@ -34,47 +170,176 @@ impl Central for Adapter {
 
         // Synthesise `DeviceDiscovered' events for existing peripherals.
         let devices = self.session.get_devices().await?;
-        let initial_events = stream::iter(
-            devices
-                .into_iter()
-                .map(|device| CentralEvent::DeviceDiscovered(BDAddr::from(&device.mac_address))),
-        );
+        let initial_events = stream::iter(devices.into_iter().map(|device| {
+            (
+                vec![CentralEvent::DeviceDiscovered(BDAddr::from(
+                    &device.mac_address,
+                ))],
+                Some(device),
+            )
+        }));
 
         let session = self.session.clone();
-        let events = events.filter_map(move |event| central_event(event, session.clone()));
+        let adapter = self.adapter.clone();
+        let metrics = self.metrics.clone();
+        let events = events.filter_map(move |event| {
+            util::event_pump(
+                "bluez",
+                central_event(event, session.clone(), adapter.clone(), metrics.clone()),
+            )
+        });
+
+        // Devices removed for having stopped advertising, or being over the limit.
+        let lost_events = self.expiry.lost_events().map(|event| (vec![event], None));
+
+        // BlueZ can only filter on services and RSSI itself, so apply the rest of the filter here.
+        let scan_filter = self.scan_filter.clone();
+        let mut state = ScanFilterState::default();
+        let identities = self.identities.clone();
+        let events = stream::select(initial_events.chain(events), lost_events).flat_map(
+            move |(events, device)| {
+                let events: Vec<_> = match device {
+                    Some(device) => {
+                        let filter = scan_filter.lock().unwrap();
+                        let mut identities = identities.lock().unwrap();
+                        let mut properties = device_properties(device);
+                        properties.address = identities.resolve(properties.address);
+                        events
+                            .into_iter()
+                            .filter_map(|event| identities.resolve_event(event))
+                            .flat_map(|event| state.filter_event(&filter, &properties, event))
+                            .collect()
+                    }
+                    None => {
+                        let mut identities = identities.lock().unwrap();
+                        events
+                            .into_iter()
+                            .filter_map(|event| identities.resolve_event(event))
+                            .collect()
+                    }
+                };
+                for event in &events {
+                    if let CentralEvent::DeviceLost(address) = event {
+                        state.forget(address);
+                    }
+                }
+                stream::iter(events)
+            },
+        );
 
-        Ok(Box::pin(initial_events.chain(events)))
+        Ok(Box::pin(events))
     }
 
-    async fn start_scan(&self) -> Result<()> {
-        let filter = DiscoveryFilter {
+    async fn events_filtered(
+        &self,
+        filter: EventFilter,
+    ) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        let events = self.events().await?;
+        Ok(Box::pin(
+            events.filter(move |event| ready(filter.matches(event))),
+        ))
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        #[cfg(feature = "hci")]
+        self.set_scan_mode(filter.scan_mode).await?;
+        if filter.scan_type == ScanType::Passive {
+            return self.start_passive_scan(filter).await;
+        }
+        self.monitor.lock().unwrap().take();
+        let discovery_filter = DiscoveryFilter {
+            service_uuids: filter.services.clone(),
+            rssi_threshold: filter.min_rssi,
+            duplicate_data: Some(filter.allow_duplicates),
             transport: Some(Transport::Auto),
             ..Default::default()
         };
-        self.session.start_discovery_with_filter(&filter).await?;
+        *self.scan_filter.lock().unwrap() = filter;
+        self.session
+            .start_discovery_with_filter(&discovery_filter)
+            .await?;
         Ok(())
     }
 
     async fn stop_scan(&self) -> Result<()> {
+        if self.monitor.lock().unwrap().take().is_some() {
+            return Ok(());
+        }
         self.session.stop_discovery().await?;
         Ok(())
     }
 
+    async fn is_scanning(&self) -> Result<bool> {
+        if self.monitor.lock().unwrap().is_some() {
+            return Ok(true);
+        }
+        let adapter = self.session.get_adapter_info(&self.adapter).await?;
+        Ok(adapter.discovering)
+    }
+
+    async fn set_powered(&self, powered: bool) -> Result<()> {
+        self.session.set_powered(&self.adapter, powered).await?;
+        Ok(())
+    }
+
+    async fn adapter_state(&self) -> Result<AdapterState> {
+        let adapter = self.session.get_adapter_info(&self.adapter).await?;
+        Ok(if adapter.powered {
+            AdapterState::PoweredOn
+        } else {
+            AdapterState::PoweredOff
+        })
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         let devices = self.session.get_devices().await?;
-        Ok(devices
-            .into_iter()
-            .map(|device| Peripheral::new(self.session.clone(), device))
-            .collect())
+        let identities = self.identities.lock().unwrap();
+        // BlueZ may still know about earlier private addresses of a device whose key has been
+        // registered, so only return one peripheral for each identity.
+        let mut resolved = HashSet::new();
+        let mut peripherals = vec![];
+        for device in devices {
+            let address = BDAddr::from(&device.mac_address);
+            match identities.lookup(address) {
+                Some(identity) => {
+                    let current = identities.current_address(identity);
+                    if current.map_or(false, |current| current != address)
+                        || !resolved.insert(identity)
+                    {
+                        continue;
+                    }
+                    peripherals.push(
+                        Peripheral::new(self.session.clone(), device, self.metrics.clone())
+                            .with_address(identity),
+                    );
+                }
+                None => peripherals.push(Peripheral::new(
+                    self.session.clone(),
+                    device,
+                    self.metrics.clone(),
+                )),
+            }
+        }
+        Ok(peripherals)
     }
 
     async fn peripheral(&self, address: BDAddr) -> Result<Peripheral> {
         let devices = self.session.get_devices().await?;
+        let identities = self.identities.lock().unwrap();
+        let current = identities.current_address(address);
         devices
             .into_iter()
             .find_map(|device| {
-                if BDAddr::from(&device.mac_address) == address {
-                    Some(Peripheral::new(self.session.clone(), device))
+                let device_address = BDAddr::from(&device.mac_address);
+                let identity = identities.lookup(device_address);
+                let matches = match current {
+                    Some(current) => device_address == current,
+                    None => device_address == address || identity == Some(address),
+                };
+                if matches {
+                    let peripheral =
+                        Peripheral::new(self.session.clone(), device, self.metrics.clone());
+                    Some(peripheral.with_address(identity.unwrap_or(device_address)))
                 } else {
                     None
                 }
@@ -82,78 +347,336 @@ impl Central for Adapter {
             .ok_or(Error::DeviceNotFound)
     }
 
-    async fn add_peripheral(&self, _address: BDAddr) -> Result<Peripheral> {
+    async fn add_identity_resolving_key(
+        &self,
+        identity: BDAddr,
+        irk: IdentityResolvingKey,
+    ) -> Result<()> {
+        self.identities.lock().unwrap().add_key(identity, irk);
+        Ok(())
+    }
+
+    async fn remove_identity_resolving_key(&self, identity: BDAddr) -> Result<()> {
+        self.identities.lock().unwrap().remove_key(identity);
+        Ok(())
+    }
+
+    async fn set_retain_peripherals(&self, _retain: bool) -> Result<()> {
+        // BlueZ manages the set of known devices itself, and keeps them after they disconnect.
+        Ok(())
+    }
+
+    async fn set_peripheral_ttl(&self, ttl: Option<Duration>) -> Result<()> {
+        self.expiry.set_ttl(&self.session, &self.adapter, ttl);
+        Ok(())
+    }
+
+    async fn set_max_peripherals(&self, max: Option<usize>) -> Result<()> {
+        self.expiry.set_max(&self.session, &self.adapter, max);
+        Ok(())
+    }
+
+    async fn add_peripheral(&self, address: BDAddr) -> Result<Peripheral> {
+        // BlueZ keeps devices which are bonded or were recently seen, and can connect to any of
+        // them without a scan. Unknown devices can't be added without scanning for them.
+        self.peripheral(address).await
+    }
+
+    async fn bonded_peripherals(&self) -> Result<Vec<Peripheral>> {
+        let devices = self.session.get_devices().await?;
+        Ok(devices
+            .into_iter()
+            .filter(|device| device.paired)
+            .map(|device| Peripheral::new(self.session.clone(), device, self.metrics.clone()))
+            .collect())
+    }
+
+    async fn start_advertising(&self, advertisement: &Advertisement) -> Result<()> {
+        // Stop the previous advertisement first, as the controller may only have room for one.
+        self.advertisement.lock().unwrap().take();
+        let adapter: Path<'static> = self.adapter.clone().into();
+        let advertisement = advertisement.clone();
+        let registered =
+            runtime::spawn_blocking(move || advertising::start(adapter, advertisement))
+                .await?
+                .map_err(error::from_dbus)?;
+        *self.advertisement.lock().unwrap() = Some(registered);
+        Ok(())
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        self.advertisement.lock().unwrap().take();
+        Ok(())
+    }
+
+    #[cfg(feature = "hci")]
+    async fn sync_periodic_advertising(
+        &self,
+        address: BDAddr,
+        sid: u8,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeriodicAdvertisement> + Send>>> {
+        // The controller needs to know whether the address is random, which BlueZ knows from
+        // scanning.
+        let device = self
+            .session
+            .get_devices()
+            .await?
+            .into_iter()
+            .find(|device| {
+                device.id.adapter() == self.adapter && BDAddr::from(&device.mac_address) == address
+            })
+            .ok_or(Error::DeviceNotFound)?;
+        let random_address = AddressType::from(device.address_type) == AddressType::Random;
+        let dev_id = hci::dev_id(&self.adapter.clone().into())?;
+        let advertisements =
+            runtime::spawn_blocking(move || periodic::sync(dev_id, address, random_address, sid))
+                .await?
+                .map_err(|e| match e.kind() {
+                    std::io::ErrorKind::TimedOut => Error::TimedOut(periodic::ESTABLISH_TIMEOUT),
+                    _ => hci::to_error(e),
+                })?;
+        Ok(Box::pin(advertisements))
+    }
+
+    #[cfg(not(feature = "hci"))]
+    async fn sync_periodic_advertising(
+        &self,
+        _address: BDAddr,
+        _sid: u8,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeriodicAdvertisement> + Send>>> {
         Err(Error::NotSupported(
-            "Can't add a Peripheral from a BDAddr".to_string(),
+            "Periodic advertising sync on BlueZ needs the hci feature".to_string(),
         ))
     }
+
+    async fn metrics(&self) -> Result<AdapterMetrics> {
+        Ok(self.metrics.snapshot())
+    }
+}
+
+#[async_trait]
+impl GattServer for Adapter {
+    async fn add_service(&self, service: &LocalService) -> Result<()> {
+        let adapter: Path<'static> = self.adapter.clone().into();
+        let local_service = service.clone();
+        let event_senders = self.server_event_senders.clone();
+        let local_services = self.local_services.clone();
+        let server_advertisement = self.server_advertisement.clone();
+        runtime::spawn_blocking(move || {
+            let application =
+                GattApplication::register(adapter.clone(), &local_service, event_senders)?;
+            local_services
+                .lock()
+                .unwrap()
+                .insert(local_service.uuid, Arc::new(application));
+            let result = advertise_services(adapter, &server_advertisement, &local_services);
+            // Without the advertisement, centrals couldn't find the service.
+            if result.is_err() {
+                local_services.lock().unwrap().remove(&local_service.uuid);
+            }
+            result
+        })
+        .await?
+        .map_err(error::from_dbus)
+    }
+
+    async fn remove_service(&self, service: Uuid) -> Result<()> {
+        if self
+            .local_services
+            .lock()
+            .unwrap()
+            .remove(&service)
+            .is_none()
+        {
+            return Ok(());
+        }
+        let adapter: Path<'static> = self.adapter.clone().into();
+        let local_services = self.local_services.clone();
+        let server_advertisement = self.server_advertisement.clone();
+        runtime::spawn_blocking(move || {
+            advertise_services(adapter, &server_advertisement, &local_services)
+        })
+        .await?
+        .map_err(error::from_dbus)
+    }
+
+    async fn set_value(&self, service: Uuid, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        self.local_service(service)?
+            .set_value(characteristic, value)
+    }
+
+    async fn notify(&self, service: Uuid, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        let application = self.local_service(service)?;
+        application.set_value(characteristic, value)?;
+        runtime::spawn_blocking(move || application.value_changed(characteristic))
+            .await?
+            .map_err(error::from_dbus)
+    }
+
+    async fn server_events(&self) -> Result<Pin<Box<dyn Stream<Item = GattServerEvent> + Send>>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.server_event_senders.lock().unwrap().push(sender);
+        Ok(Box::pin(receiver))
+    }
+}
+
+/// Updates the advertisement for the GATT server to list the services it hosts. A single
+/// advertisement is shared by all of them, as the controller may only have room for a few. It is
+/// registered along with the first service, and dropped along with the last.
+fn advertise_services(
+    adapter: Path<'static>,
+    server_advertisement: &Mutex<Option<RegisteredAdvertisement>>,
+    local_services: &Mutex<HashMap<Uuid, Arc<GattApplication>>>,
+) -> std::result::Result<(), dbus::Error> {
+    // The services are listed while the advertisement is locked, so that concurrent changes are
+    // applied in the same order as they were made to the list.
+    let mut server_advertisement = server_advertisement.lock().unwrap();
+    let services: Vec<Uuid> = local_services.lock().unwrap().keys().copied().collect();
+    if services.is_empty() {
+        *server_advertisement = None;
+    } else if let Some(registered) = &*server_advertisement {
+        registered.set_services(services)?;
+    } else {
+        let advertisement = Advertisement {
+            services,
+            ..Default::default()
+        };
+        *server_advertisement = Some(advertising::start(adapter, advertisement)?);
+    }
+    Ok(())
 }
 
 impl From<BluetoothError> for Error {
     fn from(error: BluetoothError) -> Self {
-        Error::Other(Box::new(error))
+        match error {
+            BluetoothError::DbusError(error) => error::from_dbus(error),
+            error => Error::Other(Box::new(error)),
+        }
     }
 }
 
-async fn central_event(event: BluetoothEvent, session: BluetoothSession) -> Option<CentralEvent> {
+async fn central_event(
+    event: BluetoothEvent,
+    session: BluetoothSession,
+    adapter: AdapterId,
+    metrics: Arc<Metrics>,
+) -> Option<(Vec<CentralEvent>, Option<DeviceInfo>)> {
     match event {
+        BluetoothEvent::Adapter {
+            id,
+            event: AdapterEvent::Powered { powered },
+        } if id == adapter => {
+            let state = if powered {
+                AdapterState::PoweredOn
+            } else {
+                AdapterState::PoweredOff
+            };
+            Some((vec![CentralEvent::AdapterStateChanged(state)], None))
+        }
+        BluetoothEvent::Adapter {
+            id,
+            event: AdapterEvent::Discovering { discovering },
+        } if id == adapter => {
+            let event = if discovering {
+                CentralEvent::ScanStarted
+            } else {
+                CentralEvent::ScanStopped
+            };
+            Some((vec![event], None))
+        }
         BluetoothEvent::Device {
             id,
             event: DeviceEvent::Discovered,
         } => {
+            metrics.advertisement_received();
             let device = session.get_device_info(&id).await.ok()?;
-            Some(CentralEvent::DeviceDiscovered((&device.mac_address).into()))
+            Some((
+                vec![CentralEvent::DeviceDiscovered((&device.mac_address).into())],
+                Some(device),
+            ))
         }
         BluetoothEvent::Device {
             id,
             event: DeviceEvent::Connected { connected },
         } => {
             let device = session.get_device_info(&id).await.ok()?;
-            if connected {
-                Some(CentralEvent::DeviceConnected((&device.mac_address).into()))
+            let event = if connected {
+                CentralEvent::DeviceConnected((&device.mac_address).into())
             } else {
-                Some(CentralEvent::DeviceDisconnected(
-                    (&device.mac_address).into(),
-                ))
-            }
+                // BlueZ doesn't say why a device disconnected, but it can at least be told
+                // whether the adapter has been turned off.
+                let reason = match session.get_adapter_info(&id.adapter()).await {
+                    Ok(adapter) if !adapter.powered => DisconnectReason::AdapterOff,
+                    _ => DisconnectReason::Unknown,
+                };
+                CentralEvent::DeviceDisconnected((&device.mac_address).into(), reason)
+            };
+            Some((vec![event], Some(device)))
         }
         BluetoothEvent::Device {
             id,
-            event: DeviceEvent::RSSI { rssi: _ },
+            event: DeviceEvent::RSSI { rssi },
         } => {
+            metrics.advertisement_received();
             let device = session.get_device_info(&id).await.ok()?;
-            Some(CentralEvent::DeviceUpdated((&device.mac_address).into()))
+            let address = (&device.mac_address).into();
+            Some((
+                vec![
+                    CentralEvent::DeviceUpdated {
+                        address,
+                        changes: PropertyChanges::RSSI,
+                    },
+                    CentralEvent::RssiUpdate { address, rssi },
+                ],
+                Some(device),
+            ))
         }
         BluetoothEvent::Device {
             id,
             event: DeviceEvent::ManufacturerData { manufacturer_data },
         } => {
             let device = session.get_device_info(&id).await.ok()?;
-            Some(CentralEvent::ManufacturerDataAdvertisement {
+            let event = CentralEvent::ManufacturerDataAdvertisement {
                 address: (&device.mac_address).into(),
                 manufacturer_data,
-            })
+            };
+            Some((vec![event], Some(device)))
         }
         BluetoothEvent::Device {
             id,
             event: DeviceEvent::ServiceData { service_data },
         } => {
             let device = session.get_device_info(&id).await.ok()?;
-            Some(CentralEvent::ServiceDataAdvertisement {
+            let event = CentralEvent::ServiceDataAdvertisement {
                 address: (&device.mac_address).into(),
                 service_data,
-            })
+            };
+            Some((vec![event], Some(device)))
         }
         BluetoothEvent::Device {
             id,
             event: DeviceEvent::Services { services },
         } => {
             let device = session.get_device_info(&id).await.ok()?;
-            Some(CentralEvent::ServicesAdvertisement {
-                address: (&device.mac_address).into(),
-                services,
-            })
+            let address = (&device.mac_address).into();
+            let mut events = vec![CentralEvent::ServicesAdvertisement { address, services }];
+            // BlueZ handles Service Changed indications itself, rediscovering the affected
+            // services and exporting them again, after which it updates the device's UUIDs.
+            if device.connected {
+                events.push(CentralEvent::ServicesChanged(address));
+            }
+            Some((events, Some(device)))
         }
         _ => None,
     }
 }
+
+/// Returns the scan interval and window for discovery in the given scan mode, in units of 0.625 ms.
+#[cfg(feature = "hci")]
+fn discovery_scan_parameters(scan_mode: ScanMode) -> (u16, u16) {
+    match scan_mode {
+        ScanMode::LowPower => (0x2000, 0x0333),
+        // The kernel's defaults, which scan continuously.
+        ScanMode::Balanced | ScanMode::LowLatency => (0x0012, 0x0012),
+    }
+}
@@ -0,0 +1,134 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Advertising from a local adapter by registering an `org.bluez.LEAdvertisement1` object with
+//! BlueZ's `org.bluez.LEAdvertisingManager1`. BlueZ builds the advertising data from the object's
+//! properties, and takes care of the controller's advertising sets.
+
+use super::objects::{variant, ExportedObjects, Interfaces, ObjectTree};
+use super::{failed, BLUEZ_SERVICE};
+use crate::api::Advertisement;
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::message::Message;
+use dbus::strings::Path;
+use log::debug;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+const ADVERTISEMENT_INTERFACE: &str = "org.bluez.LEAdvertisement1";
+
+/// Used to give each advertisement a distinct object path.
+static NEXT_ADVERTISEMENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A registered advertisement, which BlueZ keeps broadcasting until it is dropped.
+#[derive(Debug)]
+pub struct RegisteredAdvertisement {
+    objects: ExportedObjects,
+    path: Path<'static>,
+    advertisement: Arc<Mutex<Advertisement>>,
+}
+
+impl RegisteredAdvertisement {
+    /// Changes the services the advertisement lists. BlueZ rebuilds the advertising data when
+    /// told that the property has changed, so the advertisement needn't be registered again.
+    pub fn set_services(&self, services: Vec<Uuid>) -> Result<(), dbus::Error> {
+        let uuids: Vec<String> = services.iter().map(|uuid| uuid.to_string()).collect();
+        self.advertisement.lock().unwrap().services = services;
+        let mut changed = PropMap::new();
+        changed.insert("ServiceUUIDs".to_string(), variant(uuids));
+        self.objects
+            .properties_changed(self.path.clone(), ADVERTISEMENT_INTERFACE, changed)
+    }
+}
+
+/// Registers the given advertisement on the adapter with the given object path, and waits for
+/// BlueZ to start broadcasting it.
+pub fn start(
+    adapter: Path<'static>,
+    advertisement: Advertisement,
+) -> Result<RegisteredAdvertisement, dbus::Error> {
+    let path = Path::new(format!(
+        "/btleplug/advertisement{}",
+        NEXT_ADVERTISEMENT_ID.fetch_add(1, Ordering::Relaxed)
+    ))
+    .map_err(failed)?;
+    let register = Message::new_method_call(
+        BLUEZ_SERVICE,
+        adapter,
+        "org.bluez.LEAdvertisingManager1",
+        "RegisterAdvertisement",
+    )
+    .map_err(failed)?
+    .append2(path.clone(), PropMap::new());
+    let advertisement = Arc::new(Mutex::new(advertisement));
+    // BlueZ reads the advertisement through an object manager at its own path.
+    let objects = ExportedObjects::register(
+        path.clone(),
+        register,
+        AdvertisementTree {
+            path: path.clone(),
+            advertisement: advertisement.clone(),
+        },
+    )?;
+    Ok(RegisteredAdvertisement {
+        objects,
+        path,
+        advertisement,
+    })
+}
+
+struct AdvertisementTree {
+    path: Path<'static>,
+    advertisement: Arc<Mutex<Advertisement>>,
+}
+
+impl ObjectTree for AdvertisementTree {
+    fn objects(&self) -> HashMap<Path<'static>, Interfaces> {
+        let advertisement = self.advertisement.lock().unwrap();
+        let mut properties = PropMap::new();
+        // Connectable, so that a GATT server can be connected to through it.
+        properties.insert("Type".to_string(), variant("peripheral".to_string()));
+        if let Some(local_name) = &advertisement.local_name {
+            properties.insert("LocalName".to_string(), variant(local_name.clone()));
+        }
+        if !advertisement.services.is_empty() {
+            let services: Vec<String> = advertisement
+                .services
+                .iter()
+                .map(|uuid| uuid.to_string())
+                .collect();
+            properties.insert("ServiceUUIDs".to_string(), variant(services));
+        }
+        if !advertisement.manufacturer_data.is_empty() {
+            let manufacturer_data: HashMap<u16, Variant<Box<dyn RefArg>>> = advertisement
+                .manufacturer_data
+                .iter()
+                .map(|(&id, data)| (id, variant(data.clone())))
+                .collect();
+            properties.insert("ManufacturerData".to_string(), variant(manufacturer_data));
+        }
+        let mut interfaces = Interfaces::new();
+        interfaces.insert(ADVERTISEMENT_INTERFACE.to_string(), properties);
+        let mut objects = HashMap::new();
+        objects.insert(self.path.clone(), interfaces);
+        objects
+    }
+
+    fn call(&mut self, message: &Message) -> Option<Message> {
+        match (message.interface().as_deref(), message.member().as_deref()) {
+            (Some(ADVERTISEMENT_INTERFACE), Some("Release")) => {
+                // BlueZ has stopped broadcasting the advertisement, such as because the adapter
+                // was powered off.
+                debug!("Advertisement released");
+                Some(message.method_return())
+            }
+            _ => None,
+        }
+    }
+}
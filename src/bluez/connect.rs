@@ -0,0 +1,69 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Connecting to devices BlueZ hasn't seen yet with `org.bluez.Adapter1.ConnectDevice`, which
+//! takes the address type rather than guessing it.
+
+use super::{error, with_connection, BLUEZ_SERVICE};
+use crate::{
+    api::{AddressType, BDAddr},
+    Error, Result,
+};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::SyncConnection;
+use dbus::strings::Path;
+use std::time::Duration;
+
+/// How long to wait for BlueZ to find the device and connect to it.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Creates a device with the given address and address type on the adapter with the given object
+/// path, and connects to it.
+pub async fn connect_device(
+    adapter: Path<'static>,
+    address: BDAddr,
+    address_type: AddressType,
+) -> Result<()> {
+    let result = with_connection(move |connection| {
+        connect_device_blocking(connection, adapter, address, address_type)
+    })
+    .await?;
+    result.map_err(|e| match e.name() {
+        Some("org.freedesktop.DBus.Error.NoReply") => Error::TimedOut(CONNECT_TIMEOUT),
+        // ConnectDevice is marked experimental, so is only available if bluetoothd is run with
+        // `--experimental`.
+        Some("org.freedesktop.DBus.Error.UnknownMethod") => Error::NotSupported(
+            "Connecting to unknown devices needs bluetoothd to be run with --experimental"
+                .to_string(),
+        ),
+        _ => error::from_dbus(e),
+    })
+}
+
+fn connect_device_blocking(
+    connection: &SyncConnection,
+    adapter: Path<'static>,
+    address: BDAddr,
+    address_type: AddressType,
+) -> std::result::Result<(), dbus::Error> {
+    let proxy = connection.with_proxy(BLUEZ_SERVICE, adapter, CONNECT_TIMEOUT);
+    let address_type = match address_type {
+        AddressType::Public => "public",
+        AddressType::Random => "random",
+    };
+    let mut properties = PropMap::new();
+    properties.insert(
+        "Address".to_string(),
+        Variant(Box::new(address.to_string()) as Box<dyn RefArg>),
+    );
+    properties.insert(
+        "AddressType".to_string(),
+        Variant(Box::new(address_type.to_string()) as Box<dyn RefArg>),
+    );
+    // This returns the new device's object path, but the caller finds it by address anyway.
+    proxy.method_call("org.bluez.Adapter1", "ConnectDevice", (properties,))
+}
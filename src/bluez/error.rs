@@ -0,0 +1,89 @@
+//! Maps the errors which BlueZ reports over D-Bus to [`Error`], keeping the underlying errno.
+
+use crate::{AttError, Error, ErrorCode};
+
+const ATT_ERROR_PREFIX: &str = "Operation failed with ATT error: 0x";
+
+/// Converts an error returned by BlueZ into the most specific [`Error`] which it corresponds to.
+///
+/// BlueZ reports most failures as `org.bluez.Error.Failed`, with a message which is either the
+/// `strerror` text of the underlying errno, or on newer versions a name for the reason a
+/// connection failed. Both are mapped back to the errno.
+pub fn from_dbus(error: dbus::Error) -> Error {
+    let message = error.message().unwrap_or_default();
+    if let Some(code) = message.strip_prefix(ATT_ERROR_PREFIX) {
+        if let Ok(code) = u8::from_str_radix(code.trim(), 16) {
+            return Error::Att(AttError(code));
+        }
+    }
+    let errno = match error.name() {
+        Some("org.bluez.Error.NotConnected") => return Error::NotConnected,
+        Some("org.bluez.Error.DoesNotExist") => return Error::DeviceNotFound,
+        // BlueZ reports all of the ATT errors which call for a more secure link as this.
+        Some("org.bluez.Error.NotAuthorized") => {
+            return Error::Att(AttError::INSUFFICIENT_AUTHENTICATION)
+        }
+        Some("org.bluez.Error.NotPermitted") if message.starts_with("Read") => {
+            return Error::Att(AttError::READ_NOT_PERMITTED)
+        }
+        Some("org.bluez.Error.NotPermitted") if message.starts_with("Write") => {
+            return Error::Att(AttError::WRITE_NOT_PERMITTED)
+        }
+        Some("org.bluez.Error.AuthenticationFailed") => Some(libc::EACCES),
+        Some("org.bluez.Error.AuthenticationTimeout") => Some(libc::ETIMEDOUT),
+        Some("org.bluez.Error.ConnectionAttemptFailed") => Some(libc::EHOSTUNREACH),
+        _ => errno_from_message(message),
+    };
+    match errno {
+        Some(errno) => from_errno(errno, message),
+        None => Error::Other(Box::new(error)),
+    }
+}
+
+/// Converts an errno from BlueZ or the kernel into the most specific [`Error`] which it
+/// corresponds to.
+pub fn from_errno(errno: i32, message: &str) -> Error {
+    let code = ErrorCode::Errno(errno);
+    match errno {
+        libc::ETIMEDOUT => Error::ConnectionTimeout(code),
+        libc::EHOSTDOWN | libc::EHOSTUNREACH | libc::ECONNREFUSED | libc::ECONNABORTED => {
+            Error::DeviceUnreachable(code)
+        }
+        libc::EACCES => Error::InsufficientAuthentication(code),
+        _ => Error::Platform {
+            code,
+            message: message.to_string(),
+        },
+    }
+}
+
+fn errno_from_message(message: &str) -> Option<i32> {
+    let reason = message
+        .strip_prefix("le-connection-")
+        .or_else(|| message.strip_prefix("br-connection-"));
+    if let Some(reason) = reason {
+        return match reason {
+            "page-timeout" => Some(libc::EHOSTDOWN),
+            "timeout" => Some(libc::ETIMEDOUT),
+            "refused" => Some(libc::ECONNREFUSED),
+            "abort-by-local" => Some(libc::ECONNABORTED),
+            "abort-by-remote" => Some(libc::ECONNRESET),
+            "key-missing" => Some(libc::EACCES),
+            "already-connected" => Some(libc::EALREADY),
+            _ => None,
+        };
+    }
+    match message {
+        "Connection timed out" => Some(libc::ETIMEDOUT),
+        "Host is down" => Some(libc::EHOSTDOWN),
+        "No route to host" => Some(libc::EHOSTUNREACH),
+        "Connection refused" => Some(libc::ECONNREFUSED),
+        "Software caused connection abort" => Some(libc::ECONNABORTED),
+        "Connection reset by peer" => Some(libc::ECONNRESET),
+        "Permission denied" => Some(libc::EACCES),
+        "Operation already in progress" => Some(libc::EALREADY),
+        "Device or resource busy" => Some(libc::EBUSY),
+        "Input/output error" => Some(libc::EIO),
+        _ => None,
+    }
+}
@@ -0,0 +1,250 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Removing devices which have stopped advertising, or which are over the limit on peripherals,
+//! from BlueZ with `org.bluez.Adapter1.RemoveDevice`. BlueZ forgets such devices itself
+//! eventually, but doesn't say when, so they're removed here instead and a `DeviceLost` event is
+//! sent to each event stream. Paired devices are never removed, as that would delete their keys.
+
+use super::{error, with_connection, BLUEZ_SERVICE};
+use crate::api::{BDAddr, CentralEvent, DisconnectReason};
+use crate::common::{
+    last_seen::{LastSeenTracker, EXPIRY_CHECK_INTERVAL},
+    runtime, util,
+};
+use crate::Result;
+use bluez_async::{AdapterId, BluetoothEvent, BluetoothSession, DeviceEvent, DeviceId};
+use dbus::strings::Path;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::stream::{self, StreamExt};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long to wait for BlueZ to remove a device.
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct Settings {
+    ttl: Option<Duration>,
+    max: Option<usize>,
+    /// Whether the task which removes devices is running.
+    running: bool,
+}
+
+/// When to remove devices from an adapter, and the event streams to tell about them.
+#[derive(Clone, Debug, Default)]
+pub struct PeripheralExpiry {
+    settings: Arc<Mutex<Settings>>,
+    lost_senders: Arc<Mutex<Vec<UnboundedSender<CentralEvent>>>>,
+}
+
+impl PeripheralExpiry {
+    /// Sets how long a device may go without advertising before it is removed, or `None` to
+    /// leave that to BlueZ.
+    pub fn set_ttl(&self, session: &BluetoothSession, adapter: &AdapterId, ttl: Option<Duration>) {
+        self.settings.lock().unwrap().ttl = ttl;
+        self.start(session, adapter);
+    }
+
+    /// Sets how many devices may be kept at once, or `None` for any number.
+    pub fn set_max(&self, session: &BluetoothSession, adapter: &AdapterId, max: Option<usize>) {
+        self.settings.lock().unwrap().max = max;
+        self.start(session, adapter);
+    }
+
+    /// Returns a stream of `DeviceLost` events for the devices removed from now on.
+    pub fn lost_events(&self) -> UnboundedReceiver<CentralEvent> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.lost_senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Starts the task which removes devices, if either limit is set and it isn't running yet.
+    /// It stops by itself once neither is set.
+    fn start(&self, session: &BluetoothSession, adapter: &AdapterId) {
+        let mut settings = self.settings.lock().unwrap();
+        if (settings.ttl.is_some() || settings.max.is_some()) && !settings.running {
+            settings.running = true;
+            let expiry = self.clone();
+            let session = session.clone();
+            let adapter = adapter.clone();
+            runtime::spawn(async move { expiry.run(session, adapter).await });
+        }
+    }
+
+    async fn run(self, session: BluetoothSession, adapter: AdapterId) {
+        let events = match session.event_stream().await {
+            Ok(events) => events,
+            Err(e) => {
+                warn!("Failed to watch for devices to remove: {}", e);
+                self.settings.lock().unwrap().running = false;
+                return;
+            }
+        };
+        let mut devices = KnownDevices::default();
+        // Devices BlueZ already knows about count as just seen.
+        if let Ok(known) = session.get_devices().await {
+            for device in known
+                .into_iter()
+                .filter(|device| device.id.adapter() == adapter)
+            {
+                let connected = if device.connected { Some(true) } else { None };
+                if let Some(address) = devices.observe(device.id, connected) {
+                    devices.last_seen.set_paired(address, device.paired);
+                }
+            }
+        }
+
+        let checks = stream::unfold((), |()| async {
+            runtime::sleep(EXPIRY_CHECK_INTERVAL).await;
+            Some((None, ()))
+        });
+        let mut inputs = Box::pin(stream::select(events.map(Some), checks));
+        while let Some(input) = inputs.next().await {
+            let (ttl, max) = {
+                let mut settings = self.settings.lock().unwrap();
+                if settings.ttl.is_none() && settings.max.is_none() {
+                    settings.running = false;
+                    return;
+                }
+                (settings.ttl, settings.max)
+            };
+            match input {
+                Some(BluetoothEvent::Device { id, event }) if id.adapter() == adapter => {
+                    let connected = match event {
+                        DeviceEvent::Connected { connected } => Some(connected),
+                        _ => None,
+                    };
+                    let address = match devices.observe(id, connected) {
+                        Some(address) => address,
+                        None => continue,
+                    };
+                    if let Some(max) = max {
+                        while devices.len() > max {
+                            let oldest = match devices.least_recently_seen(address) {
+                                Some(oldest) => oldest,
+                                // Everything else is connected or paired, so the limit stays
+                                // exceeded until some of them disconnect or are removed.
+                                None => break,
+                            };
+                            // BlueZ failed to remove it, and would only fail again straight away.
+                            if !self.remove(&session, &mut devices, oldest).await {
+                                break;
+                            }
+                        }
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    if let Some(ttl) = ttl {
+                        for address in devices.last_seen.expire(ttl) {
+                            self.remove(&session, &mut devices, address).await;
+                        }
+                    }
+                }
+            }
+        }
+        self.settings.lock().unwrap().running = false;
+    }
+
+    /// Removes the device with the given address from BlueZ, unless it is paired or connected, and
+    /// tells the event streams. Devices which are paired or connected are still known, and so
+    /// still count towards the limit, but aren't picked for removal again. Returns false if BlueZ
+    /// failed to remove the device.
+    async fn remove(
+        &self,
+        session: &BluetoothSession,
+        devices: &mut KnownDevices,
+        address: BDAddr,
+    ) -> bool {
+        let id = match devices.ids.get(&address) {
+            Some(id) => id.clone(),
+            None => return false,
+        };
+        match session.get_device_info(&id).await {
+            Ok(device) if device.paired || device.connected => {
+                devices.last_seen.set_paired(address, device.paired);
+                if device.connected {
+                    devices.observe(id, Some(true));
+                }
+                return true;
+            }
+            Ok(_) => {
+                let adapter: Path<'static> = id.adapter().into();
+                if let Err(e) = remove_device(adapter, id.into()).await {
+                    warn!("Failed to remove device {}: {}", address, e);
+                    return false;
+                }
+            }
+            // BlueZ has removed the device already.
+            Err(_) => {}
+        }
+        devices.forget(&address);
+        debug!("Removed device {}", address);
+        util::send_notification(&self.lost_senders, &CentralEvent::DeviceLost(address));
+        true
+    }
+}
+
+/// The devices which may be removed, and when each was last heard from.
+#[derive(Debug, Default)]
+struct KnownDevices {
+    last_seen: LastSeenTracker,
+    ids: HashMap<BDAddr, DeviceId>,
+}
+
+impl KnownDevices {
+    /// Notes that the given device was just heard from, or connected or disconnected, and returns
+    /// its address.
+    fn observe(&mut self, id: DeviceId, connected: Option<bool>) -> Option<BDAddr> {
+        let address = device_address(&id)?;
+        let event = match connected {
+            Some(true) => CentralEvent::DeviceConnected(address),
+            Some(false) => CentralEvent::DeviceDisconnected(address, DisconnectReason::Unknown),
+            None => CentralEvent::DeviceDiscovered(address),
+        };
+        self.last_seen.observe(&event);
+        self.ids.insert(address, id);
+        Some(address)
+    }
+
+    fn forget(&mut self, address: &BDAddr) {
+        self.last_seen.forget(address);
+        self.ids.remove(address);
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns whichever device other than `added` was heard from least recently, other than
+    /// those which are connected or paired.
+    fn least_recently_seen(&self, added: BDAddr) -> Option<BDAddr> {
+        self.last_seen
+            .least_recently_seen(self.ids.keys().copied().filter(|address| *address != added))
+    }
+}
+
+/// Returns the address of a device from its object path, which BlueZ documents as ending in
+/// `dev_XX_XX_XX_XX_XX_XX`.
+fn device_address(id: &DeviceId) -> Option<BDAddr> {
+    let path: Path<'static> = id.clone().into();
+    let name = path.rsplit('/').next()?.strip_prefix("dev_")?;
+    name.replace('_', ":").parse().ok()
+}
+
+async fn remove_device(adapter: Path<'static>, device: Path<'static>) -> Result<()> {
+    let result = with_connection(move |connection| {
+        connection
+            .with_proxy(BLUEZ_SERVICE, adapter, REMOVE_TIMEOUT)
+            .method_call("org.bluez.Adapter1", "RemoveDevice", (device,))
+    })
+    .await?;
+    result.map_err(error::from_dbus)
+}
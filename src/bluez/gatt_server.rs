@@ -0,0 +1,306 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hosting GATT services by registering an application with BlueZ's `org.bluez.GattManager1`. The
+//! application is an object manager with an `org.bluez.GattService1` object for the service, and
+//! an `org.bluez.GattCharacteristic1` object under it for each of its characteristics. BlueZ
+//! calls these back when remote centrals read, write or subscribe, and notifies subscribers when
+//! the `Value` property of a characteristic changes.
+
+use super::objects::{variant, ExportedObjects, Interfaces, ObjectTree};
+use super::{failed, BLUEZ_SERVICE};
+use crate::{
+    api::{CharPropFlags, GattServerEvent, LocalService},
+    common::util,
+    Error, Result,
+};
+use dbus::arg::{prop_cast, PropMap};
+use dbus::message::Message;
+use dbus::strings::{ErrorName, Path};
+use futures::channel::mpsc::UnboundedSender;
+use log::trace;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+const SERVICE_INTERFACE: &str = "org.bluez.GattService1";
+const CHARACTERISTIC_INTERFACE: &str = "org.bluez.GattCharacteristic1";
+
+/// Used to give each application a distinct object path.
+static NEXT_APPLICATION_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub type ServerEventSenders = Arc<Mutex<Vec<UnboundedSender<GattServerEvent>>>>;
+
+/// A characteristic of a hosted service, and its current value.
+#[derive(Debug, Clone)]
+struct HostedCharacteristic {
+    uuid: Uuid,
+    path: Path<'static>,
+    properties: CharPropFlags,
+    value: Arc<Mutex<Vec<u8>>>,
+}
+
+/// A service registered with BlueZ, which remote centrals can use until it is dropped.
+#[derive(Debug)]
+pub struct GattApplication {
+    objects: ExportedObjects,
+    characteristics: Vec<HostedCharacteristic>,
+}
+
+impl GattApplication {
+    /// Registers an application hosting the given service on the adapter with the given object
+    /// path, and waits for BlueZ to add it to the local GATT database.
+    pub fn register(
+        adapter: Path<'static>,
+        service: &LocalService,
+        event_senders: ServerEventSenders,
+    ) -> std::result::Result<Self, dbus::Error> {
+        let id = NEXT_APPLICATION_ID.fetch_add(1, Ordering::Relaxed);
+        let root = Path::new(format!("/btleplug/gatt{}", id)).map_err(failed)?;
+        let service_path = Path::new(format!("{}/service0", root)).map_err(failed)?;
+        let characteristics = service
+            .characteristics
+            .iter()
+            .enumerate()
+            .map(|(index, characteristic)| {
+                Ok(HostedCharacteristic {
+                    uuid: characteristic.uuid,
+                    path: Path::new(format!("{}/char{}", service_path, index)).map_err(failed)?,
+                    properties: characteristic.properties,
+                    value: Arc::new(Mutex::new(characteristic.value.clone())),
+                })
+            })
+            .collect::<std::result::Result<Vec<_>, dbus::Error>>()?;
+
+        let register = Message::new_method_call(
+            BLUEZ_SERVICE,
+            adapter,
+            "org.bluez.GattManager1",
+            "RegisterApplication",
+        )
+        .map_err(failed)?
+        .append2(root.clone(), PropMap::new());
+        let tree = ApplicationTree {
+            service: service.uuid,
+            primary: service.primary,
+            service_path,
+            characteristics: characteristics.clone(),
+            event_senders,
+        };
+        let objects = ExportedObjects::register(root, register, tree)?;
+        Ok(GattApplication {
+            objects,
+            characteristics,
+        })
+    }
+
+    fn characteristic(&self, uuid: Uuid) -> Result<&HostedCharacteristic> {
+        self.characteristics
+            .iter()
+            .find(|characteristic| characteristic.uuid == uuid)
+            .ok_or_else(|| {
+                Error::Other(format!("Characteristic with UUID {} not found.", uuid).into())
+            })
+    }
+
+    /// Sets the value of a characteristic, which is returned when remote centrals read it.
+    pub fn set_value(&self, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        *self.characteristic(characteristic)?.value.lock().unwrap() = value.to_vec();
+        Ok(())
+    }
+
+    /// Tells BlueZ that the value of a characteristic has changed, so that it notifies subscribed
+    /// centrals. This blocks until the change has been sent.
+    pub fn value_changed(&self, characteristic: Uuid) -> std::result::Result<(), dbus::Error> {
+        let characteristic = self
+            .characteristics
+            .iter()
+            .find(|hosted| hosted.uuid == characteristic)
+            .ok_or_else(|| {
+                failed(format!(
+                    "Characteristic with UUID {} not found.",
+                    characteristic
+                ))
+            })?;
+        let mut changed = PropMap::new();
+        changed.insert(
+            "Value".to_string(),
+            variant(characteristic.value.lock().unwrap().clone()),
+        );
+        self.objects.properties_changed(
+            characteristic.path.clone(),
+            CHARACTERISTIC_INTERFACE,
+            changed,
+        )
+    }
+}
+
+/// The objects of an application hosting one service.
+struct ApplicationTree {
+    service: Uuid,
+    primary: bool,
+    service_path: Path<'static>,
+    characteristics: Vec<HostedCharacteristic>,
+    event_senders: ServerEventSenders,
+}
+
+impl ApplicationTree {
+    fn send_event(&self, event: GattServerEvent) {
+        util::send_notification(&self.event_senders, &event);
+    }
+
+    /// Answers a call to a method of `org.bluez.GattCharacteristic1`.
+    fn characteristic_reply(
+        &self,
+        message: &Message,
+        method: &str,
+        characteristic: &HostedCharacteristic,
+    ) -> Option<Message> {
+        let service = self.service;
+        let uuid = characteristic.uuid;
+        match method {
+            "ReadValue" => {
+                let options: PropMap = message.read1().ok()?;
+                let offset = offset(&options);
+                let value = characteristic.value.lock().unwrap().clone();
+                if offset > value.len() {
+                    return Some(invalid_offset(message));
+                }
+                // Long values are read in several parts, which are reported as one read.
+                if offset == 0 {
+                    self.send_event(GattServerEvent::CharacteristicRead {
+                        service,
+                        characteristic: uuid,
+                    });
+                }
+                Some(message.method_return().append1(value[offset..].to_vec()))
+            }
+            "WriteValue" => {
+                let (data, options): (Vec<u8>, PropMap) = message.read2().ok()?;
+                let offset = offset(&options);
+                let value = {
+                    let mut value = characteristic.value.lock().unwrap();
+                    if offset > value.len() {
+                        return Some(invalid_offset(message));
+                    }
+                    value.truncate(offset);
+                    value.extend_from_slice(&data);
+                    value.clone()
+                };
+                trace!("local characteristic {} written {:?}", uuid, value);
+                self.send_event(GattServerEvent::CharacteristicWritten {
+                    service,
+                    characteristic: uuid,
+                    value,
+                });
+                Some(message.method_return())
+            }
+            "StartNotify" => {
+                self.send_event(GattServerEvent::Subscribed {
+                    service,
+                    characteristic: uuid,
+                });
+                Some(message.method_return())
+            }
+            "StopNotify" => {
+                self.send_event(GattServerEvent::Unsubscribed {
+                    service,
+                    characteristic: uuid,
+                });
+                Some(message.method_return())
+            }
+            // A remote central confirmed an indication.
+            "Confirm" => Some(message.method_return()),
+            _ => None,
+        }
+    }
+}
+
+impl ObjectTree for ApplicationTree {
+    fn objects(&self) -> HashMap<Path<'static>, Interfaces> {
+        let mut objects = HashMap::new();
+
+        let mut properties = PropMap::new();
+        properties.insert("UUID".to_string(), variant(self.service.to_string()));
+        properties.insert("Primary".to_string(), variant(self.primary));
+        let mut interfaces = Interfaces::new();
+        interfaces.insert(SERVICE_INTERFACE.to_string(), properties);
+        objects.insert(self.service_path.clone(), interfaces);
+
+        for characteristic in &self.characteristics {
+            let mut properties = PropMap::new();
+            properties.insert("UUID".to_string(), variant(characteristic.uuid.to_string()));
+            properties.insert("Service".to_string(), variant(self.service_path.clone()));
+            properties.insert(
+                "Flags".to_string(),
+                variant(flags(characteristic.properties)),
+            );
+            properties.insert(
+                "Value".to_string(),
+                variant(characteristic.value.lock().unwrap().clone()),
+            );
+            let mut interfaces = Interfaces::new();
+            interfaces.insert(CHARACTERISTIC_INTERFACE.to_string(), properties);
+            objects.insert(characteristic.path.clone(), interfaces);
+        }
+        objects
+    }
+
+    fn call(&mut self, message: &Message) -> Option<Message> {
+        let path = message.path()?;
+        let member = message.member()?;
+        if message.interface().as_deref() != Some(CHARACTERISTIC_INTERFACE) {
+            return None;
+        }
+        let characteristic = self
+            .characteristics
+            .iter()
+            .find(|characteristic| characteristic.path == path)?;
+        self.characteristic_reply(message, &member, characteristic)
+    }
+}
+
+/// Returns the offset into the value given in the options of a read or write, for long values.
+fn offset(options: &PropMap) -> usize {
+    prop_cast::<u16>(options, "offset").map_or(0, |&offset| offset.into())
+}
+
+fn invalid_offset(message: &Message) -> Message {
+    message.error(
+        &ErrorName::new("org.bluez.Error.InvalidOffset").unwrap(),
+        &CString::new("Invalid offset").unwrap(),
+    )
+}
+
+/// Converts characteristic properties to the flags of `org.bluez.GattCharacteristic1`.
+fn flags(properties: CharPropFlags) -> Vec<String> {
+    let names = [
+        (CharPropFlags::BROADCAST, "broadcast"),
+        (CharPropFlags::READ, "read"),
+        (
+            CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            "write-without-response",
+        ),
+        (CharPropFlags::WRITE, "write"),
+        (CharPropFlags::NOTIFY, "notify"),
+        (CharPropFlags::INDICATE, "indicate"),
+        (
+            CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+            "authenticated-signed-writes",
+        ),
+        (CharPropFlags::EXTENDED_PROPERTIES, "extended-properties"),
+        (CharPropFlags::RELIABLE_WRITE, "reliable-write"),
+        (CharPropFlags::WRITABLE_AUXILIARIES, "writable-auxiliaries"),
+    ];
+    names
+        .iter()
+        .filter(|(flag, _)| properties.contains(*flag))
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
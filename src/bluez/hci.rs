@@ -0,0 +1,551 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Sending raw HCI commands to a controller, bypassing BlueZ. This needs `CAP_NET_RAW` (or root),
+//! and BlueZ isn't told about anything the command changes, so it's only for settings which the
+//! rest of the API doesn't cover. The kernel's own settings are changed with management commands
+//! instead, which need `CAP_NET_ADMIN`.
+
+use crate::{api::BDAddr, Error, Result};
+use dbus::strings::Path;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+const AF_BLUETOOTH: libc::c_int = 31;
+const BTPROTO_HCI: libc::c_int = 1;
+const SOL_HCI: libc::c_int = 0;
+const HCI_FILTER: libc::c_int = 2;
+const HCI_CHANNEL_RAW: u16 = 0;
+const HCI_CHANNEL_CONTROL: u16 = 3;
+const HCI_DEV_NONE: u16 = 0xffff;
+
+const HCI_COMMAND_PKT: u8 = 0x01;
+const HCI_EVENT_PKT: u8 = 0x04;
+const EVT_CMD_COMPLETE: u8 = 0x0e;
+const EVT_CMD_STATUS: u8 = 0x0f;
+const EVT_LE_META: u8 = 0x3e;
+
+const OGF_STATUS_PARAMS: u8 = 0x05;
+const OCF_READ_RSSI: u16 = 0x0005;
+const OGF_LE_CTL: u8 = 0x08;
+const OCF_LE_READ_PHY: u16 = 0x0030;
+const OCF_LE_SET_PHY: u16 = 0x0032;
+const OCF_LE_PERIODIC_ADV_CREATE_SYNC: u16 = 0x0044;
+const OCF_LE_PERIODIC_ADV_CREATE_SYNC_CANCEL: u16 = 0x0045;
+const OCF_LE_PERIODIC_ADV_TERMINATE_SYNC: u16 = 0x0046;
+
+const MGMT_EV_CMD_COMPLETE: u16 = 0x0001;
+const MGMT_EV_CMD_STATUS: u16 = 0x0002;
+const MGMT_OP_SET_DEF_SYSTEM_CONFIG: u16 = 0x004c;
+const MGMT_STATUS_UNKNOWN_COMMAND: u8 = 0x01;
+const MGMT_STATUS_NOT_SUPPORTED: u8 = 0x0c;
+const MGMT_STATUS_PERMISSION_DENIED: u8 = 0x14;
+/// The types of the default system configuration parameters for the scan interval and window the
+/// kernel uses for discovery.
+const CONFIG_LE_SCAN_INTERVAL_DISCOVERY: u16 = 0x0011;
+const CONFIG_LE_SCAN_WINDOW_DISCOVERY: u16 = 0x0012;
+
+/// `_IOR('H', 213, int)`, which looks up a connection of the controller the socket is bound to.
+const HCIGETCONNINFO: libc::c_ulong = 0x800448d5;
+const LE_LINK: u8 = 0x80;
+
+/// How long to wait for the controller to respond to a command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[repr(C)]
+struct SockaddrHci {
+    hci_family: libc::sa_family_t,
+    hci_dev: u16,
+    hci_channel: u16,
+}
+
+#[repr(C)]
+struct HciFilter {
+    type_mask: u32,
+    event_mask: [u32; 2],
+    opcode: u16,
+}
+
+/// `struct hci_conn_info_req`, followed by the `struct hci_conn_info` the kernel fills in.
+#[repr(C)]
+#[derive(Default)]
+struct HciConnInfoRequest {
+    bdaddr: [u8; 6],
+    link_type: u8,
+    info: HciConnInfo,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct HciConnInfo {
+    handle: u16,
+    bdaddr: [u8; 6],
+    link_type: u8,
+    out: u8,
+    state: u16,
+    link_mode: u32,
+}
+
+/// A raw HCI socket bound to one controller, closed when dropped.
+struct HciSocket(RawFd);
+
+impl Drop for HciSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// Returns the index of the controller (as in `hci0`) behind the adapter with the given object
+/// path, or the adapter of the device with the given object path.
+pub fn dev_id(path: &Path) -> Result<u16> {
+    path.split('/')
+        .find_map(|name| name.strip_prefix("hci")?.parse().ok())
+        .ok_or_else(|| Error::Other(format!("Can't find the adapter index in {}", path).into()))
+}
+
+/// Sends a command to the controller with the given index (as in `hci0`), and returns the
+/// parameters of the Command Complete event in response, starting with the status. This blocks
+/// until the controller responds.
+pub fn hci_command(dev_id: u16, ogf: u8, ocf: u16, params: &[u8]) -> io::Result<Vec<u8>> {
+    send_command(dev_id, ogf, ocf, params, true)
+}
+
+/// Sends a command, and waits for the Command Complete event in response if `wait_for_complete`,
+/// or otherwise only for the controller to accept it with a successful Command Status event, for
+/// commands which report their result with an event of their own.
+fn send_command(
+    dev_id: u16,
+    ogf: u8,
+    ocf: u16,
+    params: &[u8],
+    wait_for_complete: bool,
+) -> io::Result<Vec<u8>> {
+    if ogf > 0x3f || ocf > 0x3ff {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid HCI opcode OGF {:#x} OCF {:#x}", ogf, ocf),
+        ));
+    }
+    if params.len() > u8::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "HCI command parameters are too long",
+        ));
+    }
+    let opcode = (ogf as u16) << 10 | ocf;
+    let socket = open_socket(dev_id, opcode)?;
+
+    let mut command = vec![HCI_COMMAND_PKT, opcode as u8, (opcode >> 8) as u8];
+    command.push(params.len() as u8);
+    command.extend_from_slice(params);
+    let written = unsafe { libc::write(socket.0, command.as_ptr() as *const _, command.len()) };
+    if written < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut buffer = [0u8; 260];
+    loop {
+        let length = unsafe { libc::read(socket.0, buffer.as_mut_ptr() as *mut _, buffer.len()) };
+        if length < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let event = &buffer[..length as usize];
+        // Packet type, event code, parameter length, then the parameters.
+        if event.len() < 3 || event[0] != HCI_EVENT_PKT {
+            continue;
+        }
+        let event_params = &event[3..];
+        match event[1] {
+            // Number of allowed commands, opcode, return parameters.
+            EVT_CMD_COMPLETE
+                if event_params.len() >= 3
+                    && u16::from_le_bytes([event_params[1], event_params[2]]) == opcode =>
+            {
+                return Ok(event_params[3..].to_vec());
+            }
+            // Status, number of allowed commands, opcode. Commands which succeed report completion
+            // with a separate event, but a failure ends here.
+            EVT_CMD_STATUS
+                if event_params.len() >= 4
+                    && u16::from_le_bytes([event_params[2], event_params[3]]) == opcode =>
+            {
+                if event_params[0] != 0 {
+                    return Err(status_error(event_params[0]));
+                }
+                if !wait_for_complete {
+                    return Ok(vec![event_params[0]]);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sends a command about the LE connection to the device with the given address, and returns the
+/// return parameters after the status and connection handle.
+fn connection_command(
+    dev_id: u16,
+    address: BDAddr,
+    ogf: u8,
+    ocf: u16,
+    params: &[u8],
+    wait_for_complete: bool,
+) -> io::Result<Vec<u8>> {
+    let handle = connection_handle(dev_id, address)?;
+    let mut command = handle.to_le_bytes().to_vec();
+    command.extend_from_slice(params);
+    let result = send_command(dev_id, ogf, ocf, &command, wait_for_complete)?;
+    match result.split_first() {
+        Some((0, rest)) => Ok(rest.get(2..).unwrap_or_default().to_vec()),
+        Some((&status, _)) => Err(status_error(status)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "HCI command returned no status",
+        )),
+    }
+}
+
+/// Reads the RSSI of the LE connection to the device with the given address, in dBm.
+pub fn read_rssi(dev_id: u16, address: BDAddr) -> io::Result<i8> {
+    let result = connection_command(dev_id, address, OGF_STATUS_PARAMS, OCF_READ_RSSI, &[], true)?;
+    result
+        .first()
+        .map(|&rssi| rssi as i8)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Read RSSI returned no RSSI"))
+}
+
+/// Reads the transmitter and receiver PHYs of the LE connection to the device with the given
+/// address, as the PHY numbers of the LE Read PHY command (1M, 2M or Coded, from 1).
+pub fn read_phy(dev_id: u16, address: BDAddr) -> io::Result<(u8, u8)> {
+    let result = connection_command(dev_id, address, OGF_LE_CTL, OCF_LE_READ_PHY, &[], true)?;
+    match result[..] {
+        [tx, rx, ..] => Ok((tx, rx)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "LE Read PHY returned no PHYs",
+        )),
+    }
+}
+
+/// Asks for the LE connection to the device with the given address to use the given PHYs, as bit
+/// masks of acceptable PHYs (1M, 2M and Coded, from bit 0). The controller negotiates the change
+/// with the device after this returns.
+pub fn set_phy(dev_id: u16, address: BDAddr, tx_phys: u8, rx_phys: u8) -> io::Result<()> {
+    // No preference for all PHYs is left unset, and no preferred coding for the coded PHY.
+    let params = [0, tx_phys, rx_phys, 0, 0];
+    connection_command(dev_id, address, OGF_LE_CTL, OCF_LE_SET_PHY, &params, false)?;
+    Ok(())
+}
+
+/// Asks the controller with the given index to synchronize to the periodic advertising train with
+/// the given advertising set ID from the device with the given address, giving up on the train if
+/// nothing is received from it for `timeout`. The controller reports the outcome later, with an LE
+/// Periodic Advertising Sync Established event.
+pub fn create_periodic_sync(
+    dev_id: u16,
+    address: BDAddr,
+    random_address: bool,
+    sid: u8,
+    timeout: Duration,
+) -> io::Result<()> {
+    // In units of 10 ms, from 100 ms to 163.84 s.
+    let timeout = (timeout.as_millis() / 10).clamp(0x000a, 0x4000) as u16;
+    let mut params = vec![0, sid, random_address as u8];
+    params.extend_from_slice(&address.to_le_bytes());
+    // Don't skip any events, then the timeout, then no constraint on Constant Tone Extensions.
+    params.extend_from_slice(&0u16.to_le_bytes());
+    params.extend_from_slice(&timeout.to_le_bytes());
+    params.push(0);
+    send_command(
+        dev_id,
+        OGF_LE_CTL,
+        OCF_LE_PERIODIC_ADV_CREATE_SYNC,
+        &params,
+        false,
+    )?;
+    Ok(())
+}
+
+/// Cancels a sync started with `create_periodic_sync` which hasn't been established yet.
+pub fn cancel_periodic_sync(dev_id: u16) -> io::Result<()> {
+    let result = hci_command(
+        dev_id,
+        OGF_LE_CTL,
+        OCF_LE_PERIODIC_ADV_CREATE_SYNC_CANCEL,
+        &[],
+    )?;
+    check_status(&result)
+}
+
+/// Stops the controller from receiving the periodic advertising train with the given sync handle.
+pub fn terminate_periodic_sync(dev_id: u16, sync_handle: u16) -> io::Result<()> {
+    let result = hci_command(
+        dev_id,
+        OGF_LE_CTL,
+        OCF_LE_PERIODIC_ADV_TERMINATE_SYNC,
+        &sync_handle.to_le_bytes(),
+    )?;
+    check_status(&result)
+}
+
+/// A raw socket which receives the LE Meta events from one controller, closed when dropped.
+pub struct LeEventListener(HciSocket);
+
+impl LeEventListener {
+    pub fn open(dev_id: u16) -> io::Result<Self> {
+        let socket = new_socket()?;
+        let filter = HciFilter {
+            type_mask: 1 << HCI_EVENT_PKT,
+            event_mask: [0, 1 << (EVT_LE_META - 32)],
+            opcode: 0,
+        };
+        set_option(&socket, SOL_HCI, HCI_FILTER, &filter)?;
+        bind(&socket, dev_id, HCI_CHANNEL_RAW)?;
+        Ok(Self(socket))
+    }
+
+    /// Waits for the next LE Meta event, and returns its parameters starting with the subevent
+    /// code, or `None` if none arrives within the socket's receive timeout.
+    pub fn next_event(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut buffer = [0u8; 260];
+        loop {
+            let length =
+                unsafe { libc::read(self.0 .0, buffer.as_mut_ptr() as *mut _, buffer.len()) };
+            if length < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(None);
+                }
+                return Err(error);
+            }
+            // Packet type, event code, parameter length, then the parameters.
+            let event = &buffer[..length as usize];
+            if event.len() > 3 && event[0] == HCI_EVENT_PKT && event[1] == EVT_LE_META {
+                return Ok(Some(event[3..].to_vec()));
+            }
+        }
+    }
+}
+
+/// Sets the scan interval and window, in units of 0.625 ms, which the kernel uses when BlueZ starts
+/// discovery on the controller with the given index. LE Set Scan Parameters sent directly would be
+/// overwritten as soon as the kernel next starts scanning, whereas this lasts until `bluetoothd`
+/// sets the default system configuration again when it restarts.
+pub fn set_discovery_scan_parameters(dev_id: u16, interval: u16, window: u16) -> io::Result<()> {
+    let mut params = vec![];
+    // Each parameter is a type, a length and a value.
+    for &(kind, value) in &[
+        (CONFIG_LE_SCAN_INTERVAL_DISCOVERY, interval),
+        (CONFIG_LE_SCAN_WINDOW_DISCOVERY, window),
+    ] {
+        params.extend_from_slice(&kind.to_le_bytes());
+        params.push(2);
+        params.extend_from_slice(&value.to_le_bytes());
+    }
+    mgmt_command(dev_id, MGMT_OP_SET_DEF_SYSTEM_CONFIG, &params)?;
+    Ok(())
+}
+
+/// Sends a management command about the controller with the given index to the kernel, and returns
+/// the return parameters of the Command Complete event in response.
+fn mgmt_command(index: u16, opcode: u16, params: &[u8]) -> io::Result<Vec<u8>> {
+    if params.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Management command parameters are too long",
+        ));
+    }
+    let socket = open_control_socket()?;
+
+    let mut command = opcode.to_le_bytes().to_vec();
+    command.extend_from_slice(&index.to_le_bytes());
+    command.extend_from_slice(&(params.len() as u16).to_le_bytes());
+    command.extend_from_slice(params);
+    let written = unsafe { libc::write(socket.0, command.as_ptr() as *const _, command.len()) };
+    if written < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut buffer = [0u8; 1024];
+    loop {
+        let length = unsafe { libc::read(socket.0, buffer.as_mut_ptr() as *mut _, buffer.len()) };
+        if length < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Event code, controller index, parameter length, then the parameters, which for both
+        // events start with the opcode and status.
+        let event = &buffer[..length as usize];
+        if event.len() < 9 {
+            continue;
+        }
+        let code = u16::from_le_bytes([event[0], event[1]]);
+        let event_index = u16::from_le_bytes([event[2], event[3]]);
+        let event_params = &event[6..];
+        if (code == MGMT_EV_CMD_COMPLETE || code == MGMT_EV_CMD_STATUS)
+            && event_index == index
+            && u16::from_le_bytes([event_params[0], event_params[1]]) == opcode
+        {
+            return match event_params[2] {
+                0 => Ok(event_params[3..].to_vec()),
+                status => Err(mgmt_status_error(status)),
+            };
+        }
+    }
+}
+
+/// Returns the handle of the LE connection to the device with the given address. Fails with
+/// `ENOENT` if there is no such connection.
+fn connection_handle(dev_id: u16, address: BDAddr) -> io::Result<u16> {
+    let socket = open_socket(dev_id, 0)?;
+    let mut request = HciConnInfoRequest {
+        // Addresses are sent over HCI least significant byte first.
+        bdaddr: address.to_le_bytes(),
+        link_type: LE_LINK,
+        ..Default::default()
+    };
+    let result = unsafe { libc::ioctl(socket.0, HCIGETCONNINFO as _, &mut request) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(request.info.handle)
+}
+
+/// Checks the status at the start of the return parameters of a command.
+fn check_status(result: &[u8]) -> io::Result<()> {
+    match result.first() {
+        Some(0) => Ok(()),
+        Some(&status) => Err(status_error(status)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "HCI command returned no status",
+        )),
+    }
+}
+
+pub fn status_error(status: u8) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("HCI command failed with status {:#04x}", status),
+    )
+}
+
+fn mgmt_status_error(status: u8) -> io::Error {
+    match status {
+        MGMT_STATUS_PERMISSION_DENIED => io::Error::from_raw_os_error(libc::EPERM),
+        // Kernels before 5.8 don't have the command.
+        MGMT_STATUS_UNKNOWN_COMMAND | MGMT_STATUS_NOT_SUPPORTED => {
+            io::Error::from_raw_os_error(libc::EOPNOTSUPP)
+        }
+        _ => io::Error::new(
+            io::ErrorKind::Other,
+            format!("Management command failed with status {:#04x}", status),
+        ),
+    }
+}
+
+/// Opens a raw socket bound to the controller with the given index, which only receives the
+/// events in response to the command with the given opcode.
+fn open_socket(dev_id: u16, opcode: u16) -> io::Result<HciSocket> {
+    let socket = new_socket()?;
+    // Only receive the events which answer the command.
+    let filter = HciFilter {
+        type_mask: 1 << HCI_EVENT_PKT,
+        event_mask: [1 << EVT_CMD_COMPLETE | 1 << EVT_CMD_STATUS, 0],
+        opcode,
+    };
+    set_option(&socket, SOL_HCI, HCI_FILTER, &filter)?;
+    bind(&socket, dev_id, HCI_CHANNEL_RAW)?;
+    Ok(socket)
+}
+
+/// Opens a socket for sending management commands to the kernel.
+fn open_control_socket() -> io::Result<HciSocket> {
+    let socket = new_socket()?;
+    bind(&socket, HCI_DEV_NONE, HCI_CHANNEL_CONTROL)?;
+    Ok(socket)
+}
+
+fn new_socket() -> io::Result<HciSocket> {
+    let fd = unsafe {
+        libc::socket(
+            AF_BLUETOOTH,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            BTPROTO_HCI,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = HciSocket(fd);
+    let timeout = libc::timeval {
+        tv_sec: COMMAND_TIMEOUT.as_secs() as libc::time_t,
+        tv_usec: 0,
+    };
+    set_option(&socket, libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout)?;
+    Ok(socket)
+}
+
+fn bind(socket: &HciSocket, dev_id: u16, channel: u16) -> io::Result<()> {
+    let address = SockaddrHci {
+        hci_family: AF_BLUETOOTH as libc::sa_family_t,
+        hci_dev: dev_id,
+        hci_channel: channel,
+    };
+    let result = unsafe {
+        libc::bind(
+            socket.0,
+            &address as *const SockaddrHci as *const libc::sockaddr,
+            mem::size_of::<SockaddrHci>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn set_option<T>(
+    socket: &HciSocket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: &T,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.0,
+            level,
+            name,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn to_error(error: io::Error) -> Error {
+    if error.raw_os_error() == Some(libc::ENOENT) {
+        // There's no connection with the device.
+        return Error::NotConnected;
+    }
+    if error.raw_os_error() == Some(libc::EOPNOTSUPP) {
+        return Error::NotSupported("The kernel doesn't support this setting".to_string());
+    }
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => Error::PermissionDenied,
+        // The socket has a receive timeout, so reads fail like this if there is no response.
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::TimedOut(COMMAND_TIMEOUT),
+        _ => match error.raw_os_error() {
+            Some(errno) => super::error::from_errno(errno, &error.to_string()),
+            None => Error::Other(Box::new(error)),
+        },
+    }
+}
@@ -0,0 +1,47 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Finding which services a GATT service includes, from the `Includes` property of
+//! `org.bluez.GattService1`.
+
+use super::{error, with_connection, BLUEZ_SERVICE};
+use crate::Result;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::SyncConnection;
+use dbus::strings::Path;
+use log::trace;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the object paths of the services included by each of the services with the given
+/// object paths, in the same order.
+pub async fn included_services(services: Vec<Path<'static>>) -> Result<Vec<Vec<Path<'static>>>> {
+    with_connection(move |connection| included_services_blocking(connection, services))
+        .await?
+        .map_err(error::from_dbus)
+}
+
+fn included_services_blocking(
+    connection: &SyncConnection,
+    services: Vec<Path<'static>>,
+) -> std::result::Result<Vec<Vec<Path<'static>>>, dbus::Error> {
+    Ok(services
+        .into_iter()
+        .map(|service| {
+            let proxy = connection.with_proxy(BLUEZ_SERVICE, service, TIMEOUT);
+            // Versions of BlueZ before 5.48 don't have the property, in which case no includes
+            // are reported.
+            proxy
+                .get("org.bluez.GattService1", "Includes")
+                .unwrap_or_else(|e| {
+                    trace!("Failed to get included services: {}", e);
+                    vec![]
+                })
+        })
+        .collect())
+}
@@ -1,7 +1,14 @@
 use super::adapter::Adapter;
-use crate::{api, Result};
+use crate::{api, common::runtime, Result};
 use async_trait::async_trait;
-use bluez_async::BluetoothSession;
+use bluez_async::{AdapterId, BluetoothSession};
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// How often to check for adapters being added or removed.
+const ADAPTER_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Implementation of [api::Manager](crate::api::Manager).
 #[derive(Clone, Debug)]
@@ -27,4 +34,45 @@ impl api::Manager for Manager {
             .map(|adapter| Adapter::new(self.session.clone(), adapter.id))
             .collect())
     }
+
+    async fn adapter_events(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = api::ManagerEvent<Adapter>> + Send>>> {
+        // bluez-async doesn't report adapters coming and going, so poll for them instead.
+        let known: HashSet<AdapterId> = self
+            .session
+            .get_adapters()
+            .await?
+            .into_iter()
+            .map(|adapter| adapter.id)
+            .collect();
+        let events = stream::unfold(
+            (self.session.clone(), known),
+            |(session, mut known)| async move {
+                runtime::sleep(ADAPTER_POLL_INTERVAL).await;
+                let current: HashSet<AdapterId> = match session.get_adapters().await {
+                    Ok(adapters) => adapters.into_iter().map(|adapter| adapter.id).collect(),
+                    // Try again next time.
+                    Err(_) => known.clone(),
+                };
+                let mut events = Vec::new();
+                for id in current.difference(&known) {
+                    events.push(api::ManagerEvent::AdapterAdded(Adapter::new(
+                        session.clone(),
+                        id.clone(),
+                    )));
+                }
+                for id in known.difference(&current) {
+                    events.push(api::ManagerEvent::AdapterRemoved(Adapter::new(
+                        session.clone(),
+                        id.clone(),
+                    )));
+                }
+                known = current;
+                Some((stream::iter(events), (session, known)))
+            },
+        )
+        .flatten();
+        Ok(Box::pin(events))
+    }
 }
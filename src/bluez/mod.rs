@@ -1,3 +1,73 @@
+//! The BlueZ backend. Most of it is built on bluez-async, but some of the D-Bus APIs it needs
+//! aren't supported there, so the modules for those talk to BlueZ over D-Bus directly. Calls which
+//! only wait for a reply share one connection to the system bus, through [`with_connection`].
+//! Objects which BlueZ calls back, such as pairing agents, get a private connection instead, so
+//! that its requests can be answered while waiting for the reply to registering them; see
+//! [`objects`].
+
 pub mod adapter;
+mod advertising;
+mod connect;
+mod error;
+mod expiry;
+mod gatt_server;
+#[cfg(feature = "hci")]
+mod hci;
+mod includes;
 pub mod manager;
+mod monitor;
+mod mtu;
+mod objects;
+mod pairing;
+#[cfg(feature = "hci")]
+mod periodic;
 pub mod peripheral;
+
+use crate::{common::runtime, Result};
+use dbus::blocking::SyncConnection;
+use dbus::message::Message;
+use dbus::strings::ErrorName;
+use std::ffi::CString;
+use std::sync::{Arc, Mutex};
+
+const BLUEZ_SERVICE: &str = "org.bluez";
+const DBUS_ERROR_FAILED: &str = "org.freedesktop.DBus.Error.Failed";
+const DBUS_ERROR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+
+/// The connection to the system bus shared by blocking D-Bus calls, opened when first needed.
+static CONNECTION: Mutex<Option<Arc<SyncConnection>>> = Mutex::new(None);
+
+/// Runs the given blocking D-Bus calls with the shared connection to the system bus, on a thread
+/// where they won't hold up other tasks.
+async fn with_connection<T, F>(calls: F) -> Result<std::result::Result<T, dbus::Error>>
+where
+    F: FnOnce(&SyncConnection) -> std::result::Result<T, dbus::Error> + Send + 'static,
+    T: Send + 'static,
+{
+    runtime::spawn_blocking(move || calls(&*connection()?)).await
+}
+
+fn connection() -> std::result::Result<Arc<SyncConnection>, dbus::Error> {
+    let mut connection = CONNECTION.lock().unwrap();
+    match &*connection {
+        // Connect again if the bus went away, such as when it is restarted.
+        Some(connection) if connection.channel().is_connected() => Ok(connection.clone()),
+        _ => {
+            let new_connection = Arc::new(SyncConnection::new_system()?);
+            *connection = Some(new_connection.clone());
+            Ok(new_connection)
+        }
+    }
+}
+
+/// Answers a call to an object of ours with an error, for methods it doesn't have.
+fn unknown_method(message: &Message) -> Message {
+    message.error(
+        &ErrorName::new(DBUS_ERROR_UNKNOWN_METHOD).unwrap(),
+        &CString::new("Unknown method").unwrap(),
+    )
+}
+
+fn failed(message: impl AsRef<str>) -> dbus::Error {
+    dbus::Error::new_custom(DBUS_ERROR_FAILED, message.as_ref())
+}
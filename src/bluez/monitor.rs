@@ -0,0 +1,136 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Passive scanning through BlueZ's Advertisement Monitor API (`org.bluez.AdvertisementMonitor1`).
+//! BlueZ programs the monitor's patterns into the controller where it supports offloading them,
+//! so advertisements from unrelated devices don't wake the host. Devices which match are reported
+//! through the usual `org.bluez.Device1` objects.
+
+use super::objects::{variant, ExportedObjects, Interfaces, ObjectTree};
+use super::{failed, BLUEZ_SERVICE};
+use crate::api::{bleuuid::BleUuid, ScanFilter};
+use dbus::arg::PropMap;
+use dbus::message::Message;
+use dbus::strings::Path;
+use log::debug;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const MONITOR_INTERFACE: &str = "org.bluez.AdvertisementMonitor1";
+
+const AD_TYPE_INCOMPLETE_UUID16: u8 = 0x02;
+const AD_TYPE_COMPLETE_UUID16: u8 = 0x03;
+const AD_TYPE_INCOMPLETE_UUID128: u8 = 0x06;
+const AD_TYPE_COMPLETE_UUID128: u8 = 0x07;
+const AD_TYPE_SHORTENED_LOCAL_NAME: u8 = 0x08;
+const AD_TYPE_COMPLETE_LOCAL_NAME: u8 = 0x09;
+const AD_TYPE_MANUFACTURER_DATA: u8 = 0xff;
+
+/// Used to give each monitor a distinct object path.
+static NEXT_MONITOR_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A pattern matched against advertising data: the offset into the data of an AD structure, its
+/// type, and the bytes to match.
+type Pattern = (u8, u8, Vec<u8>);
+
+/// A registered Advertisement Monitor, which BlueZ keeps scanning for until it is dropped.
+#[derive(Debug)]
+pub struct AdvertisementMonitor {
+    _objects: ExportedObjects,
+}
+
+/// Converts a scan filter into patterns for a monitor. Any device advertising one of the services,
+/// a name with the prefix, or the manufacturer ID matches, so the filter must still be applied to
+/// the devices which are found.
+pub fn patterns(filter: &ScanFilter) -> Vec<Pattern> {
+    let mut patterns = Vec::new();
+    for service in &filter.services {
+        match service.to_ble_u16() {
+            Some(short) => {
+                for &ad_type in &[AD_TYPE_INCOMPLETE_UUID16, AD_TYPE_COMPLETE_UUID16] {
+                    patterns.push((0, ad_type, short.to_le_bytes().to_vec()));
+                }
+            }
+            None => {
+                let mut bytes = service.as_bytes().to_vec();
+                bytes.reverse();
+                for &ad_type in &[AD_TYPE_INCOMPLETE_UUID128, AD_TYPE_COMPLETE_UUID128] {
+                    patterns.push((0, ad_type, bytes.clone()));
+                }
+            }
+        }
+    }
+    if let Some(name_prefix) = &filter.name_prefix {
+        if !name_prefix.is_empty() {
+            for &ad_type in &[AD_TYPE_SHORTENED_LOCAL_NAME, AD_TYPE_COMPLETE_LOCAL_NAME] {
+                patterns.push((0, ad_type, name_prefix.as_bytes().to_vec()));
+            }
+        }
+    }
+    if let Some(manufacturer_id) = filter.manufacturer_id {
+        patterns.push((
+            0,
+            AD_TYPE_MANUFACTURER_DATA,
+            manufacturer_id.to_le_bytes().to_vec(),
+        ));
+    }
+    patterns
+}
+
+/// Registers a monitor with the given patterns on the adapter with the given object path, and
+/// waits for BlueZ to accept it.
+pub fn start(
+    adapter: Path<'static>,
+    patterns: Vec<Pattern>,
+) -> Result<AdvertisementMonitor, dbus::Error> {
+    let id = NEXT_MONITOR_ID.fetch_add(1, Ordering::Relaxed);
+    let root = Path::new(format!("/btleplug/monitor{}", id)).map_err(failed)?;
+    let monitor = Path::new(format!("/btleplug/monitor{}/0", id)).map_err(failed)?;
+    let register = Message::new_method_call(
+        BLUEZ_SERVICE,
+        adapter,
+        "org.bluez.AdvertisementMonitorManager1",
+        "RegisterMonitor",
+    )
+    .map_err(failed)?
+    .append1(root.clone());
+    let objects = ExportedObjects::register(root, register, MonitorTree { monitor, patterns })?;
+    Ok(AdvertisementMonitor { _objects: objects })
+}
+
+/// The monitor object, under its object manager.
+struct MonitorTree {
+    monitor: Path<'static>,
+    patterns: Vec<Pattern>,
+}
+
+impl ObjectTree for MonitorTree {
+    fn objects(&self) -> HashMap<Path<'static>, Interfaces> {
+        let mut properties = PropMap::new();
+        properties.insert("Type".to_string(), variant("or_patterns".to_string()));
+        properties.insert("Patterns".to_string(), variant(self.patterns.clone()));
+        let mut interfaces = Interfaces::new();
+        interfaces.insert(MONITOR_INTERFACE.to_string(), properties);
+        let mut objects = HashMap::new();
+        objects.insert(self.monitor.clone(), interfaces);
+        objects
+    }
+
+    fn call(&mut self, message: &Message) -> Option<Message> {
+        match (message.interface().as_deref(), message.member()) {
+            (Some(MONITOR_INTERFACE), Some(method))
+                if message.path().as_ref() == Some(&self.monitor) =>
+            {
+                // BlueZ reports the devices found through their Device1 objects too, so there's
+                // nothing more to do with these.
+                debug!("Advertisement monitor {}", method);
+                Some(message.method_return())
+            }
+            _ => None,
+        }
+    }
+}
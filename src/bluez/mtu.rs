@@ -0,0 +1,35 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Reading the ATT MTU of a connection from the `MTU` property of `org.bluez.GattCharacteristic1`,
+//! which BlueZ 5.62 and later set to the MTU negotiated for the connection.
+
+use super::{error, with_connection, BLUEZ_SERVICE};
+use crate::{Error, Result};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::strings::Path;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the MTU of the connection to the device with the characteristic with the given object
+/// path. Every characteristic of a device reports the same MTU.
+pub async fn mtu(characteristic: Path<'static>) -> Result<u16> {
+    let result = with_connection(move |connection| {
+        connection
+            .with_proxy(BLUEZ_SERVICE, characteristic, TIMEOUT)
+            .get("org.bluez.GattCharacteristic1", "MTU")
+    })
+    .await?;
+    result.map_err(|e| match e.name() {
+        Some("org.freedesktop.DBus.Error.InvalidArgs")
+        | Some("org.freedesktop.DBus.Error.UnknownProperty") => {
+            Error::NotSupported("Reading the MTU needs BlueZ 5.62 or later".to_string())
+        }
+        _ => error::from_dbus(e),
+    })
+}
@@ -0,0 +1,171 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Exporting objects of ours for BlueZ to call back, such as advertisement monitors, advertisements
+//! and GATT applications. BlueZ finds the objects through `org.freedesktop.DBus.ObjectManager` and
+//! reads their properties through `org.freedesktop.DBus.Properties`, which are answered here from
+//! an [`ObjectTree`]; anything else is passed on to the tree.
+
+use super::{failed, unknown_method};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::channel::{BusType, Channel};
+use dbus::message::{Message, MessageType};
+use dbus::strings::Path;
+use log::trace;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+const OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// How often the thread answering calls checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The properties of each interface of an object.
+pub type Interfaces = HashMap<String, PropMap>;
+
+/// A tree of objects to export, and the handlers for their methods.
+pub trait ObjectTree: Send + 'static {
+    /// Returns each object in the tree, with its interfaces and their current properties.
+    fn objects(&self) -> HashMap<Path<'static>, Interfaces>;
+
+    /// Answers a call to a method of one of the objects, other than those of the object manager
+    /// and properties interfaces. Returns `None` if the object has no such method.
+    fn call(&mut self, message: &Message) -> Option<Message>;
+}
+
+/// Objects exported on a private connection to the system bus, with a thread of their own which
+/// answers calls to them. BlueZ forgets objects registered with it when the connection is closed,
+/// which happens when this is dropped.
+#[derive(Debug)]
+pub struct ExportedObjects {
+    channel: Arc<Channel>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for ExportedObjects {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl ExportedObjects {
+    /// Exports the objects in the tree, whose object manager is at `root`, then registers them
+    /// with BlueZ by sending the given method call and waits for BlueZ to accept them. BlueZ reads
+    /// the objects before replying, so their calls are answered from the start.
+    pub fn register(
+        root: Path<'static>,
+        register: Message,
+        mut tree: impl ObjectTree,
+    ) -> Result<Self, dbus::Error> {
+        let channel = Arc::new(Channel::get_private(BusType::System)?);
+        let serial = channel
+            .send(register)
+            .map_err(|()| failed("Failed to send registration request"))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (registered_sender, registered_receiver) = mpsc::channel();
+        let thread_channel = channel.clone();
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            let mut registered_sender = Some(registered_sender);
+            while !thread_stop.load(Ordering::Relaxed) {
+                if thread_channel.read_write(Some(POLL_INTERVAL)).is_err() {
+                    break;
+                }
+                while let Some(mut message) = thread_channel.pop_message() {
+                    if message.get_reply_serial() == Some(serial) {
+                        if let Some(sender) = registered_sender.take() {
+                            let _ = sender.send(message.as_result().map(|_| ()));
+                        }
+                    } else if message.msg_type() == MessageType::MethodCall {
+                        let reply = reply(&message, &root, &mut tree);
+                        let _ = thread_channel.send(reply);
+                    }
+                }
+            }
+            if let Some(sender) = registered_sender.take() {
+                let _ = sender.send(Err(failed("Stopped before registering with BlueZ")));
+            }
+        });
+
+        let result = registered_receiver
+            .recv()
+            .unwrap_or_else(|_| Err(failed("Thread answering BlueZ stopped")));
+        let objects = ExportedObjects { channel, stop };
+        result.map(|()| objects)
+    }
+
+    /// Tells BlueZ that some properties of an interface of one of the objects have changed.
+    pub fn properties_changed(
+        &self,
+        path: Path<'static>,
+        interface: &str,
+        changed: PropMap,
+    ) -> Result<(), dbus::Error> {
+        let signal =
+            Message::new_signal(path.to_string(), PROPERTIES_INTERFACE, "PropertiesChanged")
+                .map_err(failed)?
+                .append3(interface, changed, Vec::<String>::new());
+        self.channel
+            .send(signal)
+            .map_err(|()| failed("Failed to send PropertiesChanged signal"))?;
+        self.channel.flush();
+        Ok(())
+    }
+}
+
+/// Answers a call to one of the objects in the tree.
+fn reply(message: &Message, root: &Path<'static>, tree: &mut impl ObjectTree) -> Message {
+    let path = message.path();
+    let interface = message.interface();
+    let member = message.member();
+    trace!("request {:?} {:?} {:?}", path, interface, member);
+    match (interface.as_deref(), member.as_deref()) {
+        (Some(OBJECT_MANAGER_INTERFACE), Some("GetManagedObjects"))
+            if path.as_ref() == Some(root) =>
+        {
+            message.method_return().append1(tree.objects())
+        }
+        (Some(PROPERTIES_INTERFACE), Some("GetAll")) => {
+            let properties = path.and_then(|path| {
+                let interface = message.read1::<&str>().ok()?;
+                tree.objects()
+                    .remove(&path.into_static())?
+                    .remove(interface)
+            });
+            match properties {
+                Some(properties) => message.method_return().append1(properties),
+                None => unknown_method(message),
+            }
+        }
+        (Some(PROPERTIES_INTERFACE), Some("Get")) => {
+            let value = path.and_then(|path| {
+                let (interface, name) = message.read2::<&str, &str>().ok()?;
+                tree.objects()
+                    .remove(&path.into_static())?
+                    .remove(interface)?
+                    .remove(name)
+            });
+            match value {
+                Some(value) => message.method_return().append1(value),
+                None => unknown_method(message),
+            }
+        }
+        _ => tree
+            .call(message)
+            .unwrap_or_else(|| unknown_method(message)),
+    }
+}
+
+/// Wraps a property value for [`Interfaces`].
+pub fn variant(value: impl RefArg + 'static) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value))
+}
@@ -0,0 +1,182 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Pairing through BlueZ, optionally registering a pairing agent (`org.bluez.Agent1`) to answer
+//! BlueZ's requests on behalf of a [`PairingAgent`].
+
+use super::{error, failed, BLUEZ_SERVICE};
+use crate::{
+    api::{BDAddr, PairingAgent},
+    common::runtime,
+    Error, Result,
+};
+use bluez_async::DeviceId;
+use dbus::channel::{BusType, Channel};
+use dbus::message::{Message, MessageType};
+use dbus::strings::{ErrorName, Path};
+use log::trace;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DBUS_ERROR_TIMEOUT: &str = "org.freedesktop.DBus.Error.Timeout";
+
+/// How long to wait for pairing to complete, including any time spent waiting for the user.
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Used to give each agent a distinct object path.
+static NEXT_AGENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Pairs with the given device, either through the default BlueZ agent (such as the desktop's
+/// pairing prompts) or with the given agent.
+pub async fn pair(
+    device: DeviceId,
+    address: BDAddr,
+    agent: Option<Arc<dyn PairingAgent>>,
+) -> Result<()> {
+    let result =
+        runtime::spawn_blocking(move || pair_blocking(device.into(), address, agent)).await?;
+    result.map_err(|e| match e.name() {
+        Some("org.bluez.Error.AuthenticationRejected")
+        | Some("org.bluez.Error.AuthenticationCanceled") => Error::PermissionDenied,
+        Some(DBUS_ERROR_TIMEOUT) => Error::TimedOut(PAIRING_TIMEOUT),
+        _ => error::from_dbus(e),
+    })
+}
+
+fn pair_blocking(
+    device: Path<'static>,
+    address: BDAddr,
+    agent: Option<Arc<dyn PairingAgent>>,
+) -> std::result::Result<(), dbus::Error> {
+    // Incoming agent requests are handled on this thread while waiting for the reply to Pair.
+    let channel = Channel::get_private(BusType::System)?;
+    let agent = match agent {
+        Some(agent) => {
+            let path = Path::new(format!(
+                "/btleplug/agent{}",
+                NEXT_AGENT_ID.fetch_add(1, Ordering::Relaxed)
+            ))
+            .map_err(failed)?;
+            let register =
+                new_method_call("/org/bluez", "org.bluez.AgentManager1", "RegisterAgent")?
+                    .append2(path.clone(), "KeyboardDisplay");
+            channel.send_with_reply_and_block(register, Duration::from_secs(5))?;
+            Some((path, agent))
+        }
+        None => None,
+    };
+
+    let result = wait_for_pairing(&channel, device, address, agent.as_ref());
+
+    // BlueZ also drops the agent when our connection closes, so failing to unregister it doesn't
+    // matter.
+    if let Some((path, _)) = agent {
+        if let Ok(unregister) =
+            new_method_call("/org/bluez", "org.bluez.AgentManager1", "UnregisterAgent")
+        {
+            let _ =
+                channel.send_with_reply_and_block(unregister.append1(path), Duration::from_secs(5));
+        }
+    }
+    result
+}
+
+fn wait_for_pairing(
+    channel: &Channel,
+    device: Path<'static>,
+    address: BDAddr,
+    agent: Option<&(Path<'static>, Arc<dyn PairingAgent>)>,
+) -> std::result::Result<(), dbus::Error> {
+    let pair = new_method_call(device, "org.bluez.Device1", "Pair")?;
+    let serial = channel
+        .send(pair)
+        .map_err(|()| failed("Failed to send Pair request"))?;
+    let deadline = Instant::now() + PAIRING_TIMEOUT;
+    loop {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .ok_or_else(|| {
+                dbus::Error::new_custom(DBUS_ERROR_TIMEOUT, "Timed out waiting for pairing")
+            })?;
+        channel
+            .read_write(Some(remaining))
+            .map_err(|()| failed("D-Bus connection closed"))?;
+        while let Some(mut message) = channel.pop_message() {
+            if message.get_reply_serial() == Some(serial) {
+                return match message.as_result() {
+                    Ok(_) => Ok(()),
+                    Err(e) if e.name() == Some("org.bluez.Error.AlreadyExists") => Ok(()),
+                    Err(e) => Err(e),
+                };
+            }
+            if let Some((path, agent)) = agent {
+                if message.msg_type() == MessageType::MethodCall
+                    && message.path().as_ref() == Some(path)
+                {
+                    let reply = agent_reply(&message, address, agent.as_ref());
+                    channel
+                        .send(reply)
+                        .map_err(|()| failed("Failed to reply to BlueZ"))?;
+                }
+            }
+        }
+    }
+}
+
+/// Answers a call to a method of `org.bluez.Agent1`.
+fn agent_reply(message: &Message, address: BDAddr, agent: &dyn PairingAgent) -> Message {
+    let member = message.member();
+    trace!("agent request {:?}", member);
+    let reply = match member.as_deref() {
+        Some("RequestConfirmation") => match message.read2::<Path, u32>() {
+            Ok((_, passkey)) if agent.confirm_passkey(address, passkey) => {
+                Some(message.method_return())
+            }
+            _ => None,
+        },
+        Some("RequestAuthorization") if agent.confirm(address) => Some(message.method_return()),
+        Some("RequestPasskey") => agent
+            .request_passkey(address)
+            .map(|passkey| message.method_return().append1(passkey)),
+        Some("DisplayPasskey") => match message.read2::<Path, u32>() {
+            Ok((_, passkey)) if agent.display_passkey(address, passkey) => {
+                Some(message.method_return())
+            }
+            _ => None,
+        },
+        Some("RequestPinCode") => agent
+            .request_pin(address)
+            .map(|pin| message.method_return().append1(pin)),
+        Some("DisplayPinCode") => match message.read2::<Path, &str>() {
+            Ok((_, pin)) => pin
+                .parse()
+                .ok()
+                .filter(|pin| agent.display_passkey(address, *pin))
+                .map(|_| message.method_return()),
+            _ => None,
+        },
+        Some("Release") | Some("Cancel") => Some(message.method_return()),
+        // Anything else, such as authorizing services, isn't part of pairing, so is rejected.
+        _ => None,
+    };
+    reply.unwrap_or_else(|| {
+        message.error(
+            &ErrorName::new("org.bluez.Error.Rejected").unwrap(),
+            &CString::new("Rejected by pairing agent").unwrap(),
+        )
+    })
+}
+
+fn new_method_call<'p>(
+    path: impl Into<Path<'p>>,
+    interface: &str,
+    method: &str,
+) -> std::result::Result<Message, dbus::Error> {
+    Message::new_method_call(BLUEZ_SERVICE, path, interface, method).map_err(failed)
+}
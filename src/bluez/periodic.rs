@@ -0,0 +1,288 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Synchronizing to periodic advertising trains with raw HCI commands, as BlueZ has no D-Bus API
+//! for it. The controller reports the advertisements with LE Meta events, which are read from a
+//! raw socket on a thread of their own until the receiver of the reports is dropped.
+
+use super::hci::{self, LeEventListener};
+use crate::api::{BDAddr, PeriodicAdvertisement};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use log::{debug, warn};
+use std::convert::TryInto;
+use std::io;
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SUBEVENT_SYNC_ESTABLISHED: u8 = 0x0e;
+const SUBEVENT_REPORT: u8 = 0x0f;
+const SUBEVENT_SYNC_LOST: u8 = 0x10;
+
+/// The value of the TX power and RSSI fields of a report when they aren't known.
+const NOT_AVAILABLE: i8 = 0x7f;
+
+/// How long to wait for the controller to synchronize to a train.
+pub const ESTABLISH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a train may go without being received before the controller gives up on it.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The LE Meta events about periodic advertising.
+#[derive(Debug, PartialEq)]
+enum SyncEvent<'a> {
+    Established {
+        status: u8,
+        sync_handle: u16,
+        sid: u8,
+        address: BDAddr,
+    },
+    Report {
+        sync_handle: u16,
+        tx_power_level: Option<i8>,
+        rssi: Option<i16>,
+        data_status: u8,
+        data: &'a [u8],
+    },
+    Lost {
+        sync_handle: u16,
+    },
+}
+
+impl<'a> SyncEvent<'a> {
+    /// Parses the parameters of an LE Meta event, starting with the subevent code, returning
+    /// `None` for other events and for events too short for their fields.
+    fn parse(event: &'a [u8]) -> Option<Self> {
+        let (&subevent, params) = event.split_first()?;
+        // Sync handles only have 12 bits.
+        let sync_handle = match params {
+            [low, high, ..] => u16::from_le_bytes([*low, *high]) & 0x0fff,
+            _ => return None,
+        };
+        match subevent {
+            // Status, then the sync handle, advertising set ID, address type and address.
+            SUBEVENT_SYNC_ESTABLISHED => {
+                let address: [u8; 6] = params.get(5..11)?.try_into().ok()?;
+                Some(SyncEvent::Established {
+                    status: params[0],
+                    sync_handle: u16::from_le_bytes([params[1], params[2]]) & 0x0fff,
+                    sid: params[3],
+                    address: BDAddr::from_le_bytes(address),
+                })
+            }
+            // Sync handle, TX power, RSSI, CTE type, data status, data length, then the data.
+            SUBEVENT_REPORT => {
+                let length = *params.get(6)? as usize;
+                let data = params.get(7..7 + length)?;
+                let known = |value: u8| Some(value as i8).filter(|&value| value != NOT_AVAILABLE);
+                Some(SyncEvent::Report {
+                    sync_handle,
+                    tx_power_level: known(params[2]),
+                    rssi: known(params[3]).map(i16::from),
+                    data_status: params[5],
+                    data,
+                })
+            }
+            SUBEVENT_SYNC_LOST => Some(SyncEvent::Lost { sync_handle }),
+            _ => None,
+        }
+    }
+}
+
+/// Joins up the data of advertisements which the controller splits across several reports.
+#[derive(Debug, Default)]
+struct Reassembler {
+    data: Vec<u8>,
+}
+
+impl Reassembler {
+    /// Adds the data of a report, returning the whole advertisement once it is complete.
+    fn push(&mut self, data_status: u8, data: &[u8]) -> Option<Vec<u8>> {
+        self.data.extend_from_slice(data);
+        match data_status {
+            0 => Some(mem::take(&mut self.data)),
+            // More reports are to come.
+            1 => None,
+            // The controller gave up on the rest of the data, so the advertisement is dropped.
+            _ => {
+                self.data.clear();
+                None
+            }
+        }
+    }
+}
+
+/// Synchronizes to the periodic advertising train with the given advertising set ID from the
+/// given device, blocking until the sync is established, and returns a receiver of the
+/// advertisements received on it.
+pub fn sync(
+    dev_id: u16,
+    address: BDAddr,
+    random_address: bool,
+    sid: u8,
+) -> io::Result<UnboundedReceiver<PeriodicAdvertisement>> {
+    // Listen before creating the sync, so that the event saying it was established isn't missed.
+    let listener = LeEventListener::open(dev_id)?;
+    hci::create_periodic_sync(dev_id, address, random_address, sid, SYNC_TIMEOUT)?;
+    let sync_handle = wait_until_established(&listener, dev_id, address, sid)?;
+    debug!(
+        "Synchronized to periodic advertising set {} of {}",
+        sid, address
+    );
+
+    let (sender, receiver) = mpsc::unbounded();
+    thread::spawn(move || {
+        match forward_reports(&listener, sync_handle, address, sid, &sender) {
+            // The sync was lost, so there's nothing to terminate.
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(e) => warn!(
+                "Failed to read periodic advertising from {}: {}",
+                address, e
+            ),
+        }
+        if let Err(e) = hci::terminate_periodic_sync(dev_id, sync_handle) {
+            warn!("Failed to terminate periodic advertising sync: {}", e);
+        }
+    });
+    Ok(receiver)
+}
+
+/// Waits for the controller to report that the sync to the given train is established, and
+/// returns its handle. The sync is cancelled if that takes longer than `ESTABLISH_TIMEOUT`.
+fn wait_until_established(
+    listener: &LeEventListener,
+    dev_id: u16,
+    address: BDAddr,
+    sid: u8,
+) -> io::Result<u16> {
+    let deadline = Instant::now() + ESTABLISH_TIMEOUT;
+    while Instant::now() < deadline {
+        let event = match listener.next_event()? {
+            Some(event) => event,
+            None => continue,
+        };
+        if let Some(SyncEvent::Established {
+            status,
+            sync_handle,
+            sid: event_sid,
+            address: event_address,
+        }) = SyncEvent::parse(&event)
+        {
+            if event_sid == sid && event_address == address {
+                return match status {
+                    0 => Ok(sync_handle),
+                    status => Err(hci::status_error(status)),
+                };
+            }
+        }
+    }
+    hci::cancel_periodic_sync(dev_id)?;
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "Timed out synchronizing to periodic advertising",
+    ))
+}
+
+/// Sends the advertisements received on the sync with the given handle to `sender`, until the
+/// sync is lost or the receiver is dropped, and returns whether the sync was lost.
+fn forward_reports(
+    listener: &LeEventListener,
+    sync_handle: u16,
+    address: BDAddr,
+    sid: u8,
+    sender: &UnboundedSender<PeriodicAdvertisement>,
+) -> io::Result<bool> {
+    let mut reassembler = Reassembler::default();
+    while !sender.is_closed() {
+        let event = match listener.next_event()? {
+            Some(event) => event,
+            None => continue,
+        };
+        match SyncEvent::parse(&event) {
+            Some(SyncEvent::Report {
+                sync_handle: handle,
+                tx_power_level,
+                rssi,
+                data_status,
+                data,
+            }) if handle == sync_handle => {
+                if let Some(data) = reassembler.push(data_status, data) {
+                    let _ = sender.unbounded_send(PeriodicAdvertisement {
+                        address,
+                        sid,
+                        tx_power_level,
+                        rssi,
+                        data,
+                    });
+                }
+            }
+            Some(SyncEvent::Lost {
+                sync_handle: handle,
+            }) if handle == sync_handle => {
+                debug!("Lost periodic advertising set {} of {}", sid, address);
+                return Ok(true);
+            }
+            _ => {}
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sync_established() {
+        let event = [
+            0x0e, 0x00, 0x01, 0x00, 0x02, 0x01, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x02, 0x40,
+            0x00, 0x00,
+        ];
+        assert_eq!(
+            SyncEvent::parse(&event),
+            Some(SyncEvent::Established {
+                status: 0,
+                sync_handle: 1,
+                sid: 2,
+                address: BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_report_without_tx_power() {
+        let event = [0x0f, 0x01, 0x00, 0x7f, 0xc4, 0xff, 0x00, 0x02, 0x02, 0x01];
+        assert_eq!(
+            SyncEvent::parse(&event),
+            Some(SyncEvent::Report {
+                sync_handle: 1,
+                tx_power_level: None,
+                rssi: Some(-60),
+                data_status: 0,
+                data: &[0x02, 0x01],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_truncated_report() {
+        // The data length is longer than the data which follows.
+        let event = [0x0f, 0x01, 0x00, 0x7f, 0xc4, 0xff, 0x00, 0x05, 0x02, 0x01];
+        assert_eq!(SyncEvent::parse(&event), None);
+    }
+
+    #[test]
+    fn reassemble_split_advertisement() {
+        let mut reassembler = Reassembler::default();
+        assert_eq!(reassembler.push(1, &[0x01, 0x02]), None);
+        assert_eq!(reassembler.push(0, &[0x03]), Some(vec![0x01, 0x02, 0x03]));
+        // Data the controller gave up on is dropped.
+        assert_eq!(reassembler.push(1, &[0x04]), None);
+        assert_eq!(reassembler.push(2, &[0x05]), None);
+        assert_eq!(reassembler.push(0, &[0x06]), Some(vec![0x06]));
+    }
+}
@@ -1,17 +1,37 @@
 use async_trait::async_trait;
 use bluez_async::{
-    BluetoothEvent, BluetoothSession, CharacteristicEvent, CharacteristicFlags, CharacteristicInfo,
-    DeviceId, DeviceInfo, MacAddress, WriteOptions,
+    BluetoothEvent, BluetoothSession, CharacteristicEvent, CharacteristicFlags, CharacteristicId,
+    CharacteristicInfo, DescriptorInfo, DeviceEvent, DeviceId, DeviceInfo, MacAddress,
+    WriteOptions,
 };
+use bytes::Bytes;
+use dbus::Path;
 use futures::future::ready;
-use futures::stream::{Stream, StreamExt};
+use futures::stream::{self, Stream, StreamExt};
 use std::collections::BTreeSet;
+use std::fs;
+use std::io;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use uuid::Uuid;
 
+#[cfg(feature = "hci")]
+use super::hci;
+use super::{includes, mtu, pairing};
 use crate::api::{
-    self, AddressType, BDAddr, CharPropFlags, Characteristic, PeripheralProperties,
-    ValueNotification, WriteType,
+    self, AddressType, BDAddr, CharPropFlags, Characteristic, ConnectOptions, ConnectionParameters,
+    ConnectionPriority, Descriptor, L2capChannel, PairingAgent, Peripheral as _, PeripheralOptions,
+    PeripheralProperties, PeripheralSummary, Phy, SecurityLevel, Service, ValueNotification,
+    WriteType,
+};
+#[cfg(feature = "hci")]
+use crate::common::runtime;
+use crate::common::{
+    metrics::Metrics, operation_queue::OperationQueue, subscriptions::SubscriptionCounts, util,
 };
 use crate::{Error, Result};
 
@@ -21,25 +41,59 @@ pub struct Peripheral {
     session: BluetoothSession,
     device: DeviceId,
     mac_address: BDAddr,
-    characteristics: Arc<Mutex<Vec<CharacteristicInfo>>>,
+    /// The characteristics discovered on the device, along with BlueZ's information about them.
+    characteristics: Arc<Mutex<Vec<(Characteristic, CharacteristicInfo)>>>,
+    descriptors: Arc<Mutex<Vec<DescriptorInfo>>>,
+    subscriptions: Arc<Mutex<SubscriptionCounts>>,
+    operations: Arc<OperationQueue>,
+    options: Arc<Mutex<PeripheralOptions>>,
+    dropped_notifications: Arc<AtomicU64>,
+    metrics: Arc<Metrics>,
 }
 
 impl Peripheral {
-    pub(crate) fn new(session: BluetoothSession, device: DeviceInfo) -> Self {
+    pub(crate) fn new(
+        session: BluetoothSession,
+        device: DeviceInfo,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         Peripheral {
             session,
             device: device.id,
             mac_address: (&device.mac_address).into(),
             characteristics: Arc::new(Mutex::new(vec![])),
+            descriptors: Arc::new(Mutex::new(vec![])),
+            subscriptions: Arc::new(Mutex::new(SubscriptionCounts::default())),
+            operations: Arc::new(OperationQueue::default()),
+            options: Arc::new(Mutex::new(PeripheralOptions::default())),
+            dropped_notifications: Arc::new(AtomicU64::new(0)),
+            metrics,
         }
     }
 
+    /// Reports the peripheral under the given address, such as the identity address which its
+    /// resolvable private address resolved to.
+    pub(crate) fn with_address(mut self, address: BDAddr) -> Self {
+        self.mac_address = address;
+        self
+    }
+
+    /// Returns the D-Bus object path of the BlueZ `org.bluez.Device1` object behind this
+    /// peripheral, for operations which btleplug doesn't wrap.
+    pub fn platform_handle(&self) -> Path<'static> {
+        self.device.clone().into()
+    }
+
     fn characteristic_info(&self, characteristic: &Characteristic) -> Result<CharacteristicInfo> {
         let characteristics = self.characteristics.lock().unwrap();
         characteristics
             .iter()
-            .find(|info| info.uuid == characteristic.uuid)
-            .cloned()
+            .find(|(known, _)| {
+                known.uuid == characteristic.uuid
+                    && known.service_uuid == characteristic.service_uuid
+                    && known.handle == characteristic.handle
+            })
+            .map(|(_, info)| info.clone())
             .ok_or_else(|| {
                 Error::Other(
                     format!(
@@ -51,9 +105,80 @@ impl Peripheral {
             })
     }
 
+    /// Enables notifications again for the characteristics whose subscriptions were restored after
+    /// reconnecting.
+    async fn resubscribe(&self, characteristics: &[Characteristic]) -> Result<()> {
+        self.discover_characteristics().await?;
+        for characteristic in characteristics {
+            let characteristic_info = self.characteristic_info(characteristic)?;
+            self.session.start_notify(&characteristic_info.id).await?;
+        }
+        Ok(())
+    }
+
+    fn descriptor_info(&self, descriptor: &Descriptor) -> Result<DescriptorInfo> {
+        let descriptors = self.descriptors.lock().unwrap();
+        descriptors
+            .iter()
+            .find(|info| {
+                info.uuid == descriptor.uuid
+                    && characteristic_handle(&info.id.characteristic())
+                        == descriptor.characteristic_handle
+            })
+            .cloned()
+            .ok_or_else(|| {
+                Error::Other(format!("Descriptor with UUID {} not found.", descriptor.uuid).into())
+            })
+    }
+
     async fn device_info(&self) -> Result<DeviceInfo> {
         Ok(self.session.get_device_info(&self.device).await?)
     }
+
+    /// Returns the index of the controller this peripheral is connected through, and its address,
+    /// for HCI commands about the connection.
+    #[cfg(feature = "hci")]
+    fn hci_connection(&self) -> Result<(u16, BDAddr)> {
+        let dev_id = hci::dev_id(&self.device.adapter().into())?;
+        Ok((dev_id, self.mac_address))
+    }
+}
+
+/// Converts a PHY to its bit in the PHY masks of the LE Set PHY command.
+#[cfg(feature = "hci")]
+fn phy_mask(phy: Phy) -> u8 {
+    match phy {
+        Phy::Le1M => 0x01,
+        Phy::Le2M => 0x02,
+        Phy::LeCoded => 0x04,
+    }
+}
+
+/// Converts a PHY number returned by the LE Read PHY command.
+#[cfg(feature = "hci")]
+fn to_phy(phy: u8) -> Result<Phy> {
+    match phy {
+        1 => Ok(Phy::Le1M),
+        2 => Ok(Phy::Le2M),
+        3 => Ok(Phy::LeCoded),
+        _ => Err(Error::Other(format!("Unknown PHY {}", phy).into())),
+    }
+}
+
+pub(crate) fn device_properties(device_info: DeviceInfo) -> PeripheralProperties {
+    PeripheralProperties {
+        address: (&device_info.mac_address).into(),
+        address_type: Some(device_info.address_type.into()),
+        local_name: device_info.name,
+        tx_power_level: device_info.tx_power.map(|tx_power| tx_power as i8),
+        manufacturer_data: device_info.manufacturer_data,
+        service_data: device_info.service_data,
+        services: device_info.services,
+        rssi: device_info.rssi,
+        discovery_count: 0,
+        ad_structures: Vec::new(),
+        last_seen: None,
+    }
 }
 
 #[async_trait]
@@ -64,21 +189,54 @@ impl api::Peripheral for Peripheral {
 
     async fn properties(&self) -> Result<Option<PeripheralProperties>> {
         let device_info = self.device_info().await?;
-        Ok(Some(PeripheralProperties {
-            address: (&device_info.mac_address).into(),
-            address_type: Some(device_info.address_type.into()),
-            local_name: device_info.name,
-            tx_power_level: device_info.tx_power.map(|tx_power| tx_power as i8),
-            manufacturer_data: device_info.manufacturer_data,
-            service_data: device_info.service_data,
-            services: device_info.services,
-            discovery_count: 0,
-        }))
+        let mut properties = device_properties(device_info);
+        properties.address = self.mac_address;
+        Ok(Some(properties))
+    }
+
+    async fn properties_stream(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeripheralProperties> + Send>>> {
+        // BlueZ keeps the properties itself, so they are read again whenever it reports that
+        // they have changed.
+        let events = self.session.device_event_stream(&self.device).await?;
+        let current = device_properties(self.device_info().await?);
+        let session = self.session.clone();
+        let device = self.device.clone();
+        let updates = events.filter_map(move |event| {
+            let session = session.clone();
+            let device = device.clone();
+            async move {
+                match event {
+                    BluetoothEvent::Device { .. } => session
+                        .get_device_info(&device)
+                        .await
+                        .ok()
+                        .map(device_properties),
+                    _ => None,
+                }
+            }
+        });
+        let address = self.mac_address;
+        let properties = stream::once(ready(current))
+            .chain(updates)
+            .map(move |mut properties| {
+                properties.address = address;
+                properties
+            });
+        Ok(Box::pin(properties))
+    }
+
+    async fn summary(&self) -> Result<PeripheralSummary> {
+        util::summary(self).await
     }
 
     fn characteristics(&self) -> BTreeSet<Characteristic> {
         let characteristics = &*self.characteristics.lock().unwrap();
-        characteristics.iter().map(Characteristic::from).collect()
+        characteristics
+            .iter()
+            .map(|(characteristic, _)| characteristic.clone())
+            .collect()
     }
 
     async fn is_connected(&self) -> Result<bool> {
@@ -86,80 +244,568 @@ impl api::Peripheral for Peripheral {
         Ok(device_info.connected)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.mac_address), err)
+    )]
     async fn connect(&self) -> Result<()> {
-        self.session.connect(&self.device).await?;
+        // Subscriptions don't survive the connection being closed by the device, even if no
+        // notification stream noticed it. The adapter doesn't keep the peripherals it hands out,
+        // so this is the first chance to find out.
+        if !self.is_connected().await? {
+            self.subscriptions.lock().unwrap().disconnected();
+        }
+        let attempt = self.metrics.connect_attempt();
+        let retry = self.options.lock().unwrap().retry;
+        util::with_retry(retry, move || async move {
+            self.session.connect(&self.device).await?;
+            Ok(())
+        })
+        .await?;
+        attempt.succeeded();
         Ok(())
     }
 
+    async fn connect_with_timeout(&self, timeout: Duration) -> Result<()> {
+        util::connect_with_timeout(self, timeout).await
+    }
+
+    async fn connect_with_options(&self, options: &ConnectOptions) -> Result<()> {
+        self.operations.set_concurrency(
+            options
+                .concurrent_operations
+                .map_or(1, |concurrency| concurrency.get()),
+        );
+        // Connections are kept open regardless of whether they are in use.
+        self.connect().await?;
+        let resubscriptions = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .reconnected(options.resubscribe);
+        if options.resubscribe {
+            self.resubscribe(&resubscriptions).await?;
+        }
+        Ok(())
+    }
+
+    fn set_options(&self, options: &PeripheralOptions) {
+        *self.options.lock().unwrap() = *options;
+    }
+
+    async fn pair(&self) -> Result<()> {
+        pairing::pair(self.device.clone(), self.mac_address, None).await
+    }
+
+    async fn pair_with_agent(&self, agent: Arc<dyn PairingAgent>) -> Result<()> {
+        pairing::pair(self.device.clone(), self.mac_address, Some(agent)).await
+    }
+
+    async fn request_security(&self, level: SecurityLevel) -> Result<()> {
+        // Whether pairing protects against man-in-the-middle attacks depends on the IO
+        // capabilities of whichever agent BlueZ uses, and BlueZ doesn't say which it got.
+        if level > SecurityLevel::Encrypted {
+            return Err(Error::NotSupported(
+                "BlueZ can't guarantee that a link is authenticated".to_string(),
+            ));
+        }
+        // BlueZ doesn't let other clients raise the security of its ATT connection, but it
+        // encrypts the link as soon as the device is bonded, and does so on each reconnection.
+        if self.device_info().await?.paired {
+            return Ok(());
+        }
+        // Pairing which another client finished in the meantime counts as success.
+        pairing::pair(self.device.clone(), self.mac_address, None).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.mac_address), err)
+    )]
     async fn disconnect(&self) -> Result<()> {
         self.session.disconnect(&self.device).await?;
+        self.subscriptions.lock().unwrap().disconnected();
         Ok(())
     }
 
-    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
-        let mut characteristics = vec![];
-        let services = self.session.get_services(&self.device).await?;
-        for service in services {
-            characteristics.extend(self.session.get_characteristics(&service.id).await?);
+    async fn mtu(&self) -> Result<u16> {
+        // BlueZ reports the MTU through each characteristic of the device, so any of them will do.
+        let characteristic = self
+            .characteristics
+            .lock()
+            .unwrap()
+            .first()
+            .map(|(_, info)| info.id.clone());
+        match characteristic {
+            Some(characteristic) => mtu::mtu(characteristic.into()).await,
+            None => Err(Error::NotSupported(
+                "BlueZ reports the MTU through characteristics, so they must be discovered first"
+                    .to_string(),
+            )),
+        }
+    }
+
+    async fn request_mtu(&self, _mtu: u16) -> Result<()> {
+        Err(Error::NotSupported(
+            "BlueZ negotiates the MTU automatically".to_string(),
+        ))
+    }
+
+    /// BlueZ has no D-Bus API for this, so it's read with an HCI command, which needs the `hci`
+    /// feature and the `CAP_NET_RAW` capability.
+    async fn read_rssi(&self) -> Result<i16> {
+        #[cfg(feature = "hci")]
+        {
+            let (dev_id, address) = self.hci_connection()?;
+            let rssi = runtime::spawn_blocking(move || hci::read_rssi(dev_id, address))
+                .await?
+                .map_err(hci::to_error)?;
+            Ok(rssi.into())
+        }
+        #[cfg(not(feature = "hci"))]
+        Err(Error::NotSupported(
+            "Reading the RSSI of a connection on BlueZ needs the hci feature".to_string(),
+        ))
+    }
+
+    /// BlueZ has no D-Bus API for connection parameters, so this sets the kernel's defaults for the
+    /// adapter through debugfs. They apply to all subsequent connections made by the adapter, not
+    /// only those to this device, and setting them requires root.
+    async fn set_connection_parameters(&self, parameters: &ConnectionParameters) -> Result<()> {
+        parameters.validate()?;
+        let adapter = self.device.adapter().to_string();
+        let min_interval = to_units(parameters.min_interval, 1250);
+        let max_interval = to_units(parameters.max_interval, 1250);
+        // The kernel rejects a minimum above the current maximum and vice versa, so the order of
+        // the writes depends on which way the interval is moving.
+        let current_max_interval = read_conn_param(&adapter, "conn_max_interval")?;
+        if min_interval > current_max_interval {
+            write_conn_param(&adapter, "conn_max_interval", max_interval)?;
+            write_conn_param(&adapter, "conn_min_interval", min_interval)?;
+        } else {
+            write_conn_param(&adapter, "conn_min_interval", min_interval)?;
+            write_conn_param(&adapter, "conn_max_interval", max_interval)?;
         }
-        let converted = characteristics.iter().map(Characteristic::from).collect();
-        *self.characteristics.lock().unwrap() = characteristics;
-        Ok(converted)
+        write_conn_param(&adapter, "conn_latency", parameters.latency)?;
+        write_conn_param(
+            &adapter,
+            "supervision_timeout",
+            to_units(parameters.supervision_timeout, 10000),
+        )
     }
 
+    async fn request_connection_priority(&self, priority: ConnectionPriority) -> Result<()> {
+        self.set_connection_parameters(&priority.into()).await
+    }
+
+    /// BlueZ has no D-Bus API for this, so it's set with an HCI command, which needs the `hci`
+    /// feature and the `CAP_NET_RAW` capability. This returns once the controller has accepted the
+    /// request, before the change has been negotiated with the device.
+    #[cfg_attr(not(feature = "hci"), allow(unused_variables))]
+    async fn set_preferred_phy(&self, tx: Phy, rx: Phy) -> Result<()> {
+        #[cfg(feature = "hci")]
+        {
+            let (dev_id, address) = self.hci_connection()?;
+            let (tx, rx) = (phy_mask(tx), phy_mask(rx));
+            runtime::spawn_blocking(move || hci::set_phy(dev_id, address, tx, rx))
+                .await?
+                .map_err(hci::to_error)
+        }
+        #[cfg(not(feature = "hci"))]
+        Err(Error::NotSupported(
+            "Setting the PHY on BlueZ needs the hci feature".to_string(),
+        ))
+    }
+
+    /// BlueZ has no D-Bus API for this, so it's read with an HCI command, which needs the `hci`
+    /// feature and the `CAP_NET_RAW` capability.
+    async fn read_phy(&self) -> Result<(Phy, Phy)> {
+        #[cfg(feature = "hci")]
+        {
+            let (dev_id, address) = self.hci_connection()?;
+            let (tx, rx) = runtime::spawn_blocking(move || hci::read_phy(dev_id, address))
+                .await?
+                .map_err(hci::to_error)?;
+            Ok((to_phy(tx)?, to_phy(rx)?))
+        }
+        #[cfg(not(feature = "hci"))]
+        Err(Error::NotSupported(
+            "Reading the PHY on BlueZ needs the hci feature".to_string(),
+        ))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.mac_address), err)
+    )]
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        let timeout = self.options.lock().unwrap().discovery_timeout;
+        util::with_timeout(timeout, async {
+            let mut characteristics = vec![];
+            let services = self.session.get_services(&self.device).await?;
+            for service in services {
+                let service_characteristics = self.session.get_characteristics(&service.id).await?;
+                for info in service_characteristics {
+                    characteristics.push((to_characteristic(service.uuid, &info), info));
+                }
+            }
+            let converted = characteristics
+                .iter()
+                .map(|(characteristic, _)| characteristic.clone())
+                .collect();
+            *self.characteristics.lock().unwrap() = characteristics;
+            Ok(converted)
+        })
+        .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.mac_address), err)
+    )]
+    async fn discover_services(&self) -> Result<Vec<Service>> {
+        let timeout = self.options.lock().unwrap().discovery_timeout;
+        util::with_timeout(timeout, async {
+            let mut characteristics = vec![];
+            let mut converted = vec![];
+            let services = self.session.get_services(&self.device).await?;
+            let paths: Vec<Path<'static>> = services
+                .iter()
+                .map(|service| service.id.clone().into())
+                .collect();
+            let includes = includes::included_services(paths.clone()).await?;
+            for (service, included_paths) in services.iter().zip(includes) {
+                let included_services = included_paths
+                    .iter()
+                    .filter_map(|included| {
+                        let index = paths.iter().position(|path| path == included)?;
+                        Some(services[index].uuid)
+                    })
+                    .collect();
+                let service_characteristics = self.session.get_characteristics(&service.id).await?;
+                let mut converted_characteristics = BTreeSet::new();
+                for info in service_characteristics {
+                    let characteristic = to_characteristic(service.uuid, &info);
+                    converted_characteristics.insert(characteristic.clone());
+                    characteristics.push((characteristic, info));
+                }
+                converted.push(Service {
+                    uuid: service.uuid,
+                    primary: service.primary,
+                    characteristics: converted_characteristics,
+                    included_services,
+                });
+            }
+            *self.characteristics.lock().unwrap() = characteristics;
+            Ok(converted)
+        })
+        .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.mac_address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
     async fn write(
         &self,
         characteristic: &Characteristic,
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        let options = WriteOptions {
-            write_type: Some(write_type.into()),
-            ..Default::default()
-        };
-        Ok(self
-            .session
-            .write_characteristic_value_with_options(&characteristic_info.id, data, options)
-            .await?)
+        let _operation = self.metrics.gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.write_timeout, options.retry, move || async move {
+                let characteristic_info = self.characteristic_info(characteristic)?;
+                let write_options = WriteOptions {
+                    write_type: Some(write_type.into()),
+                    ..Default::default()
+                };
+                Ok(self
+                    .session
+                    .write_characteristic_value_with_options(
+                        &characteristic_info.id,
+                        data,
+                        write_options,
+                    )
+                    .await?)
+            })
+            .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.mac_address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn write_long(
+        &self,
+        characteristic: &Characteristic,
+        offset: u16,
+        data: &[u8],
+    ) -> Result<()> {
+        let _operation = self.metrics.gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.write_timeout, options.retry, move || async move {
+                // BlueZ uses prepared writes itself when the value doesn't fit in a single
+                // request, or when it is written at an offset.
+                let characteristic_info = self.characteristic_info(characteristic)?;
+                let write_options = WriteOptions {
+                    offset: offset.into(),
+                    write_type: Some(bluez_async::WriteType::WithResponse),
+                };
+                Ok(self
+                    .session
+                    .write_characteristic_value_with_options(
+                        &characteristic_info.id,
+                        data,
+                        write_options,
+                    )
+                    .await?)
+            })
+            .await
+    }
+
+    async fn wait_until_ready_to_write(&self) -> Result<()> {
+        // BlueZ queues writes without response itself, and replies once they are queued.
+        if self.is_connected().await? {
+            Ok(())
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.mac_address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn read(&self, characteristic: &Characteristic) -> Result<Bytes> {
+        let _operation = self.metrics.gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.read_timeout, options.retry, move || async move {
+                let characteristic_info = self.characteristic_info(characteristic)?;
+                let value = self
+                    .session
+                    .read_characteristic_value(&characteristic_info.id)
+                    .await?;
+                Ok(value.into())
+            })
+            .await
+    }
+
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>> {
+        let timeout = self.options.lock().unwrap().discovery_timeout;
+        util::with_timeout(timeout, async {
+            let characteristic_info = self.characteristic_info(characteristic)?;
+            let descriptors = self
+                .session
+                .get_descriptors(&characteristic_info.id)
+                .await?;
+            let converted = descriptors
+                .iter()
+                .map(|descriptor| Descriptor {
+                    uuid: descriptor.uuid,
+                    characteristic_uuid: characteristic.uuid,
+                    characteristic_handle: characteristic.handle,
+                })
+                .collect();
+            let mut known_descriptors = self.descriptors.lock().unwrap();
+            known_descriptors.retain(|info| info.id.characteristic() != characteristic_info.id);
+            known_descriptors.extend(descriptors);
+            Ok(converted)
+        })
+        .await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let _operation = self.metrics.gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.read_timeout, options.retry, move || async move {
+                let descriptor_info = self.descriptor_info(descriptor)?;
+                Ok(self
+                    .session
+                    .read_descriptor_value(&descriptor_info.id)
+                    .await?)
+            })
+            .await
     }
 
-    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self
-            .session
-            .read_characteristic_value(&characteristic_info.id)
-            .await?)
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let _operation = self.metrics.gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.write_timeout, options.retry, move || async move {
+                let descriptor_info = self.descriptor_info(descriptor)?;
+                Ok(self
+                    .session
+                    .write_descriptor_value(&descriptor_info.id, data)
+                    .await?)
+            })
+            .await
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.mac_address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self.session.start_notify(&characteristic_info.id).await?)
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(
+                options.subscribe_timeout,
+                options.retry,
+                move || async move {
+                    let characteristic_info = self.characteristic_info(characteristic)?;
+                    if self
+                        .subscriptions
+                        .lock()
+                        .unwrap()
+                        .subscribe_if_enabled(characteristic)
+                    {
+                        return Ok(());
+                    }
+                    // Only counted once notifications are enabled, as this future is dropped if
+                    // the operation times out.
+                    self.session.start_notify(&characteristic_info.id).await?;
+                    self.subscriptions.lock().unwrap().subscribe(characteristic);
+                    Ok(())
+                },
+            )
+            .await
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.mac_address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        let characteristic_info = self.characteristic_info(characteristic)?;
-        Ok(self.session.stop_notify(&characteristic_info.id).await?)
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(
+                options.subscribe_timeout,
+                options.retry,
+                move || async move {
+                    let characteristic_info = self.characteristic_info(characteristic)?;
+                    if !self
+                        .subscriptions
+                        .lock()
+                        .unwrap()
+                        .unsubscribe(characteristic)
+                    {
+                        return Ok(());
+                    }
+                    Ok(self.session.stop_notify(&characteristic_info.id).await?)
+                },
+            )
+            .await
+    }
+
+    fn is_subscribed(&self, characteristic: &Characteristic) -> bool {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .is_subscribed(characteristic)
+    }
+
+    fn subscriptions(&self) -> BTreeSet<Characteristic> {
+        self.subscriptions.lock().unwrap().subscribed()
     }
 
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
         let device_id = self.device.clone();
         let events = self.session.device_event_stream(&device_id).await?;
         let characteristics = self.characteristics.clone();
-        Ok(Box::pin(events.filter_map(move |event| {
-            ready(value_notification(
+        let subscriptions = self.subscriptions.clone();
+        let disconnected_device_id = device_id.clone();
+        // The stream ends when the device disconnects, as its subscriptions end with it.
+        let events = events.take_while(move |event| {
+            let disconnected = matches!(
                 event,
-                &device_id,
-                characteristics.clone(),
-            ))
-        })))
+                BluetoothEvent::Device {
+                    id,
+                    event: DeviceEvent::Connected { connected: false },
+                } if *id == disconnected_device_id
+            );
+            if disconnected {
+                subscriptions.lock().unwrap().disconnected();
+            }
+            ready(!disconnected)
+        });
+        let metrics = self.metrics.clone();
+        let notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>> =
+            Box::pin(events.filter_map(move |event| {
+                let notification = value_notification(event, &device_id, characteristics.clone());
+                if notification.is_some() {
+                    metrics.notification_received();
+                }
+                ready(notification)
+            }));
+        Ok(match self.options.lock().unwrap().notification_capacity {
+            Some(capacity) => util::bounded_notifications(
+                notifications,
+                capacity,
+                self.dropped_notifications.clone(),
+                self.metrics.clone(),
+            ),
+            None => notifications,
+        })
+    }
+
+    fn dropped_notifications(&self) -> u64 {
+        self.dropped_notifications.load(Ordering::Relaxed)
+    }
+
+    async fn notifications_for(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        Ok(util::notifications_for(
+            self.notifications().await?,
+            characteristic,
+        ))
+    }
+
+    async fn open_l2cap_channel(&self, _psm: u16) -> Result<Box<dyn L2capChannel>> {
+        Err(Error::NotSupported(
+            "L2CAP channels are not supported on BlueZ".to_string(),
+        ))
     }
 }
 
 fn value_notification(
     event: BluetoothEvent,
     device_id: &DeviceId,
-    characteristics: Arc<Mutex<Vec<CharacteristicInfo>>>,
+    characteristics: Arc<Mutex<Vec<(Characteristic, CharacteristicInfo)>>>,
 ) -> Option<ValueNotification> {
     match event {
         BluetoothEvent::Characteristic {
@@ -167,16 +813,52 @@ fn value_notification(
             event: CharacteristicEvent::Value { value },
         } if id.service().device() == *device_id => {
             let characteristics = characteristics.lock().unwrap();
-            let uuid = characteristics
-                .iter()
-                .find(|characteristic| characteristic.id == id)?
-                .uuid;
-            Some(ValueNotification { uuid, value })
+            let (characteristic, _) = characteristics.iter().find(|(_, info)| info.id == id)?;
+            Some(ValueNotification {
+                uuid: characteristic.uuid,
+                service_uuid: characteristic.service_uuid,
+                handle: characteristic.handle,
+                value: value.into(),
+            })
         }
         _ => None,
     }
 }
 
+/// Converts a duration to the number of whole units of the given length in microseconds, as used
+/// by the HCI connection parameters.
+fn to_units(duration: Duration, unit_micros: u128) -> u16 {
+    (duration.as_micros() / unit_micros).min(u16::MAX as u128) as u16
+}
+
+fn conn_param_path(adapter: &str, name: &str) -> String {
+    format!("/sys/kernel/debug/bluetooth/{}/{}", adapter, name)
+}
+
+fn read_conn_param(adapter: &str, name: &str) -> Result<u16> {
+    let value = fs::read_to_string(conn_param_path(adapter, name)).map_err(conn_param_error)?;
+    value
+        .trim()
+        .parse()
+        .map_err(|e: std::num::ParseIntError| Error::Other(e.into()))
+}
+
+fn write_conn_param(adapter: &str, name: &str, value: u16) -> Result<()> {
+    fs::write(conn_param_path(adapter, name), value.to_string()).map_err(conn_param_error)
+}
+
+fn conn_param_error(error: io::Error) -> Error {
+    match error.kind() {
+        // Only root can see into debugfs.
+        io::ErrorKind::PermissionDenied => Error::PermissionDenied,
+        io::ErrorKind::NotFound => Error::NotSupported(
+            "Setting connection parameters on BlueZ needs debugfs mounted at /sys/kernel/debug"
+                .to_string(),
+        ),
+        _ => Error::Other(error.into()),
+    }
+}
+
 impl From<WriteType> for bluez_async::WriteType {
     fn from(write_type: WriteType) -> Self {
         match write_type {
@@ -201,15 +883,25 @@ impl From<bluez_async::AddressType> for AddressType {
     }
 }
 
-impl From<&CharacteristicInfo> for Characteristic {
-    fn from(characteristic: &CharacteristicInfo) -> Self {
-        Characteristic {
-            uuid: characteristic.uuid,
-            properties: characteristic.flags.into(),
-        }
+fn to_characteristic(service_uuid: Uuid, info: &CharacteristicInfo) -> Characteristic {
+    Characteristic {
+        uuid: info.uuid,
+        service_uuid,
+        handle: characteristic_handle(&info.id),
+        properties: info.flags.into(),
     }
 }
 
+/// BlueZ names the object for each characteristic after its handle, as in `.../char000b`.
+fn characteristic_handle(id: &CharacteristicId) -> u16 {
+    let path: Path = id.clone().into();
+    path.rsplit('/')
+        .next()
+        .and_then(|name| name.strip_prefix("char"))
+        .and_then(|handle| u16::from_str_radix(handle, 16).ok())
+        .unwrap_or_default()
+}
+
 impl From<CharacteristicFlags> for CharPropFlags {
     fn from(flags: CharacteristicFlags) -> Self {
         let mut result = CharPropFlags::default();
@@ -237,6 +929,13 @@ impl From<CharacteristicFlags> for CharPropFlags {
         if flags.contains(CharacteristicFlags::EXTENDED_PROPERTIES) {
             result.insert(CharPropFlags::EXTENDED_PROPERTIES);
         }
+        // BlueZ reads the Characteristic Extended Properties descriptor itself.
+        if flags.contains(CharacteristicFlags::RELIABLE_WRITE) {
+            result.insert(CharPropFlags::RELIABLE_WRITE);
+        }
+        if flags.contains(CharacteristicFlags::WRITABLE_AUXILIARIES) {
+            result.insert(CharPropFlags::WRITABLE_AUXILIARIES);
+        }
         result
     }
 }
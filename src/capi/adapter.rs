@@ -0,0 +1,252 @@
+use super::{
+    arg, ffi_call, hand_out, out, slice, BtleplugAddress, BtleplugPeripheral, BtleplugResult,
+    BtleplugUuid, Failure, UserData,
+};
+use crate::{
+    api::{BDAddr, Central, CentralEvent, ScanFilter},
+    platform::Adapter,
+};
+use futures::{
+    future::{self, AbortHandle},
+    stream::StreamExt,
+};
+use std::{
+    os::raw::c_void,
+    sync::{Arc, Mutex},
+};
+use tokio::runtime::Runtime;
+
+/// A Bluetooth adapter, through which peripherals are scanned for.
+pub struct BtleplugAdapter {
+    runtime: Arc<Runtime>,
+    adapter: Adapter,
+    events: Mutex<Option<AbortHandle>>,
+}
+
+impl BtleplugAdapter {
+    pub(super) fn new(runtime: Arc<Runtime>, adapter: Adapter) -> Self {
+        BtleplugAdapter {
+            runtime,
+            adapter,
+            events: Mutex::new(None),
+        }
+    }
+}
+
+impl Drop for BtleplugAdapter {
+    fn drop(&mut self) {
+        if let Some(events) = self.events.get_mut().unwrap().take() {
+            events.abort();
+        }
+    }
+}
+
+/// The kind of a [`BtleplugEvent`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BtleplugEventKind {
+    DeviceDiscovered,
+    DeviceUpdated,
+    DeviceConnected,
+    DeviceDisconnected,
+    DeviceLost,
+    /// A device's signal strength was measured, in `rssi`.
+    RssiUpdate,
+}
+
+/// An event from an adapter, about the peripheral with the given address.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BtleplugEvent {
+    pub kind: BtleplugEventKind,
+    pub address: BtleplugAddress,
+    /// The signal strength in dBm, for [`BtleplugEventKind::RssiUpdate`] events, or 0.
+    pub rssi: i16,
+}
+
+impl BtleplugEvent {
+    fn from_central_event(event: &CentralEvent) -> Option<Self> {
+        let (kind, address, rssi) = match *event {
+            CentralEvent::DeviceDiscovered(address) => {
+                (BtleplugEventKind::DeviceDiscovered, address, 0)
+            }
+            CentralEvent::DeviceUpdated { address, .. } => {
+                (BtleplugEventKind::DeviceUpdated, address, 0)
+            }
+            CentralEvent::DeviceConnected(address) => {
+                (BtleplugEventKind::DeviceConnected, address, 0)
+            }
+            CentralEvent::DeviceDisconnected(address, _) => {
+                (BtleplugEventKind::DeviceDisconnected, address, 0)
+            }
+            CentralEvent::DeviceLost(address) => (BtleplugEventKind::DeviceLost, address, 0),
+            CentralEvent::RssiUpdate { address, rssi } => {
+                (BtleplugEventKind::RssiUpdate, address, rssi)
+            }
+            _ => return None,
+        };
+        Some(BtleplugEvent {
+            kind,
+            address: BtleplugAddress {
+                bytes: address.into_inner(),
+            },
+            rssi,
+        })
+    }
+}
+
+/// Called with each event from an adapter, and the `user_data` it was registered with. The event
+/// is only valid for the duration of the call.
+pub type BtleplugEventCallback =
+    Option<unsafe extern "C" fn(event: *const BtleplugEvent, user_data: *mut c_void)>;
+
+/// Frees an adapter, stopping its event callback if one was set.
+///
+/// # Safety
+///
+/// `adapter` must be null or a handle which hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_adapter_free(adapter: *mut BtleplugAdapter) {
+    if !adapter.is_null() {
+        drop(Box::from_raw(adapter));
+    }
+}
+
+/// Starts scanning for peripherals. If `service_count` isn't 0, only peripherals advertising at
+/// least one of the given services are reported.
+///
+/// # Safety
+///
+/// `adapter` must be a valid handle, and `services` must be valid for reads of `service_count`
+/// UUIDs.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_adapter_start_scan(
+    adapter: *const BtleplugAdapter,
+    services: *const BtleplugUuid,
+    service_count: usize,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let adapter = arg(adapter, "adapter")?;
+        let filter = ScanFilter {
+            services: slice(services, service_count, "services")?
+                .iter()
+                .map(|&uuid| uuid.into())
+                .collect(),
+            ..ScanFilter::default()
+        };
+        adapter
+            .runtime
+            .block_on(adapter.adapter.start_scan(filter))?;
+        Ok(())
+    })
+}
+
+/// Stops scanning for peripherals.
+///
+/// # Safety
+///
+/// `adapter` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_adapter_stop_scan(
+    adapter: *const BtleplugAdapter,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let adapter = arg(adapter, "adapter")?;
+        adapter.runtime.block_on(adapter.adapter.stop_scan())?;
+        Ok(())
+    })
+}
+
+/// Sets the function to call with each event from the adapter, replacing any set before, or stops
+/// calling one if `callback` is null.
+///
+/// # Safety
+///
+/// `adapter` must be a valid handle, and `callback` must be safe to call with `user_data` from
+/// any thread until it is replaced or the adapter is freed.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_adapter_set_event_callback(
+    adapter: *const BtleplugAdapter,
+    callback: BtleplugEventCallback,
+    user_data: *mut c_void,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let adapter = arg(adapter, "adapter")?;
+        let mut current = adapter.events.lock().unwrap();
+        if let Some(events) = current.take() {
+            events.abort();
+        }
+        let callback = match callback {
+            Some(callback) => callback,
+            None => return Ok(()),
+        };
+        let events = adapter.runtime.block_on(adapter.adapter.events())?;
+        let user_data = UserData(user_data);
+        let (forward, abort_handle) = future::abortable(events.for_each(move |event| {
+            if let Some(event) = BtleplugEvent::from_central_event(&event) {
+                unsafe { callback(&event, user_data.0) };
+            }
+            future::ready(())
+        }));
+        adapter.runtime.spawn(forward);
+        *current = Some(abort_handle);
+        Ok(())
+    })
+}
+
+/// Writes a handle for the peripheral with the given address to `peripheral`, if the adapter has
+/// discovered it.
+///
+/// # Safety
+///
+/// `adapter` must be a valid handle, `address` must be valid for reads, and `peripheral` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_adapter_peripheral(
+    adapter: *const BtleplugAdapter,
+    address: *const BtleplugAddress,
+    peripheral: *mut *mut BtleplugPeripheral,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let adapter = arg(adapter, "adapter")?;
+        let address = BDAddr::from(arg(address, "address")?.bytes);
+        let peripheral = out(peripheral, "peripheral")?;
+        let found = adapter
+            .runtime
+            .block_on(adapter.adapter.peripheral(address))?;
+        *peripheral = Box::into_raw(Box::new(BtleplugPeripheral::new(
+            adapter.runtime.clone(),
+            found,
+        )));
+        Ok(())
+    })
+}
+
+/// Writes handles for up to `capacity` of the peripherals which the adapter has discovered to
+/// `peripherals`, and the number of them to `count`. If `count` is larger than `capacity`, the
+/// rest are left out, and the call can be repeated with a larger array.
+///
+/// # Safety
+///
+/// `adapter` must be a valid handle, `peripherals` must be valid for writes of `capacity` handles,
+/// and `count` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_adapter_peripherals(
+    adapter: *const BtleplugAdapter,
+    peripherals: *mut *mut BtleplugPeripheral,
+    capacity: usize,
+    count: *mut usize,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let adapter = arg(adapter, "adapter")?;
+        if peripherals.is_null() && capacity > 0 {
+            return Err(Failure::InvalidArgument("peripherals"));
+        }
+        let found = adapter.runtime.block_on(adapter.adapter.peripherals())?;
+        let found = found
+            .into_iter()
+            .map(|peripheral| BtleplugPeripheral::new(adapter.runtime.clone(), peripheral))
+            .collect();
+        hand_out(found, peripherals, capacity, count)
+    })
+}
@@ -0,0 +1,315 @@
+//! A C API for btleplug, enabled with the `capi` feature, so that it can be used from C, C++ and
+//! other languages with a C FFI without writing bindings to the Rust API.
+//!
+//! The declarations are in `include/btleplug.h`, which is generated from this module with
+//! [cbindgen](https://github.com/eqrion/cbindgen):
+//!
+//! ```sh
+//! cbindgen --config cbindgen.toml --output include/btleplug.h
+//! ```
+//!
+//! The manager, adapters and peripherals are passed to C as opaque handles, which are created by
+//! btleplug and must be released with the matching `_free` function. Each handle keeps the runtime
+//! of the manager it came from alive, so they can be freed in any order, but not from within a
+//! callback.
+//!
+//! Every function blocks until its operation completes, and returns a [`BtleplugResult`]. If it
+//! isn't [`BtleplugResult::Ok`], [`btleplug_last_error_message`] describes what went wrong. Events
+//! and notifications are passed to callbacks, which are called on the runtime's threads, so must
+//! be thread-safe and shouldn't block.
+
+mod adapter;
+mod peripheral;
+
+pub use adapter::*;
+pub use peripheral::*;
+
+use crate::{api::Manager as _, platform::Manager, Error};
+use log::error;
+use std::{
+    cell::RefCell,
+    ffi::CString,
+    os::raw::c_char,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::Arc,
+};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+/// The result of a call to the C API.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BtleplugResult {
+    Ok = 0,
+    /// A handle or argument was null or invalid, such as the UUID of a characteristic which hasn't
+    /// been discovered.
+    InvalidArgument,
+    /// A buffer was too small for the result, whose length has been returned.
+    BufferTooSmall,
+    PermissionDenied,
+    DeviceNotFound,
+    NotConnected,
+    NotSupported,
+    TimedOut,
+    /// The device returned an ATT error.
+    Att,
+    /// The link needs to be paired, or pairing failed.
+    InsufficientAuthentication,
+    DeviceUnreachable,
+    Other,
+}
+
+/// A Bluetooth device address, most significant byte first.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BtleplugAddress {
+    pub bytes: [u8; 6],
+}
+
+/// A UUID, in big-endian byte order.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BtleplugUuid {
+    pub bytes: [u8; 16],
+}
+
+impl From<Uuid> for BtleplugUuid {
+    fn from(uuid: Uuid) -> Self {
+        BtleplugUuid {
+            bytes: *uuid.as_bytes(),
+        }
+    }
+}
+
+impl From<BtleplugUuid> for Uuid {
+    fn from(uuid: BtleplugUuid) -> Self {
+        Uuid::from_bytes(uuid.bytes)
+    }
+}
+
+/// The entry point of the C API, from which adapters are found.
+pub struct BtleplugManager {
+    runtime: Arc<Runtime>,
+    manager: Manager,
+}
+
+/// Why a call failed, including the ways which are particular to the C API.
+enum Failure {
+    Error(Error),
+    InvalidArgument(&'static str),
+    BufferTooSmall,
+    Panic,
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Failure::Error(error)
+    }
+}
+
+type Result<T> = std::result::Result<T, Failure>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Runs the body of a function of the C API, turning its result into a [`BtleplugResult`] and
+/// keeping any error message for [`btleplug_last_error_message`]. Panics are caught, as they must
+/// not unwind into C.
+fn ffi_call(body: impl FnOnce() -> Result<()>) -> BtleplugResult {
+    let result = panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(Err(Failure::Panic));
+    let (code, message) = match result {
+        Ok(()) => (BtleplugResult::Ok, None),
+        Err(Failure::InvalidArgument(argument)) => (
+            BtleplugResult::InvalidArgument,
+            Some(format!("Invalid argument: {}", argument)),
+        ),
+        Err(Failure::BufferTooSmall) => (
+            BtleplugResult::BufferTooSmall,
+            Some("The buffer is too small".to_string()),
+        ),
+        Err(Failure::Panic) => (BtleplugResult::Other, Some("btleplug panicked".to_string())),
+        Err(Failure::Error(error)) => {
+            let code = match error {
+                Error::PermissionDenied => BtleplugResult::PermissionDenied,
+                Error::DeviceNotFound => BtleplugResult::DeviceNotFound,
+                Error::NotConnected => BtleplugResult::NotConnected,
+                Error::NotSupported(_) => BtleplugResult::NotSupported,
+                Error::TimedOut(_) | Error::ConnectionTimeout(_) => BtleplugResult::TimedOut,
+                Error::Uuid(_) | Error::InvalidBDAddr(_) => BtleplugResult::InvalidArgument,
+                Error::Att(_) => BtleplugResult::Att,
+                Error::InsufficientAuthentication(_) => BtleplugResult::InsufficientAuthentication,
+                Error::DeviceUnreachable(_) => BtleplugResult::DeviceUnreachable,
+                Error::Platform { .. } | Error::Other(_) => BtleplugResult::Other,
+            };
+            (code, Some(error.to_string()))
+        }
+    };
+    if let Some(message) = &message {
+        error!("C API call failed: {}", message);
+    }
+    LAST_ERROR.with(|last_error| {
+        *last_error.borrow_mut() =
+            message.map(|message| CString::new(message.replace('\0', "")).unwrap());
+    });
+    code
+}
+
+/// Returns a reference to the value behind a handle or pointer argument, failing if it is null.
+///
+/// # Safety
+///
+/// The pointer must be null or valid for the lifetime it is used for.
+unsafe fn arg<'a, T>(pointer: *const T, name: &'static str) -> Result<&'a T> {
+    pointer.as_ref().ok_or(Failure::InvalidArgument(name))
+}
+
+/// Returns a mutable reference to an out argument, failing if it is null.
+///
+/// # Safety
+///
+/// The pointer must be null or valid for writes.
+unsafe fn out<'a, T>(pointer: *mut T, name: &'static str) -> Result<&'a mut T> {
+    pointer.as_mut().ok_or(Failure::InvalidArgument(name))
+}
+
+/// Returns the slice of `len` values starting at `pointer`, which may be null if `len` is 0.
+///
+/// # Safety
+///
+/// Unless `len` is 0, the pointer must be valid for reads of `len` values.
+unsafe fn slice<'a, T>(pointer: *const T, len: usize, name: &'static str) -> Result<&'a [T]> {
+    if len == 0 {
+        Ok(&[])
+    } else if pointer.is_null() {
+        Err(Failure::InvalidArgument(name))
+    } else {
+        Ok(std::slice::from_raw_parts(pointer, len))
+    }
+}
+
+/// Copies `value` to the buffer of `capacity` bytes at `buffer`, and its length to `len`, failing
+/// with [`Failure::BufferTooSmall`] if it doesn't fit.
+///
+/// # Safety
+///
+/// The buffer must be valid for writes of `capacity` bytes, and `len` must be null or valid for
+/// writes.
+unsafe fn copy_out(value: &[u8], buffer: *mut u8, capacity: usize, len: *mut usize) -> Result<()> {
+    *out(len, "len")? = value.len();
+    if value.len() > capacity {
+        return Err(Failure::BufferTooSmall);
+    }
+    if !value.is_empty() {
+        ptr::copy_nonoverlapping(value.as_ptr(), buffer, value.len());
+    }
+    Ok(())
+}
+
+/// Passes ownership of each of `items` to C as a handle, writing up to `capacity` of them to
+/// `handles`, and the number of items to `count`.
+///
+/// # Safety
+///
+/// `handles` must be valid for writes of `capacity` pointers, and `count` must be null or valid
+/// for writes.
+unsafe fn hand_out<T>(
+    items: Vec<T>,
+    handles: *mut *mut T,
+    capacity: usize,
+    count: *mut usize,
+) -> Result<()> {
+    *out(count, "count")? = items.len();
+    for (index, item) in items.into_iter().take(capacity).enumerate() {
+        *handles.add(index) = Box::into_raw(Box::new(item));
+    }
+    Ok(())
+}
+
+/// A `void *` passed back to a callback, which C is responsible for making safe to use from
+/// whichever thread the callback is called on.
+#[derive(Clone, Copy)]
+struct UserData(*mut std::os::raw::c_void);
+
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+/// Returns a description of the last error on this thread, or null if the last call succeeded.
+/// The string remains valid until the next call to the C API on this thread.
+#[no_mangle]
+pub extern "C" fn btleplug_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Creates a manager, along with the runtime on which btleplug's operations are run, and writes
+/// its handle to `manager`.
+///
+/// # Safety
+///
+/// `manager` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_manager_new(
+    manager: *mut *mut BtleplugManager,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let manager = out(manager, "manager")?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("btleplug")
+            .build()
+            .map_err(|e| Error::Other(Box::new(e)))?;
+        let platform_manager = runtime.block_on(Manager::new())?;
+        *manager = Box::into_raw(Box::new(BtleplugManager {
+            runtime: Arc::new(runtime),
+            manager: platform_manager,
+        }));
+        Ok(())
+    })
+}
+
+/// Frees a manager. Adapters and peripherals found through it remain valid until they are freed.
+///
+/// # Safety
+///
+/// `manager` must be null or a handle from [`btleplug_manager_new`] which hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_manager_free(manager: *mut BtleplugManager) {
+    if !manager.is_null() {
+        drop(Box::from_raw(manager));
+    }
+}
+
+/// Finds the Bluetooth adapters on the system, writing handles for up to `capacity` of them to
+/// `adapters`, and the number found to `count`. If `count` is larger than `capacity`, the rest
+/// are left out, and the call can be repeated with a larger array.
+///
+/// # Safety
+///
+/// `manager` must be a valid handle, `adapters` must be valid for writes of `capacity` handles,
+/// and `count` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_manager_adapters(
+    manager: *const BtleplugManager,
+    adapters: *mut *mut BtleplugAdapter,
+    capacity: usize,
+    count: *mut usize,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let manager = arg(manager, "manager")?;
+        if adapters.is_null() && capacity > 0 {
+            return Err(Failure::InvalidArgument("adapters"));
+        }
+        let found = manager.runtime.block_on(manager.manager.adapters())?;
+        let found = found
+            .into_iter()
+            .map(|adapter| BtleplugAdapter::new(manager.runtime.clone(), adapter))
+            .collect();
+        hand_out(found, adapters, capacity, count)
+    })
+}
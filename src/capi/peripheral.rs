@@ -0,0 +1,351 @@
+use super::{
+    arg, copy_out, ffi_call, out, slice, BtleplugAddress, BtleplugResult, BtleplugUuid, Failure,
+    UserData,
+};
+use crate::{
+    api::{Characteristic, Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use futures::{
+    future::{self, AbortHandle},
+    stream::StreamExt,
+};
+use std::{
+    os::raw::{c_char, c_void},
+    sync::{Arc, Mutex},
+};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+/// A peripheral found by an adapter.
+pub struct BtleplugPeripheral {
+    runtime: Arc<Runtime>,
+    peripheral: Peripheral,
+    notifications: Mutex<Option<AbortHandle>>,
+}
+
+impl BtleplugPeripheral {
+    pub(super) fn new(runtime: Arc<Runtime>, peripheral: Peripheral) -> Self {
+        BtleplugPeripheral {
+            runtime,
+            peripheral,
+            notifications: Mutex::new(None),
+        }
+    }
+
+    /// Returns the discovered characteristic with the given UUID.
+    fn characteristic(&self, uuid: *const BtleplugUuid) -> Result<Characteristic, Failure> {
+        let uuid = Uuid::from(*unsafe { arg(uuid, "characteristic") }?);
+        self.peripheral
+            .characteristics()
+            .into_iter()
+            .find(|characteristic| characteristic.uuid == uuid)
+            .ok_or(Failure::InvalidArgument(
+                "characteristic, which hasn't been discovered",
+            ))
+    }
+}
+
+impl Drop for BtleplugPeripheral {
+    fn drop(&mut self) {
+        if let Some(notifications) = self.notifications.get_mut().unwrap().take() {
+            notifications.abort();
+        }
+    }
+}
+
+/// Called with each notification or indication from a peripheral, with the UUID of the
+/// characteristic it came from, its value and the `user_data` it was registered with. The
+/// arguments are only valid for the duration of the call.
+pub type BtleplugNotificationCallback = Option<
+    unsafe extern "C" fn(
+        characteristic: *const BtleplugUuid,
+        value: *const u8,
+        value_len: usize,
+        user_data: *mut c_void,
+    ),
+>;
+
+/// Frees a peripheral, stopping its notification callback if one was set. This doesn't disconnect
+/// from it.
+///
+/// # Safety
+///
+/// `peripheral` must be null or a handle which hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_free(peripheral: *mut BtleplugPeripheral) {
+    if !peripheral.is_null() {
+        drop(Box::from_raw(peripheral));
+    }
+}
+
+/// Writes the address of the peripheral to `address`.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle, and `address` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_address(
+    peripheral: *const BtleplugPeripheral,
+    address: *mut BtleplugAddress,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        *out(address, "address")? = BtleplugAddress {
+            bytes: peripheral.peripheral.address().into_inner(),
+        };
+        Ok(())
+    })
+}
+
+/// Copies the advertised name of the peripheral to `name`, as a NUL-terminated UTF-8 string, and
+/// its length, including the terminator, to `len`. If the peripheral hasn't advertised a name, an
+/// empty string is copied. If the name doesn't fit in `capacity` bytes,
+/// [`BtleplugResult::BufferTooSmall`] is returned and nothing is copied.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle, `name` must be valid for writes of `capacity` bytes, and
+/// `len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_local_name(
+    peripheral: *const BtleplugPeripheral,
+    name: *mut c_char,
+    capacity: usize,
+    len: *mut usize,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        let properties = peripheral
+            .runtime
+            .block_on(peripheral.peripheral.properties())?;
+        let mut local_name = properties
+            .and_then(|properties| properties.local_name)
+            .unwrap_or_default()
+            .replace('\0', "")
+            .into_bytes();
+        local_name.push(0);
+        copy_out(&local_name, name as *mut u8, capacity, len)
+    })
+}
+
+/// Connects to the peripheral.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_connect(
+    peripheral: *const BtleplugPeripheral,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        peripheral
+            .runtime
+            .block_on(peripheral.peripheral.connect())?;
+        Ok(())
+    })
+}
+
+/// Disconnects from the peripheral.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_disconnect(
+    peripheral: *const BtleplugPeripheral,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        peripheral
+            .runtime
+            .block_on(peripheral.peripheral.disconnect())?;
+        Ok(())
+    })
+}
+
+/// Writes whether the peripheral is connected to `connected`.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle, and `connected` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_is_connected(
+    peripheral: *const BtleplugPeripheral,
+    connected: *mut bool,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        let connected = out(connected, "connected")?;
+        *connected = peripheral
+            .runtime
+            .block_on(peripheral.peripheral.is_connected())?;
+        Ok(())
+    })
+}
+
+/// Discovers the services and characteristics of the peripheral, which must be done after
+/// connecting before its characteristics can be used.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_discover_services(
+    peripheral: *const BtleplugPeripheral,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        peripheral
+            .runtime
+            .block_on(peripheral.peripheral.discover_services())?;
+        Ok(())
+    })
+}
+
+/// Reads the value of the characteristic with the given UUID, copying it to `value` and its
+/// length to `len`. If it doesn't fit in `capacity` bytes, [`BtleplugResult::BufferTooSmall`] is
+/// returned and nothing is copied.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle, `characteristic` must be valid for reads, `value` must be
+/// valid for writes of `capacity` bytes, and `len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_read(
+    peripheral: *const BtleplugPeripheral,
+    characteristic: *const BtleplugUuid,
+    value: *mut u8,
+    capacity: usize,
+    len: *mut usize,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        let characteristic = peripheral.characteristic(characteristic)?;
+        let read = peripheral
+            .runtime
+            .block_on(peripheral.peripheral.read(&characteristic))?;
+        copy_out(&read, value, capacity, len)
+    })
+}
+
+/// Writes `value_len` bytes from `value` to the characteristic with the given UUID, waiting for
+/// the peripheral to acknowledge them if `with_response` is true.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle, `characteristic` must be valid for reads, and `value` must
+/// be valid for reads of `value_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_write(
+    peripheral: *const BtleplugPeripheral,
+    characteristic: *const BtleplugUuid,
+    value: *const u8,
+    value_len: usize,
+    with_response: bool,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        let characteristic = peripheral.characteristic(characteristic)?;
+        let value = slice(value, value_len, "value")?;
+        let write_type = if with_response {
+            WriteType::WithResponse
+        } else {
+            WriteType::WithoutResponse
+        };
+        peripheral.runtime.block_on(peripheral.peripheral.write(
+            &characteristic,
+            value,
+            write_type,
+        ))?;
+        Ok(())
+    })
+}
+
+/// Enables notifications or indications from the characteristic with the given UUID, which are
+/// passed to the notification callback.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle, and `characteristic` must be valid for reads.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_subscribe(
+    peripheral: *const BtleplugPeripheral,
+    characteristic: *const BtleplugUuid,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        let characteristic = peripheral.characteristic(characteristic)?;
+        peripheral
+            .runtime
+            .block_on(peripheral.peripheral.subscribe(&characteristic))?;
+        Ok(())
+    })
+}
+
+/// Disables notifications or indications from the characteristic with the given UUID.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle, and `characteristic` must be valid for reads.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_unsubscribe(
+    peripheral: *const BtleplugPeripheral,
+    characteristic: *const BtleplugUuid,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        let characteristic = peripheral.characteristic(characteristic)?;
+        peripheral
+            .runtime
+            .block_on(peripheral.peripheral.unsubscribe(&characteristic))?;
+        Ok(())
+    })
+}
+
+/// Sets the function to call with each notification or indication from the peripheral, replacing
+/// any set before, or stops calling one if `callback` is null.
+///
+/// # Safety
+///
+/// `peripheral` must be a valid handle, and `callback` must be safe to call with `user_data` from
+/// any thread until it is replaced or the peripheral is freed.
+#[no_mangle]
+pub unsafe extern "C" fn btleplug_peripheral_set_notification_callback(
+    peripheral: *const BtleplugPeripheral,
+    callback: BtleplugNotificationCallback,
+    user_data: *mut c_void,
+) -> BtleplugResult {
+    ffi_call(|| {
+        let peripheral = arg(peripheral, "peripheral")?;
+        let mut current = peripheral.notifications.lock().unwrap();
+        if let Some(notifications) = current.take() {
+            notifications.abort();
+        }
+        let callback = match callback {
+            Some(callback) => callback,
+            None => return Ok(()),
+        };
+        let notifications = peripheral
+            .runtime
+            .block_on(peripheral.peripheral.notifications())?;
+        let user_data = UserData(user_data);
+        let (forward, abort_handle) =
+            future::abortable(notifications.for_each(move |notification| {
+                let uuid = BtleplugUuid::from(notification.uuid);
+                unsafe {
+                    callback(
+                        &uuid,
+                        notification.value.as_ptr(),
+                        notification.value.len(),
+                        user_data.0,
+                    )
+                };
+                future::ready(())
+            }));
+        peripheral.runtime.spawn(forward);
+        *current = Some(abort_handle);
+        Ok(())
+    })
+}
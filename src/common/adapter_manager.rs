@@ -12,14 +12,26 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 use crate::{
-    api::{BDAddr, CentralEvent, Peripheral},
-    common::util::send_notification,
+    api::{BDAddr, CentralEvent, EventFilter, Peripheral, PeripheralProperties, ScanFilter},
+    common::{
+        last_seen::{LastSeenTracker, EXPIRY_CHECK_INTERVAL},
+        metrics::Metrics,
+        runtime,
+        scan_filter::ScanFilterState,
+    },
+};
+use dashmap::{
+    mapref::{entry::Entry, one::RefMut},
+    DashMap,
 };
-use dashmap::{mapref::one::RefMut, DashMap};
 use futures::channel::mpsc::{self, UnboundedSender};
 use futures::stream::Stream;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
 pub struct AdapterManager<PeripheralType>
@@ -27,7 +39,15 @@ where
     PeripheralType: Peripheral,
 {
     peripherals: Arc<DashMap<BDAddr, PeripheralType>>,
-    async_senders: Arc<Mutex<Vec<UnboundedSender<CentralEvent>>>>,
+    async_senders: Arc<Mutex<Vec<(EventFilter, UnboundedSender<CentralEvent>)>>>,
+    scan_filter: Arc<Mutex<(ScanFilter, ScanFilterState)>>,
+    retain_peripherals: Arc<AtomicBool>,
+    last_seen: Arc<Mutex<LastSeenTracker>>,
+    /// The time-to-live for peripherals which stop advertising, and whether the task which
+    /// removes them is running.
+    peripheral_ttl: Arc<Mutex<(Option<Duration>, bool)>>,
+    max_peripherals: Arc<Mutex<Option<usize>>>,
+    metrics: Arc<Metrics>,
 }
 
 impl<PeripheralType: Peripheral + 'static> Default for AdapterManager<PeripheralType> {
@@ -36,6 +56,12 @@ impl<PeripheralType: Peripheral + 'static> Default for AdapterManager<Peripheral
         AdapterManager {
             peripherals,
             async_senders: Arc::new(Mutex::new(vec![])),
+            scan_filter: Arc::new(Mutex::new(Default::default())),
+            retain_peripherals: Arc::new(AtomicBool::new(false)),
+            last_seen: Arc::new(Mutex::new(Default::default())),
+            peripheral_ttl: Arc::new(Mutex::new((None, false))),
+            max_peripherals: Arc::new(Mutex::new(None)),
+            metrics: Arc::new(Metrics::default()),
         }
     }
 }
@@ -44,38 +70,174 @@ impl<PeripheralType> AdapterManager<PeripheralType>
 where
     PeripheralType: Peripheral + 'static,
 {
+    /// Updates the peripheral map for the event, then sends it to every stream from
+    /// `event_stream`. Streams which have been dropped are removed rather than causing an error,
+    /// so this never fails.
     pub fn emit(&self, event: CentralEvent) {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(?event, "Emitting event");
+        self.last_seen.lock().unwrap().observe(&event);
         match event {
-            CentralEvent::DeviceDisconnected(addr) => {
-                self.peripherals.remove(&addr);
+            CentralEvent::DeviceDisconnected(addr, _) => {
+                if !self.retain_peripherals.load(Ordering::Relaxed) {
+                    self.peripherals.remove(&addr);
+                    self.last_seen.lock().unwrap().forget(&addr);
+                }
+                self.scan_filter.lock().unwrap().1.forget(&addr);
             }
             CentralEvent::DeviceLost(addr) => {
                 self.peripherals.remove(&addr);
+                self.scan_filter.lock().unwrap().1.forget(&addr);
             }
             _ => {}
         }
 
-        send_notification(&self.async_senders, &event);
+        let mut senders = self.async_senders.lock().unwrap();
+        // Remove senders from the list if the other end of the channel has been dropped.
+        senders.retain(|(filter, sender)| {
+            if filter.matches(&event) {
+                sender.unbounded_send(event.clone()).is_ok()
+            } else {
+                !sender.is_closed()
+            }
+        });
+    }
+
+    /// Emits an event caused by an advertisement from the peripheral with the given properties,
+    /// if the peripheral matches the current scan filter.
+    pub fn emit_advertisement(&self, properties: &PeripheralProperties, event: CentralEvent) {
+        let events = {
+            let (filter, state) = &mut *self.scan_filter.lock().unwrap();
+            state.filter_event(filter, properties, event)
+        };
+        for event in events {
+            self.emit(event);
+        }
+    }
+
+    /// Sets whether peripherals are kept after they disconnect, rather than being removed.
+    pub fn set_retain_peripherals(&self, retain: bool) {
+        self.retain_peripherals.store(retain, Ordering::Relaxed);
+    }
+
+    /// Sets how long a peripheral may go without advertising before it is removed and a
+    /// `DeviceLost` event is emitted for it, or `None` to keep peripherals indefinitely. With the
+    /// `tokio-runtime` feature, this must be called from within a Tokio runtime.
+    pub fn set_peripheral_ttl(&self, ttl: Option<Duration>) {
+        let (current_ttl, running) = &mut *self.peripheral_ttl.lock().unwrap();
+        *current_ttl = ttl;
+        if ttl.is_some() && !*running {
+            *running = true;
+            let manager = self.clone();
+            runtime::spawn(async move { manager.expire_peripherals().await });
+        }
+    }
+
+    async fn expire_peripherals(self) {
+        loop {
+            runtime::sleep(EXPIRY_CHECK_INTERVAL).await;
+            let ttl = {
+                let (ttl, running) = &mut *self.peripheral_ttl.lock().unwrap();
+                match ttl {
+                    Some(ttl) => *ttl,
+                    None => {
+                        *running = false;
+                        return;
+                    }
+                }
+            };
+            let expired = self.last_seen.lock().unwrap().expire(ttl);
+            for address in expired {
+                self.emit(CentralEvent::DeviceLost(address));
+            }
+        }
+    }
+
+    /// Sets how many peripherals may be kept at once, or `None` for any number. Peripherals over
+    /// the limit are evicted as new ones are added.
+    pub fn set_max_peripherals(&self, max: Option<usize>) {
+        *self.max_peripherals.lock().unwrap() = max;
+    }
+
+    /// Notes whether the peripheral is paired. Paired peripherals are kept like connected ones,
+    /// but still count towards the maximum.
+    pub fn set_paired(&self, address: BDAddr, paired: bool) {
+        self.last_seen.lock().unwrap().set_paired(address, paired);
+    }
+
+    /// Removes the least recently seen peripherals, other than `added`, while there are more than
+    /// the maximum, emitting `DeviceLost` for each.
+    fn evict_peripherals(&self, added: &BDAddr) {
+        let max = match *self.max_peripherals.lock().unwrap() {
+            Some(max) => max,
+            None => return,
+        };
+        while self.peripherals.len() > max {
+            let candidates: Vec<BDAddr> = self
+                .peripherals
+                .iter()
+                .map(|entry| *entry.key())
+                .filter(|address| address != added)
+                .collect();
+            let oldest = self
+                .last_seen
+                .lock()
+                .unwrap()
+                .least_recently_seen(candidates.into_iter());
+            match oldest {
+                Some(address) => self.emit(CentralEvent::DeviceLost(address)),
+                // Everything else is connected or paired, so the limit stays exceeded until some
+                // of them disconnect or are lost.
+                None => return,
+            }
+        }
     }
 
-    pub fn event_stream(&self) -> Pin<Box<dyn Stream<Item = CentralEvent> + Send>> {
+    pub fn set_scan_filter(&self, filter: ScanFilter) {
+        let (current_filter, state) = &mut *self.scan_filter.lock().unwrap();
+        *current_filter = filter;
+        state.clear();
+    }
+
+    /// Returns a new stream of the events emitted from now on which match the filter. Each stream
+    /// has its own unbounded buffer, so any number of them can be used at once without one
+    /// holding up the others.
+    pub fn event_stream(
+        &self,
+        filter: EventFilter,
+    ) -> Pin<Box<dyn Stream<Item = CentralEvent> + Send>> {
         let (sender, receiver) = mpsc::unbounded();
-        self.async_senders.lock().unwrap().push(sender);
+        self.async_senders.lock().unwrap().push((filter, sender));
         Box::pin(receiver)
     }
 
+    /// Returns the counters shared by the adapter and its peripherals.
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
     #[allow(dead_code)]
     pub fn has_peripheral(&self, addr: &BDAddr) -> bool {
         self.peripherals.contains_key(addr)
     }
 
-    pub fn add_peripheral(&self, addr: BDAddr, peripheral: PeripheralType) {
-        assert!(
-            !self.peripherals.contains_key(&addr),
-            "Adding a peripheral that's already in the map."
-        );
-        assert_eq!(peripheral.address(), addr, "Device has unexpected address."); // TODO remove addr argument
-        self.peripherals.insert(addr, peripheral);
+    /// Returns the peripheral with the given address, adding the one made by `new_peripheral` if
+    /// there isn't one yet, and whether it was added. The check and the insertion are one atomic
+    /// step, so callers racing to add the same peripheral all get the same one.
+    pub fn peripheral_or_insert_with(
+        &self,
+        address: BDAddr,
+        new_peripheral: impl FnOnce() -> PeripheralType,
+    ) -> (PeripheralType, bool) {
+        let (peripheral, added) = match self.peripherals.entry(address) {
+            Entry::Occupied(entry) => (entry.get().clone(), false),
+            Entry::Vacant(entry) => (entry.insert(new_peripheral()).value().clone(), true),
+        };
+        // The map mustn't be locked while evicting, as that removes other peripherals from it.
+        if added {
+            self.evict_peripherals(&address);
+        }
+        (peripheral, added)
     }
 
     pub fn peripherals(&self) -> Vec<PeripheralType> {
@@ -95,3 +257,84 @@ where
             .map(|val| val.value().clone())
     }
 }
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::{MockDevice, Peripheral as MockPeripheral};
+    use futures::stream::StreamExt;
+    use std::thread;
+
+    fn address(last: u8) -> BDAddr {
+        BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, last])
+    }
+
+    /// Adds a peripheral with the given address, as if it had just advertised.
+    fn discover(manager: &AdapterManager<MockPeripheral>, address: BDAddr) -> bool {
+        let (_, added) = manager.peripheral_or_insert_with(address, || {
+            let mut device = MockDevice::default();
+            device.properties.address = address;
+            MockPeripheral::new(manager.clone(), Arc::new(AtomicBool::new(false)), device)
+        });
+        manager.emit(CentralEvent::DeviceDiscovered(address));
+        // Give each peripheral a distinct last seen time.
+        thread::sleep(Duration::from_millis(5));
+        added
+    }
+
+    fn addresses(manager: &AdapterManager<MockPeripheral>) -> Vec<BDAddr> {
+        let mut addresses: Vec<BDAddr> = manager
+            .peripherals()
+            .iter()
+            .map(|peripheral| peripheral.address())
+            .collect();
+        addresses.sort_by_key(|address| u64::from(*address));
+        addresses
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_seen_over_limit() {
+        let manager = AdapterManager::<MockPeripheral>::default();
+        let mut events = manager.event_stream(EventFilter::default());
+        manager.set_max_peripherals(Some(2));
+        assert!(discover(&manager, address(1)));
+        assert!(discover(&manager, address(2)));
+        // Seeing the first again makes the second the least recently seen.
+        assert!(!discover(&manager, address(1)));
+        assert!(discover(&manager, address(3)));
+        assert_eq!(addresses(&manager), vec![address(1), address(3)]);
+
+        let lost: Vec<BDAddr> = events
+            .by_ref()
+            .take(5)
+            .filter_map(|event| async move {
+                match event {
+                    CentralEvent::DeviceLost(address) => Some(address),
+                    _ => None,
+                }
+            })
+            .collect()
+            .await;
+        assert_eq!(lost, vec![address(2)]);
+    }
+
+    #[test]
+    fn never_evicts_connected_peripherals() {
+        let manager = AdapterManager::<MockPeripheral>::default();
+        manager.set_max_peripherals(Some(1));
+        discover(&manager, address(1));
+        manager.emit(CentralEvent::DeviceConnected(address(1)));
+        discover(&manager, address(2));
+        // The limit is exceeded until the connected peripheral disconnects.
+        assert_eq!(addresses(&manager), vec![address(1), address(2)]);
+    }
+
+    #[test]
+    fn keeps_any_number_without_limit() {
+        let manager = AdapterManager::<MockPeripheral>::default();
+        for last in 1..=5 {
+            discover(&manager, address(last));
+        }
+        assert_eq!(manager.peripherals().len(), 5);
+    }
+}
@@ -0,0 +1,78 @@
+//! Resolution of resolvable private addresses to the identity addresses of devices whose Identity
+//! Resolving Keys have been registered, for platforms where the OS doesn't do this itself.
+
+use crate::api::{BDAddr, CentralEvent, IdentityResolvingKey};
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct IdentityResolver {
+    /// The registered keys by identity address, along with the private address which was most
+    /// recently seen to resolve with each.
+    identities: HashMap<BDAddr, (IdentityResolvingKey, Option<BDAddr>)>,
+}
+
+impl IdentityResolver {
+    pub fn add_key(&mut self, identity: BDAddr, irk: IdentityResolvingKey) {
+        self.identities.insert(identity, (irk, None));
+    }
+
+    pub fn remove_key(&mut self, identity: BDAddr) {
+        self.identities.remove(&identity);
+    }
+
+    /// Returns the identity which the given address resolves to, if any.
+    pub fn lookup(&self, address: BDAddr) -> Option<BDAddr> {
+        if self.identities.is_empty() || !address.is_resolvable_private() {
+            return None;
+        }
+        self.identities
+            .iter()
+            .find(|(_, (irk, current))| *current == Some(address) || irk.resolves(&address))
+            .map(|(identity, _)| *identity)
+    }
+
+    /// Returns the private address which was most recently seen for the given identity.
+    pub fn current_address(&self, identity: BDAddr) -> Option<BDAddr> {
+        self.identities
+            .get(&identity)
+            .and_then(|(_, current)| *current)
+    }
+
+    /// Returns the identity which the given address resolves to, recording the address as the
+    /// identity's current private address, or the address itself if it doesn't resolve.
+    pub fn resolve(&mut self, address: BDAddr) -> BDAddr {
+        match self.lookup(address) {
+            Some(identity) => {
+                if let Some((_, current)) = self.identities.get_mut(&identity) {
+                    *current = Some(address);
+                }
+                identity
+            }
+            None => address,
+        }
+    }
+
+    /// Rewrites the address of the given event to the identity it resolves to.
+    ///
+    /// A `DeviceLost` event for a private address which has since been replaced by a newer one is
+    /// dropped, as the device itself is still around.
+    pub fn resolve_event(&mut self, mut event: CentralEvent) -> Option<CentralEvent> {
+        if let CentralEvent::DeviceLost(address) = event {
+            let identity = match self.lookup(address) {
+                Some(identity) => identity,
+                None => return Some(event),
+            };
+            let (_, current) = self.identities.get_mut(&identity)?;
+            return if *current == Some(address) {
+                *current = None;
+                Some(CentralEvent::DeviceLost(identity))
+            } else {
+                None
+            };
+        }
+        if let Some(address) = event.address_mut() {
+            *address = self.resolve(*address);
+        }
+        Some(event)
+    }
+}
@@ -0,0 +1,149 @@
+/// Tracks when each peripheral was last heard from, to find those which have gone away.
+use crate::api::{BDAddr, CentralEvent};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// How often to check for peripherals which have gone away.
+pub const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+pub struct LastSeenTracker {
+    last_seen: HashMap<BDAddr, Instant>,
+    /// Connected peripherals don't advertise, so are never considered to have gone away.
+    connected: HashSet<BDAddr>,
+    /// Paired peripherals are kept too, as removing them from BlueZ would delete their keys.
+    paired: HashSet<BDAddr>,
+}
+
+impl LastSeenTracker {
+    /// Updates the tracker for an event which is about to be emitted.
+    pub fn observe(&mut self, event: &CentralEvent) {
+        match event {
+            CentralEvent::DeviceConnected(address) => {
+                self.connected.insert(*address);
+            }
+            CentralEvent::DeviceDisconnected(address, _) => {
+                self.connected.remove(address);
+                self.last_seen.insert(*address, Instant::now());
+            }
+            CentralEvent::DeviceLost(address) => self.forget(address),
+            CentralEvent::DeviceDiscovered(address)
+            | CentralEvent::DeviceUpdated { address, .. }
+            | CentralEvent::ManufacturerDataAdvertisement { address, .. }
+            | CentralEvent::ServiceDataAdvertisement { address, .. }
+            | CentralEvent::ServicesAdvertisement { address, .. }
+            | CentralEvent::RssiUpdate { address, .. } => {
+                self.last_seen.insert(*address, Instant::now());
+            }
+            _ => {}
+        }
+    }
+
+    pub fn forget(&mut self, address: &BDAddr) {
+        self.connected.remove(address);
+        self.paired.remove(address);
+        self.last_seen.remove(address);
+    }
+
+    /// Notes whether the peripheral is paired, which there's no event for.
+    pub fn set_paired(&mut self, address: BDAddr, paired: bool) {
+        if paired {
+            self.paired.insert(address);
+        } else {
+            self.paired.remove(&address);
+        }
+    }
+
+    /// Whether the peripheral is connected or paired, so is never considered to have gone away.
+    fn is_kept(&self, address: &BDAddr) -> bool {
+        self.connected.contains(address) || self.paired.contains(address)
+    }
+
+    /// Returns whichever of the given peripherals was heard from least recently, other than those
+    /// which are connected or paired. Peripherals which haven't been heard from at all count as the
+    /// oldest.
+    pub fn least_recently_seen(&self, addresses: impl Iterator<Item = BDAddr>) -> Option<BDAddr> {
+        addresses
+            .filter(|address| !self.is_kept(address))
+            .min_by_key(|address| self.last_seen.get(address).copied())
+    }
+
+    /// Removes and returns the peripherals which haven't been heard from within the given time.
+    pub fn expire(&mut self, ttl: Duration) -> Vec<BDAddr> {
+        let expired: Vec<BDAddr> = self
+            .last_seen
+            .iter()
+            .filter(|(address, last_seen)| !self.is_kept(address) && last_seen.elapsed() > ttl)
+            .map(|(address, _)| *address)
+            .collect();
+        for address in &expired {
+            self.last_seen.remove(address);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::DisconnectReason;
+    use std::thread;
+
+    const FIRST: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x01]);
+    const SECOND: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x02]);
+
+    #[test]
+    fn expires_peripherals_not_seen_within_ttl() {
+        let mut tracker = LastSeenTracker::default();
+        tracker.observe(&CentralEvent::DeviceDiscovered(FIRST));
+        assert!(tracker.expire(Duration::from_secs(60)).is_empty());
+
+        thread::sleep(Duration::from_millis(20));
+        tracker.observe(&CentralEvent::DeviceDiscovered(SECOND));
+        assert_eq!(tracker.expire(Duration::from_millis(10)), vec![FIRST]);
+        // Expired peripherals aren't reported again.
+        assert!(tracker.expire(Duration::from_millis(10)).is_empty());
+    }
+
+    #[test]
+    fn connected_peripherals_never_expire() {
+        let mut tracker = LastSeenTracker::default();
+        tracker.observe(&CentralEvent::DeviceDiscovered(FIRST));
+        tracker.observe(&CentralEvent::DeviceConnected(FIRST));
+        thread::sleep(Duration::from_millis(20));
+        assert!(tracker.expire(Duration::from_millis(10)).is_empty());
+
+        // The time-to-live starts again from the disconnection.
+        tracker.observe(&CentralEvent::DeviceDisconnected(
+            FIRST,
+            DisconnectReason::Unknown,
+        ));
+        assert!(tracker.expire(Duration::from_millis(10)).is_empty());
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(tracker.expire(Duration::from_millis(10)), vec![FIRST]);
+    }
+
+    #[test]
+    fn lost_peripherals_are_forgotten() {
+        let mut tracker = LastSeenTracker::default();
+        tracker.observe(&CentralEvent::DeviceDiscovered(FIRST));
+        tracker.observe(&CentralEvent::DeviceLost(FIRST));
+        thread::sleep(Duration::from_millis(20));
+        assert!(tracker.expire(Duration::from_millis(10)).is_empty());
+    }
+
+    #[test]
+    fn least_recently_seen_skips_connected_peripherals() {
+        let mut tracker = LastSeenTracker::default();
+        tracker.observe(&CentralEvent::DeviceDiscovered(FIRST));
+        thread::sleep(Duration::from_millis(5));
+        tracker.observe(&CentralEvent::DeviceDiscovered(SECOND));
+        let both = || vec![FIRST, SECOND].into_iter();
+        assert_eq!(tracker.least_recently_seen(both()), Some(FIRST));
+
+        tracker.observe(&CentralEvent::DeviceConnected(FIRST));
+        assert_eq!(tracker.least_recently_seen(both()), Some(SECOND));
+        tracker.observe(&CentralEvent::DeviceConnected(SECOND));
+        assert_eq!(tracker.least_recently_seen(both()), None);
+    }
+}
@@ -0,0 +1,112 @@
+/// Counts what happens on an adapter and its peripherals, for `Central::metrics`.
+use crate::api::{AdapterMetrics, LatencyHistogram};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+use std::time::Instant;
+
+#[derive(Debug)]
+pub struct Metrics {
+    start: Instant,
+    advertisements_received: AtomicU64,
+    connect_attempts: AtomicU64,
+    connect_failures: AtomicU64,
+    notifications_received: AtomicU64,
+    dropped_events: AtomicU64,
+    gatt_latency: Mutex<LatencyHistogram>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            start: Instant::now(),
+            advertisements_received: AtomicU64::new(0),
+            connect_attempts: AtomicU64::new(0),
+            connect_failures: AtomicU64::new(0),
+            notifications_received: AtomicU64::new(0),
+            dropped_events: AtomicU64::new(0),
+            gatt_latency: Mutex::new(LatencyHistogram::default()),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn advertisement_received(&self) {
+        self.advertisements_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn notification_received(&self) {
+        self.notifications_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn event_dropped(&self) {
+        self.dropped_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an attempt to connect, which is counted as having failed unless
+    /// [`ConnectAttempt::succeeded`] is called before it is dropped.
+    pub fn connect_attempt(&self) -> ConnectAttempt<'_> {
+        self.connect_attempts.fetch_add(1, Ordering::Relaxed);
+        ConnectAttempt {
+            metrics: self,
+            succeeded: false,
+        }
+    }
+
+    /// Times a GATT operation, until the returned guard is dropped.
+    pub fn gatt_operation(&self) -> GattOperation<'_> {
+        GattOperation {
+            metrics: self,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn snapshot(&self) -> AdapterMetrics {
+        AdapterMetrics {
+            elapsed: self.start.elapsed(),
+            advertisements_received: self.advertisements_received.load(Ordering::Relaxed),
+            connect_attempts: self.connect_attempts.load(Ordering::Relaxed),
+            connect_failures: self.connect_failures.load(Ordering::Relaxed),
+            notifications_received: self.notifications_received.load(Ordering::Relaxed),
+            dropped_events: self.dropped_events.load(Ordering::Relaxed),
+            gatt_latency: self.gatt_latency.lock().unwrap().clone(),
+        }
+    }
+}
+
+pub struct ConnectAttempt<'a> {
+    metrics: &'a Metrics,
+    succeeded: bool,
+}
+
+impl ConnectAttempt<'_> {
+    pub fn succeeded(mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for ConnectAttempt<'_> {
+    fn drop(&mut self) {
+        if !self.succeeded {
+            self.metrics
+                .connect_failures
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct GattOperation<'a> {
+    metrics: &'a Metrics,
+    start: Instant,
+}
+
+impl Drop for GattOperation<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .gatt_latency
+            .lock()
+            .unwrap()
+            .record(self.start.elapsed());
+    }
+}
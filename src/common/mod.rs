@@ -1,2 +1,10 @@
 pub mod adapter_manager;
+pub mod identity_resolver;
+pub mod last_seen;
+pub mod metrics;
+pub mod operation_queue;
+pub mod runtime;
+pub mod scan_filter;
+pub mod subscriptions;
 pub mod util;
+pub mod watched;
@@ -0,0 +1,271 @@
+/// Limits how many GATT operations are in progress on a connection at once. Some platforms fail
+/// requests which overlap with others, so by default operations run one at a time, in the order
+/// they were started.
+use super::util;
+use crate::{api::RetryPolicy, Result};
+use futures::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+#[derive(Debug)]
+pub struct OperationQueue {
+    /// Every operation waits on this one semaphore, which is fair, so they start in order.
+    semaphore: Semaphore,
+    limits: Mutex<Limits>,
+}
+
+#[derive(Debug)]
+struct Limits {
+    concurrency: usize,
+    /// How many permits are still to be taken away after lowering the concurrency while
+    /// operations were in progress. They are forgotten as those operations finish.
+    excess: usize,
+}
+
+impl Default for OperationQueue {
+    fn default() -> Self {
+        OperationQueue {
+            semaphore: Semaphore::new(1),
+            limits: Mutex::new(Limits {
+                concurrency: 1,
+                excess: 0,
+            }),
+        }
+    }
+}
+
+/// Lets an operation run until dropped, unless the concurrency has been lowered since it started
+/// in which case its permit is taken away rather than passed on.
+struct Permit<'a> {
+    queue: &'a OperationQueue,
+    permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut limits = self.queue.limits.lock().unwrap();
+        if limits.excess > 0 {
+            limits.excess -= 1;
+            if let Some(permit) = self.permit.take() {
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl OperationQueue {
+    /// Sets how many operations may be in progress at once. Operations which are already in
+    /// progress aren't affected, but no more start until fewer than the new limit are.
+    pub fn set_concurrency(&self, concurrency: usize) {
+        let mut limits = self.limits.lock().unwrap();
+        if concurrency > limits.concurrency {
+            let mut added = concurrency - limits.concurrency;
+            // Cancel out permits which were still to be taken away first.
+            let cancelled = added.min(limits.excess);
+            limits.excess -= cancelled;
+            added -= cancelled;
+            self.semaphore.add_permits(added);
+        } else {
+            let mut removed = limits.concurrency - concurrency;
+            // Take away the permits which aren't in use now, and the rest as they are released.
+            while removed > 0 {
+                match self.semaphore.try_acquire() {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => break,
+                }
+                removed -= 1;
+            }
+            limits.excess += removed;
+        }
+        limits.concurrency = concurrency;
+    }
+
+    /// Waits for the operations ahead in the queue, until this one may start. It's finished when
+    /// the returned permit is dropped.
+    async fn acquire(&self) -> Permit<'_> {
+        // The semaphore is never closed.
+        let permit = self.semaphore.acquire().await.unwrap();
+        Permit {
+            queue: self,
+            permit: Some(permit),
+        }
+    }
+
+    /// Runs the operation returned by the given function once those ahead of it in the queue have
+    /// finished, retrying it according to the retry policy if any. The timeout, if any, applies to
+    /// each attempt and includes the time spent waiting in the queue.
+    pub async fn run<T, F, Fut>(
+        &self,
+        timeout: Option<Duration>,
+        retry: Option<RetryPolicy>,
+        operation: F,
+    ) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let operation = &operation;
+        util::with_retry(retry, move || {
+            util::with_timeout(timeout, async move {
+                let _permit = self.acquire().await;
+                operation().await
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use std::sync::Arc;
+    use tokio::sync::oneshot;
+
+    /// Records the order in which test operations start and how many overlap.
+    struct Operations {
+        started: Arc<Mutex<Vec<usize>>>,
+        running: Arc<Mutex<(usize, usize)>>,
+    }
+
+    impl Operations {
+        fn new() -> Self {
+            Operations {
+                started: Arc::new(Mutex::new(Vec::new())),
+                running: Arc::new(Mutex::new((0, 0))),
+            }
+        }
+
+        /// Queues the given number of operations, each of which runs until its sender is used.
+        fn spawn(
+            &self,
+            queue: &Arc<OperationQueue>,
+            count: usize,
+        ) -> (Vec<oneshot::Sender<()>>, Vec<tokio::task::JoinHandle<()>>) {
+            let mut senders = Vec::new();
+            let mut handles = Vec::new();
+            for index in 0..count {
+                let (sender, receiver) = oneshot::channel();
+                let receiver = Mutex::new(Some(receiver));
+                let queue = queue.clone();
+                let started = self.started.clone();
+                let running = self.running.clone();
+                senders.push(sender);
+                handles.push(tokio::spawn(async move {
+                    queue
+                        .run(None, None, || async {
+                            started.lock().unwrap().push(index);
+                            {
+                                let mut running = running.lock().unwrap();
+                                running.0 += 1;
+                                running.1 = running.1.max(running.0);
+                            }
+                            let receiver = receiver.lock().unwrap().take().unwrap();
+                            receiver.await.ok();
+                            running.lock().unwrap().0 -= 1;
+                            Ok(())
+                        })
+                        .await
+                        .unwrap()
+                }));
+            }
+            (senders, handles)
+        }
+
+        fn started(&self) -> Vec<usize> {
+            self.started.lock().unwrap().clone()
+        }
+
+        fn max_running(&self) -> usize {
+            self.running.lock().unwrap().1
+        }
+    }
+
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_one_at_a_time_in_order() {
+        let queue = Arc::new(OperationQueue::default());
+        let operations = Operations::new();
+        let (senders, handles) = operations.spawn(&queue, 3);
+        settle().await;
+        assert_eq!(operations.started(), vec![0]);
+
+        for sender in senders {
+            sender.send(()).unwrap();
+            settle().await;
+        }
+        join_all(handles).await;
+        assert_eq!(operations.started(), vec![0, 1, 2]);
+        assert_eq!(operations.max_running(), 1);
+    }
+
+    #[tokio::test]
+    async fn raising_concurrency_starts_queued_operations() {
+        let queue = Arc::new(OperationQueue::default());
+        let operations = Operations::new();
+        let (senders, handles) = operations.spawn(&queue, 3);
+        settle().await;
+        assert_eq!(operations.started(), vec![0]);
+
+        queue.set_concurrency(2);
+        settle().await;
+        assert_eq!(operations.started(), vec![0, 1]);
+
+        for sender in senders {
+            sender.send(()).unwrap();
+        }
+        join_all(handles).await;
+        assert_eq!(operations.started(), vec![0, 1, 2]);
+        assert_eq!(operations.max_running(), 2);
+    }
+
+    #[tokio::test]
+    async fn lowering_concurrency_waits_for_operations_in_progress() {
+        let queue = Arc::new(OperationQueue::default());
+        queue.set_concurrency(2);
+        let operations = Operations::new();
+        let (mut senders, handles) = operations.spawn(&queue, 4);
+        settle().await;
+        assert_eq!(operations.started(), vec![0, 1]);
+
+        // Both operations in progress carry on, but only one runs at a time after they finish.
+        queue.set_concurrency(1);
+        senders.remove(0).send(()).unwrap();
+        settle().await;
+        assert_eq!(operations.started(), vec![0, 1]);
+        senders.remove(0).send(()).unwrap();
+        settle().await;
+        assert_eq!(operations.started(), vec![0, 1, 2]);
+
+        for sender in senders {
+            sender.send(()).unwrap();
+            settle().await;
+        }
+        join_all(handles).await;
+        assert_eq!(operations.started(), vec![0, 1, 2, 3]);
+        assert_eq!(queue.semaphore.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn raising_concurrency_again_cancels_pending_reduction() {
+        let queue = Arc::new(OperationQueue::default());
+        queue.set_concurrency(2);
+        let operations = Operations::new();
+        let (senders, handles) = operations.spawn(&queue, 2);
+        settle().await;
+
+        queue.set_concurrency(1);
+        queue.set_concurrency(2);
+        for sender in senders {
+            sender.send(()).unwrap();
+        }
+        join_all(handles).await;
+        assert_eq!(queue.semaphore.available_permits(), 2);
+    }
+}
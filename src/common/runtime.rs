@@ -0,0 +1,73 @@
+//! The parts of an async runtime which btleplug uses itself: spawning background tasks, running
+//! blocking calls off the executor, and timers. These are run on Tokio with the `tokio-runtime`
+//! feature, which is enabled by default, or on async-std with the `async-std-runtime` feature, so
+//! that applications using async-std or smol don't have to run a Tokio runtime alongside their own.
+//!
+//! The channels and locks btleplug uses from `tokio::sync` don't depend on the Tokio runtime, so
+//! work with either.
+
+use crate::Result;
+use std::{future::Future, time::Duration};
+
+#[cfg(not(any(feature = "tokio-runtime", feature = "async-std-runtime")))]
+compile_error!("btleplug needs either the `tokio-runtime` or the `async-std-runtime` feature");
+
+/// Runs the given future in the background.
+#[cfg(not(feature = "async-std-runtime"))]
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    tokio::spawn(future);
+}
+
+/// Runs the given future in the background.
+#[cfg(feature = "async-std-runtime")]
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    async_std::task::spawn(future);
+}
+
+/// Runs the given blocking function on a thread where it won't hold up other tasks, and returns
+/// its result.
+#[cfg(not(feature = "async-std-runtime"))]
+pub async fn spawn_blocking<T, F>(function: F) -> Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(function)
+        .await
+        .map_err(|e| crate::Error::Other(Box::new(e)))
+}
+
+/// Runs the given blocking function on a thread where it won't hold up other tasks, and returns
+/// its result.
+#[cfg(feature = "async-std-runtime")]
+pub async fn spawn_blocking<T, F>(function: F) -> Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Ok(async_std::task::spawn_blocking(function).await)
+}
+
+/// Waits until the given duration has passed.
+#[cfg(not(feature = "async-std-runtime"))]
+pub async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+/// Waits until the given duration has passed.
+#[cfg(feature = "async-std-runtime")]
+pub async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+/// Waits for the given future, returning `None` if it doesn't complete within the given duration.
+#[cfg(not(feature = "async-std-runtime"))]
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    tokio::time::timeout(duration, future).await.ok()
+}
+
+/// Waits for the given future, returning `None` if it doesn't complete within the given duration.
+#[cfg(feature = "async-std-runtime")]
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    async_std::future::timeout(duration, future).await.ok()
+}
@@ -0,0 +1,280 @@
+/// Applies a [`ScanFilter`] to advertisement events on the host, for platforms which can't apply
+/// all of it natively.
+use crate::api::{
+    beacons::{eddystone::EddystoneFrame, ibeacon::IBeacon},
+    BDAddr, CentralEvent, PeripheralProperties, ScanFilter,
+};
+use std::collections::HashSet;
+
+#[derive(Debug, Default)]
+pub struct ScanFilterState {
+    /// Devices for which a `DeviceDiscovered` event has already been emitted.
+    reported: HashSet<BDAddr>,
+}
+
+impl ScanFilterState {
+    /// Returns the events which should be emitted in place of the given event, given the current
+    /// properties of the device it is about.
+    ///
+    /// Advertisement events are dropped until the device matches the filter, at which point a
+    /// `DeviceDiscovered` event is emitted for it. Other events are passed through unchanged.
+    pub fn filter_event(
+        &mut self,
+        filter: &ScanFilter,
+        properties: &PeripheralProperties,
+        event: CentralEvent,
+    ) -> Vec<CentralEvent> {
+        match event {
+            CentralEvent::DeviceDiscovered(address)
+            | CentralEvent::DeviceUpdated { address, .. }
+            | CentralEvent::ManufacturerDataAdvertisement { address, .. }
+            | CentralEvent::ServiceDataAdvertisement { address, .. }
+            | CentralEvent::ServicesAdvertisement { address, .. }
+            | CentralEvent::RssiUpdate { address, .. } => {
+                let is_duplicate = matches!(event, CentralEvent::RssiUpdate { .. });
+                if !filter.matches(properties) || (is_duplicate && !filter.allow_duplicates) {
+                    return vec![];
+                }
+                let beacon_event = match &event {
+                    CentralEvent::ManufacturerDataAdvertisement {
+                        manufacturer_data, ..
+                    } => IBeacon::from_manufacturer_data(manufacturer_data)
+                        .map(|beacon| CentralEvent::IBeaconDiscovered { address, beacon }),
+                    CentralEvent::ServiceDataAdvertisement { service_data, .. } => {
+                        EddystoneFrame::from_service_data(service_data)
+                            .map(|frame| CentralEvent::EddystoneDiscovered { address, frame })
+                    }
+                    _ => None,
+                };
+                let is_discovered = matches!(event, CentralEvent::DeviceDiscovered(_));
+                let mut events = match (self.reported.insert(address), is_discovered) {
+                    (true, false) => vec![CentralEvent::DeviceDiscovered(address), event],
+                    (false, true) => vec![],
+                    _ => vec![event],
+                };
+                events.extend(beacon_event);
+                events
+            }
+            CentralEvent::MalformedAdvertisement { .. } if !filter.matches(properties) => vec![],
+            CentralEvent::DeviceDisconnected(address, _) | CentralEvent::DeviceLost(address) => {
+                self.forget(&address);
+                vec![event]
+            }
+            _ => vec![event],
+        }
+    }
+
+    /// Forgets that the given device has been reported, so it will be reported again the next
+    /// time it matches the filter.
+    pub fn forget(&mut self, address: &BDAddr) {
+        self.reported.remove(address);
+    }
+
+    /// Forgets all reported devices, e.g. because the filter has changed.
+    pub fn clear(&mut self) {
+        self.reported.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{bleuuid::uuid_from_u16, DisconnectReason};
+    use std::collections::HashMap;
+
+    const ADDRESS: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+    const APPLE: u16 = 0x004c;
+    const IBEACON: [u8; 23] = [
+        0x02, 0x15, 0xe2, 0xc5, 0x6d, 0xb5, 0xdf, 0xfb, 0x48, 0xd2, 0xb0, 0x60, 0xd0, 0xf5, 0xa7,
+        0x10, 0x96, 0xe0, 0x00, 0x01, 0x01, 0x02, 0xc5,
+    ];
+
+    fn properties() -> PeripheralProperties {
+        let mut properties = PeripheralProperties {
+            address: ADDRESS,
+            local_name: Some("Thermometer 1".to_string()),
+            services: vec![uuid_from_u16(0x1809)],
+            rssi: Some(-60),
+            ..Default::default()
+        };
+        properties.manufacturer_data.insert(APPLE, IBEACON.to_vec());
+        properties
+    }
+
+    /// Names the kind of each event, as events can't be compared directly.
+    fn kinds(events: &[CentralEvent]) -> Vec<&'static str> {
+        events
+            .iter()
+            .map(|event| match event {
+                CentralEvent::DeviceDiscovered(_) => "discovered",
+                CentralEvent::DeviceUpdated { .. } => "updated",
+                CentralEvent::ManufacturerDataAdvertisement { .. } => "manufacturer data",
+                CentralEvent::RssiUpdate { .. } => "rssi",
+                CentralEvent::IBeaconDiscovered { .. } => "ibeacon",
+                CentralEvent::DeviceLost(_) => "lost",
+                _ => "other",
+            })
+            .collect()
+    }
+
+    fn updated() -> CentralEvent {
+        CentralEvent::DeviceUpdated {
+            address: ADDRESS,
+            changes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        assert!(ScanFilter::default().matches(&properties()));
+        assert!(ScanFilter::default().matches(&PeripheralProperties::default()));
+    }
+
+    #[test]
+    fn matches_any_of_the_services() {
+        let mut filter = ScanFilter {
+            services: vec![uuid_from_u16(0x180d), uuid_from_u16(0x1809)],
+            ..Default::default()
+        };
+        assert!(filter.matches(&properties()));
+        filter.services = vec![uuid_from_u16(0x180d)];
+        assert!(!filter.matches(&properties()));
+    }
+
+    #[test]
+    fn matches_name_prefix() {
+        let mut filter = ScanFilter {
+            name_prefix: Some("Thermo".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&properties()));
+        assert!(!filter.matches(&PeripheralProperties::default()));
+        filter.name_prefix = Some("meter".to_string());
+        assert!(!filter.matches(&properties()));
+    }
+
+    #[test]
+    fn matches_manufacturer_id() {
+        let mut filter = ScanFilter {
+            manufacturer_id: Some(APPLE),
+            ..Default::default()
+        };
+        assert!(filter.matches(&properties()));
+        filter.manufacturer_id = Some(0x0059);
+        assert!(!filter.matches(&properties()));
+    }
+
+    #[test]
+    fn matches_min_rssi_unless_unknown() {
+        let mut filter = ScanFilter {
+            min_rssi: Some(-60),
+            ..Default::default()
+        };
+        assert!(filter.matches(&properties()));
+        filter.min_rssi = Some(-59);
+        assert!(!filter.matches(&properties()));
+        let unknown = PeripheralProperties {
+            rssi: None,
+            ..properties()
+        };
+        assert!(filter.matches(&unknown));
+    }
+
+    #[test]
+    fn every_condition_must_match() {
+        let filter = ScanFilter {
+            name_prefix: Some("Thermo".to_string()),
+            manufacturer_id: Some(0x0059),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&properties()));
+    }
+
+    #[test]
+    fn reports_discovery_once_device_matches() {
+        let filter = ScanFilter {
+            name_prefix: Some("Thermo".to_string()),
+            ..Default::default()
+        };
+        let mut state = ScanFilterState::default();
+        let unnamed = PeripheralProperties {
+            local_name: None,
+            ..properties()
+        };
+        let discovered = CentralEvent::DeviceDiscovered(ADDRESS);
+        assert!(state
+            .filter_event(&filter, &unnamed, discovered.clone())
+            .is_empty());
+        // The name arrives in a later advertisement, such as a scan response.
+        assert_eq!(
+            kinds(&state.filter_event(&filter, &properties(), updated())),
+            vec!["discovered", "updated"]
+        );
+        assert!(state
+            .filter_event(&filter, &properties(), discovered)
+            .is_empty());
+        assert_eq!(
+            kinds(&state.filter_event(&filter, &properties(), updated())),
+            vec!["updated"]
+        );
+    }
+
+    #[test]
+    fn reports_again_after_device_is_lost() {
+        let filter = ScanFilter::default();
+        let mut state = ScanFilterState::default();
+        state.filter_event(&filter, &properties(), updated());
+        assert_eq!(
+            kinds(&state.filter_event(&filter, &properties(), CentralEvent::DeviceLost(ADDRESS))),
+            vec!["lost"]
+        );
+        assert_eq!(
+            kinds(&state.filter_event(&filter, &properties(), updated())),
+            vec!["discovered", "updated"]
+        );
+
+        state.filter_event(
+            &filter,
+            &properties(),
+            CentralEvent::DeviceDisconnected(ADDRESS, DisconnectReason::Unknown),
+        );
+        assert_eq!(
+            kinds(&state.filter_event(&filter, &properties(), updated())),
+            vec!["discovered", "updated"]
+        );
+    }
+
+    #[test]
+    fn drops_duplicates_unless_allowed() {
+        let mut filter = ScanFilter::default();
+        let mut state = ScanFilterState::default();
+        state.filter_event(&filter, &properties(), updated());
+        let rssi = || CentralEvent::RssiUpdate {
+            address: ADDRESS,
+            rssi: -60,
+        };
+        assert!(state
+            .filter_event(&filter, &properties(), rssi())
+            .is_empty());
+        filter.allow_duplicates = true;
+        assert_eq!(
+            kinds(&state.filter_event(&filter, &properties(), rssi())),
+            vec!["rssi"]
+        );
+    }
+
+    #[test]
+    fn reports_beacons_in_advertisements() {
+        let mut state = ScanFilterState::default();
+        let mut manufacturer_data = HashMap::new();
+        manufacturer_data.insert(APPLE, IBEACON.to_vec());
+        let event = CentralEvent::ManufacturerDataAdvertisement {
+            address: ADDRESS,
+            manufacturer_data,
+        };
+        assert_eq!(
+            kinds(&state.filter_event(&ScanFilter::default(), &properties(), event)),
+            vec!["discovered", "manufacturer data", "ibeacon"]
+        );
+    }
+}
@@ -0,0 +1,227 @@
+/// Counts subscriptions to each characteristic of a peripheral, so that notifications stay enabled
+/// until every subscriber has unsubscribed.
+use crate::api::Characteristic;
+use std::collections::{BTreeSet, HashMap};
+use uuid::Uuid;
+
+/// Identifies a characteristic regardless of its properties.
+type CharacteristicKey = (Uuid, Uuid, u16);
+
+fn key(characteristic: &Characteristic) -> CharacteristicKey {
+    (
+        characteristic.service_uuid,
+        characteristic.uuid,
+        characteristic.handle,
+    )
+}
+
+#[derive(Debug, Default)]
+pub struct SubscriptionCounts {
+    counts: HashMap<CharacteristicKey, (Characteristic, usize)>,
+    /// Subscriptions from a previous connection, which can be restored after reconnecting.
+    lost: HashMap<CharacteristicKey, (Characteristic, usize)>,
+}
+
+/// Adds the subscriptions for a characteristic to those in `counts`, and returns whether it had
+/// no subscribers before.
+fn merge(
+    counts: &mut HashMap<CharacteristicKey, (Characteristic, usize)>,
+    characteristic: Characteristic,
+    count: usize,
+) -> bool {
+    let (_, total) = counts
+        .entry(key(&characteristic))
+        .or_insert_with(|| (characteristic, 0));
+    *total += count;
+    *total == count
+}
+
+impl SubscriptionCounts {
+    /// Records a subscription if the characteristic already has subscribers, and returns whether
+    /// it did. Otherwise notifications need to be enabled on the device first, and the
+    /// subscription recorded with [`subscribe`](Self::subscribe) once that has succeeded, so a
+    /// failed or cancelled attempt isn't counted.
+    pub fn subscribe_if_enabled(&mut self, characteristic: &Characteristic) -> bool {
+        match self.counts.get_mut(&key(characteristic)) {
+            Some((_, count)) => {
+                *count += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records a subscription, and returns whether it is the first, so notifications need to be
+    /// enabled on the device.
+    pub fn subscribe(&mut self, characteristic: &Characteristic) -> bool {
+        let (_, count) = self
+            .counts
+            .entry(key(characteristic))
+            .or_insert_with(|| (characteristic.clone(), 0));
+        *count += 1;
+        *count == 1
+    }
+
+    /// Records an unsubscription, and returns whether there are no subscribers left, so
+    /// notifications need to be disabled on the device. Unsubscribing from a characteristic whose
+    /// subscriptions were lost by disconnecting only stops it being restored.
+    pub fn unsubscribe(&mut self, characteristic: &Characteristic) -> bool {
+        let key = key(characteristic);
+        if !self.counts.contains_key(&key) {
+            if let Some((_, count)) = self.lost.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    self.lost.remove(&key);
+                }
+                return false;
+            }
+        }
+        match self.counts.get_mut(&key) {
+            Some((_, count)) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                self.counts.remove(&key);
+                true
+            }
+            // Notifications enabled by someone else mustn't be disabled.
+            None => false,
+        }
+    }
+
+    /// Marks every subscription as lost, because the connection to the device has been closed and
+    /// notifications with it.
+    pub fn disconnected(&mut self) {
+        for (_, (characteristic, count)) in self.counts.drain() {
+            merge(&mut self.lost, characteristic, count);
+        }
+    }
+
+    /// Restores the subscriptions lost by disconnecting, or forgets them if `restore` is false.
+    /// Returns the characteristics for which notifications need to be enabled on the device again.
+    pub fn reconnected(&mut self, restore: bool) -> Vec<Characteristic> {
+        let mut resubscribe = Vec::new();
+        for (_, (characteristic, count)) in self.lost.drain() {
+            if restore && merge(&mut self.counts, characteristic.clone(), count) {
+                resubscribe.push(characteristic);
+            }
+        }
+        resubscribe
+    }
+
+    /// Returns whether the characteristic has any subscribers.
+    pub fn is_subscribed(&self, characteristic: &Characteristic) -> bool {
+        self.counts.contains_key(&key(characteristic))
+    }
+
+    /// Returns the characteristics which have any subscribers.
+    pub fn subscribed(&self) -> BTreeSet<Characteristic> {
+        self.counts
+            .values()
+            .map(|(characteristic, _)| characteristic.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::CharPropFlags;
+
+    fn characteristic(handle: u16) -> Characteristic {
+        Characteristic {
+            uuid: Uuid::from_u128(0x2a37),
+            service_uuid: Uuid::from_u128(0x180d),
+            handle,
+            properties: CharPropFlags::NOTIFY,
+        }
+    }
+
+    #[test]
+    fn nested_subscriptions() {
+        let mut subscriptions = SubscriptionCounts::default();
+        let characteristic = characteristic(1);
+
+        assert!(!subscriptions.subscribe_if_enabled(&characteristic));
+        assert!(subscriptions.subscribe(&characteristic));
+        assert!(subscriptions.subscribe_if_enabled(&characteristic));
+        assert!(!subscriptions.subscribe(&characteristic));
+        assert!(subscriptions.is_subscribed(&characteristic));
+
+        assert!(!subscriptions.unsubscribe(&characteristic));
+        assert!(!subscriptions.unsubscribe(&characteristic));
+        assert!(subscriptions.is_subscribed(&characteristic));
+        assert!(subscriptions.unsubscribe(&characteristic));
+        assert!(!subscriptions.is_subscribed(&characteristic));
+        assert!(subscriptions.subscribed().is_empty());
+    }
+
+    #[test]
+    fn characteristics_are_counted_separately() {
+        let mut subscriptions = SubscriptionCounts::default();
+        assert!(subscriptions.subscribe(&characteristic(1)));
+        assert!(subscriptions.subscribe(&characteristic(2)));
+        assert_eq!(subscriptions.subscribed().len(), 2);
+
+        assert!(subscriptions.unsubscribe(&characteristic(1)));
+        assert!(!subscriptions.is_subscribed(&characteristic(1)));
+        assert!(subscriptions.is_subscribed(&characteristic(2)));
+    }
+
+    #[test]
+    fn unsubscribe_without_subscribing() {
+        let mut subscriptions = SubscriptionCounts::default();
+        assert!(!subscriptions.unsubscribe(&characteristic(1)));
+
+        // Nor does it take away someone else's subscription.
+        assert!(subscriptions.subscribe(&characteristic(1)));
+        assert!(!subscriptions.unsubscribe(&characteristic(2)));
+        assert!(subscriptions.is_subscribed(&characteristic(1)));
+    }
+
+    #[test]
+    fn restore_lost_subscriptions() {
+        let mut subscriptions = SubscriptionCounts::default();
+        subscriptions.subscribe(&characteristic(1));
+        subscriptions.subscribe(&characteristic(1));
+        subscriptions.subscribe(&characteristic(2));
+
+        subscriptions.disconnected();
+        assert!(subscriptions.subscribed().is_empty());
+
+        // Unsubscribing while disconnected only stops the subscription being restored.
+        assert!(!subscriptions.unsubscribe(&characteristic(2)));
+        assert!(!subscriptions.unsubscribe(&characteristic(1)));
+
+        assert_eq!(subscriptions.reconnected(true), vec![characteristic(1)]);
+        assert!(subscriptions.is_subscribed(&characteristic(1)));
+        assert!(!subscriptions.is_subscribed(&characteristic(2)));
+        // Only one subscriber is left.
+        assert!(subscriptions.unsubscribe(&characteristic(1)));
+    }
+
+    #[test]
+    fn forget_lost_subscriptions() {
+        let mut subscriptions = SubscriptionCounts::default();
+        subscriptions.subscribe(&characteristic(1));
+        subscriptions.disconnected();
+
+        assert!(subscriptions.reconnected(false).is_empty());
+        assert!(subscriptions.subscribed().is_empty());
+        assert!(subscriptions.reconnected(true).is_empty());
+    }
+
+    #[test]
+    fn lost_subscriptions_merge_with_new_ones() {
+        let mut subscriptions = SubscriptionCounts::default();
+        subscriptions.subscribe(&characteristic(1));
+        subscriptions.disconnected();
+
+        // Subscribing again before the lost subscription is restored.
+        assert!(subscriptions.subscribe(&characteristic(1)));
+        assert!(subscriptions.reconnected(true).is_empty());
+        assert!(!subscriptions.unsubscribe(&characteristic(1)));
+        assert!(subscriptions.unsubscribe(&characteristic(1)));
+    }
+}
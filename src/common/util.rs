@@ -5,9 +5,24 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use futures::channel::mpsc::UnboundedSender;
+use super::{metrics::Metrics, runtime};
+use crate::{
+    api::{Characteristic, Peripheral, PeripheralSummary, RetryPolicy, ValueNotification},
+    Error, Result,
+};
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::future::{ready, Future};
+use futures::stream::{Stream, StreamExt};
+use log::debug;
 
-use std::sync::{Arc, Mutex};
+use std::collections::BTreeSet;
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use uuid::Uuid;
 
 pub fn send_notification<T: Clone>(
     notification_senders: &Arc<Mutex<Vec<UnboundedSender<T>>>>,
@@ -17,3 +32,216 @@ pub fn send_notification<T: Clone>(
     // Remove sender from the list if the other end of the channel has been dropped.
     senders.retain(|sender| sender.unbounded_send(n.clone()).is_ok());
 }
+
+/// Filters a stream of notifications down to those from the given characteristic.
+pub fn notifications_for(
+    notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+    characteristic: &Characteristic,
+) -> Pin<Box<dyn Stream<Item = ValueNotification> + Send>> {
+    let (uuid, service_uuid, handle) = (
+        characteristic.uuid,
+        characteristic.service_uuid,
+        characteristic.handle,
+    );
+    Box::pin(notifications.filter(move |notification| {
+        ready(
+            notification.uuid == uuid
+                && notification.service_uuid == service_uuid
+                && notification.handle == handle,
+        )
+    }))
+}
+
+/// Buffers `capacity` notifications from the stream for its reader. Notifications which
+/// arrive while the buffer is full are dropped and counted in `dropped` and the adapter's
+/// `metrics`, rather than holding up the stream or using more memory. With the `tokio-runtime`
+/// feature, this must be called from within a Tokio runtime.
+pub fn bounded_notifications(
+    mut notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+    capacity: usize,
+    dropped: Arc<AtomicU64>,
+    metrics: Arc<Metrics>,
+) -> Pin<Box<dyn Stream<Item = ValueNotification> + Send>> {
+    let (mut sender, receiver) = mpsc::channel(capacity);
+    runtime::spawn(async move {
+        while let Some(notification) = notifications.next().await {
+            if let Err(e) = sender.try_send(notification) {
+                if e.is_disconnected() {
+                    break;
+                }
+                dropped.fetch_add(1, Ordering::Relaxed);
+                metrics.event_dropped();
+            }
+        }
+    });
+    Box::pin(receiver)
+}
+
+/// Waits for the given operation, failing with [`Error::TimedOut`] if a timeout is given and it
+/// doesn't complete within it.
+pub async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    operation: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => runtime::timeout(timeout, operation)
+            .await
+            .unwrap_or(Err(Error::TimedOut(timeout))),
+        None => operation.await,
+    }
+}
+
+/// Runs the operation returned by the given function, running it again if it fails with an error
+/// which the retry policy allows.
+pub async fn with_retry<T, F, Fut>(policy: Option<RetryPolicy>, operation: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return operation().await,
+    };
+    let mut delay = policy.initial_delay;
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(e) if attempt < policy.max_attempts && policy.is_retryable(&e) => {
+                debug!("Attempt {} failed, retrying: {}", attempt, e)
+            }
+            result => return result,
+        }
+        runtime::sleep(delay).await;
+        delay = (delay * 2).min(policy.max_delay);
+        attempt += 1;
+    }
+}
+
+/// Connects to the peripheral, cancelling the attempt by disconnecting if it doesn't complete
+/// within the given timeout.
+pub async fn connect_with_timeout<P: Peripheral>(peripheral: &P, timeout: Duration) -> Result<()> {
+    // The result mustn't be held across the await below, as errors aren't `Send`.
+    if let Some(result) = runtime::timeout(timeout, peripheral.connect()).await {
+        return result;
+    }
+    peripheral.disconnect().await?;
+    Err(Error::TimedOut(timeout))
+}
+
+/// Builds the summary of a peripheral from its properties, connection state and characteristics.
+pub async fn summary<P: Peripheral>(peripheral: &P) -> Result<PeripheralSummary> {
+    let properties = peripheral.properties().await?;
+    let connected = peripheral.is_connected().await?;
+    let services: BTreeSet<Uuid> = peripheral
+        .characteristics()
+        .iter()
+        .map(|characteristic| characteristic.service_uuid)
+        .collect();
+    Ok(PeripheralSummary {
+        address: peripheral.address(),
+        local_name: properties.as_ref().and_then(|p| p.local_name.clone()),
+        rssi: properties.as_ref().and_then(|p| p.rssi),
+        connected,
+        service_count: services.len(),
+    })
+}
+
+/// Runs an event loop of a backend within a `tracing` span naming the backend, if the `tracing`
+/// feature is enabled, so that what it logs can be told apart from the operations of the
+/// application.
+#[cfg(feature = "tracing")]
+pub fn event_pump<F: Future>(backend: &'static str, future: F) -> impl Future<Output = F::Output> {
+    use tracing::Instrument;
+    future.instrument(tracing::debug_span!("event_pump", backend))
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn event_pump<F: Future>(_backend: &'static str, future: F) -> F {
+    future
+}
+
+/// Handles an event from a platform callback within the same span as [`event_pump`].
+pub fn in_event_pump<T>(_backend: &'static str, handler: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("event_pump", backend = _backend).entered();
+    handler()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_delay: Duration::from_millis(20),
+            max_delay: Duration::from_millis(50),
+            ..Default::default()
+        }
+    }
+
+    fn timed_out() -> Error {
+        Error::TimedOut(Duration::from_secs(1))
+    }
+
+    /// Runs `with_retry` with an operation which fails the given number of times with the given
+    /// error and then succeeds, returning the result and when each attempt started.
+    async fn run(
+        policy: Option<RetryPolicy>,
+        failures: usize,
+        error: fn() -> Error,
+    ) -> (Result<usize>, Vec<Instant>) {
+        let attempts = Mutex::new(Vec::new());
+        let result = with_retry(policy, || {
+            let mut attempts = attempts.lock().unwrap();
+            attempts.push(Instant::now());
+            let attempt = attempts.len();
+            ready(if attempt <= failures {
+                Err(error())
+            } else {
+                Ok(attempt)
+            })
+        })
+        .await;
+        (result, attempts.into_inner().unwrap())
+    }
+
+    #[tokio::test]
+    async fn runs_once_without_policy() {
+        let (result, attempts) = run(None, 1, timed_out).await;
+        assert!(matches!(result, Err(Error::TimedOut(_))));
+        assert_eq!(attempts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        let (result, attempts) = run(Some(policy()), 2, timed_out).await;
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(attempts.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let (result, attempts) = run(Some(policy()), 10, timed_out).await;
+        assert!(matches!(result, Err(Error::TimedOut(_))));
+        assert_eq!(attempts.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn returns_errors_which_are_not_retryable() {
+        let (result, attempts) = run(Some(policy()), 1, || Error::DeviceNotFound).await;
+        assert!(matches!(result, Err(Error::DeviceNotFound)));
+        assert_eq!(attempts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn delay_doubles_up_to_max() {
+        let (_, attempts) = run(Some(policy()), 10, timed_out).await;
+        let delays: Vec<Duration> = attempts.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        // Only lower bounds can be checked against the real clock, as sleeps may overrun.
+        for (delay, expected) in delays.iter().zip(&[20, 40, 50]) {
+            assert!(*delay >= Duration::from_millis(*expected), "{:?}", delays);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+/// Holds the latest version of a value, such as the properties of a peripheral, for streams which
+/// watch it for changes.
+use futures::stream::{self, Stream};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone)]
+pub struct Watched<T> {
+    sender: Arc<watch::Sender<T>>,
+    // Kept so that sending never fails for lack of receivers, and cloned for each new stream.
+    receiver: watch::Receiver<T>,
+}
+
+impl<T: Clone + Send + Sync + 'static> Watched<T> {
+    pub fn new(value: T) -> Self {
+        let (sender, receiver) = watch::channel(value);
+        Watched {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    /// Replaces the value, waking the streams watching it.
+    pub fn publish(&self, value: T) {
+        // This only fails if there are no receivers, but there is always `self.receiver`.
+        let _ = self.sender.send(value);
+    }
+
+    /// Returns a stream which yields the current value, then each new value as it is published.
+    /// Values published faster than the stream is read are skipped, so it always yields the
+    /// latest.
+    pub fn stream(&self) -> Pin<Box<dyn Stream<Item = T> + Send>> {
+        let receiver = self.receiver.clone();
+        Box::pin(stream::unfold(
+            (receiver, true),
+            |(mut receiver, first)| async move {
+                if !first {
+                    receiver.changed().await.ok()?;
+                }
+                let value = receiver.borrow_and_update().clone();
+                Some((value, (receiver, false)))
+            },
+        ))
+    }
+}
@@ -1,7 +1,14 @@
-use super::internal::{run_corebluetooth_thread, CoreBluetoothEvent, CoreBluetoothMessage};
+use super::internal::{
+    run_corebluetooth_thread, CoreBluetoothEvent, CoreBluetoothMessage, CoreBluetoothReply,
+    CoreBluetoothReplyFuture,
+};
 use super::peripheral::Peripheral;
-use crate::api::{BDAddr, Central, CentralEvent};
-use crate::common::adapter_manager::AdapterManager;
+use crate::api::{
+    AdapterMetrics, AdapterState, Advertisement, BDAddr, Central, CentralEvent, EventFilter,
+    EventKinds, GattServer, GattServerEvent, IdentityResolvingKey, LocalService,
+    PeriodicAdvertisement, ScanFilter, ScanType,
+};
+use crate::common::{adapter_manager::AdapterManager, runtime, util};
 use crate::{Error, Result};
 use async_trait::async_trait;
 use futures::channel::mpsc::{self, Sender};
@@ -10,13 +17,20 @@ use futures::stream::{Stream, StreamExt};
 use log::*;
 use std::convert::{TryFrom, TryInto};
 use std::pin::Pin;
-use tokio::task;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use uuid::Uuid;
 
 /// Implementation of [api::Central](crate::api::Central).
 #[derive(Clone, Debug)]
 pub struct Adapter {
     manager: AdapterManager<Peripheral>,
     sender: Sender<CoreBluetoothMessage>,
+    scanning: Arc<AtomicBool>,
+    state: Arc<Mutex<AdapterState>>,
 }
 
 pub(crate) fn uuid_to_bdaddr(uuid: &str) -> BDAddr {
@@ -25,57 +39,99 @@ pub(crate) fn uuid_to_bdaddr(uuid: &str) -> BDAddr {
 }
 
 impl Adapter {
-    pub(crate) async fn new() -> Result<Self> {
+    pub(crate) async fn new(restore_identifier: Option<String>) -> Result<Self> {
         let (sender, mut receiver) = mpsc::channel(256);
-        let adapter_sender = run_corebluetooth_thread(sender)?;
+        let adapter_sender = run_corebluetooth_thread(sender, restore_identifier)?;
         // Since init currently blocked until the state update, we know the
         // receiver is dropped after that. We can pick it up here and make it
         // part of our event loop to update our peripherals.
         debug!("Waiting on adapter connect");
-        if !matches!(
-            receiver.next().await,
-            Some(CoreBluetoothEvent::AdapterConnected)
-        ) {
-            return Err(Error::Other(
-                "Adapter failed to connect.".to_string().into(),
-            ));
-        }
+        let state = match receiver.next().await {
+            Some(CoreBluetoothEvent::AdapterStateChanged(state)) => state,
+            _ => {
+                return Err(Error::Other(
+                    "Adapter failed to connect.".to_string().into(),
+                ))
+            }
+        };
         debug!("Adapter connected");
         let manager = AdapterManager::default();
+        let scanning = Arc::new(AtomicBool::new(false));
+        let state = Arc::new(Mutex::new(state));
 
         let manager_clone = manager.clone();
+        let scanning_clone = scanning.clone();
+        let state_clone = state.clone();
         let adapter_sender_clone = adapter_sender.clone();
-        task::spawn(async move {
+        runtime::spawn(util::event_pump("corebluetooth", async move {
             while let Some(msg) = receiver.next().await {
                 match msg {
                     CoreBluetoothEvent::DeviceDiscovered(uuid, name, event_receiver) => {
                         // TODO Gotta change uuid into a BDAddr for now. Expand
                         // library identifier type. :(
                         let id = uuid_to_bdaddr(&uuid.to_string());
-                        manager_clone.add_peripheral(
-                            id,
+                        let (peripheral, _) = manager_clone.peripheral_or_insert_with(id, || {
                             Peripheral::new(
                                 uuid,
                                 name,
                                 manager_clone.clone(),
                                 event_receiver,
                                 adapter_sender_clone.clone(),
-                            ),
-                        );
-                        manager_clone.emit(CentralEvent::DeviceDiscovered(id));
+                            )
+                        });
+                        peripheral.emit_advertisement(CentralEvent::DeviceDiscovered(id));
                     }
                     CoreBluetoothEvent::DeviceUpdated(uuid, name) => {
                         let id = uuid_to_bdaddr(&uuid.to_string());
-                        if let Some(mut entry) = manager_clone.peripheral_mut(id) {
-                            entry.value().update_name(&name);
-                            manager_clone.emit(CentralEvent::DeviceUpdated(id));
+                        if let Some(entry) = manager_clone.peripheral_mut(id) {
+                            let changes = entry.value().update_name(&name);
+                            entry
+                                .value()
+                                .emit_advertisement(CentralEvent::DeviceUpdated {
+                                    address: id,
+                                    changes,
+                                });
                         }
                     }
-                    CoreBluetoothEvent::DeviceLost(uuid) => {
+                    CoreBluetoothEvent::DeviceLost(uuid, reason) => {
                         let id = uuid_to_bdaddr(&uuid.to_string());
-                        manager_clone.emit(CentralEvent::DeviceDisconnected(id));
+                        manager_clone.emit(CentralEvent::DeviceDisconnected(id, reason));
+                    }
+                    CoreBluetoothEvent::DeviceRetrieved(_uuid, fut) => {
+                        fut.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+                    }
+                    CoreBluetoothEvent::AdapterStateChanged(state) => {
+                        *state_clone.lock().unwrap() = state;
+                        manager_clone.emit(CentralEvent::AdapterStateChanged(state));
+                        // CoreBluetooth stops scanning when the adapter is turned off, and doesn't
+                        // start again by itself.
+                        if state != AdapterState::PoweredOn
+                            && scanning_clone.swap(false, Ordering::Relaxed)
+                        {
+                            manager_clone.emit(CentralEvent::ScanStopped);
+                        }
+                    }
+                }
+            }
+        }));
+
+        // Peripherals which stop advertising are removed from the manager, so the thread must
+        // forget them too for them to be discovered again.
+        let mut manager_events = manager.event_stream(EventFilter {
+            kinds: EventKinds::DEVICE_LOST,
+            ..Default::default()
+        });
+        let mut forget_sender = adapter_sender.clone();
+        runtime::spawn(async move {
+            while let Some(event) = manager_events.next().await {
+                if let CentralEvent::DeviceLost(address) = event {
+                    if forget_sender
+                        .send(CoreBluetoothMessage::ForgetPeripheral(address))
+                        .await
+                        .is_err()
+                    {
+                        break;
                     }
-                    _ => {}
                 }
             }
         });
@@ -83,8 +139,32 @@ impl Adapter {
         Ok(Adapter {
             manager,
             sender: adapter_sender,
+            scanning,
+            state,
         })
     }
+
+    /// Returns the peripheral with the given CoreBluetooth identifier, if the system still knows
+    /// about it. The identifier is available from [`Peripheral::identifier`], and can be stored to
+    /// reconnect to the same device after the application restarts without scanning for it.
+    pub async fn retrieve_peripheral(&self, identifier: Uuid) -> Result<Peripheral> {
+        let fut = CoreBluetoothReplyFuture::default();
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::RetrievePeripheral(
+                identifier,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => self
+                .manager
+                .peripheral(uuid_to_bdaddr(&identifier.to_string()))
+                .ok_or(Error::DeviceNotFound),
+            CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+    }
 }
 
 #[async_trait]
@@ -92,14 +172,44 @@ impl Central for Adapter {
     type Peripheral = Peripheral;
 
     async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
-        Ok(self.manager.event_stream())
+        Ok(self.manager.event_stream(EventFilter::default()))
+    }
+
+    async fn events_filtered(
+        &self,
+        filter: EventFilter,
+    ) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        Ok(self.manager.event_stream(filter))
     }
 
-    async fn start_scan(&self) -> Result<()> {
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        if filter.scan_type == ScanType::Passive {
+            return Err(Error::NotSupported(
+                "Passive scanning is not supported on CoreBluetooth".to_string(),
+            ));
+        }
+        // CoreBluetooth silently ignores scan requests unless it is powered on, so fail here rather
+        // than leave the caller waiting for devices which will never be discovered.
+        match *self.state.lock().unwrap() {
+            AdapterState::PoweredOn | AdapterState::Unknown => {}
+            AdapterState::Unauthorized => return Err(Error::PermissionDenied),
+            state => {
+                return Err(Error::Other(
+                    format!("Can't scan while the adapter is {:?}", state).into(),
+                ))
+            }
+        }
+        // CoreBluetooth can only filter on services itself, so the rest of the filter is applied
+        // as advertisements are received.
+        let services = filter.services.clone();
+        self.manager.set_scan_filter(filter);
         self.sender
             .to_owned()
-            .send(CoreBluetoothMessage::StartScanning)
+            .send(CoreBluetoothMessage::StartScanning(services))
             .await?;
+        if !self.scanning.swap(true, Ordering::Relaxed) {
+            self.manager.emit(CentralEvent::ScanStarted);
+        }
         Ok(())
     }
 
@@ -108,9 +218,26 @@ impl Central for Adapter {
             .to_owned()
             .send(CoreBluetoothMessage::StopScanning)
             .await?;
+        if self.scanning.swap(false, Ordering::Relaxed) {
+            self.manager.emit(CentralEvent::ScanStopped);
+        }
         Ok(())
     }
 
+    async fn is_scanning(&self) -> Result<bool> {
+        Ok(self.scanning.load(Ordering::Relaxed))
+    }
+
+    async fn set_powered(&self, _powered: bool) -> Result<()> {
+        Err(Error::NotSupported(
+            "Turning the adapter on or off is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn adapter_state(&self) -> Result<AdapterState> {
+        Ok(*self.state.lock().unwrap())
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         Ok(self.manager.peripherals())
     }
@@ -121,9 +248,110 @@ impl Central for Adapter {
             .ok_or(Error::DeviceNotFound)
     }
 
+    async fn set_retain_peripherals(&self, retain: bool) -> Result<()> {
+        self.manager.set_retain_peripherals(retain);
+        self.sender
+            .to_owned()
+            .send(CoreBluetoothMessage::RetainPeripherals(retain))
+            .await?;
+        Ok(())
+    }
+
+    async fn set_peripheral_ttl(&self, ttl: Option<Duration>) -> Result<()> {
+        self.manager.set_peripheral_ttl(ttl);
+        Ok(())
+    }
+
+    async fn set_max_peripherals(&self, max: Option<usize>) -> Result<()> {
+        self.manager.set_max_peripherals(max);
+        Ok(())
+    }
+
+    async fn add_identity_resolving_key(
+        &self,
+        _identity: BDAddr,
+        _irk: IdentityResolvingKey,
+    ) -> Result<()> {
+        Err(Error::NotSupported(
+            "Resolving private addresses is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn remove_identity_resolving_key(&self, _identity: BDAddr) -> Result<()> {
+        Err(Error::NotSupported(
+            "Resolving private addresses is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
     async fn add_peripheral(&self, _address: BDAddr) -> Result<Peripheral> {
         Err(Error::NotSupported(
-            "Can't add a Peripheral from a BDAddr".to_string(),
+            "Can't add a Peripheral from a BDAddr on CoreBluetooth, use Adapter::retrieve_peripheral"
+                .to_string(),
+        ))
+    }
+
+    async fn bonded_peripherals(&self) -> Result<Vec<Peripheral>> {
+        Err(Error::NotSupported(
+            "CoreBluetooth does not expose the list of bonded devices".to_string(),
+        ))
+    }
+
+    async fn start_advertising(&self, _advertisement: &Advertisement) -> Result<()> {
+        Err(Error::NotSupported(
+            "Advertising is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "Advertising is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn sync_periodic_advertising(
+        &self,
+        _address: BDAddr,
+        _sid: u8,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeriodicAdvertisement> + Send>>> {
+        Err(Error::NotSupported(
+            "Periodic advertising sync is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn metrics(&self) -> Result<AdapterMetrics> {
+        Ok(self.manager.metrics().snapshot())
+    }
+}
+
+#[async_trait]
+impl GattServer for Adapter {
+    async fn add_service(&self, _service: &LocalService) -> Result<()> {
+        Err(Error::NotSupported(
+            "Hosting GATT services is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn remove_service(&self, _service: Uuid) -> Result<()> {
+        Err(Error::NotSupported(
+            "Hosting GATT services is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn set_value(&self, _service: Uuid, _characteristic: Uuid, _value: &[u8]) -> Result<()> {
+        Err(Error::NotSupported(
+            "Hosting GATT services is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn notify(&self, _service: Uuid, _characteristic: Uuid, _value: &[u8]) -> Result<()> {
+        Err(Error::NotSupported(
+            "Hosting GATT services is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn server_events(&self) -> Result<Pin<Box<dyn Stream<Item = GattServerEvent> + Send>>> {
+        Err(Error::NotSupported(
+            "Hosting GATT services is not supported on CoreBluetooth".to_string(),
         ))
     }
 }
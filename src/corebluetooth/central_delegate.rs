@@ -20,9 +20,12 @@ use super::{
     framework::{cb, nil, ns},
     utils::{
         core_bluetooth::{cbuuid_to_uuid, characteristic_debug, peripheral_debug, service_debug},
-        nsdata_to_vec, nsuuid_to_uuid,
+        nsdata_to_vec,
+        nsstring::nsstring_to_string,
+        nsuuid_to_uuid,
     },
 };
+use crate::api::{AdapterState, DisconnectReason};
 use futures::channel::mpsc::{self, Receiver, Sender};
 use futures::sink::SinkExt;
 use libc::{c_char, c_void};
@@ -44,30 +47,45 @@ use std::{
 use uuid::Uuid;
 
 pub enum CentralDelegateEvent {
-    DidUpdateState,
+    DidUpdateState(AdapterState),
     DiscoveredPeripheral(StrongPtr),
     // Peripheral UUID, HashMap Service Uuid to StrongPtr
     DiscoveredServices(Uuid, HashMap<Uuid, StrongPtr>),
+    // Peripheral UUID
+    ServicesModified(Uuid),
     ManufacturerData(Uuid, u16, Vec<u8>),
     ServiceData(Uuid, HashMap<Uuid, Vec<u8>>),
     Services(Uuid, Vec<Uuid>),
-    // DiscoveredIncludedServices(Uuid, HashMap<Uuid, StrongPtr>),
-    // Peripheral UUID, HashMap Characteristic Uuid to StrongPtr
-    DiscoveredCharacteristics(Uuid, HashMap<Uuid, StrongPtr>),
+    // Peripheral UUID, RSSI of the advertisement if known
+    Advertised(Uuid, Option<i16>),
+    // Peripheral UUID, Service UUID, HashMap Included Service Uuid to StrongPtr
+    DiscoveredIncludedServices(Uuid, Uuid, HashMap<Uuid, StrongPtr>),
+    // Peripheral UUID, Service UUID, HashMap Characteristic Uuid to StrongPtr
+    DiscoveredCharacteristics(Uuid, Uuid, HashMap<Uuid, StrongPtr>),
     ConnectedDevice(Uuid),
-    DisconnectedDevice(Uuid),
+    DisconnectedDevice(Uuid, DisconnectReason),
     CharacteristicSubscribed(Uuid, Uuid),
     CharacteristicUnsubscribed(Uuid, Uuid),
     CharacteristicNotified(Uuid, Uuid, Vec<u8>),
     CharacteristicWritten(Uuid, Uuid),
-    // TODO Deal with descriptors at some point, but not a huge worry at the moment.
-    // DiscoveredDescriptors(String, )
+    // Peripheral UUID
+    ReadyToWriteWithoutResponse(Uuid),
+    // Peripheral UUID, Characteristic UUID, HashMap Descriptor Uuid to StrongPtr
+    DiscoveredDescriptors(Uuid, Uuid, HashMap<Uuid, StrongPtr>),
+    DescriptorNotified(Uuid, Uuid, Uuid, Vec<u8>),
+    DescriptorWritten(Uuid, Uuid, Uuid),
+    // Peripheral UUID, RSSI or error description
+    ReadRssi(Uuid, Result<i16, String>),
+    // Peripheral UUID, CBL2CAPChannel or error description
+    L2capChannelOpened(Uuid, Result<StrongPtr, String>),
 }
 
 impl Debug for CentralDelegateEvent {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            CentralDelegateEvent::DidUpdateState => f.debug_tuple("DidUpdateState").finish(),
+            CentralDelegateEvent::DidUpdateState(state) => {
+                f.debug_tuple("DidUpdateState").field(state).finish()
+            }
             CentralDelegateEvent::DiscoveredPeripheral(p) => f
                 .debug_tuple("CentralDelegateEvent")
                 .field(p.deref())
@@ -77,17 +95,37 @@ impl Debug for CentralDelegateEvent {
                 .field(uuid)
                 .field(&services.keys().collect::<Vec<_>>())
                 .finish(),
-            CentralDelegateEvent::DiscoveredCharacteristics(uuid, characteristics) => f
+            CentralDelegateEvent::ReadyToWriteWithoutResponse(uuid) => f
+                .debug_tuple("ReadyToWriteWithoutResponse")
+                .field(uuid)
+                .finish(),
+            CentralDelegateEvent::ServicesModified(uuid) => {
+                f.debug_tuple("ServicesModified").field(uuid).finish()
+            }
+            CentralDelegateEvent::DiscoveredIncludedServices(uuid, service_uuid, services) => f
+                .debug_tuple("DiscoveredIncludedServices")
+                .field(uuid)
+                .field(service_uuid)
+                .field(&services.keys().collect::<Vec<_>>())
+                .finish(),
+            CentralDelegateEvent::DiscoveredCharacteristics(
+                uuid,
+                service_uuid,
+                characteristics,
+            ) => f
                 .debug_tuple("DiscoveredCharacteristics")
                 .field(uuid)
+                .field(service_uuid)
                 .field(&characteristics.keys().collect::<Vec<_>>())
                 .finish(),
             CentralDelegateEvent::ConnectedDevice(uuid) => {
                 f.debug_tuple("ConnectedDevice").field(uuid).finish()
             }
-            CentralDelegateEvent::DisconnectedDevice(uuid) => {
-                f.debug_tuple("DisconnectedDevice").field(uuid).finish()
-            }
+            CentralDelegateEvent::DisconnectedDevice(uuid, reason) => f
+                .debug_tuple("DisconnectedDevice")
+                .field(uuid)
+                .field(reason)
+                .finish(),
             CentralDelegateEvent::CharacteristicSubscribed(uuid1, uuid2) => f
                 .debug_tuple("CharacteristicSubscribed")
                 .field(uuid1)
@@ -109,6 +147,33 @@ impl Debug for CentralDelegateEvent {
                 .field(uuid1)
                 .field(uuid2)
                 .finish(),
+            CentralDelegateEvent::DiscoveredDescriptors(uuid1, uuid2, descriptors) => f
+                .debug_tuple("DiscoveredDescriptors")
+                .field(uuid1)
+                .field(uuid2)
+                .field(&descriptors.keys().collect::<Vec<_>>())
+                .finish(),
+            CentralDelegateEvent::DescriptorNotified(uuid1, uuid2, uuid3, vec) => f
+                .debug_tuple("DescriptorNotified")
+                .field(uuid1)
+                .field(uuid2)
+                .field(uuid3)
+                .field(vec)
+                .finish(),
+            CentralDelegateEvent::DescriptorWritten(uuid1, uuid2, uuid3) => f
+                .debug_tuple("DescriptorWritten")
+                .field(uuid1)
+                .field(uuid2)
+                .field(uuid3)
+                .finish(),
+            CentralDelegateEvent::ReadRssi(uuid, rssi) => {
+                f.debug_tuple("ReadRssi").field(uuid).field(rssi).finish()
+            }
+            CentralDelegateEvent::L2capChannelOpened(uuid, result) => f
+                .debug_tuple("L2capChannelOpened")
+                .field(uuid)
+                .field(&result.as_ref().map(|channel| channel.deref()))
+                .finish(),
             CentralDelegateEvent::ManufacturerData(uuid, manufacturer_id, manufacturer_data) => f
                 .debug_tuple("ManufacturerData")
                 .field(uuid)
@@ -125,13 +190,14 @@ impl Debug for CentralDelegateEvent {
                 .field(uuid)
                 .field(services)
                 .finish(),
+            CentralDelegateEvent::Advertised(uuid, rssi) => {
+                f.debug_tuple("Advertised").field(uuid).field(rssi).finish()
+            }
         }
     }
 }
 
 pub mod CentralDelegate {
-    use std::convert::TryInto;
-
     use super::*;
 
     pub fn delegate() -> (*mut Object, Receiver<CentralDelegateEvent>) {
@@ -178,8 +244,8 @@ pub mod CentralDelegate {
                 // CentralManager Events
                 decl.add_method(sel!(centralManagerDidUpdateState:),
                                 delegate_centralmanagerdidupdatestate as extern fn(&mut Object, Sel, *mut Object));
-                // decl.add_method(sel!(centralManager:willRestoreState:),
-                //                 delegate_centralmanager_willrestorestate as extern fn(&mut Object, Sel, *mut Object, *mut Object));
+                decl.add_method(sel!(centralManager:willRestoreState:),
+                                delegate_centralmanager_willrestorestate as extern fn(&mut Object, Sel, *mut Object, *mut Object));
                 decl.add_method(sel!(centralManager:didConnectPeripheral:),
                                 delegate_centralmanager_didconnectperipheral as extern fn(&mut Object, Sel, *mut Object, *mut Object));
                 decl.add_method(sel!(centralManager:didDisconnectPeripheral:error:),
@@ -192,13 +258,20 @@ pub mod CentralDelegate {
                 // Peripheral events
                 decl.add_method(sel!(peripheral:didDiscoverServices:),
                                 delegate_peripheral_diddiscoverservices as extern fn(&mut Object, Sel, *mut Object, *mut Object));
+                decl.add_method(sel!(peripheral:didModifyServices:),
+                                delegate_peripheral_didmodifyservices as extern fn(&mut Object, Sel, *mut Object, *mut Object));
+                decl.add_method(sel!(peripheralIsReadyToSendWriteWithoutResponse:),
+                                delegate_peripheral_isreadytosendwritewithoutresponse as extern fn(&mut Object, Sel, *mut Object));
                 decl.add_method(sel!(peripheral:didDiscoverIncludedServicesForService:error:),
                                 delegate_peripheral_diddiscoverincludedservicesforservice_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
                 decl.add_method(sel!(peripheral:didDiscoverCharacteristicsForService:error:),
                                 delegate_peripheral_diddiscovercharacteristicsforservice_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
-                // TODO Finish implementing this.
-                // decl.add_method(sel!(peripheral:didDiscoverDescriptorsForCharacteristic:error:),
-                //                 delegate_peripheral_diddiscoverdescriptorsforcharacteristic_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
+                decl.add_method(sel!(peripheral:didDiscoverDescriptorsForCharacteristic:error:),
+                                delegate_peripheral_diddiscoverdescriptorsforcharacteristic_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
+                decl.add_method(sel!(peripheral:didUpdateValueForDescriptor:error:),
+                                delegate_peripheral_didupdatevaluefordescriptor_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
+                decl.add_method(sel!(peripheral:didWriteValueForDescriptor:error:),
+                                delegate_peripheral_didwritevaluefordescriptor_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
                 decl.add_method(sel!(peripheral:didUpdateValueForCharacteristic:error:),
                                 delegate_peripheral_didupdatevalueforcharacteristic_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
                 decl.add_method(sel!(peripheral:didUpdateNotificationStateForCharacteristic:error:),
@@ -207,6 +280,8 @@ pub mod CentralDelegate {
                                 delegate_peripheral_didwritevalueforcharacteristic_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
                 decl.add_method(sel!(peripheral:didReadRSSI:error:),
                                 delegate_peripheral_didreadrssi_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
+                decl.add_method(sel!(peripheral:didOpenL2CAPChannel:error:),
+                                delegate_peripheral_didopenl2capchannel_error as extern fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object));
             }
 
             decl.register();
@@ -275,6 +350,25 @@ pub mod CentralDelegate {
         v
     }
 
+    fn get_descriptor_value(descriptor: *mut Object) -> Vec<u8> {
+        // Unlike characteristics, CoreBluetooth converts the values of well known descriptors into
+        // NSNumber or NSString objects rather than handing back the raw NSData.
+        let value = cb::descriptor_value(descriptor);
+        if value == nil {
+            vec![]
+        } else if ns::object_iskindofclass(value, "NSData") {
+            nsdata_to_vec(value)
+        } else if ns::object_iskindofclass(value, "NSString") {
+            nsstring_to_string(value).unwrap_or_default().into_bytes()
+        } else if ns::object_iskindofclass(value, "NSNumber") {
+            (ns::number_unsignedlonglongvalue(value) as u16)
+                .to_le_bytes()
+                .to_vec()
+        } else {
+            vec![]
+        }
+    }
+
     ////////////////////////////////////////////////////////////////
     //
     // CentralManager Handlers
@@ -284,15 +378,45 @@ pub mod CentralDelegate {
     extern "C" fn delegate_centralmanagerdidupdatestate(
         delegate: &mut Object,
         _cmd: Sel,
-        _central: *mut Object,
+        central: *mut Object,
     ) {
         trace!("delegate_centralmanagerdidupdatestate");
-        send_delegate_event(delegate, CentralDelegateEvent::DidUpdateState);
+        let state = match cb::manager_state(central) {
+            cb::MANAGERSTATE_POWEREDON => AdapterState::PoweredOn,
+            cb::MANAGERSTATE_POWEREDOFF => AdapterState::PoweredOff,
+            cb::MANAGERSTATE_RESETTING => AdapterState::Resetting,
+            cb::MANAGERSTATE_UNSUPPORTED => AdapterState::Unsupported,
+            cb::MANAGERSTATE_UNAUTHORIZED => AdapterState::Unauthorized,
+            _ => AdapterState::Unknown,
+        };
+        send_delegate_event(delegate, CentralDelegateEvent::DidUpdateState(state));
     }
 
-    // extern fn delegate_centralmanager_willrestorestate(_delegate: &mut Object, _cmd: Sel, _central: *mut Object, _dict: *mut Object) {
-    //     trace!("delegate_centralmanager_willrestorestate");
-    // }
+    extern "C" fn delegate_centralmanager_willrestorestate(
+        delegate: &mut Object,
+        _cmd: Sel,
+        _central: *mut Object,
+        dict: *mut Object,
+    ) {
+        trace!("delegate_centralmanager_willrestorestate");
+        // Peripherals which were connected or being connected to when the app was suspended.
+        // Treat them as newly discovered, so that the adapter knows about them again.
+        let peripherals = ns::dictionary_objectforkey(dict, unsafe {
+            cb::CENTRALMANAGERRESTOREDSTATEPERIPHERALSKEY
+        });
+        if peripherals == nil {
+            return;
+        }
+        for i in 0..ns::array_count(peripherals) {
+            let peripheral = ns::array_objectatindex(peripherals, i);
+            cb::peripheral_setdelegate(peripheral, delegate);
+            let held_peripheral = unsafe { StrongPtr::retain(peripheral) };
+            send_delegate_event(
+                delegate,
+                CentralDelegateEvent::DiscoveredPeripheral(held_peripheral),
+            );
+        }
+    }
 
     extern "C" fn delegate_centralmanager_didconnectperipheral(
         delegate: &mut Object,
@@ -315,14 +439,27 @@ pub mod CentralDelegate {
         _cmd: Sel,
         _central: *mut Object,
         peripheral: *mut Object,
-        _error: *mut Object,
+        error: *mut Object,
     ) {
         trace!(
             "delegate_centralmanager_diddisconnectperipheral_error {}",
             peripheral_debug(peripheral)
         );
         let uuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
-        send_delegate_event(delegate, CentralDelegateEvent::DisconnectedDevice(uuid));
+        // There is no error if the connection was cancelled by us.
+        let reason = if error == nil {
+            DisconnectReason::UserRequest
+        } else {
+            match ns::error_code(error) {
+                cb::ERROR_CONNECTIONTIMEOUT => DisconnectReason::SupervisionTimeout,
+                cb::ERROR_PERIPHERALDISCONNECTED => DisconnectReason::RemoteTerminated,
+                _ => DisconnectReason::Unknown,
+            }
+        };
+        send_delegate_event(
+            delegate,
+            CentralDelegateEvent::DisconnectedDevice(uuid, reason),
+        );
     }
 
     // extern fn delegate_centralmanager_didfailtoconnectperipheral_error(_delegate: &mut Object, _cmd: Sel, _central: *mut Object, _peripheral: *mut Object, _error: *mut Object) {
@@ -335,7 +472,7 @@ pub mod CentralDelegate {
         _central: *mut Object,
         peripheral: *mut Object,
         adv_data: *mut Object,
-        _rssi: *mut Object,
+        rssi: *mut Object,
     ) {
         trace!(
             "delegate_centralmanager_diddiscoverperipheral_advertisementdata_rssi {}",
@@ -353,6 +490,10 @@ pub mod CentralDelegate {
 
         let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
 
+        // CoreBluetooth reports an RSSI of 127 if it isn't available.
+        let rssi = Some(ns::number_integervalue(rssi) as i16).filter(|rssi| *rssi != 127);
+        send_delegate_event(delegate, CentralDelegateEvent::Advertised(puuid, rssi));
+
         let manufacturer_data = ns::dictionary_objectforkey(adv_data, unsafe {
             cb::ADVERTISEMENT_DATA_MANUFACTURER_DATA_KEY
         });
@@ -368,7 +509,7 @@ pub mod CentralDelegate {
                     delegate,
                     CentralDelegateEvent::ManufacturerData(
                         puuid,
-                        u16::from_le_bytes(manufacturer_id.try_into().unwrap()),
+                        u16::from_le_bytes([manufacturer_id[0], manufacturer_id[1]]),
                         Vec::from(manufacturer_data),
                     ),
                 );
@@ -451,8 +592,38 @@ pub mod CentralDelegate {
         }
     }
 
+    extern "C" fn delegate_peripheral_didmodifyservices(
+        delegate: &mut Object,
+        _cmd: Sel,
+        peripheral: *mut Object,
+        _invalidated_services: *mut Object,
+    ) {
+        trace!(
+            "delegate_peripheral_didmodifyservices {}",
+            peripheral_debug(peripheral)
+        );
+        let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
+        send_delegate_event(delegate, CentralDelegateEvent::ServicesModified(puuid));
+    }
+
+    extern "C" fn delegate_peripheral_isreadytosendwritewithoutresponse(
+        delegate: &mut Object,
+        _cmd: Sel,
+        peripheral: *mut Object,
+    ) {
+        trace!(
+            "delegate_peripheral_isreadytosendwritewithoutresponse {}",
+            peripheral_debug(peripheral)
+        );
+        let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
+        send_delegate_event(
+            delegate,
+            CentralDelegateEvent::ReadyToWriteWithoutResponse(puuid),
+        );
+    }
+
     extern "C" fn delegate_peripheral_diddiscoverincludedservicesforservice_error(
-        _delegate: &mut Object,
+        delegate: &mut Object,
         _cmd: Sel,
         peripheral: *mut Object,
         service: *mut Object,
@@ -464,13 +635,27 @@ pub mod CentralDelegate {
             service_debug(service),
             localized_description(error)
         );
+        // Report the includes even if they couldn't be discovered, as connecting waits for them.
+        let mut service_map = HashMap::new();
         if error == nil {
             let includes = cb::service_includedservices(service);
             for i in 0..ns::array_count(includes) {
                 let s = ns::array_objectatindex(includes, i);
                 cb::peripheral_discovercharacteristicsforservice(peripheral, s);
+                let uuid = cbuuid_to_uuid(cb::attribute_uuid(s));
+                let held_service;
+                unsafe {
+                    held_service = StrongPtr::retain(s);
+                }
+                service_map.insert(uuid, held_service);
             }
         }
+        let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
+        let service_uuid = cbuuid_to_uuid(cb::attribute_uuid(service));
+        send_delegate_event(
+            delegate,
+            CentralDelegateEvent::DiscoveredIncludedServices(puuid, service_uuid, service_map),
+        );
     }
 
     extern "C" fn delegate_peripheral_diddiscovercharacteristicsforservice_error(
@@ -502,9 +687,10 @@ pub mod CentralDelegate {
                 char_map.insert(uuid, held_char);
             }
             let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
+            let service_uuid = cbuuid_to_uuid(cb::attribute_uuid(service));
             send_delegate_event(
                 delegate,
-                CentralDelegateEvent::DiscoveredCharacteristics(puuid, char_map),
+                CentralDelegateEvent::DiscoveredCharacteristics(puuid, service_uuid, char_map),
             );
         }
     }
@@ -581,29 +767,148 @@ pub mod CentralDelegate {
         }
     }
 
-    // extern fn delegate_peripheral_diddiscoverdescriptorsforcharacteristic_error(_delegate: &mut Object, _cmd: Sel, _peripheral: *mut Object, _characteristic: *mut Object, _error: *mut Object) {
-    //     info!("delegate_peripheral_diddiscoverdescriptorsforcharacteristic_error");
-    // }
+    extern "C" fn delegate_peripheral_diddiscoverdescriptorsforcharacteristic_error(
+        delegate: &mut Object,
+        _cmd: Sel,
+        peripheral: *mut Object,
+        characteristic: *mut Object,
+        error: *mut Object,
+    ) {
+        trace!(
+            "delegate_peripheral_diddiscoverdescriptorsforcharacteristic_error {} {} {}",
+            peripheral_debug(peripheral),
+            characteristic_debug(characteristic),
+            localized_description(error)
+        );
+        if error == nil {
+            let mut descriptor_map = HashMap::new();
+            let descriptors = cb::characteristic_descriptors(characteristic);
+            for i in 0..ns::array_count(descriptors) {
+                let d = ns::array_objectatindex(descriptors, i);
+                let uuid = cbuuid_to_uuid(cb::attribute_uuid(d));
+                let held_descriptor;
+                unsafe {
+                    held_descriptor = StrongPtr::retain(d);
+                }
+                descriptor_map.insert(uuid, held_descriptor);
+            }
+            let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
+            let characteristic_uuid = cbuuid_to_uuid(cb::attribute_uuid(characteristic));
+            send_delegate_event(
+                delegate,
+                CentralDelegateEvent::DiscoveredDescriptors(
+                    puuid,
+                    characteristic_uuid,
+                    descriptor_map,
+                ),
+            );
+        }
+    }
 
-    // extern fn delegate_peripheral_didupdatevaluefordescriptor(_delegate: &mut Object, _cmd: Sel, _peripheral: *mut Object, _descriptor: *mut Object, _error: *mut Object) {
-    //     trace!("delegate_peripheral_didupdatevaluefordescriptor");
-    // }
+    extern "C" fn delegate_peripheral_didupdatevaluefordescriptor_error(
+        delegate: &mut Object,
+        _cmd: Sel,
+        peripheral: *mut Object,
+        descriptor: *mut Object,
+        error: *mut Object,
+    ) {
+        trace!(
+            "delegate_peripheral_didupdatevaluefordescriptor_error {} {}",
+            peripheral_debug(peripheral),
+            localized_description(error)
+        );
+        if error == nil {
+            let v = get_descriptor_value(descriptor);
+            let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
+            let characteristic_uuid = cbuuid_to_uuid(cb::attribute_uuid(
+                cb::descriptor_characteristic(descriptor),
+            ));
+            let descriptor_uuid = cbuuid_to_uuid(cb::attribute_uuid(descriptor));
+            send_delegate_event(
+                delegate,
+                CentralDelegateEvent::DescriptorNotified(
+                    puuid,
+                    characteristic_uuid,
+                    descriptor_uuid,
+                    v,
+                ),
+            );
+        }
+    }
 
-    // extern fn delegate_peripheral_didwritevaluefordescriptor_error(_delegate: &mut Object, _cmd: Sel, _peripheral: *mut Object, _descriptor: *mut Object, _error: *mut Object) {
-    //     trace!("delegate_peripheral_didwritevaluefordescriptor_error");
-    // }
+    extern "C" fn delegate_peripheral_didwritevaluefordescriptor_error(
+        delegate: &mut Object,
+        _cmd: Sel,
+        peripheral: *mut Object,
+        descriptor: *mut Object,
+        error: *mut Object,
+    ) {
+        trace!(
+            "delegate_peripheral_didwritevaluefordescriptor_error {} {}",
+            peripheral_debug(peripheral),
+            localized_description(error)
+        );
+        if error == nil {
+            let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
+            let characteristic_uuid = cbuuid_to_uuid(cb::attribute_uuid(
+                cb::descriptor_characteristic(descriptor),
+            ));
+            let descriptor_uuid = cbuuid_to_uuid(cb::attribute_uuid(descriptor));
+            send_delegate_event(
+                delegate,
+                CentralDelegateEvent::DescriptorWritten(
+                    puuid,
+                    characteristic_uuid,
+                    descriptor_uuid,
+                ),
+            );
+        }
+    }
 
     extern "C" fn delegate_peripheral_didreadrssi_error(
-        _delegate: &mut Object,
+        delegate: &mut Object,
         _cmd: Sel,
         peripheral: *mut Object,
-        _rssi: *mut Object,
+        rssi: *mut Object,
         error: *mut Object,
     ) {
         trace!(
             "delegate_peripheral_didreadrssi_error {}",
             peripheral_debug(peripheral)
         );
-        if error == nil {}
+        let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
+        let result = if error == nil {
+            Ok(ns::number_integervalue(rssi) as i16)
+        } else {
+            Err(nsstring_to_string(ns::error_localizeddescription(error))
+                .unwrap_or_else(|| "Failed to read RSSI".to_string()))
+        };
+        send_delegate_event(delegate, CentralDelegateEvent::ReadRssi(puuid, result));
+    }
+
+    extern "C" fn delegate_peripheral_didopenl2capchannel_error(
+        delegate: &mut Object,
+        _cmd: Sel,
+        peripheral: *mut Object,
+        channel: *mut Object,
+        error: *mut Object,
+    ) {
+        trace!(
+            "delegate_peripheral_didopenl2capchannel_error {}",
+            peripheral_debug(peripheral)
+        );
+        let puuid = nsuuid_to_uuid(cb::peer_identifier(peripheral));
+        let result = if error != nil {
+            Err(nsstring_to_string(ns::error_localizeddescription(error))
+                .unwrap_or_else(|| "Failed to open L2CAP channel".to_string()))
+        } else if channel == nil {
+            Err("Failed to open L2CAP channel".to_string())
+        } else {
+            Ok(unsafe { StrongPtr::retain(channel) })
+        };
+        send_delegate_event(
+            delegate,
+            CentralDelegateEvent::L2capChannelOpened(puuid, result),
+        );
     }
 }
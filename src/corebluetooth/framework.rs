@@ -16,7 +16,7 @@
 // This file may not be copied, modified, or distributed except
 // according to those terms.
 
-use objc::runtime::{Class, Object, BOOL};
+use objc::runtime::{Class, Object, BOOL, NO};
 use objc::{msg_send, sel, sel_impl};
 use std::os::raw::{c_char, c_int, c_uint};
 
@@ -32,6 +32,13 @@ pub mod ns {
         unsafe { msg_send![nsobject, copy] }
     }
 
+    pub fn object_iskindofclass(nsobject: *mut Object, class: &str) -> bool {
+        unsafe {
+            let result: BOOL = msg_send![nsobject, isKindOfClass: Class::get(class).unwrap()];
+            result != NO
+        }
+    }
+
     // NSNumber
 
     pub fn number_withbool(value: BOOL) -> *mut Object {
@@ -51,6 +58,20 @@ pub mod ns {
         unsafe { msg_send![nsnumber, unsignedLongLongValue] }
     }
 
+    pub fn number_integervalue(nsnumber: *mut Object) -> isize {
+        unsafe { msg_send![nsnumber, integerValue] }
+    }
+
+    // NSError
+
+    pub fn error_code(nserror: *mut Object) -> isize {
+        unsafe { msg_send![nserror, code] }
+    }
+
+    pub fn error_localizeddescription(nserror: *mut Object) -> *mut Object /* NSString* */ {
+        unsafe { msg_send![nserror, localizedDescription] }
+    }
+
     // NSString
 
     pub fn string(cstring: *const c_char) -> *mut Object /* NSString* */ {
@@ -76,6 +97,16 @@ pub mod ns {
         unsafe { msg_send![nsarray, objectAtIndex: index] }
     }
 
+    // NSMutableArray : NSArray
+
+    pub fn mutablearray() -> *mut Object {
+        unsafe { msg_send![Class::get("NSMutableArray").unwrap(), arrayWithCapacity:0] }
+    }
+
+    pub fn mutablearray_addobject(nsmutarray: *mut Object, object: *mut Object) {
+        unsafe { msg_send![nsmutarray, addObject: object] }
+    }
+
     // NSDictionary
 
     pub fn dictionary_allkeys(nsdict: *mut Object) -> *mut Object /* NSArray* */ {
@@ -118,8 +149,49 @@ pub mod ns {
         unsafe { msg_send![nsdata, bytes] }
     }
 
+    // NSStream
+
+    pub fn stream_open(nsstream: *mut Object) {
+        unsafe { msg_send![nsstream, open] }
+    }
+
+    pub fn stream_close(nsstream: *mut Object) {
+        unsafe { msg_send![nsstream, close] }
+    }
+
+    pub fn stream_streamerror(nsstream: *mut Object) -> *mut Object /* NSError* */ {
+        unsafe { msg_send![nsstream, streamError] }
+    }
+
+    // NSInputStream : NSStream
+
+    pub fn inputstream_read_maxlength(
+        nsinputstream: *mut Object,
+        buffer: *mut u8,
+        length: usize,
+    ) -> isize {
+        unsafe { msg_send![nsinputstream, read:buffer maxLength:length] }
+    }
+
+    // NSOutputStream : NSStream
+
+    pub fn outputstream_write_maxlength(
+        nsoutputstream: *mut Object,
+        buffer: *const u8,
+        length: usize,
+    ) -> isize {
+        unsafe { msg_send![nsoutputstream, write:buffer maxLength:length] }
+    }
+
     // NSUUID
 
+    pub fn uuid_initwithuuidstring(nsstring: *mut Object) -> *mut Object /* NSUUID* */ {
+        unsafe {
+            let nsuuid: *mut Object = msg_send![Class::get("NSUUID").unwrap(), alloc];
+            msg_send![nsuuid, initWithUUIDString: nsstring]
+        }
+    }
+
     pub fn uuid_uuidstring(nsuuid: *mut Object) -> *mut Object /* NSString* */ {
         unsafe {
             let uuidstring: *mut Object = msg_send![nsuuid, UUIDString];
@@ -128,6 +200,7 @@ pub mod ns {
     }
 }
 
+#[cfg(target_os = "macos")]
 pub mod io {
     use super::*;
 
@@ -201,7 +274,7 @@ pub mod cb {
     pub type dispatch_queue_attr_t = *const dispatch_object_s;
     pub const DISPATCH_QUEUE_SERIAL: dispatch_queue_attr_t = 0 as dispatch_queue_attr_t;
 
-    #[link(name = "AppKit", kind = "framework")]
+    #[cfg_attr(target_os = "macos", link(name = "AppKit", kind = "framework"))]
     #[link(name = "Foundation", kind = "framework")]
     #[link(name = "CoreBluetooth", kind = "framework")]
     extern "C" {
@@ -221,28 +294,36 @@ pub mod cb {
             pub static CBAdvertisementDataServiceUUIDsKey: *mut Object;
 
             pub static CBCentralManagerScanOptionAllowDuplicatesKey: *mut Object;
+
+            pub static CBCentralManagerOptionRestoreIdentifierKey: *mut Object;
+            pub static CBCentralManagerRestoredStatePeripheralsKey: *mut Object;
         }
     }
 
     // CBCentralManager
 
-    pub fn centralmanager(delegate: *mut Object, /*CBCentralManagerDelegate* */) -> *mut Object /*CBCentralManager* */
-    {
+    pub fn centralmanager(
+        delegate: *mut Object, /*CBCentralManagerDelegate* */
+        options: *mut Object,  /* NSDictionary<NSString*,id> */
+    ) -> *mut Object /*CBCentralManager* */ {
         let label = CString::new("CBqueue").unwrap();
         unsafe {
             let cbcentralmanager: *mut Object =
                 msg_send![Class::get("CBCentralManager").unwrap(), alloc];
             let queue = dispatch_queue_create(label.as_ptr(), DISPATCH_QUEUE_SERIAL);
 
-            msg_send![cbcentralmanager, initWithDelegate:delegate queue:queue]
+            msg_send![cbcentralmanager, initWithDelegate:delegate queue:queue options:options]
         }
     }
 
     pub fn centralmanager_scanforperipherals_options(
         cbcentralmanager: *mut Object,
-        options: *mut Object, /* NSDictionary<NSString*,id> */
+        services: *mut Object, /* NSArray<CBUUID*>* */
+        options: *mut Object,  /* NSDictionary<NSString*,id> */
     ) {
-        unsafe { msg_send![cbcentralmanager, scanForPeripheralsWithServices:nil options:options] }
+        unsafe {
+            msg_send![cbcentralmanager, scanForPeripheralsWithServices:services options:options]
+        }
     }
 
     pub fn centralmanager_stopscan(cbcentralmanager: *mut Object) {
@@ -256,6 +337,13 @@ pub mod cb {
         unsafe { msg_send![cbcentralmanager, connectPeripheral:peripheral options:nil] }
     }
 
+    pub fn centralmanager_retrieveperipheralswithidentifiers(
+        cbcentralmanager: *mut Object,
+        identifiers: *mut Object, /* NSArray<NSUUID*>* */
+    ) -> *mut Object /* NSArray<CBPeripheral*>* */ {
+        unsafe { msg_send![cbcentralmanager, retrievePeripheralsWithIdentifiers: identifiers] }
+    }
+
     pub fn centralmanager_cancelperipheralconnection(
         cbcentralmanager: *mut Object,
         peripheral: *mut Object, /* CBPeripheral* */
@@ -264,6 +352,18 @@ pub mod cb {
     }
 
     // CBManager
+    // CBManagerState = NSInteger from CBManager.h
+
+    pub const MANAGERSTATE_RESETTING: isize = 1; // CBManagerStateResetting
+    pub const MANAGERSTATE_UNSUPPORTED: isize = 2; // CBManagerStateUnsupported
+    pub const MANAGERSTATE_UNAUTHORIZED: isize = 3; // CBManagerStateUnauthorized
+    pub const MANAGERSTATE_POWEREDOFF: isize = 4; // CBManagerStatePoweredOff
+    pub const MANAGERSTATE_POWEREDON: isize = 5; // CBManagerStatePoweredOn
+
+    pub fn manager_state(cbmanager: *mut Object) -> isize {
+        unsafe { msg_send![cbmanager, state] }
+    }
+
     pub fn manager_authorization() -> CBManagerAuthorization {
         unsafe { msg_send![Class::get("CBManager").unwrap(), authorization] }
     }
@@ -350,6 +450,25 @@ pub mod cb {
         unsafe { msg_send![cbperipheral, setNotifyValue:value forCharacteristic:characteristic] }
     }
 
+    pub fn peripheral_openl2capchannel(cbperipheral: *mut Object, psm: u16) {
+        unsafe { msg_send![cbperipheral, openL2CAPChannel: psm] }
+    }
+
+    pub fn peripheral_readrssi(cbperipheral: *mut Object) {
+        unsafe { msg_send![cbperipheral, readRSSI] }
+    }
+
+    pub fn peripheral_maximumwritevaluelengthfortype(
+        cbperipheral: *mut Object,
+        write_type: usize,
+    ) -> usize {
+        unsafe { msg_send![cbperipheral, maximumWriteValueLengthForType: write_type] }
+    }
+
+    pub fn peripheral_cansendwritewithoutresponse(cbperipheral: *mut Object) -> BOOL {
+        unsafe { msg_send![cbperipheral, canSendWriteWithoutResponse] }
+    }
+
     pub fn peripheral_discoverdescriptorsforcharacteristic(
         cbperipheral: *mut Object,
         characteristic: *mut Object, /* CBCharacteristic* */
@@ -362,10 +481,30 @@ pub mod cb {
         }
     }
 
+    pub fn peripheral_readvalue_fordescriptor(
+        cbperipheral: *mut Object,
+        descriptor: *mut Object, /* CBDescriptor* */
+    ) {
+        unsafe { msg_send![cbperipheral, readValueForDescriptor: descriptor] }
+    }
+
+    pub fn peripheral_writevalue_fordescriptor(
+        cbperipheral: *mut Object,
+        value: *mut Object,      /* NSData* */
+        descriptor: *mut Object, /* CBDescriptor* */
+    ) {
+        unsafe { msg_send![cbperipheral, writeValue:value forDescriptor:descriptor] }
+    }
+
     // CBPeripheralState = NSInteger from CBPeripheral.h
 
     pub const PERIPHERALSTATE_CONNECTED: c_int = 2; // CBPeripheralStateConnected
 
+    // CBError = NSInteger from CBError.h
+
+    pub const ERROR_CONNECTIONTIMEOUT: isize = 6; // CBErrorConnectionTimeout
+    pub const ERROR_PERIPHERALDISCONNECTED: isize = 7; // CBErrorPeripheralDisconnected
+
     // CBAttribute
 
     pub fn attribute_uuid(cbattribute: *mut Object) -> *mut Object /* CBUUID* */ {
@@ -374,12 +513,12 @@ pub mod cb {
 
     // CBService : CBAttribute
 
-    // pub fn service_isprimary(cbservice: *mut Object) -> BOOL {
-    //     unsafe {
-    //         let isprimary: BOOL = msg_send![cbservice, isPrimary];
-    //         isprimary
-    //     }
-    // }
+    pub fn service_isprimary(cbservice: *mut Object) -> BOOL {
+        unsafe {
+            let isprimary: BOOL = msg_send![cbservice, isPrimary];
+            isprimary
+        }
+    }
 
     pub fn service_includedservices(cbservice: *mut Object) -> *mut Object /* NSArray<CBService*>* */
     {
@@ -405,6 +544,11 @@ pub mod cb {
         unsafe { msg_send![cbcharacteristic, properties] }
     }
 
+    pub fn characteristic_descriptors(cbcharacteristic: *mut Object) -> *mut Object /* NSArray<CBDescriptor*>* */
+    {
+        unsafe { msg_send![cbcharacteristic, descriptors] }
+    }
+
     // CBCharacteristicProperties = NSUInteger from CBCharacteristic.h
 
     pub const CHARACTERISTICPROPERTY_BROADCAST: c_uint = 0x01; // CBCharacteristicPropertyBroadcast
@@ -414,17 +558,57 @@ pub mod cb {
     pub const CHARACTERISTICPROPERTY_NOTIFY: c_uint = 0x10; // CBCharacteristicPropertyNotify
     pub const CHARACTERISTICPROPERTY_INDICATE: c_uint = 0x20; // CBCharacteristicPropertyIndicate
     pub const CHARACTERISTICPROPERTY_AUTHENTICATEDSIGNEDWRITES: c_uint = 0x40; // CBCharacteristicPropertyAuthenticatedSignedWrites
+    pub const CHARACTERISTICPROPERTY_EXTENDEDPROPERTIES: c_uint = 0x80; // CBCharacteristicPropertyExtendedProperties
+
+    // CBDescriptor : CBAttribute
+
+    pub fn descriptor_characteristic(cbdescriptor: *mut Object) -> *mut Object /* CBCharacteristic* */
+    {
+        unsafe { msg_send![cbdescriptor, characteristic] }
+    }
+
+    pub fn descriptor_value(cbdescriptor: *mut Object) -> *mut Object /* id */ {
+        unsafe { msg_send![cbdescriptor, value] }
+    }
 
     // CBUUID
 
+    pub fn uuid_uuidwithstring(nsstring: *mut Object) -> *mut Object /* CBUUID* */ {
+        unsafe { msg_send![Class::get("CBUUID").unwrap(), UUIDWithString: nsstring] }
+    }
+
     pub fn uuid_uuidstring(cbuuid: *mut Object) -> *mut Object /* NSString* */ {
         unsafe { msg_send![cbuuid, UUIDString] }
     }
 
+    // CBL2CAPChannel
+
+    pub fn l2capchannel_psm(cbl2capchannel: *mut Object) -> u16 {
+        unsafe { msg_send![cbl2capchannel, PSM] }
+    }
+
+    pub fn l2capchannel_inputstream(cbl2capchannel: *mut Object) -> *mut Object /* NSInputStream* */
+    {
+        unsafe { msg_send![cbl2capchannel, inputStream] }
+    }
+
+    pub fn l2capchannel_outputstream(cbl2capchannel: *mut Object) -> *mut Object /* NSOutputStream* */
+    {
+        unsafe { msg_send![cbl2capchannel, outputStream] }
+    }
+
     // CBCentralManagerScanOption...Key
 
     pub use self::link::CBCentralManagerScanOptionAllowDuplicatesKey as CENTRALMANAGERSCANOPTIONALLOWDUPLICATESKEY;
 
+    // CBCentralManagerOption...Key
+
+    pub use self::link::CBCentralManagerOptionRestoreIdentifierKey as CENTRALMANAGEROPTIONRESTOREIDENTIFIERKEY;
+
+    // CBCentralManagerRestoredState...Key
+
+    pub use self::link::CBCentralManagerRestoredStatePeripheralsKey as CENTRALMANAGERRESTOREDSTATEPERIPHERALSKEY;
+
     // CBAdvertisementData...Key
 
     pub use self::link::CBAdvertisementDataManufacturerDataKey as ADVERTISEMENT_DATA_MANUFACTURER_DATA_KEY;
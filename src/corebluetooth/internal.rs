@@ -9,17 +9,27 @@
 // multiple), see https://forums.developer.apple.com/thread/20810
 
 use super::{
+    adapter::uuid_to_bdaddr,
     central_delegate::{CentralDelegate, CentralDelegateEvent},
     framework::{
         cb::{self, CBManagerAuthorization},
-        ns,
+        nil, ns,
     },
     future::{BtlePlugFuture, BtlePlugFutureStateShared},
-    utils::{core_bluetooth::cbuuid_to_uuid, nsstring::nsstring_to_string, nsuuid_to_uuid},
+    peripheral::PeripheralHandle,
+    utils::{
+        core_bluetooth::{cbuuid_to_uuid, uuid_to_cbuuid},
+        nsstring::{nsstring_to_string, str_to_nsstring},
+        nsuuid_to_uuid, uuid_to_nsuuid,
+    },
+};
+use crate::api::{
+    AdapterState, BDAddr, CharPropFlags, Characteristic, Descriptor, DisconnectReason, Service,
+    WriteType,
 };
-use crate::api::{CharPropFlags, Characteristic, WriteType};
 use crate::Error;
 use futures::channel::mpsc::{self, Receiver, Sender};
+use futures::executor::block_on;
 use futures::select;
 use futures::sink::SinkExt;
 use futures::stream::{Fuse, StreamExt};
@@ -35,17 +45,48 @@ use std::{
     os::raw::c_uint,
     thread,
 };
-use tokio::runtime;
 use uuid::Uuid;
 
+struct CBDescriptor {
+    pub descriptor: StrongPtr,
+    pub uuid: Uuid,
+    pub read_future_state: VecDeque<CoreBluetoothReplyStateShared>,
+    pub write_future_state: VecDeque<CoreBluetoothReplyStateShared>,
+}
+
+impl Debug for CBDescriptor {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("CBDescriptor")
+            .field("descriptor", self.descriptor.deref())
+            .field("uuid", &self.uuid)
+            .field("read_future_state", &self.read_future_state)
+            .field("write_future_state", &self.write_future_state)
+            .finish()
+    }
+}
+
+impl CBDescriptor {
+    pub fn new(descriptor: StrongPtr) -> Self {
+        let uuid = cbuuid_to_uuid(cb::attribute_uuid(*descriptor));
+        Self {
+            descriptor,
+            uuid,
+            read_future_state: VecDeque::with_capacity(10),
+            write_future_state: VecDeque::with_capacity(10),
+        }
+    }
+}
+
 struct CBCharacteristic {
     pub characteristic: StrongPtr,
     pub uuid: Uuid,
     pub properties: CharPropFlags,
+    pub descriptors: HashMap<Uuid, CBDescriptor>,
     pub read_future_state: VecDeque<CoreBluetoothReplyStateShared>,
     pub write_future_state: VecDeque<CoreBluetoothReplyStateShared>,
     pub subscribe_future_state: VecDeque<CoreBluetoothReplyStateShared>,
     pub unsubscribe_future_state: VecDeque<CoreBluetoothReplyStateShared>,
+    pub discover_descriptors_future_state: VecDeque<CoreBluetoothReplyStateShared>,
 }
 
 impl Debug for CBCharacteristic {
@@ -54,10 +95,15 @@ impl Debug for CBCharacteristic {
             .field("characteristic", self.characteristic.deref())
             .field("uuid", &self.uuid)
             .field("properties", &self.properties)
+            .field("descriptors", &self.descriptors)
             .field("read_future_state", &self.read_future_state)
             .field("write_future_state", &self.write_future_state)
             .field("subscribe_future_state", &self.subscribe_future_state)
             .field("unsubscribe_future_state", &self.unsubscribe_future_state)
+            .field(
+                "discover_descriptors_future_state",
+                &self.discover_descriptors_future_state,
+            )
             .finish()
     }
 }
@@ -70,10 +116,12 @@ impl CBCharacteristic {
             characteristic,
             uuid,
             properties,
+            descriptors: HashMap::new(),
             read_future_state: VecDeque::with_capacity(10),
             write_future_state: VecDeque::with_capacity(10),
             subscribe_future_state: VecDeque::with_capacity(10),
             unsubscribe_future_state: VecDeque::with_capacity(10),
+            discover_descriptors_future_state: VecDeque::with_capacity(10),
         }
     }
 
@@ -101,6 +149,9 @@ impl CBCharacteristic {
         if (flags & cb::CHARACTERISTICPROPERTY_AUTHENTICATEDSIGNEDWRITES) != 0 {
             v |= CharPropFlags::AUTHENTICATED_SIGNED_WRITES;
         }
+        if (flags & cb::CHARACTERISTICPROPERTY_EXTENDEDPROPERTIES) != 0 {
+            v |= CharPropFlags::EXTENDED_PROPERTIES;
+        }
         trace!("Flags: {:?}", v);
         v
     }
@@ -109,7 +160,11 @@ impl CBCharacteristic {
 #[derive(Clone, Debug)]
 pub enum CoreBluetoothReply {
     ReadResult(Vec<u8>),
-    Connected(BTreeSet<Characteristic>),
+    Connected(BTreeSet<Service>),
+    Descriptors(BTreeSet<Descriptor>),
+    Mtu(u16),
+    Rssi(i16),
+    Peripheral(PeripheralHandle),
     Ok,
     Err(String),
 }
@@ -117,22 +172,131 @@ pub enum CoreBluetoothReply {
 #[derive(Debug)]
 pub enum CBPeripheralEvent {
     Disconnected,
-    Notification(Uuid, Vec<u8>),
+    Advertised(Option<i16>),
+    Notification(Uuid, Uuid, Vec<u8>),
     ManufacturerData(u16, Vec<u8>),
     ServiceData(HashMap<Uuid, Vec<u8>>),
     Services(Vec<Uuid>),
+    // The services discovered again after the peripheral changed them
+    ServicesChanged(BTreeSet<Service>),
 }
 
 pub type CoreBluetoothReplyStateShared = BtlePlugFutureStateShared<CoreBluetoothReply>;
 pub type CoreBluetoothReplyFuture = BtlePlugFuture<CoreBluetoothReply>;
 
+/// An open L2CAP channel. Its streams are closed when it is dropped, which also ends the thread
+/// reading from it.
+struct CBL2capChannel {
+    // Keeps the channel open, as the streams don't retain it.
+    _channel: StrongPtr,
+    input: StrongPtr,
+    output: StrongPtr,
+}
+
+impl Debug for CBL2capChannel {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("CBL2capChannel")
+            .field("input", self.input.deref())
+            .field("output", self.output.deref())
+            .finish()
+    }
+}
+
+impl CBL2capChannel {
+    pub fn open(channel: StrongPtr, mut data_sender: Sender<Vec<u8>>) -> Self {
+        let (input, output) = unsafe {
+            (
+                StrongPtr::retain(cb::l2capchannel_inputstream(*channel)),
+                StrongPtr::retain(cb::l2capchannel_outputstream(*channel)),
+            )
+        };
+        ns::stream_open(*input);
+        ns::stream_open(*output);
+
+        // The input stream isn't scheduled on a run loop, so reads block until there is data, and
+        // must happen on a thread of their own.
+        let reader = InputStream(input.clone());
+        thread::spawn(move || {
+            let reader = reader;
+            let mut buffer = [0u8; 2048];
+            loop {
+                let length =
+                    ns::inputstream_read_maxlength(*reader.0, buffer.as_mut_ptr(), buffer.len());
+                // 0 means the end of the stream, and negative an error.
+                if length <= 0 {
+                    break;
+                }
+                let data = buffer[..length as usize].to_vec();
+                if block_on(data_sender.send(data)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            _channel: channel,
+            input,
+            output,
+        }
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<(), String> {
+        let mut written = 0;
+        while written < data.len() {
+            let length = ns::outputstream_write_maxlength(
+                *self.output,
+                data[written..].as_ptr(),
+                data.len() - written,
+            );
+            if length < 0 {
+                return Err(nsstring_to_string(ns::error_localizeddescription(
+                    ns::stream_streamerror(*self.output),
+                ))
+                .unwrap_or_else(|| "Failed to write to L2CAP channel".to_string()));
+            } else if length == 0 {
+                return Err("L2CAP channel closed".to_string());
+            }
+            written += length as usize;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CBL2capChannel {
+    fn drop(&mut self) {
+        ns::stream_close(*self.input);
+        ns::stream_close(*self.output);
+    }
+}
+
+/// An input stream which is handed to the thread reading from it. NSStream can be used from any
+/// thread, but not from several at once, and the reader is the only user of its input stream.
+struct InputStream(StrongPtr);
+
+unsafe impl Send for InputStream {}
+
 struct CBPeripheral {
     pub peripheral: StrongPtr,
     services: HashMap<Uuid, StrongPtr>,
     pub characteristics: HashMap<Uuid, CBCharacteristic>,
+    // Service Uuid to the Uuids of the characteristics it contains
+    service_characteristics: HashMap<Uuid, BTreeSet<Uuid>>,
+    // Service Uuid to the Uuids of the services it includes
+    included_services: HashMap<Uuid, BTreeSet<Uuid>>,
     pub event_sender: Sender<CBPeripheralEvent>,
     pub connected_future_state: Option<CoreBluetoothReplyStateShared>,
-    characteristic_update_count: u32,
+    pub rssi_future_state: VecDeque<CoreBluetoothReplyStateShared>,
+    // Channels being opened, with the sender for the data they receive.
+    pub l2cap_future_state: VecDeque<(Sender<Vec<u8>>, CoreBluetoothReplyStateShared)>,
+    // PSM to open channel
+    pub l2cap_channels: HashMap<u16, CBL2capChannel>,
+    // Whether services are being discovered again after the peripheral changed them
+    rediscovering: bool,
+    // Writes without response waiting for CoreBluetooth to have room for them: characteristic
+    // Uuid, data, future
+    write_without_response_queue: VecDeque<(Uuid, Vec<u8>, CoreBluetoothReplyStateShared)>,
+    // Futures waiting until CoreBluetooth is ready for another write without response
+    write_ready_future_state: VecDeque<CoreBluetoothReplyStateShared>,
 }
 
 impl Debug for CBPeripheral {
@@ -141,12 +305,19 @@ impl Debug for CBPeripheral {
             .field("peripheral", self.peripheral.deref())
             .field("services", &self.services.keys().collect::<Vec<_>>())
             .field("characteristics", &self.characteristics)
+            .field("service_characteristics", &self.service_characteristics)
+            .field("included_services", &self.included_services)
             .field("event_sender", &self.event_sender)
             .field("connected_future_state", &self.connected_future_state)
+            .field("rssi_future_state", &self.rssi_future_state)
+            .field("l2cap_future_state", &self.l2cap_future_state)
+            .field("l2cap_channels", &self.l2cap_channels)
+            .field("rediscovering", &self.rediscovering)
             .field(
-                "characteristic_update_count",
-                &self.characteristic_update_count,
+                "write_without_response_queue",
+                &self.write_without_response_queue,
             )
+            .field("write_ready_future_state", &self.write_ready_future_state)
             .finish()
     }
 }
@@ -157,49 +328,213 @@ impl CBPeripheral {
             peripheral,
             services: HashMap::new(),
             characteristics: HashMap::new(),
+            service_characteristics: HashMap::new(),
+            included_services: HashMap::new(),
             event_sender,
             connected_future_state: None,
-            characteristic_update_count: 0,
+            rssi_future_state: VecDeque::with_capacity(10),
+            l2cap_future_state: VecDeque::new(),
+            l2cap_channels: HashMap::new(),
+            rediscovering: false,
+            write_without_response_queue: VecDeque::new(),
+            write_ready_future_state: VecDeque::new(),
+        }
+    }
+
+    /// Forgets everything discovered while connected, ready for the next connection.
+    pub fn reset(&mut self) {
+        self.services.clear();
+        self.characteristics.clear();
+        self.service_characteristics.clear();
+        self.included_services.clear();
+        self.rediscovering = false;
+        if let Some(state) = self.connected_future_state.take() {
+            state
+                .lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Err("Device disconnected.".to_string()));
+        }
+        for (_, state) in self.l2cap_future_state.drain(..) {
+            state
+                .lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Err("Device disconnected.".to_string()));
+        }
+        self.l2cap_channels.clear();
+        let waiting_writes = self
+            .write_without_response_queue
+            .drain(..)
+            .map(|(_, _, state)| state);
+        for state in waiting_writes.chain(self.write_ready_future_state.drain(..)) {
+            state
+                .lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Err("Device disconnected.".to_string()));
         }
     }
 
+    /// Writes a value without response once CoreBluetooth has room for it, after any earlier
+    /// writes which are still waiting. CoreBluetooth drops writes without response which are sent
+    /// while it isn't ready for them.
+    pub fn write_without_response(
+        &mut self,
+        characteristic_uuid: Uuid,
+        data: Vec<u8>,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        self.write_without_response_queue
+            .push_back((characteristic_uuid, data, fut));
+        self.send_queued_writes();
+    }
+
+    /// Completes the future once CoreBluetooth is ready for another write without response.
+    pub fn wait_until_ready_to_write(&mut self, fut: CoreBluetoothReplyStateShared) {
+        self.write_ready_future_state.push_back(fut);
+        self.send_queued_writes();
+    }
+
+    /// Sends as many of the waiting writes without response as CoreBluetooth has room for. If
+    /// there is still room after all of them are sent, the futures waiting to write are completed.
+    pub fn send_queued_writes(&mut self) {
+        while !self.write_without_response_queue.is_empty()
+            && cb::peripheral_cansendwritewithoutresponse(*self.peripheral) == YES
+        {
+            let (characteristic_uuid, data, fut) =
+                self.write_without_response_queue.pop_front().unwrap();
+            let reply = match self.characteristics.get(&characteristic_uuid) {
+                Some(c) => {
+                    cb::peripheral_writevalue_forcharacteristic(
+                        *self.peripheral,
+                        ns::data(data.as_ptr(), data.len() as c_uint),
+                        *c.characteristic,
+                        1,
+                    );
+                    CoreBluetoothReply::Ok
+                }
+                None => CoreBluetoothReply::Err(format!(
+                    "Characteristic with UUID {} not found.",
+                    characteristic_uuid
+                )),
+            };
+            fut.lock().unwrap().set_reply(reply);
+        }
+        if self.write_without_response_queue.is_empty()
+            && cb::peripheral_cansendwritewithoutresponse(*self.peripheral) == YES
+        {
+            for fut in self.write_ready_future_state.drain(..) {
+                fut.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+            }
+        }
+    }
+
+    /// Forgets the services and characteristics discovered so far and discovers them again, because
+    /// the peripheral has changed them. The new services are sent as a `ServicesChanged` event once
+    /// all of their characteristics have been discovered.
+    pub fn rediscover(&mut self) {
+        self.services.clear();
+        self.characteristics.clear();
+        self.service_characteristics.clear();
+        self.included_services.clear();
+        self.rediscovering = true;
+        cb::peripheral_discoverservices(*self.peripheral);
+    }
+
     pub fn set_services(&mut self, services: HashMap<Uuid, StrongPtr>) {
         self.services = services;
     }
 
-    pub fn set_characteristics(&mut self, characteristics: HashMap<Uuid, StrongPtr>) {
+    /// Records the services included by a service. Secondary services are only found this way, so
+    /// they are added to the services too.
+    pub fn set_included_services(
+        &mut self,
+        service_uuid: Uuid,
+        included_services: HashMap<Uuid, StrongPtr>,
+    ) {
+        let included = self.included_services.entry(service_uuid).or_default();
+        for (uuid, service) in included_services {
+            included.insert(uuid);
+            self.services.entry(uuid).or_insert(service);
+        }
+        self.check_discovery_complete();
+    }
+
+    pub fn set_characteristics(
+        &mut self,
+        service_uuid: Uuid,
+        characteristics: HashMap<Uuid, StrongPtr>,
+    ) {
+        let service_characteristics = self
+            .service_characteristics
+            .entry(service_uuid)
+            .or_default();
         for (c_uuid, c_obj) in characteristics {
+            service_characteristics.insert(c_uuid);
             self.characteristics
                 .insert(c_uuid, CBCharacteristic::new(c_obj));
         }
-        // It's time for QUESTIONABLE ASSUMPTIONS.
-        //
-        // For sake of being lazy, we don't want to fire device connection until
-        // we have all of our services and characteristics. We assume that
-        // set_characteristics should be called once for every entry in the
-        // service map. Once that's done, we're filled out enough and can send
-        // back a Connected reply to the waiting future with all of the
-        // characteristic info in it.
-        self.characteristic_update_count += 1;
-        if self.characteristic_update_count == (self.services.len() as u32) {
-            if self.connected_future_state.is_none() {
-                panic!("We should still have a future at this point!");
-            }
-            let mut char_set = BTreeSet::new();
-            for (&uuid, c) in &self.characteristics {
-                let char = Characteristic {
-                    uuid,
-                    properties: c.properties,
-                };
-                trace!("{:?}", char.uuid);
-                char_set.insert(char);
+        self.check_discovery_complete();
+    }
+
+    /// We don't want to fire device connection until we have all of our services and
+    /// characteristics, which is once the characteristics of every service and the includes of
+    /// every primary service have been discovered. Then we can send back a Connected reply to the
+    /// waiting future with all of the characteristic info in it.
+    fn check_discovery_complete(&mut self) {
+        let complete = self.services.iter().all(|(uuid, service)| {
+            self.service_characteristics.contains_key(uuid)
+                && (cb::service_isprimary(**service) != YES
+                    || self.included_services.contains_key(uuid))
+        });
+        if complete {
+            if self.connected_future_state.is_none() && !self.rediscovering {
+                // The connection attempt was cancelled while discovery was in progress.
+                return;
+            }
+            let mut service_set = BTreeSet::new();
+            for (&service_uuid, service) in &self.services {
+                let mut char_set = BTreeSet::new();
+                for uuid in self
+                    .service_characteristics
+                    .get(&service_uuid)
+                    .into_iter()
+                    .flatten()
+                {
+                    if let Some(c) = self.characteristics.get(uuid) {
+                        let char = Characteristic {
+                            uuid: *uuid,
+                            service_uuid,
+                            handle: 0,
+                            properties: c.properties,
+                        };
+                        trace!("{:?}", char.uuid);
+                        char_set.insert(char);
+                    }
+                }
+                service_set.insert(Service {
+                    uuid: service_uuid,
+                    primary: cb::service_isprimary(**service) == YES,
+                    characteristics: char_set,
+                    included_services: self
+                        .included_services
+                        .get(&service_uuid)
+                        .cloned()
+                        .unwrap_or_default(),
+                });
+            }
+            if let Some(state) = self.connected_future_state.take() {
+                state
+                    .lock()
+                    .unwrap()
+                    .set_reply(CoreBluetoothReply::Connected(service_set));
+            } else {
+                self.rediscovering = false;
+                if let Err(e) = self
+                    .event_sender
+                    .try_send(CBPeripheralEvent::ServicesChanged(service_set))
+                {
+                    error!("Error sending services changed event: {}", e);
+                }
             }
-            self.connected_future_state
-                .take()
-                .unwrap()
-                .lock()
-                .unwrap()
-                .set_reply(CoreBluetoothReply::Connected(char_set));
         }
     }
 }
@@ -218,6 +553,8 @@ struct CoreBluetoothInternal {
     // task::block this when sending even though it'll never actually block.
     event_sender: Sender<CoreBluetoothEvent>,
     message_receiver: Fuse<Receiver<CoreBluetoothMessage>>,
+    // Whether to keep peripherals after they disconnect, so they can be connected to again.
+    retain_peripherals: bool,
 }
 
 impl Debug for CoreBluetoothInternal {
@@ -235,8 +572,14 @@ impl Debug for CoreBluetoothInternal {
 
 #[derive(Debug)]
 pub enum CoreBluetoothMessage {
-    StartScanning,
+    // services to scan for
+    StartScanning(Vec<Uuid>),
     StopScanning,
+    RetainPeripherals(bool),
+    // address of a peripheral which has stopped advertising
+    ForgetPeripheral(BDAddr),
+    // identifier of a peripheral known to the system, future
+    RetrievePeripheral(Uuid, CoreBluetoothReplyStateShared),
     ConnectDevice(Uuid, CoreBluetoothReplyStateShared),
     DisconnectDevice(Uuid, CoreBluetoothReplyStateShared),
     // device uuid, characteristic uuid, future
@@ -253,34 +596,70 @@ pub enum CoreBluetoothMessage {
     Subscribe(Uuid, Uuid, CoreBluetoothReplyStateShared),
     // device uuid, characteristic uuid, future
     Unsubscribe(Uuid, Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, future
+    WaitUntilReadyToWrite(Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, future
+    GetMtu(Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, future
+    ReadRssi(Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, future
+    GetPeripheral(Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, psm, sender for received data, future
+    OpenL2capChannel(Uuid, u16, Sender<Vec<u8>>, CoreBluetoothReplyStateShared),
+    // device uuid, psm, data, future
+    WriteL2capChannel(Uuid, u16, Vec<u8>, CoreBluetoothReplyStateShared),
+    // device uuid, psm
+    CloseL2capChannel(Uuid, u16),
+    // device uuid, characteristic uuid, future
+    DiscoverDescriptors(Uuid, Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, characteristic uuid, descriptor uuid, future
+    ReadDescriptorValue(Uuid, Uuid, Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, characteristic uuid, descriptor uuid, data, future
+    WriteDescriptorValue(Uuid, Uuid, Uuid, Vec<u8>, CoreBluetoothReplyStateShared),
 }
 
 #[derive(Debug)]
 pub enum CoreBluetoothEvent {
-    AdapterConnected,
+    AdapterStateChanged(AdapterState),
     // name, identifier, event receiver, message sender
     DeviceDiscovered(Uuid, Option<String>, Receiver<CBPeripheralEvent>),
     DeviceUpdated(Uuid, String),
-    // identifier
-    DeviceLost(Uuid),
+    // identifier, reason for disconnecting
+    DeviceLost(Uuid, DisconnectReason),
+    // identifier, future to complete once the DeviceDiscovered event for it has been handled
+    DeviceRetrieved(Uuid, CoreBluetoothReplyStateShared),
 }
 
 impl CoreBluetoothInternal {
     pub fn new(
         message_receiver: Receiver<CoreBluetoothMessage>,
         event_sender: Sender<CoreBluetoothEvent>,
+        restore_identifier: Option<String>,
     ) -> Self {
+        let options = match restore_identifier {
+            Some(identifier) => {
+                let options = ns::mutabledictionary();
+                ns::mutabledictionary_setobject_forkey(
+                    options,
+                    str_to_nsstring(&identifier),
+                    unsafe { cb::CENTRALMANAGEROPTIONRESTOREIDENTIFIERKEY },
+                );
+                options
+            }
+            None => nil,
+        };
         // Pretty sure these come preallocated?
         unsafe {
             let (delegate, delegate_receiver) = CentralDelegate::delegate();
             let delegate = StrongPtr::new(delegate);
             Self {
-                manager: StrongPtr::new(cb::centralmanager(*delegate)),
+                manager: StrongPtr::new(cb::centralmanager(*delegate, options)),
                 peripherals: HashMap::new(),
                 delegate_receiver: delegate_receiver.fuse(),
                 event_sender,
                 message_receiver: message_receiver.fuse(),
                 delegate,
+                retain_peripherals: false,
             }
         }
     }
@@ -334,6 +713,18 @@ impl CoreBluetoothInternal {
         }
     }
 
+    async fn on_advertised(&mut self, peripheral_uuid: Uuid, rssi: Option<i16>) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Err(e) = p
+                .event_sender
+                .send(CBPeripheralEvent::Advertised(rssi))
+                .await
+            {
+                error!("Error sending notification event: {}", e);
+            }
+        }
+    }
+
     async fn on_services(&mut self, peripheral_uuid: Uuid, services: Vec<Uuid>) {
         trace!("Got service advertisement! {:?}", services);
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
@@ -383,9 +774,22 @@ impl CoreBluetoothInternal {
         }
     }
 
+    fn on_discovered_included_services(
+        &mut self,
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        service_map: HashMap<Uuid, StrongPtr>,
+    ) {
+        trace!("Found included services!");
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            p.set_included_services(service_uuid, service_map);
+        }
+    }
+
     fn on_discovered_characteristics(
         &mut self,
         peripheral_uuid: Uuid,
+        service_uuid: Uuid,
         char_map: HashMap<Uuid, StrongPtr>,
     ) {
         trace!("Found chars!");
@@ -393,7 +797,14 @@ impl CoreBluetoothInternal {
             trace!("{}", id);
         }
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            p.set_characteristics(char_map);
+            p.set_characteristics(service_uuid, char_map);
+        }
+    }
+
+    fn on_services_modified(&mut self, peripheral_uuid: Uuid) {
+        trace!("Services modified!");
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            p.rediscover();
         }
     }
 
@@ -402,9 +813,18 @@ impl CoreBluetoothInternal {
         // itself when it receives all of its service/characteristic info.
     }
 
-    async fn on_peripheral_disconnect(&mut self, peripheral_uuid: Uuid) {
-        self.peripherals.remove(&peripheral_uuid);
-        self.dispatch_event(CoreBluetoothEvent::DeviceLost(peripheral_uuid))
+    async fn on_peripheral_disconnect(&mut self, peripheral_uuid: Uuid, reason: DisconnectReason) {
+        if self.retain_peripherals {
+            if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+                p.reset();
+                if let Err(e) = p.event_sender.send(CBPeripheralEvent::Disconnected).await {
+                    error!("Error sending notification event: {}", e);
+                }
+            }
+        } else {
+            self.peripherals.remove(&peripheral_uuid);
+        }
+        self.dispatch_event(CoreBluetoothEvent::DeviceLost(peripheral_uuid, reason))
             .await;
     }
 
@@ -435,6 +855,11 @@ impl CoreBluetoothInternal {
         data: Vec<u8>,
     ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            let service_uuid = p
+                .service_characteristics
+                .iter()
+                .find(|(_, characteristics)| characteristics.contains(&characteristic_uuid))
+                .map_or_else(Uuid::nil, |(&service_uuid, _)| service_uuid);
             if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
                 trace!("Got read event!");
 
@@ -454,7 +879,11 @@ impl CoreBluetoothInternal {
                         .set_reply(CoreBluetoothReply::ReadResult(data_clone));
                 } else if let Err(e) = p
                     .event_sender
-                    .send(CBPeripheralEvent::Notification(characteristic_uuid, data))
+                    .send(CBPeripheralEvent::Notification(
+                        service_uuid,
+                        characteristic_uuid,
+                        data,
+                    ))
                     .await
                 {
                     error!("Error sending notification event: {}", e);
@@ -473,15 +902,145 @@ impl CoreBluetoothInternal {
         }
     }
 
+    fn on_discovered_descriptors(
+        &mut self,
+        peripheral_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        descriptor_map: HashMap<Uuid, StrongPtr>,
+    ) {
+        trace!("Found descriptors!");
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+                for (d_uuid, d_obj) in descriptor_map {
+                    c.descriptors
+                        .entry(d_uuid)
+                        .or_insert_with(|| CBDescriptor::new(d_obj));
+                }
+                let descriptors = c
+                    .descriptors
+                    .keys()
+                    .map(|&uuid| Descriptor {
+                        uuid,
+                        characteristic_uuid,
+                        characteristic_handle: 0,
+                    })
+                    .collect();
+                if let Some(state) = c.discover_descriptors_future_state.pop_back() {
+                    state
+                        .lock()
+                        .unwrap()
+                        .set_reply(CoreBluetoothReply::Descriptors(descriptors));
+                }
+            }
+        }
+    }
+
+    fn on_descriptor_read(
+        &mut self,
+        peripheral_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        descriptor_uuid: Uuid,
+        data: Vec<u8>,
+    ) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+                if let Some(d) = c.descriptors.get_mut(&descriptor_uuid) {
+                    trace!("Got descriptor read event!");
+                    if let Some(state) = d.read_future_state.pop_back() {
+                        state
+                            .lock()
+                            .unwrap()
+                            .set_reply(CoreBluetoothReply::ReadResult(data));
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_descriptor_written(
+        &mut self,
+        peripheral_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        descriptor_uuid: Uuid,
+    ) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+                if let Some(d) = c.descriptors.get_mut(&descriptor_uuid) {
+                    trace!("Got descriptor written event!");
+                    if let Some(state) = d.write_future_state.pop_back() {
+                        state.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_rssi_read(&mut self, peripheral_uuid: Uuid, result: Result<i16, String>) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            trace!("Got RSSI read event!");
+            if let Some(state) = p.rssi_future_state.pop_back() {
+                let reply = match result {
+                    Ok(rssi) => CoreBluetoothReply::Rssi(rssi),
+                    Err(msg) => CoreBluetoothReply::Err(msg),
+                };
+                state.lock().unwrap().set_reply(reply);
+            }
+        }
+    }
+
+    fn on_l2cap_channel_opened(
+        &mut self,
+        peripheral_uuid: Uuid,
+        result: Result<StrongPtr, String>,
+    ) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            trace!("Got L2CAP channel opened event!");
+            if let Some((sender, state)) = p.l2cap_future_state.pop_back() {
+                let reply = match result {
+                    Ok(channel) => {
+                        let psm = cb::l2capchannel_psm(*channel);
+                        p.l2cap_channels
+                            .insert(psm, CBL2capChannel::open(channel, sender));
+                        CoreBluetoothReply::Ok
+                    }
+                    Err(msg) => CoreBluetoothReply::Err(msg),
+                };
+                state.lock().unwrap().set_reply(reply);
+            }
+        }
+    }
+
     fn connect_peripheral(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
         trace!("Trying to connect peripheral!");
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
             trace!("Connecting peripheral!");
             p.connected_future_state = Some(fut);
             cb::centralmanager_connectperipheral(*self.manager, *p.peripheral);
+        } else {
+            fut.lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Err(format!(
+                    "Peripheral with UUID {} not found.",
+                    peripheral_uuid
+                )));
         }
     }
 
+    fn disconnect_peripheral(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+        trace!("Trying to disconnect peripheral!");
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            // Cancelling also covers a connection which is still pending, so fail that first.
+            if let Some(state) = p.connected_future_state.take() {
+                state
+                    .lock()
+                    .unwrap()
+                    .set_reply(CoreBluetoothReply::Err("Connection cancelled.".to_string()));
+            }
+            cb::centralmanager_cancelperipheralconnection(*self.manager, *p.peripheral);
+        }
+        fut.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
+    }
+
     fn write_value(
         &mut self,
         peripheral_uuid: Uuid,
@@ -491,24 +1050,19 @@ impl CoreBluetoothInternal {
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
-                trace!("Writing value! With kind {:?}", kind);
+            trace!("Writing value! With kind {:?}", kind);
+            // WriteWithoutResponse does not call the corebluetooth callback, so is done once the
+            // write has been handed to CoreBluetooth.
+            if kind == WriteType::WithoutResponse {
+                p.write_without_response(characteristic_uuid, data, fut);
+            } else if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
                 cb::peripheral_writevalue_forcharacteristic(
                     *p.peripheral,
                     ns::data(data.as_ptr(), data.len() as c_uint),
                     *c.characteristic,
-                    match kind {
-                        WriteType::WithResponse => 0,
-                        WriteType::WithoutResponse => 1,
-                    },
+                    0,
                 );
-                // WriteWithoutResponse does not call the corebluetooth
-                // callback, it just always succeeds silently.
-                if kind == WriteType::WithoutResponse {
-                    fut.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
-                } else {
-                    c.write_future_state.push_front(fut);
-                }
+                c.write_future_state.push_front(fut);
             }
         }
     }
@@ -566,18 +1120,213 @@ impl CoreBluetoothInternal {
         }
     }
 
+    fn wait_until_ready_to_write(
+        &mut self,
+        peripheral_uuid: Uuid,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            p.wait_until_ready_to_write(fut);
+        } else {
+            fut.lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Err(format!(
+                    "Peripheral with UUID {} not found.",
+                    peripheral_uuid
+                )));
+        }
+    }
+
+    fn on_ready_to_write(&mut self, peripheral_uuid: Uuid) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            p.send_queued_writes();
+        }
+    }
+
+    fn get_mtu(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            // CoreBluetooth doesn't expose the MTU directly, but the largest write without
+            // response is the MTU less the 3 byte ATT header.
+            let max_write = cb::peripheral_maximumwritevaluelengthfortype(*p.peripheral, 1);
+            let mtu = (max_write + 3).min(u16::MAX as usize) as u16;
+            fut.lock().unwrap().set_reply(CoreBluetoothReply::Mtu(mtu));
+        } else {
+            fut.lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Err(format!(
+                    "Peripheral with UUID {} not found.",
+                    peripheral_uuid
+                )));
+        }
+    }
+
+    fn get_peripheral(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+        let reply = match self.peripherals.get(&peripheral_uuid) {
+            Some(p) => CoreBluetoothReply::Peripheral(PeripheralHandle::new(p.peripheral.clone())),
+            None => CoreBluetoothReply::Err(format!(
+                "Peripheral with UUID {} not found.",
+                peripheral_uuid
+            )),
+        };
+        fut.lock().unwrap().set_reply(reply);
+    }
+
+    fn read_rssi(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            trace!("Reading RSSI!");
+            cb::peripheral_readrssi(*p.peripheral);
+            p.rssi_future_state.push_front(fut);
+        } else {
+            fut.lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Err(format!(
+                    "Peripheral with UUID {} not found.",
+                    peripheral_uuid
+                )));
+        }
+    }
+
+    fn open_l2cap_channel(
+        &mut self,
+        peripheral_uuid: Uuid,
+        psm: u16,
+        sender: Sender<Vec<u8>>,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            trace!("Opening L2CAP channel!");
+            cb::peripheral_openl2capchannel(*p.peripheral, psm);
+            p.l2cap_future_state.push_front((sender, fut));
+        } else {
+            fut.lock()
+                .unwrap()
+                .set_reply(CoreBluetoothReply::Err(format!(
+                    "Peripheral with UUID {} not found.",
+                    peripheral_uuid
+                )));
+        }
+    }
+
+    fn write_l2cap_channel(
+        &mut self,
+        peripheral_uuid: Uuid,
+        psm: u16,
+        data: Vec<u8>,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        let reply = match self
+            .peripherals
+            .get(&peripheral_uuid)
+            .and_then(|p| p.l2cap_channels.get(&psm))
+        {
+            // This may block while the peripheral catches up, but writes are rarely large enough
+            // for that to matter.
+            Some(channel) => match channel.write(&data) {
+                Ok(()) => CoreBluetoothReply::Ok,
+                Err(msg) => CoreBluetoothReply::Err(msg),
+            },
+            None => CoreBluetoothReply::Err(format!("L2CAP channel with PSM {} not open.", psm)),
+        };
+        fut.lock().unwrap().set_reply(reply);
+    }
+
+    fn close_l2cap_channel(&mut self, peripheral_uuid: Uuid, psm: u16) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            trace!("Closing L2CAP channel!");
+            p.l2cap_channels.remove(&psm);
+        }
+    }
+
+    fn discover_descriptors(
+        &mut self,
+        peripheral_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+                trace!("Discovering descriptors!");
+                cb::peripheral_discoverdescriptorsforcharacteristic(
+                    *p.peripheral,
+                    *c.characteristic,
+                );
+                c.discover_descriptors_future_state.push_front(fut);
+                return;
+            }
+        }
+        fut.lock()
+            .unwrap()
+            .set_reply(CoreBluetoothReply::Err(format!(
+                "Characteristic with UUID {} not found.",
+                characteristic_uuid
+            )));
+    }
+
+    fn read_descriptor_value(
+        &mut self,
+        peripheral_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        descriptor_uuid: Uuid,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+                if let Some(d) = c.descriptors.get_mut(&descriptor_uuid) {
+                    trace!("Reading descriptor value!");
+                    cb::peripheral_readvalue_fordescriptor(*p.peripheral, *d.descriptor);
+                    d.read_future_state.push_front(fut);
+                    return;
+                }
+            }
+        }
+        fut.lock()
+            .unwrap()
+            .set_reply(CoreBluetoothReply::Err(format!(
+                "Descriptor with UUID {} not found.",
+                descriptor_uuid
+            )));
+    }
+
+    fn write_descriptor_value(
+        &mut self,
+        peripheral_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        descriptor_uuid: Uuid,
+        data: Vec<u8>,
+        fut: CoreBluetoothReplyStateShared,
+    ) {
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+                if let Some(d) = c.descriptors.get_mut(&descriptor_uuid) {
+                    trace!("Writing descriptor value!");
+                    cb::peripheral_writevalue_fordescriptor(
+                        *p.peripheral,
+                        ns::data(data.as_ptr(), data.len() as c_uint),
+                        *d.descriptor,
+                    );
+                    d.write_future_state.push_front(fut);
+                    return;
+                }
+            }
+        }
+        fut.lock()
+            .unwrap()
+            .set_reply(CoreBluetoothReply::Err(format!(
+                "Descriptor with UUID {} not found.",
+                descriptor_uuid
+            )));
+    }
+
     async fn wait_for_message(&mut self) {
         select! {
             delegate_msg = self.delegate_receiver.select_next_some() => {
                 match delegate_msg {
-                    // TODO DidUpdateState does not imply that the adapter is
-                    // on, just that it updated state.
-                    //
                     // TODO We should probably also register some sort of
                     // "ready" variable in our adapter that will cause scans/etc
-                    // to fail if this hasn't updated.
-                    CentralDelegateEvent::DidUpdateState => {
-                        self.dispatch_event(CoreBluetoothEvent::AdapterConnected).await
+                    // to fail if the adapter isn't powered on.
+                    CentralDelegateEvent::DidUpdateState(state) => {
+                        self.dispatch_event(CoreBluetoothEvent::AdapterStateChanged(state))
+                            .await
                     }
                     CentralDelegateEvent::DiscoveredPeripheral(peripheral) => {
                         self.on_discovered_peripheral(peripheral).await
@@ -585,14 +1334,20 @@ impl CoreBluetoothInternal {
                     CentralDelegateEvent::DiscoveredServices(peripheral_id, service_map) => {
                         self.on_discovered_services(peripheral_id, service_map)
                     }
-                    CentralDelegateEvent::DiscoveredCharacteristics(peripheral_id, char_map) => {
-                        self.on_discovered_characteristics(peripheral_id, char_map)
+                    CentralDelegateEvent::ServicesModified(peripheral_id) => {
+                        self.on_services_modified(peripheral_id)
+                    }
+                    CentralDelegateEvent::DiscoveredIncludedServices(peripheral_id, service_id, service_map) => {
+                        self.on_discovered_included_services(peripheral_id, service_id, service_map)
+                    }
+                    CentralDelegateEvent::DiscoveredCharacteristics(peripheral_id, service_id, char_map) => {
+                        self.on_discovered_characteristics(peripheral_id, service_id, char_map)
                     }
                     CentralDelegateEvent::ConnectedDevice(peripheral_id) => {
                         self.on_peripheral_connect(peripheral_id)
                     }
-                    CentralDelegateEvent::DisconnectedDevice(peripheral_id) => {
-                        self.on_peripheral_disconnect(peripheral_id).await
+                    CentralDelegateEvent::DisconnectedDevice(peripheral_id, reason) => {
+                        self.on_peripheral_disconnect(peripheral_id, reason).await
                     }
                     CentralDelegateEvent::CharacteristicSubscribed(
                         peripheral_id,
@@ -611,6 +1366,34 @@ impl CoreBluetoothInternal {
                         peripheral_id,
                         characteristic_id,
                     ) => self.on_characteristic_written(peripheral_id, characteristic_id),
+                    CentralDelegateEvent::ReadyToWriteWithoutResponse(peripheral_id) => {
+                        self.on_ready_to_write(peripheral_id)
+                    }
+                    CentralDelegateEvent::DiscoveredDescriptors(
+                        peripheral_id,
+                        characteristic_id,
+                        descriptor_map,
+                    ) => self.on_discovered_descriptors(peripheral_id, characteristic_id, descriptor_map),
+                    CentralDelegateEvent::DescriptorNotified(
+                        peripheral_id,
+                        characteristic_id,
+                        descriptor_id,
+                        data,
+                    ) => self.on_descriptor_read(peripheral_id, characteristic_id, descriptor_id, data),
+                    CentralDelegateEvent::DescriptorWritten(
+                        peripheral_id,
+                        characteristic_id,
+                        descriptor_id,
+                    ) => self.on_descriptor_written(peripheral_id, characteristic_id, descriptor_id),
+                    CentralDelegateEvent::ReadRssi(peripheral_id, result) => {
+                        self.on_rssi_read(peripheral_id, result)
+                    }
+                    CentralDelegateEvent::L2capChannelOpened(peripheral_id, result) => {
+                        self.on_l2cap_channel_opened(peripheral_id, result)
+                    }
+                    CentralDelegateEvent::Advertised(peripheral_id, rssi) => {
+                        self.on_advertised(peripheral_id, rssi).await
+                    },
                     CentralDelegateEvent::ManufacturerData(peripheral_id, manufacturer_id, manufacturer_data) => {
                         self.on_manufacturer_data(peripheral_id, manufacturer_id, manufacturer_data).await
                     },
@@ -625,13 +1408,25 @@ impl CoreBluetoothInternal {
             adapter_msg = self.message_receiver.select_next_some() => {
                 trace!("Adapter message!");
                 match adapter_msg {
-                    CoreBluetoothMessage::StartScanning => self.start_discovery(),
+                    CoreBluetoothMessage::StartScanning(services) => self.start_discovery(services),
                     CoreBluetoothMessage::StopScanning => self.stop_discovery(),
+                    CoreBluetoothMessage::RetainPeripherals(retain) => {
+                        self.retain_peripherals = retain
+                    }
+                    CoreBluetoothMessage::ForgetPeripheral(address) => {
+                        self.forget_peripheral(address)
+                    }
+                    CoreBluetoothMessage::RetrievePeripheral(peripheral_uuid, fut) => {
+                        self.retrieve_peripheral(peripheral_uuid, fut).await
+                    }
                     CoreBluetoothMessage::ConnectDevice(peripheral_uuid, fut) => {
                         trace!("got connectdevice msg!");
                         self.connect_peripheral(peripheral_uuid, fut);
                     }
-                    CoreBluetoothMessage::DisconnectDevice(_peripheral_uuid, _fut) => {}
+                    CoreBluetoothMessage::DisconnectDevice(peripheral_uuid, fut) => {
+                        trace!("got disconnectdevice msg!");
+                        self.disconnect_peripheral(peripheral_uuid, fut);
+                    }
                     CoreBluetoothMessage::ReadValue(peripheral_uuid, char_uuid, fut) => {
                         self.read_value(peripheral_uuid, char_uuid, fut)
                     }
@@ -648,20 +1443,102 @@ impl CoreBluetoothInternal {
                     CoreBluetoothMessage::Unsubscribe(peripheral_uuid, char_uuid, fut) => {
                         self.unsubscribe(peripheral_uuid, char_uuid, fut)
                     }
+                    CoreBluetoothMessage::WaitUntilReadyToWrite(peripheral_uuid, fut) => {
+                        self.wait_until_ready_to_write(peripheral_uuid, fut)
+                    }
+                    CoreBluetoothMessage::GetMtu(peripheral_uuid, fut) => {
+                        self.get_mtu(peripheral_uuid, fut)
+                    }
+                    CoreBluetoothMessage::ReadRssi(peripheral_uuid, fut) => {
+                        self.read_rssi(peripheral_uuid, fut)
+                    }
+                    CoreBluetoothMessage::GetPeripheral(peripheral_uuid, fut) => {
+                        self.get_peripheral(peripheral_uuid, fut)
+                    }
+                    CoreBluetoothMessage::OpenL2capChannel(peripheral_uuid, psm, sender, fut) => {
+                        self.open_l2cap_channel(peripheral_uuid, psm, sender, fut)
+                    }
+                    CoreBluetoothMessage::WriteL2capChannel(peripheral_uuid, psm, data, fut) => {
+                        self.write_l2cap_channel(peripheral_uuid, psm, data, fut)
+                    }
+                    CoreBluetoothMessage::CloseL2capChannel(peripheral_uuid, psm) => {
+                        self.close_l2cap_channel(peripheral_uuid, psm)
+                    }
+                    CoreBluetoothMessage::DiscoverDescriptors(peripheral_uuid, char_uuid, fut) => {
+                        self.discover_descriptors(peripheral_uuid, char_uuid, fut)
+                    }
+                    CoreBluetoothMessage::ReadDescriptorValue(
+                        peripheral_uuid,
+                        char_uuid,
+                        desc_uuid,
+                        fut,
+                    ) => self.read_descriptor_value(peripheral_uuid, char_uuid, desc_uuid, fut),
+                    CoreBluetoothMessage::WriteDescriptorValue(
+                        peripheral_uuid,
+                        char_uuid,
+                        desc_uuid,
+                        data,
+                        fut,
+                    ) => self.write_descriptor_value(peripheral_uuid, char_uuid, desc_uuid, data, fut),
                 };
             }
         }
     }
 
-    fn start_discovery(&mut self) {
+    fn start_discovery(&mut self, services: Vec<Uuid>) {
         trace!("BluetoothAdapter::start_discovery");
+        let service_uuids = if services.is_empty() {
+            nil
+        } else {
+            let service_uuids = ns::mutablearray();
+            for service in services {
+                ns::mutablearray_addobject(service_uuids, uuid_to_cbuuid(service));
+            }
+            service_uuids
+        };
         let options = ns::mutabledictionary();
         // NOTE: If duplicates are not allowed then a peripheral will not show
         // up again once connected and then disconnected.
         ns::mutabledictionary_setobject_forkey(options, ns::number_withbool(YES), unsafe {
             cb::CENTRALMANAGERSCANOPTIONALLOWDUPLICATESKEY
         });
-        cb::centralmanager_scanforperipherals_options(*self.manager, options);
+        cb::centralmanager_scanforperipherals_options(*self.manager, service_uuids, options);
+    }
+
+    // Drops a peripheral which the adapter has given up on, so that it will be discovered afresh
+    // if it starts advertising again.
+    fn forget_peripheral(&mut self, address: BDAddr) {
+        trace!("BluetoothAdapter::forget_peripheral");
+        self.peripherals
+            .retain(|uuid, _| uuid_to_bdaddr(&uuid.to_string()) != address);
+    }
+
+    // Looks up a peripheral which the system already knows about, so it can be connected to
+    // without scanning for it first.
+    async fn retrieve_peripheral(&mut self, uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
+        trace!("BluetoothAdapter::retrieve_peripheral");
+        if !self.peripherals.contains_key(&uuid) {
+            let identifier = uuid_to_nsuuid(uuid);
+            let identifiers = ns::mutablearray();
+            ns::mutablearray_addobject(identifiers, *identifier);
+            let peripherals =
+                cb::centralmanager_retrieveperipheralswithidentifiers(*self.manager, identifiers);
+            if ns::array_count(peripherals) == 0 {
+                fut.lock()
+                    .unwrap()
+                    .set_reply(CoreBluetoothReply::Err(format!(
+                        "Peripheral with UUID {} not found.",
+                        uuid
+                    )));
+                return;
+            }
+            let peripheral = unsafe { StrongPtr::retain(ns::array_objectatindex(peripherals, 0)) };
+            self.on_discovered_peripheral(peripheral).await;
+        }
+        // The adapter only learns about the peripheral from the DeviceDiscovered event, so reply
+        // through the same channel to be sure that it has been handled first.
+        self.dispatch_event(CoreBluetoothEvent::DeviceRetrieved(uuid, fut))
+            .await;
     }
 
     fn stop_discovery(&mut self) {
@@ -681,6 +1558,7 @@ impl Drop for CoreBluetoothInternal {
 
 pub fn run_corebluetooth_thread(
     event_sender: Sender<CoreBluetoothEvent>,
+    restore_identifier: Option<String>,
 ) -> Result<Sender<CoreBluetoothMessage>, Error> {
     let authorization = cb::manager_authorization();
     if authorization != CBManagerAuthorization::AllowedAlways
@@ -694,9 +1572,8 @@ pub fn run_corebluetooth_thread(
     let (sender, receiver) = mpsc::channel::<CoreBluetoothMessage>(256);
     // CoreBluetoothInternal is !Send, so we need to keep it on a single thread.
     thread::spawn(move || {
-        let runtime = runtime::Builder::new_current_thread().build().unwrap();
-        runtime.block_on(async move {
-            let mut cbi = CoreBluetoothInternal::new(receiver, event_sender);
+        block_on(async move {
+            let mut cbi = CoreBluetoothInternal::new(receiver, event_sender, restore_identifier);
             loop {
                 cbi.wait_for_message().await;
             }
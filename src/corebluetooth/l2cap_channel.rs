@@ -0,0 +1,94 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::internal::{CoreBluetoothMessage, CoreBluetoothReply, CoreBluetoothReplyFuture};
+use crate::{api, Error, Result};
+use async_trait::async_trait;
+use futures::channel::mpsc::{Receiver, Sender};
+use futures::lock::Mutex;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use uuid::Uuid;
+
+/// Implementation of [api::L2capChannel](crate::api::L2capChannel). The channel itself is held by
+/// the objc thread, like the peripheral it belongs to.
+#[derive(Debug)]
+pub struct L2capChannel {
+    peripheral_uuid: Uuid,
+    psm: u16,
+    message_sender: Sender<CoreBluetoothMessage>,
+    data_receiver: Mutex<Receiver<Vec<u8>>>,
+}
+
+impl L2capChannel {
+    pub(super) fn new(
+        peripheral_uuid: Uuid,
+        psm: u16,
+        message_sender: Sender<CoreBluetoothMessage>,
+        data_receiver: Receiver<Vec<u8>>,
+    ) -> Self {
+        Self {
+            peripheral_uuid,
+            psm,
+            message_sender,
+            data_receiver: Mutex::new(data_receiver),
+        }
+    }
+}
+
+#[async_trait]
+impl api::L2capChannel for L2capChannel {
+    fn psm(&self) -> u16 {
+        self.psm
+    }
+
+    async fn write(&self, data: &[u8]) -> Result<()> {
+        let fut = CoreBluetoothReplyFuture::default();
+        self.message_sender
+            .to_owned()
+            .send(CoreBluetoothMessage::WriteL2capChannel(
+                self.peripheral_uuid,
+                self.psm,
+                data.to_vec(),
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => Ok(()),
+            CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+    }
+
+    async fn read(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.data_receiver.lock().await.next().await)
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.message_sender
+            .to_owned()
+            .send(CoreBluetoothMessage::CloseL2capChannel(
+                self.peripheral_uuid,
+                self.psm,
+            ))
+            .await?;
+        Ok(())
+    }
+}
+
+impl Drop for L2capChannel {
+    fn drop(&mut self) {
+        // Closing a channel which is already closed does nothing, and if the objc thread has gone
+        // then so has the channel.
+        let _ = self
+            .message_sender
+            .try_send(CoreBluetoothMessage::CloseL2capChannel(
+                self.peripheral_uuid,
+                self.psm,
+            ));
+    }
+}
@@ -6,16 +6,34 @@
 // for full license information.
 
 use super::adapter::Adapter;
-use crate::{api, Result};
+use crate::{api, Error, Result};
 use async_trait::async_trait;
+use futures::stream::Stream;
+use std::pin::Pin;
 
 /// Implementation of [api::Manager](crate::api::Manager).
 #[derive(Clone, Debug)]
-pub struct Manager {}
+pub struct Manager {
+    restore_identifier: Option<String>,
+}
 
 impl Manager {
     pub async fn new() -> Result<Self> {
-        Ok(Self {})
+        Ok(Self {
+            restore_identifier: None,
+        })
+    }
+
+    /// Creates a manager whose adapters opt in to CoreBluetooth state preservation and
+    /// restoration, using the given identifier. On iOS, this lets the system keep connections
+    /// and pending connections alive while the app is suspended, and relaunch the app for their
+    /// events. Peripherals restored by the system are announced as discovered again.
+    ///
+    /// The identifier must stay the same across launches of the app.
+    pub async fn with_restore_identifier(identifier: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            restore_identifier: Some(identifier.into()),
+        })
     }
 }
 
@@ -24,8 +42,16 @@ impl api::Manager for Manager {
     type Adapter = Adapter;
 
     async fn adapters(&self) -> Result<Vec<Adapter>> {
-        Ok(vec![Adapter::new().await?])
+        Ok(vec![Adapter::new(self.restore_identifier.clone()).await?])
         // TODO What do we do if there is no bluetooth adapter, like on an older
         // macbook pro? Will BluetoothAdapter::init() fail?
     }
+
+    async fn adapter_events(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = api::ManagerEvent<Adapter>> + Send>>> {
+        Err(Error::NotSupported(
+            "Adapter events are not supported on CoreBluetooth".to_string(),
+        ))
+    }
 }
@@ -10,6 +10,7 @@ mod central_delegate;
 mod framework;
 mod future;
 mod internal;
+mod l2cap_channel;
 pub mod manager;
 pub mod peripheral;
 mod utils;
@@ -10,27 +10,39 @@ use super::{
     internal::{
         CBPeripheralEvent, CoreBluetoothMessage, CoreBluetoothReply, CoreBluetoothReplyFuture,
     },
+    l2cap_channel::L2capChannel,
 };
 use crate::{
     api::{
-        self, BDAddr, CentralEvent, CharPropFlags, Characteristic, PeripheralProperties,
-        ValueNotification, WriteType,
+        self, descriptors, BDAddr, CentralEvent, CharPropFlags, Characteristic, ConnectOptions,
+        ConnectionParameters, ConnectionPriority, Descriptor, PairingAgent, Peripheral as _,
+        PeripheralOptions, PeripheralProperties, PeripheralSummary, Phy, PropertyChanges,
+        SecurityLevel, Service, ValueNotification, WriteType,
+    },
+    common::{
+        adapter_manager::AdapterManager, operation_queue::OperationQueue, runtime,
+        subscriptions::SubscriptionCounts, util, watched::Watched,
     },
-    common::{adapter_manager::AdapterManager, util},
     Error, Result,
 };
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::channel::mpsc::{self, Receiver, SendError, Sender, UnboundedSender};
 use futures::sink::SinkExt;
 use futures::stream::{Stream, StreamExt};
 use log::*;
+use objc::rc::StrongPtr;
+use std::os::raw::c_void;
 use std::{
     collections::{BTreeSet, HashMap},
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, SystemTime},
 };
-use tokio::task;
 use uuid::Uuid;
 
 /// Implementation of [api::Peripheral](crate::api::Peripheral).
@@ -39,16 +51,54 @@ pub struct Peripheral {
     notification_senders: Arc<Mutex<Vec<UnboundedSender<ValueNotification>>>>,
     manager: AdapterManager<Self>,
     uuid: Uuid,
-    characteristics: Arc<Mutex<BTreeSet<Characteristic>>>,
+    services: Arc<Mutex<BTreeSet<Service>>>,
     properties: Arc<Mutex<PeripheralProperties>>,
+    properties_watch: Watched<PeripheralProperties>,
     message_sender: Sender<CoreBluetoothMessage>,
+    subscriptions: Arc<Mutex<SubscriptionCounts>>,
+    operations: Arc<OperationQueue>,
+    options: Arc<Mutex<PeripheralOptions>>,
+    dropped_notifications: Arc<AtomicU64>,
     // We're not actually holding a peripheral object here, that's held out in
     // the objc thread. We'll just communicate with it through our
     // receiver/sender pair.
 }
 
+/// A reference to the `CBPeripheral` behind a [`Peripheral`], from
+/// [`Peripheral::platform_handle`], which keeps it retained while the handle exists.
+///
+/// btleplug is the peripheral's delegate, and handles CoreBluetooth's callbacks on its own thread,
+/// so the delegate mustn't be replaced, and operations on the peripheral which btleplug also
+/// performs may confuse it.
+#[derive(Clone)]
+pub struct PeripheralHandle(StrongPtr);
+
+// CBPeripheral may be retained and released from any thread.
+unsafe impl Send for PeripheralHandle {}
+unsafe impl Sync for PeripheralHandle {}
+
+impl PeripheralHandle {
+    pub(super) fn new(peripheral: StrongPtr) -> Self {
+        PeripheralHandle(peripheral)
+    }
+
+    /// Returns the `CBPeripheral *`, which is only valid while the handle exists.
+    pub fn as_ptr(&self) -> *mut c_void {
+        *self.0 as *mut c_void
+    }
+}
+
+impl Debug for PeripheralHandle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("PeripheralHandle")
+            .field(&self.as_ptr())
+            .finish()
+    }
+}
+
 impl Peripheral {
-    // This calls tokio::task::spawn, so it must be called from the context of a Tokio Runtime.
+    // This spawns a task, so with the `tokio-runtime` feature it must be called from the context
+    // of a Tokio Runtime.
     pub(crate) fn new(
         uuid: Uuid,
         local_name: Option<String>,
@@ -68,20 +118,35 @@ impl Peripheral {
             manufacturer_data: HashMap::new(),
             service_data: HashMap::new(),
             services: Vec::new(),
+            rssi: None,
             discovery_count: 1,
+            ad_structures: Vec::new(),
+            last_seen: Some(SystemTime::now()),
         }));
         let notification_senders = Arc::new(Mutex::new(Vec::new()));
         let ns_clone = notification_senders.clone();
         let p_clone = properties.clone();
+        let properties_watch = Watched::new(properties.lock().unwrap().clone());
+        let w_clone = properties_watch.clone();
         let m_clone = manager.clone();
-        task::spawn(async move {
+        let services = Arc::new(Mutex::new(BTreeSet::new()));
+        let services_clone = services.clone();
+        let subscriptions = Arc::new(Mutex::new(SubscriptionCounts::default()));
+        let s_clone = subscriptions.clone();
+        runtime::spawn(util::event_pump("corebluetooth", async move {
             let mut event_receiver = event_receiver;
             loop {
                 match event_receiver.next().await {
-                    Some(CBPeripheralEvent::Notification(uuid, data)) => {
+                    Some(CBPeripheralEvent::Notification(service_uuid, uuid, data)) => {
+                        m_clone.metrics().notification_received();
                         util::send_notification(
                             &ns_clone,
-                            &ValueNotification { uuid, value: data },
+                            &ValueNotification {
+                                uuid,
+                                service_uuid,
+                                handle: 0,
+                                value: data.into(),
+                            },
                         );
                     }
                     Some(CBPeripheralEvent::ManufacturerData(manufacturer_id, data)) => {
@@ -89,44 +154,179 @@ impl Peripheral {
                         properties
                             .manufacturer_data
                             .insert(manufacturer_id, data.clone());
-                        m_clone.emit(CentralEvent::ManufacturerDataAdvertisement {
-                            address: properties.address,
-                            manufacturer_data: properties.manufacturer_data.clone(),
-                        });
+                        w_clone.publish(properties.clone());
+                        m_clone.emit_advertisement(
+                            &properties,
+                            CentralEvent::ManufacturerDataAdvertisement {
+                                address: properties.address,
+                                manufacturer_data: properties.manufacturer_data.clone(),
+                            },
+                        );
                     }
                     Some(CBPeripheralEvent::ServiceData(service_data)) => {
                         let mut properties = p_clone.lock().unwrap();
                         properties.service_data.extend(service_data.clone());
+                        w_clone.publish(properties.clone());
 
-                        m_clone.emit(CentralEvent::ServiceDataAdvertisement {
-                            address: properties.address,
-                            service_data,
-                        });
+                        m_clone.emit_advertisement(
+                            &properties,
+                            CentralEvent::ServiceDataAdvertisement {
+                                address: properties.address,
+                                service_data,
+                            },
+                        );
                     }
                     Some(CBPeripheralEvent::Services(services)) => {
                         let mut properties = p_clone.lock().unwrap();
                         properties.services = services.clone();
+                        w_clone.publish(properties.clone());
 
-                        m_clone.emit(CentralEvent::ServicesAdvertisement {
-                            address: properties.address,
-                            services,
-                        });
+                        m_clone.emit_advertisement(
+                            &properties,
+                            CentralEvent::ServicesAdvertisement {
+                                address: properties.address,
+                                services,
+                            },
+                        );
+                    }
+                    Some(CBPeripheralEvent::Advertised(rssi)) => {
+                        m_clone.metrics().advertisement_received();
+                        let mut properties = p_clone.lock().unwrap();
+                        properties.last_seen = Some(SystemTime::now());
+                        properties.rssi = rssi.or(properties.rssi);
+                        w_clone.publish(properties.clone());
+                        if let Some(rssi) = rssi {
+                            m_clone.emit_advertisement(
+                                &properties,
+                                CentralEvent::RssiUpdate {
+                                    address: properties.address,
+                                    rssi,
+                                },
+                            );
+                        }
+                    }
+                    Some(CBPeripheralEvent::ServicesChanged(services)) => {
+                        *services_clone.lock().unwrap() = services;
+                        let address = p_clone.lock().unwrap().address;
+                        m_clone.emit(CentralEvent::ServicesChanged(address));
+                    }
+                    Some(CBPeripheralEvent::Disconnected) => {
+                        s_clone.lock().unwrap().disconnected();
+                        // Dropping the senders ends the streams returned by `notifications`.
+                        ns_clone.lock().unwrap().clear();
                     }
-                    Some(CBPeripheralEvent::Disconnected) => (),
                     None => {
                         error!("Event receiver died, breaking out of corebluetooth device loop.");
                         break;
                     }
                 }
             }
-        });
+        }));
         Self {
             properties,
+            properties_watch,
             manager,
-            characteristics: Arc::new(Mutex::new(BTreeSet::new())),
+            services,
             notification_senders,
             uuid,
             message_sender,
+            subscriptions,
+            operations: Arc::new(OperationQueue::default()),
+            options: Arc::new(Mutex::new(PeripheralOptions::default())),
+            dropped_notifications: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the identifier CoreBluetooth uses for this peripheral. It is stable for as long as
+    /// the system remembers the device, and can be passed to
+    /// [`Adapter::retrieve_peripheral`](super::adapter::Adapter::retrieve_peripheral) later.
+    pub fn identifier(&self) -> Uuid {
+        self.uuid
+    }
+
+    /// Returns the `CBPeripheral` behind this peripheral, for operations which btleplug doesn't
+    /// wrap. Fails if CoreBluetooth no longer knows about the peripheral.
+    pub async fn platform_handle(&self) -> Result<PeripheralHandle> {
+        let fut = CoreBluetoothReplyFuture::default();
+        self.message_sender
+            .to_owned()
+            .send(CoreBluetoothMessage::GetPeripheral(
+                self.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Peripheral(handle) => Ok(handle),
+            CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+    }
+
+    /// Enables notifications again for the characteristics whose subscriptions were restored after
+    /// reconnecting. CoreBluetooth discovers characteristics while connecting.
+    async fn resubscribe(&self, characteristics: &[Characteristic]) -> Result<()> {
+        for characteristic in characteristics {
+            let fut = CoreBluetoothReplyFuture::default();
+            self.message_sender
+                .to_owned()
+                .send(CoreBluetoothMessage::Subscribe(
+                    self.uuid,
+                    characteristic.uuid,
+                    fut.get_state_clone(),
+                ))
+                .await?;
+            match fut.await {
+                CoreBluetoothReply::Ok => trace!("resubscribed!"),
+                _ => panic!("Didn't subscribe!"),
+            }
+        }
+        Ok(())
+    }
+
+    /// CoreBluetooth doesn't report extended properties, so reads them from the Characteristic
+    /// Extended Properties descriptor of each characteristic which has one. The descriptor is
+    /// optional information, so failing to read it doesn't fail the connection.
+    async fn read_extended_properties(&self, services: BTreeSet<Service>) -> BTreeSet<Service> {
+        let mut result = BTreeSet::new();
+        for mut service in services {
+            let mut characteristics = BTreeSet::new();
+            for mut characteristic in service.characteristics {
+                if characteristic
+                    .properties
+                    .contains(CharPropFlags::EXTENDED_PROPERTIES)
+                {
+                    match self
+                        .read_extended_properties_descriptor(&characteristic)
+                        .await
+                    {
+                        Ok(properties) => characteristic.properties |= properties,
+                        Err(e) => debug!(
+                            "Failed to read extended properties of {}: {}",
+                            characteristic.uuid, e
+                        ),
+                    }
+                }
+                characteristics.insert(characteristic);
+            }
+            service.characteristics = characteristics;
+            result.insert(service);
+        }
+        result
+    }
+
+    async fn read_extended_properties_descriptor(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<CharPropFlags> {
+        let characteristic_descriptors = self.discover_descriptors(characteristic).await?;
+        match characteristic_descriptors
+            .iter()
+            .find(|descriptor| descriptor.uuid == descriptors::EXTENDED_PROPERTIES)
+        {
+            Some(descriptor) => Ok(descriptors::extended_properties(
+                &self.read_descriptor(descriptor).await?,
+            )),
+            None => Ok(CharPropFlags::default()),
         }
     }
 
@@ -135,18 +335,37 @@ impl Peripheral {
         self.manager.emit(event)
     }
 
-    pub(super) fn update_name(&self, name: &str) {
-        self.properties.lock().unwrap().local_name = Some(name.to_string());
+    /// Emits an event about this peripheral being seen, subject to the adapter's scan filter.
+    pub(super) fn emit_advertisement(&self, event: CentralEvent) {
+        let properties = self.properties.lock().unwrap();
+        self.manager.emit_advertisement(&properties, event)
+    }
+
+    /// Sets the peripheral's name, returning what changed.
+    pub(super) fn update_name(&self, name: &str) -> PropertyChanges {
+        let mut properties = self.properties.lock().unwrap();
+        if properties.local_name.as_deref() == Some(name) {
+            return PropertyChanges::empty();
+        }
+        properties.local_name = Some(name.to_string());
+        self.properties_watch.publish(properties.clone());
+        PropertyChanges::LOCAL_NAME
     }
 }
 
 impl Display for Peripheral {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        // let connected = if self.is_connected() { " connected" } else { "" };
-        // let properties = self.properties.lock().unwrap();
-        // write!(f, "{} {}{}", self.address, properties.local_name.clone()
-        //     .unwrap_or_else(|| "(unknown)".to_string()), connected)
-        write!(f, "Peripheral")
+        // The connection state is only known by the CoreBluetooth thread, so it's left out.
+        let properties = self
+            .properties
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        write!(
+            f,
+            "{} {}",
+            properties.address,
+            properties.local_name.as_deref().unwrap_or("(unknown)")
+        )
     }
 }
 
@@ -154,7 +373,7 @@ impl Debug for Peripheral {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("Peripheral")
             .field("uuid", &self.uuid)
-            .field("characteristics", &self.characteristics)
+            .field("services", &self.services)
             .field("properties", &self.properties)
             .field("message_sender", &self.message_sender)
             .finish()
@@ -171,8 +390,23 @@ impl api::Peripheral for Peripheral {
         Ok(Some(self.properties.lock().unwrap().clone()))
     }
 
+    async fn properties_stream(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeripheralProperties> + Send>>> {
+        Ok(self.properties_watch.stream())
+    }
+
+    async fn summary(&self) -> Result<PeripheralSummary> {
+        util::summary(self).await
+    }
+
     fn characteristics(&self) -> BTreeSet<Characteristic> {
-        self.characteristics.lock().unwrap().clone()
+        self.services
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|service| service.characteristics.iter().cloned())
+            .collect()
     }
 
     async fn is_connected(&self) -> Result<bool> {
@@ -180,129 +414,559 @@ impl api::Peripheral for Peripheral {
         Ok(false)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address()), err)
+    )]
     async fn connect(&self) -> Result<()> {
-        let fut = CoreBluetoothReplyFuture::default();
-        self.message_sender
-            .to_owned()
-            .send(CoreBluetoothMessage::ConnectDevice(
-                self.uuid,
-                fut.get_state_clone(),
-            ))
-            .await?;
-        match fut.await {
-            CoreBluetoothReply::Connected(chars) => {
-                *(self.characteristics.lock().unwrap()) = chars;
-                self.emit(CentralEvent::DeviceConnected(
-                    self.properties.lock().unwrap().address,
-                ));
+        let attempt = self.manager.metrics().connect_attempt();
+        let retry = self.options.lock().unwrap().retry;
+        util::with_retry(retry, move || async move {
+            let fut = CoreBluetoothReplyFuture::default();
+            self.message_sender
+                .to_owned()
+                .send(CoreBluetoothMessage::ConnectDevice(
+                    self.uuid,
+                    fut.get_state_clone(),
+                ))
+                .await?;
+            match fut.await {
+                CoreBluetoothReply::Connected(services) => {
+                    let services = self.read_extended_properties(services).await;
+                    *(self.services.lock().unwrap()) = services;
+                    self.emit(CentralEvent::DeviceConnected(
+                        self.properties.lock().unwrap().address,
+                    ));
+                }
+                CoreBluetoothReply::Err(msg) => return Err(Error::Other(msg.into())),
+                _ => panic!("Shouldn't get anything but connected!"),
             }
-            _ => panic!("Shouldn't get anything but connected!"),
-        }
-        trace!("Device connected!");
+            trace!("Device connected!");
+            Ok(())
+        })
+        .await?;
+        attempt.succeeded();
         Ok(())
     }
 
-    async fn disconnect(&self) -> Result<()> {
-        // TODO
+    async fn connect_with_timeout(&self, timeout: Duration) -> Result<()> {
+        util::connect_with_timeout(self, timeout).await
+    }
+
+    async fn connect_with_options(&self, options: &ConnectOptions) -> Result<()> {
+        self.operations.set_concurrency(
+            options
+                .concurrent_operations
+                .map_or(1, |concurrency| concurrency.get()),
+        );
+        // Connections are kept open regardless of whether they are in use.
+        self.connect().await?;
+        let resubscriptions = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .reconnected(options.resubscribe);
+        if options.resubscribe {
+            self.resubscribe(&resubscriptions).await?;
+        }
         Ok(())
     }
 
-    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
-        let characteristics = self.characteristics.lock().unwrap().clone();
-        Ok(characteristics.into_iter().collect())
+    fn set_options(&self, options: &PeripheralOptions) {
+        *self.options.lock().unwrap() = *options;
     }
 
-    async fn write(
-        &self,
-        characteristic: &Characteristic,
-        data: &[u8],
-        mut write_type: WriteType,
-    ) -> Result<()> {
+    async fn pair(&self) -> Result<()> {
+        Err(Error::NotSupported(
+            "CoreBluetooth pairs automatically when a protected characteristic is accessed"
+                .to_string(),
+        ))
+    }
+
+    async fn pair_with_agent(&self, _agent: Arc<dyn PairingAgent>) -> Result<()> {
+        Err(Error::NotSupported(
+            "CoreBluetooth pairs automatically when a protected characteristic is accessed"
+                .to_string(),
+        ))
+    }
+
+    async fn request_security(&self, _level: SecurityLevel) -> Result<()> {
+        Err(Error::NotSupported(
+            "CoreBluetooth pairs automatically when a protected characteristic is accessed"
+                .to_string(),
+        ))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address()), err)
+    )]
+    async fn disconnect(&self) -> Result<()> {
         let fut = CoreBluetoothReplyFuture::default();
-        // If we get WriteWithoutResponse for a characteristic that only
-        // supports WriteWithResponse, slam the type to WriteWithResponse.
-        // Otherwise we won't handle the future correctly.
-        if write_type == WriteType::WithoutResponse
-            && !characteristic
-                .properties
-                .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
-        {
-            write_type = WriteType::WithResponse
-        }
         self.message_sender
             .to_owned()
-            .send(CoreBluetoothMessage::WriteValue(
+            .send(CoreBluetoothMessage::DisconnectDevice(
                 self.uuid,
-                characteristic.uuid,
-                Vec::from(data),
-                write_type,
                 fut.get_state_clone(),
             ))
             .await?;
         match fut.await {
-            CoreBluetoothReply::Ok => {}
-            reply => panic!("Unexpected reply: {:?}", reply),
+            CoreBluetoothReply::Ok => Ok(()),
+            _ => panic!("Shouldn't get anything but ok!"),
         }
-        Ok(())
     }
 
-    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+    async fn mtu(&self) -> Result<u16> {
         let fut = CoreBluetoothReplyFuture::default();
         self.message_sender
             .to_owned()
-            .send(CoreBluetoothMessage::ReadValue(
+            .send(CoreBluetoothMessage::GetMtu(
                 self.uuid,
-                characteristic.uuid,
                 fut.get_state_clone(),
             ))
             .await?;
         match fut.await {
-            CoreBluetoothReply::ReadResult(chars) => Ok(chars),
-            _ => {
-                panic!("Shouldn't get anything but read result!");
-            }
+            CoreBluetoothReply::Mtu(mtu) => Ok(mtu),
+            CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+            reply => panic!("Unexpected reply: {:?}", reply),
         }
     }
 
-    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+    async fn request_mtu(&self, _mtu: u16) -> Result<()> {
+        Err(Error::NotSupported(
+            "CoreBluetooth negotiates the MTU automatically".to_string(),
+        ))
+    }
+
+    async fn set_connection_parameters(&self, _parameters: &ConnectionParameters) -> Result<()> {
+        Err(Error::NotSupported(
+            "Setting connection parameters is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn request_connection_priority(&self, _priority: ConnectionPriority) -> Result<()> {
+        Err(Error::NotSupported(
+            "Setting connection parameters is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn set_preferred_phy(&self, _tx: Phy, _rx: Phy) -> Result<()> {
+        Err(Error::NotSupported(
+            "Setting the PHY is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn read_phy(&self) -> Result<(Phy, Phy)> {
+        Err(Error::NotSupported(
+            "Reading the PHY is not supported on CoreBluetooth".to_string(),
+        ))
+    }
+
+    async fn read_rssi(&self) -> Result<i16> {
         let fut = CoreBluetoothReplyFuture::default();
         self.message_sender
             .to_owned()
-            .send(CoreBluetoothMessage::Subscribe(
+            .send(CoreBluetoothMessage::ReadRssi(
                 self.uuid,
-                characteristic.uuid,
                 fut.get_state_clone(),
             ))
             .await?;
         match fut.await {
-            CoreBluetoothReply::Ok => trace!("subscribed!"),
-            _ => panic!("Didn't subscribe!"),
+            CoreBluetoothReply::Rssi(rssi) => {
+                // Advertisements stop once connected, so this is the only way to keep the RSSI in
+                // the properties up to date.
+                let (address, changes) = {
+                    let mut properties = self.properties.lock().unwrap();
+                    let changes = if properties.rssi == Some(rssi) {
+                        PropertyChanges::empty()
+                    } else {
+                        PropertyChanges::RSSI
+                    };
+                    properties.rssi = Some(rssi);
+                    self.properties_watch.publish(properties.clone());
+                    (properties.address, changes)
+                };
+                self.emit(CentralEvent::DeviceUpdated { address, changes });
+                self.emit(CentralEvent::RssiUpdate { address, rssi });
+                Ok(rssi)
+            }
+            CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+            reply => panic!("Unexpected reply: {:?}", reply),
         }
-        Ok(())
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address()), err)
+    )]
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        Ok(self.characteristics().into_iter().collect())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address()), err)
+    )]
+    async fn discover_services(&self) -> Result<Vec<Service>> {
+        let services = self.services.lock().unwrap().clone();
+        Ok(services.into_iter().collect())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address(), characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        mut write_type: WriteType,
+    ) -> Result<()> {
+        let _operation = self.manager.metrics().gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.write_timeout, options.retry, move || async move {
+                let fut = CoreBluetoothReplyFuture::default();
+                // If we get WriteWithoutResponse for a characteristic that only
+                // supports WriteWithResponse, slam the type to WriteWithResponse.
+                // Otherwise we won't handle the future correctly.
+                if write_type == WriteType::WithoutResponse
+                    && !characteristic
+                        .properties
+                        .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+                {
+                    write_type = WriteType::WithResponse
+                }
+                self.message_sender
+                    .to_owned()
+                    .send(CoreBluetoothMessage::WriteValue(
+                        self.uuid,
+                        characteristic.uuid,
+                        Vec::from(data),
+                        write_type,
+                        fut.get_state_clone(),
+                    ))
+                    .await?;
+                match fut.await {
+                    CoreBluetoothReply::Ok => {}
+                    reply => panic!("Unexpected reply: {:?}", reply),
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address(), characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn write_long(
+        &self,
+        characteristic: &Characteristic,
+        offset: u16,
+        data: &[u8],
+    ) -> Result<()> {
+        if offset != 0 {
+            return Err(Error::NotSupported(
+                "Writing at an offset is not supported on CoreBluetooth".to_string(),
+            ));
+        }
+        // Writes with response which don't fit in a single request are split into prepared writes
+        // automatically.
+        self.write(characteristic, data, WriteType::WithResponse)
+            .await
+    }
+
+    async fn wait_until_ready_to_write(&self) -> Result<()> {
+        let timeout = self.options.lock().unwrap().write_timeout;
+        util::with_timeout(timeout, async {
+            let fut = CoreBluetoothReplyFuture::default();
+            self.message_sender
+                .to_owned()
+                .send(CoreBluetoothMessage::WaitUntilReadyToWrite(
+                    self.uuid,
+                    fut.get_state_clone(),
+                ))
+                .await?;
+            match fut.await {
+                CoreBluetoothReply::Ok => Ok(()),
+                CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+                reply => panic!("Unexpected reply: {:?}", reply),
+            }
+        })
+        .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address(), characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn read(&self, characteristic: &Characteristic) -> Result<Bytes> {
+        let _operation = self.manager.metrics().gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.read_timeout, options.retry, move || async move {
+                let fut = CoreBluetoothReplyFuture::default();
+                self.message_sender
+                    .to_owned()
+                    .send(CoreBluetoothMessage::ReadValue(
+                        self.uuid,
+                        characteristic.uuid,
+                        fut.get_state_clone(),
+                    ))
+                    .await?;
+                match fut.await {
+                    CoreBluetoothReply::ReadResult(chars) => Ok(chars.into()),
+                    _ => {
+                        panic!("Shouldn't get anything but read result!");
+                    }
+                }
+            })
+            .await
+    }
+
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>> {
+        let timeout = self.options.lock().unwrap().discovery_timeout;
+        util::with_timeout(timeout, async {
+            let fut = CoreBluetoothReplyFuture::default();
+            self.message_sender
+                .to_owned()
+                .send(CoreBluetoothMessage::DiscoverDescriptors(
+                    self.uuid,
+                    characteristic.uuid,
+                    fut.get_state_clone(),
+                ))
+                .await?;
+            match fut.await {
+                CoreBluetoothReply::Descriptors(descriptors) => {
+                    Ok(descriptors.into_iter().collect())
+                }
+                CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+                reply => panic!("Unexpected reply: {:?}", reply),
+            }
+        })
+        .await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let _operation = self.manager.metrics().gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.read_timeout, options.retry, move || async move {
+                let fut = CoreBluetoothReplyFuture::default();
+                self.message_sender
+                    .to_owned()
+                    .send(CoreBluetoothMessage::ReadDescriptorValue(
+                        self.uuid,
+                        descriptor.characteristic_uuid,
+                        descriptor.uuid,
+                        fut.get_state_clone(),
+                    ))
+                    .await?;
+                match fut.await {
+                    CoreBluetoothReply::ReadResult(data) => Ok(data),
+                    CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+                    reply => panic!("Unexpected reply: {:?}", reply),
+                }
+            })
+            .await
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let _operation = self.manager.metrics().gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.write_timeout, options.retry, move || async move {
+                let fut = CoreBluetoothReplyFuture::default();
+                self.message_sender
+                    .to_owned()
+                    .send(CoreBluetoothMessage::WriteDescriptorValue(
+                        self.uuid,
+                        descriptor.characteristic_uuid,
+                        descriptor.uuid,
+                        Vec::from(data),
+                        fut.get_state_clone(),
+                    ))
+                    .await?;
+                match fut.await {
+                    CoreBluetoothReply::Ok => Ok(()),
+                    CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+                    reply => panic!("Unexpected reply: {:?}", reply),
+                }
+            })
+            .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address(), characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(
+                options.subscribe_timeout,
+                options.retry,
+                move || async move {
+                    if self
+                        .subscriptions
+                        .lock()
+                        .unwrap()
+                        .subscribe_if_enabled(characteristic)
+                    {
+                        return Ok(());
+                    }
+                    let fut = CoreBluetoothReplyFuture::default();
+                    self.message_sender
+                        .to_owned()
+                        .send(CoreBluetoothMessage::Subscribe(
+                            self.uuid,
+                            characteristic.uuid,
+                            fut.get_state_clone(),
+                        ))
+                        .await?;
+                    match fut.await {
+                        CoreBluetoothReply::Ok => trace!("subscribed!"),
+                        _ => panic!("Didn't subscribe!"),
+                    }
+                    // Only counted once notifications are enabled, as this future is dropped if
+                    // the operation times out.
+                    self.subscriptions.lock().unwrap().subscribe(characteristic);
+                    Ok(())
+                },
+            )
+            .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address(), characteristic = %characteristic.uuid),
+            err
+        )
+    )]
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(
+                options.subscribe_timeout,
+                options.retry,
+                move || async move {
+                    if !self
+                        .subscriptions
+                        .lock()
+                        .unwrap()
+                        .unsubscribe(characteristic)
+                    {
+                        return Ok(());
+                    }
+                    let fut = CoreBluetoothReplyFuture::default();
+                    self.message_sender
+                        .to_owned()
+                        .send(CoreBluetoothMessage::Unsubscribe(
+                            self.uuid,
+                            characteristic.uuid,
+                            fut.get_state_clone(),
+                        ))
+                        .await?;
+                    match fut.await {
+                        CoreBluetoothReply::Ok => {}
+                        _ => panic!("Didn't unsubscribe!"),
+                    }
+                    Ok(())
+                },
+            )
+            .await
+    }
+
+    fn is_subscribed(&self, characteristic: &Characteristic) -> bool {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .is_subscribed(characteristic)
+    }
+
+    fn subscriptions(&self) -> BTreeSet<Characteristic> {
+        self.subscriptions.lock().unwrap().subscribed()
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.notification_senders.lock().unwrap().push(sender);
+        let notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>> =
+            Box::pin(receiver);
+        Ok(match self.options.lock().unwrap().notification_capacity {
+            Some(capacity) => util::bounded_notifications(
+                notifications,
+                capacity,
+                self.dropped_notifications.clone(),
+                self.manager.metrics().clone(),
+            ),
+            None => notifications,
+        })
+    }
+
+    fn dropped_notifications(&self) -> u64 {
+        self.dropped_notifications.load(Ordering::Relaxed)
+    }
+
+    async fn notifications_for(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        Ok(util::notifications_for(
+            self.notifications().await?,
+            characteristic,
+        ))
+    }
+
+    async fn open_l2cap_channel(&self, psm: u16) -> Result<Box<dyn api::L2capChannel>> {
+        let (data_sender, data_receiver) = mpsc::channel(256);
         let fut = CoreBluetoothReplyFuture::default();
         self.message_sender
             .to_owned()
-            .send(CoreBluetoothMessage::Unsubscribe(
+            .send(CoreBluetoothMessage::OpenL2capChannel(
                 self.uuid,
-                characteristic.uuid,
+                psm,
+                data_sender,
                 fut.get_state_clone(),
             ))
             .await?;
         match fut.await {
-            CoreBluetoothReply::Ok => {}
-            _ => panic!("Didn't unsubscribe!"),
+            CoreBluetoothReply::Ok => Ok(Box::new(L2capChannel::new(
+                self.uuid,
+                psm,
+                self.message_sender.clone(),
+                data_receiver,
+            ))),
+            CoreBluetoothReply::Err(msg) => Err(Error::Other(msg.into())),
+            reply => panic!("Unexpected reply: {:?}", reply),
         }
-        Ok(())
-    }
-
-    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
-        let (sender, receiver) = mpsc::unbounded();
-        let mut senders = self.notification_senders.lock().unwrap();
-        senders.push(sender);
-        Ok(Box::pin(receiver))
     }
 }
 
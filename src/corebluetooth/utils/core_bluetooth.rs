@@ -20,7 +20,7 @@ use objc::runtime::Object;
 use uuid::Uuid;
 
 use super::super::framework::{cb, nil, ns};
-use super::nsstring::nsstring_to_string;
+use super::nsstring::{nsstring_to_string, str_to_nsstring};
 
 /// Convert a CBUUID object to the standard Uuid type.
 pub fn cbuuid_to_uuid(cbuuid: *mut Object) -> Uuid {
@@ -39,6 +39,11 @@ pub fn cbuuid_to_uuid(cbuuid: *mut Object) -> Uuid {
     uuid_string.parse().unwrap()
 }
 
+/// Convert a standard Uuid to a CBUUID object.
+pub fn uuid_to_cbuuid(uuid: Uuid) -> *mut Object {
+    cb::uuid_uuidwithstring(str_to_nsstring(&uuid.to_string()))
+}
+
 pub fn peripheral_debug(peripheral: *mut Object) -> String {
     if peripheral == nil {
         return String::from("nil");
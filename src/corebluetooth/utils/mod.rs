@@ -16,7 +16,7 @@
 // This file may not be copied, modified, or distributed except
 // according to those terms.
 
-use objc::runtime::Object;
+use objc::{rc::StrongPtr, runtime::Object};
 use std::slice;
 use uuid::Uuid;
 
@@ -39,3 +39,8 @@ pub fn nsuuid_to_uuid(uuid: *mut Object) -> Uuid {
     let uuid_nsstring = ns::uuid_uuidstring(uuid);
     nsstring_to_string(uuid_nsstring).unwrap().parse().unwrap()
 }
+
+pub fn uuid_to_nsuuid(uuid: Uuid) -> StrongPtr {
+    let uuid_nsstring = nsstring::str_to_nsstring(&uuid.to_string());
+    unsafe { StrongPtr::new(ns::uuid_initwithuuidstring(uuid_nsstring)) }
+}
@@ -35,7 +35,6 @@ pub fn nsstring_to_string(nsstring: *mut Object) -> Option<String> {
     }
 }
 
-#[allow(dead_code)]
 pub fn str_to_nsstring(string: &str) -> *mut Object {
     let cstring = CString::new(string).unwrap();
     ns::string(cstring.as_ptr())
@@ -20,7 +20,7 @@
 //! An example of how to use the library to control some BLE smart lights:
 //!
 //! ```rust,no_run
-//! use btleplug::api::{bleuuid::uuid_from_u16, Central, Manager as _, Peripheral as _, WriteType};
+//! use btleplug::api::{bleuuid::uuid_from_u16, Central, Manager as _, Peripheral as _, ScanFilter, WriteType};
 //! use btleplug::platform::{Adapter, Manager, Peripheral};
 //! use rand::{Rng, thread_rng};
 //! use std::error::Error;
@@ -40,7 +40,7 @@
 //!     let central = adapters.into_iter().nth(0).unwrap();
 //!
 //!     // start scanning for devices
-//!     central.start_scan().await?;
+//!     central.start_scan(ScanFilter::default()).await?;
 //!     // instead of waiting, you can use central.event_receiver() to fetch a channel and
 //!     // be notified of new devices
 //!     time::sleep(Duration::from_secs(2)).await;
@@ -87,23 +87,33 @@
 
 // We won't actually use anything specifically out of this crate. However, if we
 // want the CoreBluetooth code to compile, we need the objc protocols
-// (specifically, the core bluetooth protocols) exposed by it.
-#[cfg(any(target_os = "macos", target_os = "ios"))]
+// (specifically, the core bluetooth protocols) exposed by it. It links AppKit, so
+// isn't available on iOS, where the protocols come from the CoreBluetooth framework.
+#[cfg(target_os = "macos")]
 extern crate cocoa;
 
 use crate::api::ParseBDAddrError;
+use std::fmt;
 use std::result;
 use std::time::Duration;
 
 pub mod api;
 #[cfg(target_os = "linux")]
 mod bluez;
+#[cfg(feature = "capi")]
+pub mod capi;
 mod common;
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 mod corebluetooth;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod platform;
+#[cfg(feature = "record")]
+pub mod record;
 #[cfg(feature = "serde")]
 pub mod serde;
+#[cfg(feature = "uniffi-bindings")]
+mod uniffi_bindings;
 #[cfg(target_os = "windows")]
 mod winrtble;
 
@@ -131,9 +141,318 @@ pub enum Error {
     #[error("Invalid Bluetooth address: {0}")]
     InvalidBDAddr(#[from] ParseBDAddrError),
 
+    #[error("The device returned {}", _0)]
+    Att(AttError),
+
+    /// Pairing or encrypting the link with the device failed. Errors returned by the device itself
+    /// because the link isn't secure enough are [`Error::Att`] errors instead; see
+    /// [`AttError::requires_pairing`].
+    #[error("Authentication failed: {}", _0)]
+    InsufficientAuthentication(ErrorCode),
+
+    /// The connection to the device timed out, either while it was being established or because
+    /// the device stopped responding.
+    #[error("Connection timed out: {}", _0)]
+    ConnectionTimeout(ErrorCode),
+
+    /// The device couldn't be reached, such as because it is out of range or not accepting
+    /// connections.
+    #[error("Device unreachable: {}", _0)]
+    DeviceUnreachable(ErrorCode),
+
+    /// Any other error reported by the Bluetooth stack with a code.
+    #[error("{message}: {code}")]
+    Platform { code: ErrorCode, message: String },
+
     #[error("{}", _0)]
     Other(Box<dyn std::error::Error>),
 }
 
+impl Error {
+    /// Returns the code which the device or the Bluetooth stack gave for the error, if any, so
+    /// that callers can tell failures apart beyond the variants of [`Error`].
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Att(error) => Some(ErrorCode::Att(*error)),
+            Error::InsufficientAuthentication(code)
+            | Error::ConnectionTimeout(code)
+            | Error::DeviceUnreachable(code)
+            | Error::Platform { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Classifies the error by whether the operation which failed may succeed if tried again.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::TimedOut(_) | Error::ConnectionTimeout(_) | Error::DeviceUnreachable(_) => {
+                ErrorKind::Transient
+            }
+            Error::NotConnected => ErrorKind::Disconnected,
+            Error::PermissionDenied
+            | Error::DeviceNotFound
+            | Error::NotSupported(_)
+            | Error::Uuid(_)
+            | Error::InvalidBDAddr(_)
+            | Error::InsufficientAuthentication(_) => ErrorKind::Permanent,
+            Error::Att(error) => ErrorCode::Att(*error).kind(),
+            Error::Platform { code, .. } => code.kind(),
+            Error::Other(_) => ErrorKind::Unknown,
+        }
+    }
+
+    /// Returns whether the operation which failed may succeed if it is simply tried again, such as
+    /// after a timeout or while the radio is congested.
+    pub fn is_transient(&self) -> bool {
+        self.kind() == ErrorKind::Transient
+    }
+}
+
+/// Whether an operation which failed with an [`Error`] may succeed if tried again, from
+/// [`Error::kind`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ErrorKind {
+    /// A failure which may clear up by itself, such as a timeout, the device being out of range
+    /// or the controller being busy, so the operation can be tried again.
+    Transient,
+    /// The connection to the device was lost, so it must be reconnected to before the operation
+    /// is tried again.
+    Disconnected,
+    /// The operation can't succeed as it was made, such as because it isn't supported, permission
+    /// was denied, or the link must be paired first. Trying it again won't help.
+    Permanent,
+    /// A failure whose cause isn't known, such as most errors from the operating system which come
+    /// without a code.
+    Unknown,
+}
+
+impl From<HciError> for Error {
+    fn from(error: HciError) -> Self {
+        let code = ErrorCode::Hci(error);
+        match error {
+            HciError::CONNECTION_TIMEOUT | HciError::LMP_RESPONSE_TIMEOUT => {
+                Error::ConnectionTimeout(code)
+            }
+            HciError::PAGE_TIMEOUT | HciError::CONNECTION_FAILED_TO_BE_ESTABLISHED => {
+                Error::DeviceUnreachable(code)
+            }
+            HciError::AUTHENTICATION_FAILURE
+            | HciError::PIN_OR_KEY_MISSING
+            | HciError::INSUFFICIENT_SECURITY => Error::InsufficientAuthentication(code),
+            _ => Error::Platform {
+                code,
+                message: "The controller reported an error".to_string(),
+            },
+        }
+    }
+}
+
+/// The code underlying an [`Error`], as reported by the device, the controller or the operating
+/// system's Bluetooth stack.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ErrorCode {
+    /// An attribute protocol error returned by the device.
+    Att(AttError),
+    /// An HCI status code returned by the controller.
+    Hci(HciError),
+    /// An `errno` value from BlueZ or the Linux kernel.
+    Errno(i32),
+    /// An `HRESULT` from the Windows Runtime.
+    HResult(i32),
+}
+
+impl ErrorCode {
+    /// Classifies the code by whether the operation which failed may succeed if tried again.
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            ErrorCode::Att(error) => match error {
+                AttError::PREPARE_QUEUE_FULL
+                | AttError::UNLIKELY_ERROR
+                | AttError::INSUFFICIENT_RESOURCES => ErrorKind::Transient,
+                _ => ErrorKind::Permanent,
+            },
+            ErrorCode::Hci(error) => match error {
+                HciError::PAGE_TIMEOUT
+                | HciError::MEMORY_CAPACITY_EXCEEDED
+                | HciError::CONNECTION_TIMEOUT
+                | HciError::CONNECTION_LIMIT_EXCEEDED
+                | HciError::LMP_RESPONSE_TIMEOUT
+                | HciError::CONTROLLER_BUSY
+                | HciError::CONNECTION_FAILED_TO_BE_ESTABLISHED => ErrorKind::Transient,
+                HciError::REMOTE_USER_TERMINATED
+                | HciError::CONNECTION_TERMINATED_BY_LOCAL_HOST => ErrorKind::Disconnected,
+                HciError::UNKNOWN_COMMAND
+                | HciError::AUTHENTICATION_FAILURE
+                | HciError::PIN_OR_KEY_MISSING
+                | HciError::COMMAND_DISALLOWED
+                | HciError::INVALID_PARAMETERS
+                | HciError::UNSUPPORTED_REMOTE_FEATURE
+                | HciError::INSUFFICIENT_SECURITY => ErrorKind::Permanent,
+                _ => ErrorKind::Unknown,
+            },
+            // Errno codes only come from BlueZ, so these are the Linux values.
+            ErrorCode::Errno(errno) => match errno {
+                // EAGAIN, EBUSY, ENOBUFS, ETIMEDOUT, EHOSTDOWN, EHOSTUNREACH, EALREADY, EINPROGRESS
+                11 | 16 | 105 | 110 | 112 | 113 | 114 | 115 => ErrorKind::Transient,
+                // ECONNABORTED, ECONNRESET, ENOTCONN
+                103 | 104 | 107 => ErrorKind::Disconnected,
+                // EPERM, EACCES, EINVAL, ENOSYS, EOPNOTSUPP
+                1 | 13 | 22 | 38 | 95 => ErrorKind::Permanent,
+                _ => ErrorKind::Unknown,
+            },
+            ErrorCode::HResult(_) => ErrorKind::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorCode::Att(error) => write!(f, "{}", error),
+            ErrorCode::Hci(error) => write!(f, "{}", error),
+            ErrorCode::Errno(errno) => write!(f, "errno {}", errno),
+            ErrorCode::HResult(hresult) => write!(f, "HRESULT {:#010x}", hresult),
+        }
+    }
+}
+
+/// An error code returned by a peripheral in response to an attribute protocol request, as listed
+/// in the Bluetooth Core Specification, Vol 3, Part F, 3.4.1.1. Codes from 0x80 onwards are
+/// defined by the application.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct AttError(pub u8);
+
+impl AttError {
+    pub const INVALID_HANDLE: AttError = AttError(0x01);
+    pub const READ_NOT_PERMITTED: AttError = AttError(0x02);
+    pub const WRITE_NOT_PERMITTED: AttError = AttError(0x03);
+    pub const INVALID_PDU: AttError = AttError(0x04);
+    pub const INSUFFICIENT_AUTHENTICATION: AttError = AttError(0x05);
+    pub const REQUEST_NOT_SUPPORTED: AttError = AttError(0x06);
+    pub const INVALID_OFFSET: AttError = AttError(0x07);
+    pub const INSUFFICIENT_AUTHORIZATION: AttError = AttError(0x08);
+    pub const PREPARE_QUEUE_FULL: AttError = AttError(0x09);
+    pub const ATTRIBUTE_NOT_FOUND: AttError = AttError(0x0a);
+    pub const ATTRIBUTE_NOT_LONG: AttError = AttError(0x0b);
+    pub const INSUFFICIENT_ENCRYPTION_KEY_SIZE: AttError = AttError(0x0c);
+    pub const INVALID_ATTRIBUTE_VALUE_LENGTH: AttError = AttError(0x0d);
+    pub const UNLIKELY_ERROR: AttError = AttError(0x0e);
+    pub const INSUFFICIENT_ENCRYPTION: AttError = AttError(0x0f);
+    pub const UNSUPPORTED_GROUP_TYPE: AttError = AttError(0x10);
+    pub const INSUFFICIENT_RESOURCES: AttError = AttError(0x11);
+
+    /// Whether the request may succeed once the link has been paired or encrypted.
+    pub fn requires_pairing(&self) -> bool {
+        *self == AttError::INSUFFICIENT_AUTHENTICATION
+            || *self == AttError::INSUFFICIENT_AUTHORIZATION
+            || *self == AttError::INSUFFICIENT_ENCRYPTION
+            || *self == AttError::INSUFFICIENT_ENCRYPTION_KEY_SIZE
+    }
+}
+
+impl fmt::Display for AttError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            AttError::INVALID_HANDLE => "invalid handle",
+            AttError::READ_NOT_PERMITTED => "read not permitted",
+            AttError::WRITE_NOT_PERMITTED => "write not permitted",
+            AttError::INVALID_PDU => "invalid PDU",
+            AttError::INSUFFICIENT_AUTHENTICATION => "insufficient authentication",
+            AttError::REQUEST_NOT_SUPPORTED => "request not supported",
+            AttError::INVALID_OFFSET => "invalid offset",
+            AttError::INSUFFICIENT_AUTHORIZATION => "insufficient authorization",
+            AttError::PREPARE_QUEUE_FULL => "prepare queue full",
+            AttError::ATTRIBUTE_NOT_FOUND => "attribute not found",
+            AttError::ATTRIBUTE_NOT_LONG => "attribute not long",
+            AttError::INSUFFICIENT_ENCRYPTION_KEY_SIZE => "insufficient encryption key size",
+            AttError::INVALID_ATTRIBUTE_VALUE_LENGTH => "invalid attribute value length",
+            AttError::UNLIKELY_ERROR => "unlikely error",
+            AttError::INSUFFICIENT_ENCRYPTION => "insufficient encryption",
+            AttError::UNSUPPORTED_GROUP_TYPE => "unsupported group type",
+            AttError::INSUFFICIENT_RESOURCES => "insufficient resources",
+            _ => "unknown",
+        };
+        write!(f, "ATT error {:#04x} ({})", self.0, description)
+    }
+}
+
+/// A status code returned by the Bluetooth controller over HCI, as listed in the Bluetooth Core
+/// Specification, Vol 1, Part F.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct HciError(pub u8);
+
+impl HciError {
+    pub const UNKNOWN_COMMAND: HciError = HciError(0x01);
+    pub const UNKNOWN_CONNECTION_IDENTIFIER: HciError = HciError(0x02);
+    pub const HARDWARE_FAILURE: HciError = HciError(0x03);
+    pub const PAGE_TIMEOUT: HciError = HciError(0x04);
+    pub const AUTHENTICATION_FAILURE: HciError = HciError(0x05);
+    pub const PIN_OR_KEY_MISSING: HciError = HciError(0x06);
+    pub const MEMORY_CAPACITY_EXCEEDED: HciError = HciError(0x07);
+    pub const CONNECTION_TIMEOUT: HciError = HciError(0x08);
+    pub const CONNECTION_LIMIT_EXCEEDED: HciError = HciError(0x09);
+    pub const COMMAND_DISALLOWED: HciError = HciError(0x0c);
+    pub const INVALID_PARAMETERS: HciError = HciError(0x12);
+    pub const REMOTE_USER_TERMINATED: HciError = HciError(0x13);
+    pub const CONNECTION_TERMINATED_BY_LOCAL_HOST: HciError = HciError(0x16);
+    pub const UNSUPPORTED_REMOTE_FEATURE: HciError = HciError(0x1a);
+    pub const LMP_RESPONSE_TIMEOUT: HciError = HciError(0x22);
+    pub const INSUFFICIENT_SECURITY: HciError = HciError(0x2f);
+    pub const CONTROLLER_BUSY: HciError = HciError(0x3a);
+    pub const CONNECTION_FAILED_TO_BE_ESTABLISHED: HciError = HciError(0x3e);
+}
+
+impl fmt::Display for HciError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            HciError::UNKNOWN_COMMAND => "unknown command",
+            HciError::UNKNOWN_CONNECTION_IDENTIFIER => "unknown connection identifier",
+            HciError::HARDWARE_FAILURE => "hardware failure",
+            HciError::PAGE_TIMEOUT => "page timeout",
+            HciError::AUTHENTICATION_FAILURE => "authentication failure",
+            HciError::PIN_OR_KEY_MISSING => "PIN or key missing",
+            HciError::MEMORY_CAPACITY_EXCEEDED => "memory capacity exceeded",
+            HciError::CONNECTION_TIMEOUT => "connection timeout",
+            HciError::CONNECTION_LIMIT_EXCEEDED => "connection limit exceeded",
+            HciError::COMMAND_DISALLOWED => "command disallowed",
+            HciError::INVALID_PARAMETERS => "invalid parameters",
+            HciError::REMOTE_USER_TERMINATED => "remote user terminated connection",
+            HciError::CONNECTION_TERMINATED_BY_LOCAL_HOST => "connection terminated by local host",
+            HciError::UNSUPPORTED_REMOTE_FEATURE => "unsupported remote feature",
+            HciError::LMP_RESPONSE_TIMEOUT => "LMP response timeout",
+            HciError::INSUFFICIENT_SECURITY => "insufficient security",
+            HciError::CONTROLLER_BUSY => "controller busy",
+            HciError::CONNECTION_FAILED_TO_BE_ESTABLISHED => "connection failed to be established",
+            _ => "unknown",
+        };
+        write!(f, "HCI status {:#04x} ({})", self.0, description)
+    }
+}
+
 /// Convenience type for a result using the btleplug [`Error`] type.
 pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::RetryPolicy;
+
+    #[test]
+    fn classify_errors() {
+        assert!(Error::from(HciError::CONNECTION_TIMEOUT).is_transient());
+        assert!(Error::Att(AttError::INSUFFICIENT_RESOURCES).is_transient());
+        assert_eq!(
+            Error::Att(AttError::INSUFFICIENT_AUTHENTICATION).kind(),
+            ErrorKind::Permanent
+        );
+        assert_eq!(
+            Error::NotSupported(String::new()).kind(),
+            ErrorKind::Permanent
+        );
+        assert_eq!(Error::NotConnected.kind(), ErrorKind::Disconnected);
+
+        let policy = RetryPolicy::default();
+        assert!(policy.is_retryable(&Error::DeviceUnreachable(ErrorCode::Errno(112))));
+        assert!(!policy.is_retryable(&Error::PermissionDenied));
+    }
+}
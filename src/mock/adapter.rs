@@ -0,0 +1,282 @@
+#[cfg(feature = "serde")]
+use super::DeviceDescription;
+use super::{MockDevice, Peripheral};
+use crate::{
+    api::{
+        AdapterMetrics, AdapterState, Advertisement, BDAddr, Central, CentralEvent,
+        DisconnectReason, EventFilter, IdentityResolvingKey, PeriodicAdvertisement,
+        Peripheral as _, ScanFilter,
+    },
+    common::adapter_manager::AdapterManager,
+    Error, Result,
+};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use futures::stream::Stream;
+use std::fmt::{self, Debug, Formatter};
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+/// Implementation of [api::Central](crate::api::Central) for a simulated adapter. Devices added
+/// with [`add_device`](Self::add_device) are discovered as soon as the adapter is scanning.
+#[derive(Clone)]
+pub struct Adapter {
+    manager: AdapterManager<Peripheral>,
+    /// Every simulated device in range, whether or not it has been discovered.
+    devices: Arc<DashMap<BDAddr, Peripheral>>,
+    scanning: Arc<AtomicBool>,
+    state: Arc<Mutex<AdapterState>>,
+    advertisement: Arc<Mutex<Option<Advertisement>>>,
+}
+
+impl Default for Adapter {
+    fn default() -> Self {
+        Adapter {
+            manager: AdapterManager::default(),
+            devices: Arc::new(DashMap::new()),
+            scanning: Arc::new(AtomicBool::new(false)),
+            state: Arc::new(Mutex::new(AdapterState::PoweredOn)),
+            advertisement: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl Adapter {
+    /// Creates a powered on adapter with no devices in range.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Brings a simulated device into range, returning the peripheral with which to control it.
+    /// It is discovered straight away if the adapter is scanning, or else once it starts to.
+    /// Any device with the same address is replaced.
+    pub fn add_device(&self, device: MockDevice) -> Peripheral {
+        let address = device.properties.address;
+        self.remove_device(address);
+        let peripheral = Peripheral::new(self.manager.clone(), self.scanning.clone(), device);
+        self.devices.insert(address, peripheral.clone());
+        let properties = peripheral.current_properties();
+        peripheral.advertise(&properties);
+        peripheral
+    }
+
+    /// Brings a device described by a [`DeviceDescription`] into range, as with
+    /// [`add_device`](Self::add_device), and starts sending its scheduled notifications. With the
+    /// `tokio-runtime` feature, this must be called from within a Tokio runtime if the device has
+    /// any.
+    #[cfg(feature = "serde")]
+    pub fn add_described_device(&self, description: &DeviceDescription) -> Peripheral {
+        let peripheral = self.add_device(MockDevice::from(description));
+        for schedule in &description.notifications {
+            crate::common::runtime::spawn(peripheral.clone().run_schedule(schedule.clone()));
+        }
+        peripheral
+    }
+
+    /// Takes a simulated device out of range. It is disconnected if it was connected, and a
+    /// [`CentralEvent::DeviceLost`] event is emitted for it if it had been discovered.
+    pub fn remove_device(&self, address: BDAddr) {
+        if let Some((_, peripheral)) = self.devices.remove(&address) {
+            peripheral.remove();
+            if self.manager.has_peripheral(&address) {
+                self.manager.emit(CentralEvent::DeviceLost(address));
+            }
+        }
+    }
+
+    /// Returns the simulated device with the given address, whether or not it has been
+    /// discovered.
+    pub fn device(&self, address: BDAddr) -> Option<Peripheral> {
+        self.devices.get(&address).map(|device| device.clone())
+    }
+
+    /// Returns the advertisement started with [`Central::start_advertising`], if it hasn't been
+    /// stopped.
+    pub fn advertisement(&self) -> Option<Advertisement> {
+        self.advertisement.lock().unwrap().clone()
+    }
+
+    fn devices_in_range(&self) -> Vec<Peripheral> {
+        self.devices
+            .iter()
+            .map(|device| device.value().clone())
+            .collect()
+    }
+
+    /// Adds a device to the discovered peripherals without it advertising, as when connecting to
+    /// a known address.
+    fn known_peripheral(&self, device: &Peripheral) -> Peripheral {
+        let (peripheral, _) = self
+            .manager
+            .peripheral_or_insert_with(device.address(), || device.clone());
+        peripheral
+    }
+
+    fn check_powered(&self) -> Result<()> {
+        if *self.state.lock().unwrap() == AdapterState::PoweredOn {
+            Ok(())
+        } else {
+            Err(Error::Other(
+                "The mock adapter is powered off".to_string().into(),
+            ))
+        }
+    }
+}
+
+impl Debug for Adapter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Adapter")
+            .field("manager", &self.manager)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl Central for Adapter {
+    type Peripheral = Peripheral;
+
+    async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        Ok(self.manager.event_stream(EventFilter::default()))
+    }
+
+    async fn events_filtered(
+        &self,
+        filter: EventFilter,
+    ) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        Ok(self.manager.event_stream(filter))
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.check_powered()?;
+        self.manager.set_scan_filter(filter);
+        if !self.scanning.swap(true, Ordering::Relaxed) {
+            self.manager.emit(CentralEvent::ScanStarted);
+        }
+        // Every device in range advertises once when scanning starts.
+        for device in self.devices_in_range() {
+            let properties = device.current_properties();
+            device.advertise(&properties);
+        }
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        if self.scanning.swap(false, Ordering::Relaxed) {
+            self.manager.emit(CentralEvent::ScanStopped);
+        }
+        Ok(())
+    }
+
+    async fn is_scanning(&self) -> Result<bool> {
+        Ok(self.scanning.load(Ordering::Relaxed))
+    }
+
+    /// Turning the adapter off stops scanning and closes every connection.
+    async fn set_powered(&self, powered: bool) -> Result<()> {
+        let state = if powered {
+            AdapterState::PoweredOn
+        } else {
+            AdapterState::PoweredOff
+        };
+        if std::mem::replace(&mut *self.state.lock().unwrap(), state) == state {
+            return Ok(());
+        }
+        if !powered {
+            self.stop_scan().await?;
+            for device in self.devices_in_range() {
+                device.drop_connection(DisconnectReason::AdapterOff);
+            }
+        }
+        self.manager.emit(CentralEvent::AdapterStateChanged(state));
+        Ok(())
+    }
+
+    async fn adapter_state(&self) -> Result<AdapterState> {
+        Ok(*self.state.lock().unwrap())
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Peripheral>> {
+        Ok(self.manager.peripherals())
+    }
+
+    async fn peripheral(&self, address: BDAddr) -> Result<Peripheral> {
+        self.manager
+            .peripheral(address)
+            .ok_or(Error::DeviceNotFound)
+    }
+
+    async fn set_retain_peripherals(&self, retain: bool) -> Result<()> {
+        self.manager.set_retain_peripherals(retain);
+        Ok(())
+    }
+
+    async fn set_peripheral_ttl(&self, ttl: Option<Duration>) -> Result<()> {
+        self.manager.set_peripheral_ttl(ttl);
+        Ok(())
+    }
+
+    async fn set_max_peripherals(&self, max: Option<usize>) -> Result<()> {
+        self.manager.set_max_peripherals(max);
+        Ok(())
+    }
+
+    async fn add_identity_resolving_key(
+        &self,
+        _identity: BDAddr,
+        _irk: IdentityResolvingKey,
+    ) -> Result<()> {
+        Err(Error::NotSupported(
+            "Resolving private addresses is not supported on the mock backend".to_string(),
+        ))
+    }
+
+    async fn remove_identity_resolving_key(&self, _identity: BDAddr) -> Result<()> {
+        Err(Error::NotSupported(
+            "Resolving private addresses is not supported on the mock backend".to_string(),
+        ))
+    }
+
+    /// Only devices which are in range can be added.
+    async fn add_peripheral(&self, address: BDAddr) -> Result<Peripheral> {
+        let device = self.device(address).ok_or(Error::DeviceNotFound)?;
+        Ok(self.known_peripheral(&device))
+    }
+
+    async fn bonded_peripherals(&self) -> Result<Vec<Peripheral>> {
+        Ok(self
+            .devices_in_range()
+            .iter()
+            .filter(|device| device.is_bonded())
+            .map(|device| self.known_peripheral(device))
+            .collect())
+    }
+
+    async fn start_advertising(&self, advertisement: &Advertisement) -> Result<()> {
+        self.check_powered()?;
+        *self.advertisement.lock().unwrap() = Some(advertisement.clone());
+        Ok(())
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        *self.advertisement.lock().unwrap() = None;
+        Ok(())
+    }
+
+    async fn sync_periodic_advertising(
+        &self,
+        _address: BDAddr,
+        _sid: u8,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeriodicAdvertisement> + Send>>> {
+        Err(Error::NotSupported(
+            "Periodic advertising sync is not supported on the mock backend".to_string(),
+        ))
+    }
+
+    async fn metrics(&self) -> Result<AdapterMetrics> {
+        Ok(self.manager.metrics().snapshot())
+    }
+}
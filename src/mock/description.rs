@@ -0,0 +1,455 @@
+//! Descriptions of simulated devices which can be loaded with `serde`, from any format it
+//! supports such as JSON or TOML, so that tests can model real hardware in a data file.
+//!
+//! UUIDs are written as 4 or 8 hex digits for short UUIDs, or in the usual hyphenated form.
+//! Values are written as an array of bytes, a string of hex digits (which may be separated by
+//! spaces), or a table like `{ "utf8": "text" }`. Characteristic properties are written as a list
+//! of the lowercase names of the [`CharPropFlags`], like `["read", "notify"]`.
+//!
+//! ```
+//! use btleplug::mock::DeviceDescription;
+//!
+//! let description: DeviceDescription = serde_json::from_str(r#"{
+//!     "address": "11:22:33:44:55:66",
+//!     "local_name": "Polar H10",
+//!     "services": ["180d"],
+//!     "gatt": [{
+//!         "uuid": "180d",
+//!         "characteristics": [
+//!             { "uuid": "2a37", "properties": ["notify"] },
+//!             { "uuid": "2a38", "properties": ["read"], "value": [1] }
+//!         ]
+//!     }],
+//!     "notifications": [
+//!         { "characteristic": "2a37", "values": ["00 48", "00 4a"], "interval_ms": 1000 }
+//!     ]
+//! }"#).unwrap();
+//! assert_eq!(description.local_name.as_deref(), Some("Polar H10"));
+//! ```
+
+use super::{MockCharacteristic, MockDescriptor, MockDevice, MockService};
+use crate::api::{
+    bleuuid::{uuid_from_u16, uuid_from_u32},
+    AddressType, BDAddr, CharPropFlags, PeripheralProperties,
+};
+use serde_cr::{
+    de::{Deserializer, Error as DeError, MapAccess, SeqAccess, Visitor},
+    Deserialize,
+};
+use std::fmt;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A simulated device, as added to a mock [`Adapter`](super::Adapter) with
+/// [`add_described_device`](super::Adapter::add_described_device).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub struct DeviceDescription {
+    pub address: BDAddr,
+    #[serde(default)]
+    pub address_type: Option<AddressType>,
+    #[serde(default)]
+    pub local_name: Option<String>,
+    #[serde(default)]
+    pub tx_power_level: Option<i8>,
+    #[serde(default)]
+    pub rssi: Option<i16>,
+    #[serde(default)]
+    pub manufacturer_data: Vec<ManufacturerDataDescription>,
+    #[serde(default)]
+    pub service_data: Vec<ServiceDataDescription>,
+    /// The UUIDs of the services which the device advertises.
+    #[serde(default, deserialize_with = "uuids")]
+    pub services: Vec<Uuid>,
+    /// The GATT database of the device.
+    #[serde(default)]
+    pub gatt: Vec<ServiceDescription>,
+    #[serde(default = "default_mtu")]
+    pub mtu: u16,
+    #[serde(default)]
+    pub bonded: bool,
+    /// Values which the device notifies on its own while it is connected.
+    #[serde(default)]
+    pub notifications: Vec<NotificationSchedule>,
+}
+
+/// Manufacturer specific data advertised by a [`DeviceDescription`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub struct ManufacturerDataDescription {
+    pub company_id: u16,
+    #[serde(deserialize_with = "value")]
+    pub data: Vec<u8>,
+}
+
+/// Service data advertised by a [`DeviceDescription`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub struct ServiceDataDescription {
+    #[serde(deserialize_with = "uuid")]
+    pub uuid: Uuid,
+    #[serde(deserialize_with = "value")]
+    pub data: Vec<u8>,
+}
+
+/// A service in the GATT database of a [`DeviceDescription`], as for a [`MockService`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub struct ServiceDescription {
+    #[serde(deserialize_with = "uuid")]
+    pub uuid: Uuid,
+    #[serde(default = "default_primary")]
+    pub primary: bool,
+    #[serde(default)]
+    pub characteristics: Vec<CharacteristicDescription>,
+    #[serde(default, deserialize_with = "uuids")]
+    pub included_services: Vec<Uuid>,
+}
+
+/// A characteristic in the GATT database of a [`DeviceDescription`], as for a
+/// [`MockCharacteristic`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub struct CharacteristicDescription {
+    #[serde(deserialize_with = "uuid")]
+    pub uuid: Uuid,
+    #[serde(default, deserialize_with = "properties")]
+    pub properties: CharPropFlags,
+    #[serde(default, deserialize_with = "value")]
+    pub value: Vec<u8>,
+    #[serde(default)]
+    pub descriptors: Vec<DescriptorDescription>,
+}
+
+/// A descriptor of a [`CharacteristicDescription`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub struct DescriptorDescription {
+    #[serde(deserialize_with = "uuid")]
+    pub uuid: Uuid,
+    #[serde(default, deserialize_with = "value")]
+    pub value: Vec<u8>,
+}
+
+/// Values which a simulated device notifies for one of its characteristics, one each interval
+/// while it is connected. As with a real device, values are only received if the characteristic
+/// is subscribed to when they are sent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub struct NotificationSchedule {
+    #[serde(deserialize_with = "uuid")]
+    pub characteristic: Uuid,
+    #[serde(deserialize_with = "values")]
+    pub values: Vec<Vec<u8>>,
+    /// The time before each value is sent, in milliseconds.
+    pub interval_ms: u64,
+    /// Whether to start again from the first value after sending the last. Defaults to true.
+    #[serde(default = "default_repeat")]
+    pub repeat: bool,
+}
+
+impl NotificationSchedule {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+impl From<&DeviceDescription> for MockDevice {
+    fn from(description: &DeviceDescription) -> Self {
+        let properties = PeripheralProperties {
+            address: description.address,
+            address_type: description.address_type.clone(),
+            local_name: description.local_name.clone(),
+            tx_power_level: description.tx_power_level,
+            rssi: description.rssi,
+            manufacturer_data: description
+                .manufacturer_data
+                .iter()
+                .map(|data| (data.company_id, data.data.clone()))
+                .collect(),
+            service_data: description
+                .service_data
+                .iter()
+                .map(|data| (data.uuid, data.data.clone()))
+                .collect(),
+            services: description.services.clone(),
+            ..Default::default()
+        };
+        MockDevice {
+            properties,
+            services: description.gatt.iter().map(MockService::from).collect(),
+            mtu: description.mtu,
+            bonded: description.bonded,
+        }
+    }
+}
+
+impl From<&ServiceDescription> for MockService {
+    fn from(description: &ServiceDescription) -> Self {
+        MockService {
+            uuid: description.uuid,
+            primary: description.primary,
+            characteristics: description
+                .characteristics
+                .iter()
+                .map(MockCharacteristic::from)
+                .collect(),
+            included_services: description.included_services.clone(),
+        }
+    }
+}
+
+impl From<&CharacteristicDescription> for MockCharacteristic {
+    fn from(description: &CharacteristicDescription) -> Self {
+        MockCharacteristic {
+            uuid: description.uuid,
+            properties: description.properties,
+            value: description.value.clone(),
+            descriptors: description
+                .descriptors
+                .iter()
+                .map(|descriptor| MockDescriptor {
+                    uuid: descriptor.uuid,
+                    value: descriptor.value.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn default_mtu() -> u16 {
+    MockDevice::default().mtu
+}
+
+fn default_primary() -> bool {
+    true
+}
+
+fn default_repeat() -> bool {
+    true
+}
+
+fn parse_uuid(s: &str) -> Option<Uuid> {
+    let is_hex = s.bytes().all(|b| b.is_ascii_hexdigit());
+    match s.len() {
+        4 if is_hex => u16::from_str_radix(s, 16).ok().map(uuid_from_u16),
+        8 if is_hex => u32::from_str_radix(s, 16).ok().map(uuid_from_u32),
+        _ => Uuid::parse_str(s).ok(),
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.len() % 2 != 0 || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// A value, which may be written as an array of bytes, a string of hex digits or a table with a
+/// UTF-8 string.
+struct Value(Vec<u8>);
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "an array of bytes, a string of hex digits, or a table like `{{ utf8 = \"text\" }}`"
+        )
+    }
+
+    fn visit_str<E: DeError>(self, v: &str) -> Result<Value, E> {
+        parse_hex(v)
+            .map(Value)
+            .ok_or_else(|| E::custom(format!("invalid hex value `{}`", v)))
+    }
+
+    fn visit_bytes<E: DeError>(self, v: &[u8]) -> Result<Value, E> {
+        Ok(Value(v.to_vec()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut bytes = Vec::new();
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(Value(bytes))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut text: Option<String> = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key != "utf8" || text.is_some() {
+                return Err(A::Error::custom(format!(
+                    "unexpected key `{}` in value",
+                    key
+                )));
+            }
+            text = Some(map.next_value()?);
+        }
+        text.map(|text| Value(text.into_bytes()))
+            .ok_or_else(|| A::Error::missing_field("utf8"))
+    }
+}
+
+fn uuid<'de, D: Deserializer<'de>>(d: D) -> Result<Uuid, D::Error> {
+    let s = String::deserialize(d)?;
+    parse_uuid(&s).ok_or_else(|| D::Error::custom(format!("invalid UUID `{}`", s)))
+}
+
+fn uuids<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Uuid>, D::Error> {
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|s| parse_uuid(s).ok_or_else(|| D::Error::custom(format!("invalid UUID `{}`", s))))
+        .collect()
+}
+
+fn value<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+    Ok(Value::deserialize(d)?.0)
+}
+
+fn values<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Vec<u8>>, D::Error> {
+    Ok(Vec::<Value>::deserialize(d)?
+        .into_iter()
+        .map(|value| value.0)
+        .collect())
+}
+
+fn properties<'de, D: Deserializer<'de>>(d: D) -> Result<CharPropFlags, D::Error> {
+    let mut properties = CharPropFlags::default();
+    for name in Vec::<String>::deserialize(d)? {
+        properties |= match name.as_str() {
+            "broadcast" => CharPropFlags::BROADCAST,
+            "read" => CharPropFlags::READ,
+            "write_without_response" => CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            "write" => CharPropFlags::WRITE,
+            "notify" => CharPropFlags::NOTIFY,
+            "indicate" => CharPropFlags::INDICATE,
+            "authenticated_signed_writes" => CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+            "extended_properties" => CharPropFlags::EXTENDED_PROPERTIES,
+            "reliable_write" => CharPropFlags::RELIABLE_WRITE,
+            "writable_auxiliaries" => CharPropFlags::WRITABLE_AUXILIARIES,
+            _ => {
+                return Err(D::Error::custom(format!(
+                    "unknown characteristic property `{}`",
+                    name
+                )))
+            }
+        };
+    }
+    Ok(properties)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Central, Peripheral as _, ScanFilter};
+    use crate::mock::Adapter;
+    use futures::stream::StreamExt;
+
+    const SMART_LOCK: &str = r#"{
+        "address": "C0:FF:EE:00:00:01",
+        "address_type": "Random",
+        "local_name": "Lock",
+        "manufacturer_data": [{ "company_id": 89, "data": "01 02" }],
+        "gatt": [{
+            "uuid": "6e400001-b5a3-f393-e0a9-e50e24dcca9e",
+            "characteristics": [{
+                "uuid": "6e400002-b5a3-f393-e0a9-e50e24dcca9e",
+                "properties": ["write", "write_without_response"],
+                "descriptors": [{ "uuid": "2901", "value": { "utf8": "Command" } }]
+            }]
+        }]
+    }"#;
+
+    #[test]
+    fn parse_device() {
+        let description: DeviceDescription = serde_json::from_str(SMART_LOCK).unwrap();
+        let device = MockDevice::from(&description);
+        assert_eq!(device.properties.address_type, Some(AddressType::Random));
+        assert_eq!(device.properties.manufacturer_data[&89], vec![0x01, 0x02]);
+        assert_eq!(device.mtu, 23);
+        let service = &device.services[0];
+        assert!(service.primary);
+        let characteristic = &service.characteristics[0];
+        assert_eq!(
+            characteristic.properties,
+            CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE
+        );
+        assert_eq!(characteristic.descriptors[0].uuid, uuid_from_u16(0x2901));
+        assert_eq!(characteristic.descriptors[0].value, b"Command".to_vec());
+    }
+
+    #[test]
+    fn parse_uuids() {
+        assert_eq!(parse_uuid("180D"), Some(uuid_from_u16(0x180d)));
+        assert_eq!(parse_uuid("0000fe59"), Some(uuid_from_u32(0xfe59)));
+        assert_eq!(parse_uuid("+18d"), None);
+        assert_eq!(parse_uuid("180"), None);
+    }
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(parse_hex("00 6c0e"), Some(vec![0x00, 0x6c, 0x0e]));
+        assert_eq!(parse_hex("6c0"), None);
+        assert_eq!(parse_hex("zz"), None);
+    }
+
+    #[test]
+    fn reject_unknown_property() {
+        let result: Result<CharacteristicDescription, _> =
+            serde_json::from_str(r#"{ "uuid": "2a37", "properties": ["listen"] }"#);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("unknown characteristic property `listen`"));
+    }
+
+    #[tokio::test]
+    async fn scheduled_notifications() {
+        let description: DeviceDescription = serde_json::from_str(
+            r#"{
+                "address": "11:22:33:44:55:66",
+                "gatt": [{
+                    "uuid": "180d",
+                    "characteristics": [{ "uuid": "2a37", "properties": ["notify"] }]
+                }],
+                "notifications": [
+                    { "characteristic": "2a37", "values": ["0048", "004a"], "interval_ms": 10 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let adapter = Adapter::new();
+        adapter.add_described_device(&description);
+        adapter.start_scan(ScanFilter::default()).await.unwrap();
+        let peripheral = adapter.peripheral(description.address).await.unwrap();
+        peripheral.connect().await.unwrap();
+        let characteristics = peripheral.discover_characteristics().await.unwrap();
+        peripheral.subscribe(&characteristics[0]).await.unwrap();
+        let notifications = peripheral.notifications().await.unwrap();
+
+        let values: Vec<_> = notifications
+            .take(3)
+            .map(|notification| notification.value)
+            .collect()
+            .await;
+        assert_eq!(
+            values,
+            vec![&[0x00, 0x48][..], &[0x00, 0x4a], &[0x00, 0x48]]
+        );
+    }
+}
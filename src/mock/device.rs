@@ -0,0 +1,85 @@
+//! Descriptions of the simulated devices and GATT databases served by the mock backend.
+
+use crate::api::{CharPropFlags, PeripheralProperties, WriteType};
+use uuid::Uuid;
+
+/// A simulated device, added to a mock [`Adapter`](super::Adapter) with
+/// [`add_device`](super::Adapter::add_device).
+#[derive(Debug, Clone)]
+pub struct MockDevice {
+    /// What the device advertises. `properties.address` is the address of the device.
+    pub properties: PeripheralProperties,
+    /// The GATT database of the device, which can be discovered once connected.
+    pub services: Vec<MockService>,
+    /// The ATT MTU of connections to the device. Defaults to 23, the minimum.
+    pub mtu: u16,
+    /// Whether the device is bonded with the adapter, so that it is returned by
+    /// [`Central::bonded_peripherals`](crate::api::Central::bonded_peripherals).
+    pub bonded: bool,
+}
+
+impl Default for MockDevice {
+    fn default() -> Self {
+        MockDevice {
+            properties: PeripheralProperties::default(),
+            services: Vec::new(),
+            mtu: 23,
+            bonded: false,
+        }
+    }
+}
+
+/// A service in the GATT database of a [`MockDevice`].
+#[derive(Debug, Clone)]
+pub struct MockService {
+    pub uuid: Uuid,
+    /// Whether this is a primary service. Defaults to true.
+    pub primary: bool,
+    pub characteristics: Vec<MockCharacteristic>,
+    /// The UUIDs of the other services of the device which this service includes.
+    pub included_services: Vec<Uuid>,
+}
+
+impl Default for MockService {
+    fn default() -> Self {
+        MockService {
+            uuid: Uuid::nil(),
+            primary: true,
+            characteristics: Vec::new(),
+            included_services: Vec::new(),
+        }
+    }
+}
+
+/// A characteristic in the GATT database of a [`MockDevice`]. Reads, writes and subscriptions
+/// are only allowed if its `properties` allow them, as on a real device.
+#[derive(Debug, Clone, Default)]
+pub struct MockCharacteristic {
+    pub uuid: Uuid,
+    pub properties: CharPropFlags,
+    /// The initial value of the characteristic.
+    pub value: Vec<u8>,
+    /// The descriptors of the characteristic. A Characteristic User Description or Characteristic
+    /// Presentation Format descriptor fills in the corresponding field of the discovered
+    /// [`Characteristic`](crate::api::Characteristic).
+    pub descriptors: Vec<MockDescriptor>,
+}
+
+/// A descriptor of a [`MockCharacteristic`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MockDescriptor {
+    pub uuid: Uuid,
+    pub value: Vec<u8>,
+}
+
+/// A write to a characteristic of a mock peripheral, as returned by
+/// [`Peripheral::take_writes`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MockWrite {
+    /// The UUID of the characteristic written to.
+    pub characteristic: Uuid,
+    /// The attribute handle of the characteristic written to.
+    pub handle: u16,
+    pub value: Vec<u8>,
+    pub write_type: WriteType,
+}
@@ -0,0 +1,44 @@
+use super::Adapter;
+use crate::{
+    api::{self, ManagerEvent},
+    Result,
+};
+use async_trait::async_trait;
+use futures::stream::{self, Stream};
+use std::pin::Pin;
+
+/// Implementation of [api::Manager](crate::api::Manager) with a single simulated adapter.
+#[derive(Clone, Debug, Default)]
+pub struct Manager {
+    adapter: Adapter,
+}
+
+impl Manager {
+    /// Creates a manager with a new adapter. This is async and fallible only to match
+    /// [`platform::Manager::new`](crate::platform::Manager::new), so that the two can be swapped
+    /// for each other.
+    pub async fn new() -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    /// Returns the simulated adapter, as also returned by `adapters`.
+    pub fn adapter(&self) -> Adapter {
+        self.adapter.clone()
+    }
+}
+
+#[async_trait]
+impl api::Manager for Manager {
+    type Adapter = Adapter;
+
+    async fn adapters(&self) -> Result<Vec<Adapter>> {
+        Ok(vec![self.adapter.clone()])
+    }
+
+    /// The simulated adapter is never removed, and no others are added.
+    async fn adapter_events(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = ManagerEvent<Adapter>> + Send>>> {
+        Ok(Box::pin(stream::pending()))
+    }
+}
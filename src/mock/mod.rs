@@ -0,0 +1,403 @@
+//! An in-memory backend which simulates an adapter and the devices around it, for testing code
+//! which uses btleplug without any Bluetooth hardware. It is enabled by the `mock` feature.
+//!
+//! [`Manager`], [`Adapter`] and [`Peripheral`] implement the same traits as the types in
+//! [`platform`](crate::platform), so code which is generic over the [`api`](crate::api) traits
+//! can be run against simulated devices. Devices are described by a [`MockDevice`], added with
+//! [`Adapter::add_device`], and controlled through the returned [`Peripheral`]. With the `serde`
+//! feature, devices can also be loaded from a data file as a [`DeviceDescription`].
+//!
+//! ```
+//! use btleplug::api::{
+//!     bleuuid::uuid_from_u16, Central, CharPropFlags, Peripheral as _, ScanFilter,
+//! };
+//! use btleplug::mock::{Adapter, MockCharacteristic, MockDevice, MockService};
+//! use futures::stream::StreamExt;
+//! # use std::error::Error;
+//!
+//! # async fn example() -> Result<(), Box<dyn Error>> {
+//! let adapter = Adapter::new();
+//! let mut device = MockDevice::default();
+//! device.properties.address = "11:22:33:44:55:66".parse()?;
+//! device.properties.local_name = Some("Thermometer".to_string());
+//! device.services.push(MockService {
+//!     uuid: uuid_from_u16(0x1809),
+//!     characteristics: vec![MockCharacteristic {
+//!         uuid: uuid_from_u16(0x2A1C),
+//!         properties: CharPropFlags::READ | CharPropFlags::INDICATE,
+//!         value: vec![0x00, 0x6c, 0x0e, 0x00, 0xfe],
+//!         ..Default::default()
+//!     }],
+//!     ..Default::default()
+//! });
+//! let simulated = adapter.add_device(device);
+//!
+//! // Discover and connect to the device as with a real adapter.
+//! adapter.start_scan(ScanFilter::default()).await?;
+//! let peripheral = adapter.peripheral(simulated.address()).await?;
+//! peripheral.connect().await?;
+//! peripheral.discover_characteristics().await?;
+//! let temperature = peripheral.characteristics().into_iter().next().unwrap();
+//! assert_eq!(peripheral.read(&temperature).await?, &[0x00, 0x6c, 0x0e, 0x00, 0xfe][..]);
+//!
+//! // Have the device send a new value.
+//! peripheral.subscribe(&temperature).await?;
+//! let mut notifications = peripheral.notifications().await?;
+//! simulated.notify(temperature.uuid, &[0x00, 0x72, 0x0e, 0x00, 0xfe])?;
+//! assert_eq!(notifications.next().await.unwrap().value, &[0x00, 0x72, 0x0e, 0x00, 0xfe][..]);
+//! # Ok(())
+//! # }
+//! # tokio::runtime::Runtime::new().unwrap().block_on(example()).unwrap();
+//! ```
+
+mod adapter;
+#[cfg(feature = "serde")]
+mod description;
+mod device;
+mod manager;
+mod peripheral;
+
+pub use self::adapter::Adapter;
+#[cfg(feature = "serde")]
+pub use self::description::{
+    CharacteristicDescription, DescriptorDescription, DeviceDescription,
+    ManufacturerDataDescription, NotificationSchedule, ServiceDataDescription, ServiceDescription,
+};
+pub use self::device::{MockCharacteristic, MockDescriptor, MockDevice, MockService, MockWrite};
+pub use self::manager::Manager;
+pub use self::peripheral::Peripheral;
+
+use crate::api::{self, Central};
+use static_assertions::assert_impl_all;
+use std::fmt::Debug;
+
+// Ensure that the exported types implement all the expected traits.
+assert_impl_all!(Adapter: Central, Clone, Debug, Send, Sized, Sync);
+assert_impl_all!(Manager: api::Manager, Clone, Debug, Send, Sized, Sync);
+assert_impl_all!(Peripheral: api::Peripheral, Clone, Debug, Send, Sized, Sync);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        bleuuid::uuid_from_u16, descriptors, AdapterMetrics, BDAddr, CentralEvent, CharPropFlags,
+        CharacteristicMetadata, ConnectionParameters, ConnectionPriority, DisconnectReason,
+        Peripheral as _, ScanFilter, WriteType,
+    };
+    use crate::{AttError, Error};
+    use futures::stream::StreamExt;
+    use std::time::Duration;
+
+    const ADDRESS: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    fn device() -> MockDevice {
+        let mut device = MockDevice::default();
+        device.properties.address = ADDRESS;
+        device.services.push(MockService {
+            uuid: uuid_from_u16(0xfff0),
+            characteristics: vec![
+                MockCharacteristic {
+                    uuid: uuid_from_u16(0xfff1),
+                    properties: CharPropFlags::WRITE,
+                    ..Default::default()
+                },
+                MockCharacteristic {
+                    uuid: uuid_from_u16(0xfff2),
+                    properties: CharPropFlags::READ | CharPropFlags::NOTIFY,
+                    value: vec![0x00],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        });
+        device
+    }
+
+    #[tokio::test]
+    async fn discovered_when_scanning() {
+        let adapter = Adapter::new();
+        let mut events = adapter.events().await.unwrap();
+        adapter.add_device(device());
+        assert!(adapter.peripherals().await.unwrap().is_empty());
+
+        adapter.start_scan(ScanFilter::default()).await.unwrap();
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::ScanStarted)
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceDiscovered(ADDRESS))
+        ));
+        assert!(adapter.peripheral(ADDRESS).await.is_ok());
+
+        adapter.remove_device(ADDRESS);
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceLost(ADDRESS))
+        ));
+        assert!(adapter.peripheral(ADDRESS).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn write_handler_responds() {
+        let adapter = Adapter::new();
+        let simulated = adapter.add_device(device());
+        simulated
+            .set_write_handler(uuid_from_u16(0xfff1), |device, value| {
+                device.notify(uuid_from_u16(0xfff2), value).unwrap();
+                Ok(())
+            })
+            .unwrap();
+
+        let peripheral = adapter.add_peripheral(ADDRESS).await.unwrap();
+        peripheral.connect().await.unwrap();
+        let characteristics = peripheral.discover_characteristics().await.unwrap();
+        let (command, response) = (&characteristics[0], &characteristics[1]);
+        peripheral.subscribe(response).await.unwrap();
+        let mut notifications = peripheral.notifications().await.unwrap();
+
+        peripheral
+            .write(command, &[0x01, 0x02], WriteType::WithResponse)
+            .await
+            .unwrap();
+        assert_eq!(notifications.next().await.unwrap().value, &[0x01, 0x02][..]);
+        assert_eq!(simulated.take_writes()[0].value, vec![0x01, 0x02]);
+        assert_eq!(peripheral.read(response).await.unwrap(), &[0x01, 0x02][..]);
+
+        // The characteristic only allows writes with response.
+        assert!(matches!(
+            peripheral
+                .write(command, &[0x03], WriteType::WithoutResponse)
+                .await,
+            Err(Error::Att(AttError::WRITE_NOT_PERMITTED))
+        ));
+    }
+
+    #[tokio::test]
+    async fn canned_errors_and_disconnection() {
+        let adapter = Adapter::new();
+        let simulated = adapter.add_device(device());
+        let peripheral = adapter.add_peripheral(ADDRESS).await.unwrap();
+        peripheral.connect().await.unwrap();
+        let characteristics = peripheral.discover_characteristics().await.unwrap();
+
+        simulated
+            .set_error(
+                uuid_from_u16(0xfff2),
+                Some(AttError::INSUFFICIENT_AUTHENTICATION),
+            )
+            .unwrap();
+        assert!(matches!(
+            peripheral.read(&characteristics[1]).await,
+            Err(Error::Att(AttError::INSUFFICIENT_AUTHENTICATION))
+        ));
+
+        simulated.disconnect_remotely(DisconnectReason::RemoteTerminated);
+        assert!(!peripheral.is_connected().await.unwrap());
+        assert!(matches!(
+            peripheral.read(&characteristics[1]).await,
+            Err(Error::NotConnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn metrics_count_operations() {
+        let adapter = Adapter::new();
+        let simulated = adapter.add_device(device());
+        adapter.start_scan(ScanFilter::default()).await.unwrap();
+        let peripheral = adapter.peripheral(ADDRESS).await.unwrap();
+        simulated.set_connectable(false);
+        assert!(matches!(
+            peripheral.connect().await,
+            Err(Error::DeviceUnreachable(_))
+        ));
+        simulated.set_connectable(true);
+        peripheral.connect().await.unwrap();
+        let characteristics = peripheral.discover_characteristics().await.unwrap();
+        peripheral.subscribe(&characteristics[1]).await.unwrap();
+        peripheral.read(&characteristics[1]).await.unwrap();
+        simulated.notify(uuid_from_u16(0xfff2), &[0x01]).unwrap();
+
+        let metrics = adapter.metrics().await.unwrap();
+        assert_eq!(metrics.advertisements_received, 1);
+        assert_eq!(metrics.connect_attempts, 2);
+        assert_eq!(metrics.connect_failures, 1);
+        assert_eq!(metrics.notifications_received, 1);
+        assert_eq!(metrics.gatt_latency.count(), 1);
+        assert_eq!(metrics.since(&metrics), AdapterMetrics::default());
+    }
+
+    #[tokio::test]
+    async fn summary_reflects_connection() {
+        let adapter = Adapter::new();
+        adapter.add_device(device());
+        let peripheral = adapter.add_peripheral(ADDRESS).await.unwrap();
+        let summary = peripheral.summary().await.unwrap();
+        assert!(!summary.connected);
+        assert_eq!(summary.service_count, 0);
+        assert_eq!(summary.to_string(), "11:22:33:44:55:66 (unknown)");
+
+        peripheral.connect().await.unwrap();
+        peripheral.discover_characteristics().await.unwrap();
+        let summary = peripheral.summary().await.unwrap();
+        assert!(summary.connected);
+        assert_eq!(summary.service_count, 1);
+    }
+
+    #[tokio::test]
+    async fn characteristic_metadata_read_on_request() {
+        let mut device = device();
+        device.services[0].characteristics[1].descriptors = vec![
+            MockDescriptor {
+                uuid: descriptors::USER_DESCRIPTION,
+                value: b"Level\0".to_vec(),
+            },
+            MockDescriptor {
+                uuid: descriptors::PRESENTATION_FORMAT,
+                value: vec![0x04, 0x00, 0xad, 0x27, 0x01, 0x00, 0x00],
+            },
+        ];
+        let adapter = Adapter::new();
+        adapter.add_device(device);
+        let peripheral = adapter.add_peripheral(ADDRESS).await.unwrap();
+        peripheral.connect().await.unwrap();
+        let characteristics = peripheral.discover_characteristics().await.unwrap();
+
+        let metadata = peripheral
+            .read_characteristic_metadata(&characteristics[1])
+            .await
+            .unwrap();
+        assert_eq!(metadata.user_description.as_deref(), Some("Level"));
+        assert_eq!(metadata.presentation_format.unwrap().unit, 0x27ad);
+        assert_eq!(
+            peripheral
+                .read_characteristic_metadata(&characteristics[0])
+                .await
+                .unwrap(),
+            CharacteristicMetadata::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_connection_parameters_rejected() {
+        let adapter = Adapter::new();
+        adapter.add_device(device());
+        let peripheral = adapter.add_peripheral(ADDRESS).await.unwrap();
+        peripheral.connect().await.unwrap();
+        for priority in &[
+            ConnectionPriority::High,
+            ConnectionPriority::Balanced,
+            ConnectionPriority::LowPower,
+        ] {
+            peripheral
+                .request_connection_priority(*priority)
+                .await
+                .unwrap();
+        }
+
+        let valid = ConnectionParameters::from(ConnectionPriority::Balanced);
+        let invalid = [
+            ConnectionParameters {
+                min_interval: Duration::from_millis(5),
+                ..valid
+            },
+            ConnectionParameters {
+                min_interval: valid.max_interval + Duration::from_millis(10),
+                ..valid
+            },
+            ConnectionParameters {
+                latency: 500,
+                ..valid
+            },
+            ConnectionParameters {
+                supervision_timeout: Duration::from_secs(40),
+                ..valid
+            },
+            // Too short for the peripheral to skip 10 connection events.
+            ConnectionParameters {
+                latency: 10,
+                supervision_timeout: Duration::from_millis(500),
+                ..valid
+            },
+        ];
+        for parameters in &invalid {
+            assert!(
+                matches!(
+                    peripheral.set_connection_parameters(parameters).await,
+                    Err(Error::Other(_))
+                ),
+                "{:?}",
+                parameters
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn oldest_peripheral_evicted_over_limit() {
+        const OTHER_ADDRESS: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x77]);
+        let adapter = Adapter::new();
+        adapter.set_max_peripherals(Some(1)).await.unwrap();
+        adapter.start_scan(ScanFilter::default()).await.unwrap();
+        let mut events = adapter.events().await.unwrap();
+        adapter.add_device(device());
+        let mut other = device();
+        other.properties.address = OTHER_ADDRESS;
+        adapter.add_device(other);
+
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceDiscovered(ADDRESS))
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceLost(ADDRESS))
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(CentralEvent::DeviceDiscovered(OTHER_ADDRESS))
+        ));
+        let peripherals = adapter.peripherals().await.unwrap();
+        assert_eq!(peripherals.len(), 1);
+        assert_eq!(peripherals[0].address(), OTHER_ADDRESS);
+    }
+
+    #[tokio::test]
+    async fn bonded_peripheral_kept_but_counted_over_limit() {
+        const SECOND_ADDRESS: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x77]);
+        const THIRD_ADDRESS: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x88]);
+        let adapter = Adapter::new();
+        adapter.set_max_peripherals(Some(2)).await.unwrap();
+        adapter.start_scan(ScanFilter::default()).await.unwrap();
+        let mut events = adapter.events().await.unwrap();
+        // The bonded device is the oldest, so the next oldest is evicted instead.
+        adapter.add_device(MockDevice {
+            bonded: true,
+            ..device()
+        });
+        let mut second = device();
+        second.properties.address = SECOND_ADDRESS;
+        adapter.add_device(second);
+        let mut third = device();
+        third.properties.address = THIRD_ADDRESS;
+        adapter.add_device(third);
+
+        let lost = loop {
+            match events.next().await {
+                Some(CentralEvent::DeviceLost(address)) => break address,
+                Some(_) => {}
+                None => panic!("No peripheral was evicted"),
+            }
+        };
+        assert_eq!(lost, SECOND_ADDRESS);
+        let mut addresses: Vec<BDAddr> = adapter
+            .peripherals()
+            .await
+            .unwrap()
+            .iter()
+            .map(|peripheral| peripheral.address())
+            .collect();
+        addresses.sort_by_key(|address| u64::from(*address));
+        assert_eq!(addresses, vec![ADDRESS, THIRD_ADDRESS]);
+    }
+}
@@ -0,0 +1,939 @@
+#[cfg(feature = "serde")]
+use super::NotificationSchedule;
+use super::{MockDescriptor, MockDevice, MockService, MockWrite};
+use crate::{
+    api::{
+        self, BDAddr, CentralEvent, CharPropFlags, Characteristic, ConnectOptions,
+        ConnectionParameters, ConnectionPriority, Descriptor, DisconnectReason, L2capChannel,
+        PairingAgent, PeripheralOptions, PeripheralProperties, PeripheralSummary, Phy,
+        PropertyChanges, SecurityLevel, Service, ValueNotification, WriteType,
+    },
+    common::{
+        adapter_manager::AdapterManager, subscriptions::SubscriptionCounts, util, watched::Watched,
+    },
+    AttError, Error, HciError, Result,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::stream::Stream;
+#[cfg(feature = "serde")]
+use log::debug;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Debug, Display, Formatter},
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+type ReadHandler = Arc<dyn Fn(&Peripheral) -> std::result::Result<Vec<u8>, AttError> + Send + Sync>;
+type WriteHandler =
+    Arc<dyn Fn(&Peripheral, &[u8]) -> std::result::Result<(), AttError> + Send + Sync>;
+
+/// A characteristic of the simulated device, along with its current value.
+struct Attribute {
+    characteristic: Characteristic,
+    value: Vec<u8>,
+    descriptors: Vec<MockDescriptor>,
+    error: Option<AttError>,
+    read_handler: Option<ReadHandler>,
+    write_handler: Option<WriteHandler>,
+}
+
+/// The state of the simulated device, shared by every clone of its [`Peripheral`].
+struct DeviceState {
+    properties: PeripheralProperties,
+    services: Vec<Service>,
+    /// The characteristics of the device by attribute handle.
+    attributes: BTreeMap<u16, Attribute>,
+    mtu: u16,
+    mtu_reported: bool,
+    bonded: bool,
+    /// Whether the device has been removed from the adapter, after which it can no longer
+    /// advertise or be connected to.
+    removed: bool,
+    connectable: bool,
+    connected: bool,
+    discovered: BTreeSet<Characteristic>,
+    writes: Vec<MockWrite>,
+}
+
+impl DeviceState {
+    fn new(device: MockDevice) -> Self {
+        let (services, attributes) = build_database(device.services);
+        DeviceState {
+            properties: device.properties,
+            services,
+            attributes,
+            mtu: device.mtu,
+            mtu_reported: true,
+            bonded: device.bonded,
+            removed: false,
+            connectable: true,
+            connected: false,
+            discovered: BTreeSet::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    fn check_connected(&self) -> Result<()> {
+        if self.connected {
+            Ok(())
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
+    /// Returns the characteristic with the given handle, failing if the device isn't connected or
+    /// has been set to fail requests for it.
+    fn attribute(&mut self, characteristic: &Characteristic) -> Result<&mut Attribute> {
+        self.check_connected()?;
+        let attribute = self
+            .attributes
+            .get_mut(&characteristic.handle)
+            .ok_or_else(|| {
+                Error::Other(
+                    format!(
+                        "Characteristic with UUID {} not found.",
+                        characteristic.uuid
+                    )
+                    .into(),
+                )
+            })?;
+        match attribute.error {
+            Some(error) => Err(Error::Att(error)),
+            None => Ok(attribute),
+        }
+    }
+
+    /// Returns the first characteristic with the given UUID, for the methods which control the
+    /// simulated device.
+    fn attribute_by_uuid(&mut self, uuid: Uuid) -> Result<&mut Attribute> {
+        self.attributes
+            .values_mut()
+            .find(|attribute| attribute.characteristic.uuid == uuid)
+            .ok_or_else(|| {
+                Error::Other(format!("Characteristic with UUID {} not found.", uuid).into())
+            })
+    }
+
+    fn descriptor(&mut self, descriptor: &Descriptor) -> Result<&mut MockDescriptor> {
+        self.check_connected()?;
+        self.attributes
+            .get_mut(&descriptor.characteristic_handle)
+            .and_then(|attribute| {
+                attribute
+                    .descriptors
+                    .iter_mut()
+                    .find(|mock_descriptor| mock_descriptor.uuid == descriptor.uuid)
+            })
+            .ok_or_else(|| {
+                Error::Other(format!("Descriptor with UUID {} not found.", descriptor.uuid).into())
+            })
+    }
+}
+
+/// Assigns attribute handles to the services, characteristics and descriptors of a simulated
+/// device in the order they are listed, as a device would.
+fn build_database(mock_services: Vec<MockService>) -> (Vec<Service>, BTreeMap<u16, Attribute>) {
+    let mut handle = 0;
+    let mut services = Vec::new();
+    let mut attributes = BTreeMap::new();
+    for mock_service in mock_services {
+        handle += 1;
+        let mut characteristics = BTreeSet::new();
+        for mock_characteristic in mock_service.characteristics {
+            handle += 1;
+            let characteristic = Characteristic {
+                uuid: mock_characteristic.uuid,
+                service_uuid: mock_service.uuid,
+                handle,
+                properties: mock_characteristic.properties,
+            };
+            handle += mock_characteristic.descriptors.len() as u16;
+            characteristics.insert(characteristic.clone());
+            attributes.insert(
+                characteristic.handle,
+                Attribute {
+                    characteristic,
+                    value: mock_characteristic.value,
+                    descriptors: mock_characteristic.descriptors,
+                    error: None,
+                    read_handler: None,
+                    write_handler: None,
+                },
+            );
+        }
+        services.push(Service {
+            uuid: mock_service.uuid,
+            primary: mock_service.primary,
+            characteristics,
+            included_services: mock_service.included_services.into_iter().collect(),
+        });
+    }
+    (services, attributes)
+}
+
+/// Implementation of [api::Peripheral](crate::api::Peripheral) for a simulated device. Besides
+/// the trait methods, it has methods to control the device, such as to send notifications or to
+/// make requests fail.
+#[derive(Clone)]
+pub struct Peripheral {
+    adapter: AdapterManager<Self>,
+    address: BDAddr,
+    scanning: Arc<AtomicBool>,
+    state: Arc<Mutex<DeviceState>>,
+    properties_watch: Watched<PeripheralProperties>,
+    notification_senders: Arc<Mutex<Vec<UnboundedSender<ValueNotification>>>>,
+    subscriptions: Arc<Mutex<SubscriptionCounts>>,
+    options: Arc<Mutex<PeripheralOptions>>,
+    dropped_notifications: Arc<AtomicU64>,
+}
+
+impl Peripheral {
+    pub(crate) fn new(
+        adapter: AdapterManager<Self>,
+        scanning: Arc<AtomicBool>,
+        device: MockDevice,
+    ) -> Self {
+        let address = device.properties.address;
+        let properties_watch = Watched::new(device.properties.clone());
+        Peripheral {
+            adapter,
+            address,
+            scanning,
+            state: Arc::new(Mutex::new(DeviceState::new(device))),
+            properties_watch,
+            notification_senders: Arc::new(Mutex::new(Vec::new())),
+            subscriptions: Arc::new(Mutex::new(SubscriptionCounts::default())),
+            options: Arc::new(Mutex::new(PeripheralOptions::default())),
+            dropped_notifications: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub(crate) fn current_properties(&self) -> PeripheralProperties {
+        self.state.lock().unwrap().properties.clone()
+    }
+
+    pub(crate) fn is_bonded(&self) -> bool {
+        self.state.lock().unwrap().bonded
+    }
+
+    /// Takes the device out of range, closing its connection if it has one.
+    pub(crate) fn remove(&self) {
+        self.state.lock().unwrap().removed = true;
+        self.drop_connection(DisconnectReason::SupervisionTimeout);
+    }
+
+    /// Has the device send an advertisement, if the adapter is scanning. `previous` is what the
+    /// device advertised before, to tell which properties changed.
+    pub(crate) fn advertise(&self, previous: &PeripheralProperties) {
+        if !self.scanning.load(Ordering::Relaxed) {
+            return;
+        }
+        let (properties, bonded) = {
+            let mut state = self.state.lock().unwrap();
+            if state.removed {
+                return;
+            }
+            state.properties.discovery_count += 1;
+            state.properties.last_seen = Some(SystemTime::now());
+            (state.properties.clone(), state.bonded)
+        };
+        self.adapter.metrics().advertisement_received();
+        self.properties_watch.publish(properties.clone());
+        let address = self.address;
+        let (_, added) = self
+            .adapter
+            .peripheral_or_insert_with(address, || self.clone());
+        // Like BlueZ, keep bonded devices rather than evicting them.
+        self.adapter.set_paired(address, bonded);
+        if added {
+            self.adapter
+                .emit_advertisement(&properties, CentralEvent::DeviceDiscovered(address));
+        } else {
+            let changes = PropertyChanges::between(previous, &properties);
+            self.adapter.emit_advertisement(
+                &properties,
+                CentralEvent::DeviceUpdated { address, changes },
+            );
+        }
+        if !properties.manufacturer_data.is_empty() {
+            self.adapter.emit_advertisement(
+                &properties,
+                CentralEvent::ManufacturerDataAdvertisement {
+                    address,
+                    manufacturer_data: properties.manufacturer_data.clone(),
+                },
+            );
+        }
+        if !properties.service_data.is_empty() {
+            self.adapter.emit_advertisement(
+                &properties,
+                CentralEvent::ServiceDataAdvertisement {
+                    address,
+                    service_data: properties.service_data.clone(),
+                },
+            );
+        }
+        if !properties.services.is_empty() {
+            self.adapter.emit_advertisement(
+                &properties,
+                CentralEvent::ServicesAdvertisement {
+                    address,
+                    services: properties.services.clone(),
+                },
+            );
+        }
+        if let Some(rssi) = properties.rssi {
+            self.adapter
+                .emit_advertisement(&properties, CentralEvent::RssiUpdate { address, rssi });
+        }
+    }
+
+    /// Sends the values of the schedule in turn while the device is connected, until the device is
+    /// removed or the values run out.
+    #[cfg(feature = "serde")]
+    pub(crate) async fn run_schedule(self, schedule: NotificationSchedule) {
+        let mut index = 0;
+        while index < schedule.values.len() {
+            crate::common::runtime::sleep(schedule.interval()).await;
+            let connected = {
+                let state = self.state.lock().unwrap();
+                if state.removed {
+                    return;
+                }
+                state.connected
+            };
+            if !connected {
+                continue;
+            }
+            if let Err(e) = self.notify(schedule.characteristic, &schedule.values[index]) {
+                debug!("Stopping notification schedule of {}: {}", self.address, e);
+                return;
+            }
+            index += 1;
+            if schedule.repeat && index == schedule.values.len() {
+                index = 0;
+            }
+        }
+    }
+
+    /// Closes the connection, if there is one, ending the notification streams.
+    pub(crate) fn drop_connection(&self, reason: DisconnectReason) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.connected {
+                return;
+            }
+            state.connected = false;
+        }
+        self.subscriptions.lock().unwrap().disconnected();
+        // Dropping the senders ends the streams returned by `notifications`.
+        self.notification_senders.lock().unwrap().clear();
+        self.adapter
+            .emit(CentralEvent::DeviceDisconnected(self.address, reason));
+    }
+
+    /// Changes what the device advertises. If the adapter is scanning, this is received as a new
+    /// advertisement. The address of the device can't be changed.
+    pub fn set_properties(&self, mut properties: PeripheralProperties) {
+        properties.address = self.address;
+        let previous = std::mem::replace(&mut self.state.lock().unwrap().properties, properties);
+        if self.scanning.load(Ordering::Relaxed) {
+            self.advertise(&previous);
+        } else {
+            self.properties_watch
+                .publish(self.state.lock().unwrap().properties.clone());
+        }
+    }
+
+    /// Replaces the GATT database of the device, as when it switches into a firmware update
+    /// mode. If it is connected, a [`CentralEvent::ServicesChanged`] event is emitted, and the
+    /// characteristics need to be discovered again.
+    pub fn set_services(&self, services: Vec<MockService>) {
+        let connected = {
+            let mut state = self.state.lock().unwrap();
+            let (services, attributes) = build_database(services);
+            state.services = services;
+            state.attributes = attributes;
+            state.discovered.clear();
+            state.connected
+        };
+        if connected {
+            self.adapter
+                .emit(CentralEvent::ServicesChanged(self.address));
+        }
+    }
+
+    /// Sets the value of the first characteristic with the given UUID, without notifying it.
+    pub fn set_value(&self, uuid: Uuid, value: &[u8]) -> Result<()> {
+        self.state.lock().unwrap().attribute_by_uuid(uuid)?.value = value.to_vec();
+        Ok(())
+    }
+
+    /// Returns the current value of the first characteristic with the given UUID, as last set or
+    /// written.
+    pub fn value(&self, uuid: Uuid) -> Result<Vec<u8>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .attribute_by_uuid(uuid)?
+            .value
+            .clone())
+    }
+
+    /// Sets the value of the first characteristic with the given UUID, and sends it as a
+    /// notification if the characteristic is subscribed to.
+    pub fn notify(&self, uuid: Uuid, value: &[u8]) -> Result<()> {
+        let characteristic = {
+            let mut state = self.state.lock().unwrap();
+            let connected = state.connected;
+            let attribute = state.attribute_by_uuid(uuid)?;
+            attribute.value = value.to_vec();
+            if !connected {
+                return Ok(());
+            }
+            attribute.characteristic.clone()
+        };
+        if self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .is_subscribed(&characteristic)
+        {
+            let notification = ValueNotification {
+                uuid: characteristic.uuid,
+                service_uuid: characteristic.service_uuid,
+                handle: characteristic.handle,
+                value: Bytes::copy_from_slice(value),
+            };
+            self.adapter.metrics().notification_received();
+            util::send_notification(&self.notification_senders, &notification);
+        }
+        Ok(())
+    }
+
+    /// Makes reads, writes and subscriptions for the first characteristic with the given UUID
+    /// fail with the given error, or succeed again if it is `None`.
+    pub fn set_error(&self, uuid: Uuid, error: Option<AttError>) -> Result<()> {
+        self.state.lock().unwrap().attribute_by_uuid(uuid)?.error = error;
+        Ok(())
+    }
+
+    /// Sets a function to be called for each read of the first characteristic with the given UUID,
+    /// which returns the value to respond with instead of the stored one. The returned value is
+    /// stored as the new value of the characteristic, and an error fails the read.
+    pub fn set_read_handler(
+        &self,
+        uuid: Uuid,
+        handler: impl Fn(&Peripheral) -> std::result::Result<Vec<u8>, AttError> + Send + Sync + 'static,
+    ) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .attribute_by_uuid(uuid)?
+            .read_handler = Some(Arc::new(handler));
+        Ok(())
+    }
+
+    /// Sets a function to be called with each value written to the first characteristic with the
+    /// given UUID, before the value is stored. It may respond by sending notifications with
+    /// [`notify`](Self::notify), or reject the write by returning an error, in which case the
+    /// value of the characteristic is left as it was.
+    pub fn set_write_handler(
+        &self,
+        uuid: Uuid,
+        handler: impl Fn(&Peripheral, &[u8]) -> std::result::Result<(), AttError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .attribute_by_uuid(uuid)?
+            .write_handler = Some(Arc::new(handler));
+        Ok(())
+    }
+
+    /// Returns the writes made to characteristics of the device since this was last called,
+    /// including those which were rejected.
+    pub fn take_writes(&self) -> Vec<MockWrite> {
+        std::mem::take(&mut self.state.lock().unwrap().writes)
+    }
+
+    /// Sets whether the MTU of connections to the device can be read, to simulate backends which
+    /// can't report it. If not, [`mtu`](crate::api::Peripheral::mtu) fails with
+    /// [`Error::NotSupported`].
+    pub fn set_mtu_reported(&self, reported: bool) {
+        self.state.lock().unwrap().mtu_reported = reported;
+    }
+
+    /// Sets whether the device accepts connections. Connecting to a device which doesn't fails with
+    /// [`Error::DeviceUnreachable`].
+    pub fn set_connectable(&self, connectable: bool) {
+        self.state.lock().unwrap().connectable = connectable;
+    }
+
+    /// Closes the connection from the device's side, as if it had been turned off or gone out of
+    /// range.
+    pub fn disconnect_remotely(&self, reason: DisconnectReason) {
+        self.drop_connection(reason);
+    }
+
+    /// Writes to the characteristic starting at the given offset into its value, calling its write
+    /// handler if it has one.
+    fn write_value(
+        &self,
+        characteristic: &Characteristic,
+        offset: u16,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        let handler = {
+            let mut state = self.state.lock().unwrap();
+            let attribute = state.attribute(characteristic)?;
+            let property = match write_type {
+                WriteType::WithResponse => CharPropFlags::WRITE,
+                WriteType::WithoutResponse => CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            };
+            if !attribute.characteristic.properties.contains(property) {
+                return Err(Error::Att(AttError::WRITE_NOT_PERMITTED));
+            }
+            if usize::from(offset) > attribute.value.len() {
+                return Err(Error::Att(AttError::INVALID_OFFSET));
+            }
+            let handler = attribute.write_handler.clone();
+            state.writes.push(MockWrite {
+                characteristic: characteristic.uuid,
+                handle: characteristic.handle,
+                value: data.to_vec(),
+                write_type,
+            });
+            handler
+        };
+        // The handler is called without holding the lock, so that it can control the device.
+        let result = match handler {
+            Some(handler) => handler(self, data),
+            None => Ok(()),
+        };
+        match result {
+            Ok(()) => {
+                let mut state = self.state.lock().unwrap();
+                let value = &mut state.attribute(characteristic)?.value;
+                value.truncate(usize::from(offset));
+                value.extend_from_slice(data);
+                Ok(())
+            }
+            // The result of a write without response never reaches the central.
+            Err(_) if write_type == WriteType::WithoutResponse => Ok(()),
+            Err(error) => Err(Error::Att(error)),
+        }
+    }
+}
+
+impl Display for Peripheral {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let connected = if state.connected { " connected" } else { "" };
+        write!(
+            f,
+            "{} {}{}",
+            self.address,
+            state
+                .properties
+                .local_name
+                .as_deref()
+                .unwrap_or("(unknown)"),
+            connected
+        )
+    }
+}
+
+impl Debug for Peripheral {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("Peripheral")
+            .field("address", &self.address)
+            .field("properties", &state.properties)
+            .field("connected", &state.connected)
+            .field("services", &state.services)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl api::Peripheral for Peripheral {
+    fn address(&self) -> BDAddr {
+        self.address
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        Ok(Some(self.state.lock().unwrap().properties.clone()))
+    }
+
+    async fn properties_stream(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeripheralProperties> + Send>>> {
+        Ok(self.properties_watch.stream())
+    }
+
+    async fn summary(&self) -> Result<PeripheralSummary> {
+        util::summary(self).await
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.state.lock().unwrap().discovered.clone()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.state.lock().unwrap().connected)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.connect_with_options(&ConnectOptions::default()).await
+    }
+
+    async fn connect_with_timeout(&self, timeout: Duration) -> Result<()> {
+        util::connect_with_timeout(self, timeout).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "connect",
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address),
+            err
+        )
+    )]
+    async fn connect_with_options(&self, options: &ConnectOptions) -> Result<()> {
+        let attempt = self.adapter.metrics().connect_attempt();
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.connected {
+                attempt.succeeded();
+                return Ok(());
+            }
+            if state.removed || !state.connectable {
+                return Err(HciError::CONNECTION_FAILED_TO_BE_ESTABLISHED.into());
+            }
+            state.connected = true;
+        }
+        self.adapter
+            .emit(CentralEvent::DeviceConnected(self.address));
+        // The device keeps its attribute handles, so there is nothing to do on it to resubscribe.
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .reconnected(options.resubscribe);
+        attempt.succeeded();
+        Ok(())
+    }
+
+    fn set_options(&self, options: &PeripheralOptions) {
+        *self.options.lock().unwrap() = *options;
+    }
+
+    async fn pair(&self) -> Result<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.check_connected()?;
+            state.bonded = true;
+        }
+        self.adapter.set_paired(self.address, true);
+        Ok(())
+    }
+
+    async fn pair_with_agent(&self, _agent: Arc<dyn PairingAgent>) -> Result<()> {
+        self.pair().await
+    }
+
+    async fn request_security(&self, _level: SecurityLevel) -> Result<()> {
+        self.pair().await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address), err)
+    )]
+    async fn disconnect(&self) -> Result<()> {
+        self.drop_connection(DisconnectReason::UserRequest);
+        Ok(())
+    }
+
+    async fn mtu(&self) -> Result<u16> {
+        let state = self.state.lock().unwrap();
+        state.check_connected()?;
+        if !state.mtu_reported {
+            return Err(Error::NotSupported(
+                "Reading the MTU is not supported by this mock device".to_string(),
+            ));
+        }
+        Ok(state.mtu)
+    }
+
+    /// The mock device always uses the MTU given in its [`MockDevice`], so this is not supported.
+    async fn request_mtu(&self, _mtu: u16) -> Result<()> {
+        Err(Error::NotSupported(
+            "Requesting an MTU is not supported on the mock backend".to_string(),
+        ))
+    }
+
+    /// Returns the RSSI which the device advertised with.
+    async fn read_rssi(&self) -> Result<i16> {
+        let state = self.state.lock().unwrap();
+        state.check_connected()?;
+        state
+            .properties
+            .rssi
+            .ok_or_else(|| Error::NotSupported("The mock device has no RSSI".to_string()))
+    }
+
+    async fn set_connection_parameters(&self, parameters: &ConnectionParameters) -> Result<()> {
+        parameters.validate()?;
+        self.state.lock().unwrap().check_connected()
+    }
+
+    async fn request_connection_priority(&self, _priority: ConnectionPriority) -> Result<()> {
+        self.state.lock().unwrap().check_connected()
+    }
+
+    async fn set_preferred_phy(&self, _tx: Phy, _rx: Phy) -> Result<()> {
+        self.state.lock().unwrap().check_connected()
+    }
+
+    async fn read_phy(&self) -> Result<(Phy, Phy)> {
+        self.state.lock().unwrap().check_connected()?;
+        Ok((Phy::Le1M, Phy::Le1M))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address), err)
+    )]
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        let mut state = self.state.lock().unwrap();
+        state.check_connected()?;
+        state.discovered = state
+            .attributes
+            .values()
+            .map(|attribute| attribute.characteristic.clone())
+            .collect();
+        Ok(state.discovered.iter().cloned().collect())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address), err)
+    )]
+    async fn discover_services(&self) -> Result<Vec<Service>> {
+        self.discover_characteristics().await?;
+        Ok(self.state.lock().unwrap().services.clone())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        let _operation = self.adapter.metrics().gatt_operation();
+        self.write_value(characteristic, 0, data, write_type)
+    }
+
+    async fn wait_until_ready_to_write(&self) -> Result<()> {
+        self.state.lock().unwrap().check_connected()
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn write_long(
+        &self,
+        characteristic: &Characteristic,
+        offset: u16,
+        data: &[u8],
+    ) -> Result<()> {
+        let _operation = self.adapter.metrics().gatt_operation();
+        self.write_value(characteristic, offset, data, WriteType::WithResponse)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn read(&self, characteristic: &Characteristic) -> Result<Bytes> {
+        let _operation = self.adapter.metrics().gatt_operation();
+        let handler = {
+            let mut state = self.state.lock().unwrap();
+            let attribute = state.attribute(characteristic)?;
+            if !attribute
+                .characteristic
+                .properties
+                .contains(CharPropFlags::READ)
+            {
+                return Err(Error::Att(AttError::READ_NOT_PERMITTED));
+            }
+            match attribute.read_handler.clone() {
+                Some(handler) => handler,
+                None => return Ok(Bytes::copy_from_slice(&attribute.value)),
+            }
+        };
+        // As for writes, the handler is called without holding the lock.
+        let value = handler(self).map_err(Error::Att)?;
+        self.state.lock().unwrap().attribute(characteristic)?.value = value.clone();
+        Ok(Bytes::from(value))
+    }
+
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>> {
+        let mut state = self.state.lock().unwrap();
+        let attribute = state.attribute(characteristic)?;
+        Ok(attribute
+            .descriptors
+            .iter()
+            .map(|descriptor| Descriptor {
+                uuid: descriptor.uuid,
+                characteristic_uuid: characteristic.uuid,
+                characteristic_handle: characteristic.handle,
+            })
+            .collect())
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let _operation = self.adapter.metrics().gatt_operation();
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .descriptor(descriptor)?
+            .value
+            .clone())
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let _operation = self.adapter.metrics().gatt_operation();
+        self.state.lock().unwrap().descriptor(descriptor)?.value = data.to_vec();
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let attribute = state.attribute(characteristic)?;
+        if !attribute
+            .characteristic
+            .properties
+            .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+        {
+            return Err(Error::NotSupported(format!(
+                "Characteristic {} doesn't support notifications or indications",
+                characteristic.uuid
+            )));
+        }
+        self.subscriptions.lock().unwrap().subscribe(characteristic);
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.state.lock().unwrap().attribute(characteristic)?;
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .unsubscribe(characteristic);
+        Ok(())
+    }
+
+    fn is_subscribed(&self, characteristic: &Characteristic) -> bool {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .is_subscribed(characteristic)
+    }
+
+    fn subscriptions(&self) -> BTreeSet<Characteristic> {
+        self.subscriptions.lock().unwrap().subscribed()
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.notification_senders.lock().unwrap().push(sender);
+        let notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>> =
+            Box::pin(receiver);
+        Ok(match self.options.lock().unwrap().notification_capacity {
+            Some(capacity) => util::bounded_notifications(
+                notifications,
+                capacity,
+                self.dropped_notifications.clone(),
+                self.adapter.metrics().clone(),
+            ),
+            None => notifications,
+        })
+    }
+
+    fn dropped_notifications(&self) -> u64 {
+        self.dropped_notifications.load(Ordering::Relaxed)
+    }
+
+    async fn notifications_for(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        Ok(util::notifications_for(
+            self.notifications().await?,
+            characteristic,
+        ))
+    }
+
+    async fn open_l2cap_channel(&self, _psm: u16) -> Result<Box<dyn L2capChannel>> {
+        Err(Error::NotSupported(
+            "L2CAP channels are not supported on the mock backend".to_string(),
+        ))
+    }
+}
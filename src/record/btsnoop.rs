@@ -0,0 +1,406 @@
+//! Capture of the ATT traffic of a session in the btsnoop format, which Wireshark can open.
+
+use super::{RecordedError, SessionEntry, SessionEvent, SessionSink};
+use crate::api::{
+    AddressType, BDAddr, CentralEvent, CharPropFlags, Characteristic, DisconnectReason, WriteType,
+};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The datalink type of HCI packets with a UART (H4) packet indicator.
+const DATALINK_H4: u32 = 1002;
+/// Microseconds between the btsnoop epoch, midnight on 1st January of year 0, and the Unix epoch.
+const EPOCH_DELTA: u64 = 0x00dc_ddb3_0f2f_8000;
+
+const FLAG_RECEIVED: u32 = 0x01;
+const FLAG_EVENT: u32 = 0x02;
+
+const H4_ACL: u8 = 0x02;
+const H4_EVENT: u8 = 0x04;
+const EVENT_DISCONNECTION_COMPLETE: u8 = 0x05;
+const EVENT_LE_META: u8 = 0x3e;
+const LE_CONNECTION_COMPLETE: u8 = 0x01;
+/// The packet boundary flag of the first packet of an L2CAP PDU which may be flushed.
+const ACL_FIRST_FLUSHABLE: u16 = 0x2000;
+const ATT_CID: u16 = 0x0004;
+/// The first connection handle assigned to devices, which is arbitrary.
+const FIRST_CONNECTION_HANDLE: u16 = 0x0040;
+
+const ATT_ERROR_RSP: u8 = 0x01;
+const ATT_READ_REQ: u8 = 0x0a;
+const ATT_READ_RSP: u8 = 0x0b;
+const ATT_WRITE_REQ: u8 = 0x12;
+const ATT_WRITE_RSP: u8 = 0x13;
+const ATT_HANDLE_VALUE_NTF: u8 = 0x1b;
+const ATT_WRITE_CMD: u8 = 0x52;
+
+/// A [`SessionSink`] which writes the ATT operations of a session as a btsnoop capture, so that it
+/// can be opened in Wireshark alongside the traces of the operating system.
+///
+/// The packets are reconstructed from the operations made through btleplug, as the platforms
+/// don't expose the underlying traffic: each device is given a connection handle of its own,
+/// connections and disconnections are written as the HCI events which report them, and only the
+/// requests and responses of reads, writes, subscriptions and notifications are written.
+/// btleplug doesn't know the handles of descriptors, so operations on descriptors, including the
+/// Client Characteristic Configuration descriptor written to subscribe, are written with handle
+/// 0.
+///
+/// ```no_run
+/// use btleplug::api::Manager as _;
+/// use btleplug::platform::Manager;
+/// use btleplug::record::{Btsnoop, RecordingAdapter};
+/// use std::fs::File;
+/// # use std::error::Error;
+///
+/// # async fn example() -> Result<(), Box<dyn Error>> {
+/// let manager = Manager::new().await?;
+/// let adapter = manager.adapters().await?.into_iter().next().unwrap();
+/// let capture = Btsnoop::new(File::create("btleplug.btsnoop")?)?;
+/// let adapter = RecordingAdapter::with_sink(adapter, capture).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Btsnoop<W> {
+    writer: W,
+    connection_handles: HashMap<BDAddr, u16>,
+    address_types: HashMap<BDAddr, AddressType>,
+}
+
+impl<W: Write> Btsnoop<W> {
+    /// Starts a capture, writing its header to the writer.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(b"btsnoop\0")?;
+        writer.write_all(&1u32.to_be_bytes())?;
+        writer.write_all(&DATALINK_H4.to_be_bytes())?;
+        writer.flush()?;
+        Ok(Btsnoop {
+            writer,
+            connection_handles: HashMap::new(),
+            address_types: HashMap::new(),
+        })
+    }
+
+    /// Returns the writer which the capture is written to.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn connection_handle(&mut self, address: BDAddr) -> u16 {
+        let next = FIRST_CONNECTION_HANDLE + self.connection_handles.len() as u16;
+        *self.connection_handles.entry(address).or_insert(next)
+    }
+
+    fn write_packet(&mut self, flags: u32, packet: &[u8]) -> io::Result<()> {
+        let since_unix_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let timestamp = since_unix_epoch.as_micros() as u64 + EPOCH_DELTA;
+        let length = packet.len() as u32;
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(&flags.to_be_bytes())?;
+        // The number of packets dropped.
+        self.writer.write_all(&0u32.to_be_bytes())?;
+        self.writer.write_all(&timestamp.to_be_bytes())?;
+        self.writer.write_all(packet)
+    }
+
+    fn write_event(&mut self, code: u8, parameters: &[u8]) -> io::Result<()> {
+        let mut packet = vec![H4_EVENT, code, parameters.len() as u8];
+        packet.extend_from_slice(parameters);
+        self.write_packet(FLAG_EVENT | FLAG_RECEIVED, &packet)
+    }
+
+    fn write_att(&mut self, address: BDAddr, received: bool, pdu: &[u8]) -> io::Result<()> {
+        let handle = self.connection_handle(address);
+        let mut packet = Vec::with_capacity(9 + pdu.len());
+        packet.push(H4_ACL);
+        packet.extend_from_slice(&(handle | ACL_FIRST_FLUSHABLE).to_le_bytes());
+        packet.extend_from_slice(&(pdu.len() as u16 + 4).to_le_bytes());
+        packet.extend_from_slice(&(pdu.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&ATT_CID.to_le_bytes());
+        packet.extend_from_slice(pdu);
+        let flags = if received { FLAG_RECEIVED } else { 0 };
+        self.write_packet(flags, &packet)
+    }
+
+    /// Writes a request along with its response, or the error response the device returned.
+    /// Requests which failed for any other reason may not have reached the device, so only the
+    /// request is written for them, or nothing if the device wasn't connected.
+    fn write_exchange(
+        &mut self,
+        address: BDAddr,
+        request: &[u8],
+        response: std::result::Result<Vec<u8>, &RecordedError>,
+    ) -> io::Result<()> {
+        if response == Err(&RecordedError::NotConnected) {
+            return Ok(());
+        }
+        self.write_att(address, false, request)?;
+        match response {
+            Ok(response) => self.write_att(address, true, &response),
+            Err(RecordedError::Att(code)) => {
+                let error = [ATT_ERROR_RSP, request[0], request[1], request[2], *code];
+                self.write_att(address, true, &error)
+            }
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn write_connection_complete(&mut self, address: BDAddr) -> io::Result<()> {
+        let handle = self.connection_handle(address);
+        let peer_address_type = match self.address_types.get(&address) {
+            Some(AddressType::Random) => 0x01,
+            _ => 0x00,
+        };
+        let mut parameters = vec![LE_CONNECTION_COMPLETE, 0x00];
+        parameters.extend_from_slice(&handle.to_le_bytes());
+        // The role, which is always central.
+        parameters.push(0x00);
+        parameters.push(peer_address_type);
+        parameters.extend(address.as_ref().iter().rev());
+        // The connection interval, latency, supervision timeout and clock accuracy aren't known.
+        parameters.extend_from_slice(&[0; 7]);
+        self.write_event(EVENT_LE_META, &parameters)
+    }
+
+    fn write_disconnection_complete(
+        &mut self,
+        address: BDAddr,
+        reason: DisconnectReason,
+    ) -> io::Result<()> {
+        let handle = self.connection_handle(address);
+        let reason = match reason {
+            DisconnectReason::UserRequest => 0x16,
+            DisconnectReason::SupervisionTimeout => 0x08,
+            DisconnectReason::RemoteTerminated => 0x13,
+            DisconnectReason::AdapterOff => 0x15,
+            // Unspecified error.
+            DisconnectReason::Unknown => 0x1f,
+        };
+        let mut parameters = vec![0x00];
+        parameters.extend_from_slice(&handle.to_le_bytes());
+        parameters.push(reason);
+        self.write_event(EVENT_DISCONNECTION_COMPLETE, &parameters)
+    }
+}
+
+/// Builds an ATT PDU with the given opcode, attribute handle and value.
+fn pdu(opcode: u8, handle: u16, value: &[u8]) -> Vec<u8> {
+    let mut pdu = vec![opcode];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    pdu.extend_from_slice(value);
+    pdu
+}
+
+/// The value written to the Client Characteristic Configuration descriptor of the characteristic
+/// to subscribe to it.
+fn configuration_value(characteristic: &Characteristic) -> &'static [u8] {
+    if characteristic.properties.contains(CharPropFlags::NOTIFY) {
+        &[0x01, 0x00]
+    } else {
+        &[0x02, 0x00]
+    }
+}
+
+impl<W: Write + Send + 'static> SessionSink for Btsnoop<W> {
+    fn record(&mut self, entry: &SessionEntry) -> io::Result<()> {
+        match &entry.event {
+            SessionEvent::Advertisement(properties) => {
+                if let Some(address_type) = &properties.address_type {
+                    self.address_types
+                        .insert(properties.address, address_type.clone());
+                }
+                return Ok(());
+            }
+            SessionEvent::Central(CentralEvent::DeviceConnected(address)) => {
+                self.write_connection_complete(*address)?;
+            }
+            SessionEvent::Central(CentralEvent::DeviceDisconnected(address, reason)) => {
+                self.write_disconnection_complete(*address, *reason)?;
+            }
+            SessionEvent::Read {
+                address,
+                characteristic,
+                result,
+            } => {
+                let request = pdu(ATT_READ_REQ, characteristic.handle, &[]);
+                let response = result.as_ref().map(|value| {
+                    let mut response = vec![ATT_READ_RSP];
+                    response.extend_from_slice(value);
+                    response
+                });
+                self.write_exchange(*address, &request, response)?;
+            }
+            SessionEvent::Write {
+                address,
+                characteristic,
+                value,
+                write_type: WriteType::WithoutResponse,
+                result,
+            } => {
+                if result != &Err(RecordedError::NotConnected) {
+                    let command = pdu(ATT_WRITE_CMD, characteristic.handle, value);
+                    self.write_att(*address, false, &command)?;
+                }
+            }
+            SessionEvent::Write {
+                address,
+                characteristic,
+                value,
+                result,
+                ..
+            } => {
+                let request = pdu(ATT_WRITE_REQ, characteristic.handle, value);
+                let response = result.as_ref().map(|()| vec![ATT_WRITE_RSP]);
+                self.write_exchange(*address, &request, response)?;
+            }
+            SessionEvent::ReadDescriptor {
+                address, result, ..
+            } => {
+                let request = pdu(ATT_READ_REQ, 0, &[]);
+                let response = result.as_ref().map(|value| {
+                    let mut response = vec![ATT_READ_RSP];
+                    response.extend_from_slice(value);
+                    response
+                });
+                self.write_exchange(*address, &request, response)?;
+            }
+            SessionEvent::WriteDescriptor {
+                address,
+                value,
+                result,
+                ..
+            } => {
+                let request = pdu(ATT_WRITE_REQ, 0, value);
+                let response = result.as_ref().map(|()| vec![ATT_WRITE_RSP]);
+                self.write_exchange(*address, &request, response)?;
+            }
+            SessionEvent::Subscribe {
+                address,
+                characteristic,
+                result,
+            } => {
+                let request = pdu(ATT_WRITE_REQ, 0, configuration_value(characteristic));
+                let response = result.as_ref().map(|()| vec![ATT_WRITE_RSP]);
+                self.write_exchange(*address, &request, response)?;
+            }
+            SessionEvent::Unsubscribe {
+                address, result, ..
+            } => {
+                let request = pdu(ATT_WRITE_REQ, 0, &[0x00, 0x00]);
+                let response = result.as_ref().map(|()| vec![ATT_WRITE_RSP]);
+                self.write_exchange(*address, &request, response)?;
+            }
+            SessionEvent::Notification {
+                address,
+                handle,
+                value,
+                ..
+            } => {
+                let notification = pdu(ATT_HANDLE_VALUE_NTF, *handle, value);
+                self.write_att(*address, true, &notification)?;
+            }
+            _ => return Ok(()),
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::bleuuid::uuid_from_u16;
+
+    const ADDRESS: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    fn entry(event: SessionEvent) -> SessionEntry {
+        SessionEntry {
+            elapsed_ms: 0,
+            event,
+        }
+    }
+
+    /// Splits a capture into its records, returning the flags and packet of each.
+    fn records(capture: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        assert_eq!(&capture[..8], b"btsnoop\0");
+        assert_eq!(&capture[12..16], &DATALINK_H4.to_be_bytes());
+        let mut records = Vec::new();
+        let mut rest = &capture[16..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let flags = u32::from_be_bytes([rest[8], rest[9], rest[10], rest[11]]);
+            records.push((flags, rest[24..24 + length].to_vec()));
+            rest = &rest[24 + length..];
+        }
+        records
+    }
+
+    #[test]
+    fn capture_att_traffic() {
+        let characteristic = Characteristic {
+            uuid: uuid_from_u16(0x2a19),
+            service_uuid: uuid_from_u16(0x180f),
+            handle: 0x0003,
+            properties: CharPropFlags::READ | CharPropFlags::NOTIFY,
+        };
+        let mut capture = Btsnoop::new(Vec::new()).unwrap();
+        let events = vec![
+            SessionEvent::Central(CentralEvent::DeviceConnected(ADDRESS)),
+            SessionEvent::Read {
+                address: ADDRESS,
+                characteristic: characteristic.clone(),
+                result: Ok(vec![0x64]),
+            },
+            SessionEvent::Read {
+                address: ADDRESS,
+                characteristic: characteristic.clone(),
+                result: Err(RecordedError::Att(0x05)),
+            },
+            SessionEvent::Subscribe {
+                address: ADDRESS,
+                characteristic,
+                result: Err(RecordedError::NotConnected),
+            },
+            SessionEvent::Notification {
+                address: ADDRESS,
+                uuid: uuid_from_u16(0x2a19),
+                service_uuid: uuid_from_u16(0x180f),
+                handle: 0x0003,
+                value: vec![0x63],
+            },
+        ];
+        for event in events {
+            capture.record(&entry(event)).unwrap();
+        }
+
+        let records = records(&capture.into_inner());
+        // The subscription never reached the device, so nothing is written for it.
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[0].0, FLAG_EVENT | FLAG_RECEIVED);
+        assert_eq!(
+            records[0].1,
+            vec![
+                0x04, 0x3e, 0x13, 0x01, 0x00, 0x40, 0x00, 0x00, 0x00, 0x66, 0x55, 0x44, 0x33, 0x22,
+                0x11, 0, 0, 0, 0, 0, 0, 0
+            ]
+        );
+        assert_eq!(
+            records[1],
+            (
+                0,
+                vec![0x02, 0x40, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00]
+            )
+        );
+        assert_eq!(records[2].1[9..], [0x0b, 0x64]);
+        assert_eq!(records[4].1[9..], [0x01, 0x0a, 0x03, 0x00, 0x05]);
+        assert_eq!(
+            records[5],
+            (
+                FLAG_RECEIVED,
+                vec![0x02, 0x40, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x1b, 0x03, 0x00, 0x63]
+            )
+        );
+    }
+}
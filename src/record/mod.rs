@@ -0,0 +1,355 @@
+//! Recording of everything observed through an adapter, and replaying of recorded sessions. It is
+//! enabled by the `record` feature.
+//!
+//! Wrapping an adapter in a [`RecordingAdapter`] writes the advertisements and events it reports,
+//! the GATT operations made through its peripherals along with their results, and the
+//! notifications they receive to a file, as one JSON [`SessionEntry`] per line. A [`Replay`] feeds
+//! a recorded session back through the [`Central`](crate::api::Central) and
+//! [`Peripheral`](crate::api::Peripheral) API of a [mock](crate::mock) adapter, so that a problem
+//! seen with a real device can be debugged deterministically, without the device.
+//!
+//! ```no_run
+//! use btleplug::api::{Central, Manager as _, ScanFilter};
+//! use btleplug::platform::Manager;
+//! use btleplug::record::{self, RecordingAdapter, Replay};
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! # use std::error::Error;
+//!
+//! # async fn example() -> Result<(), Box<dyn Error>> {
+//! let manager = Manager::new().await?;
+//! let adapter = manager.adapters().await?.into_iter().next().unwrap();
+//! let recording = RecordingAdapter::new(adapter, File::create("session.jsonl")?).await?;
+//! recording.start_scan(ScanFilter::default()).await?;
+//! // Use `recording` as the adapter...
+//!
+//! // Later, play the session back.
+//! let session = record::read_session(BufReader::new(File::open("session.jsonl")?))?;
+//! let replay = Replay::new(session);
+//! let adapter = replay.adapter();
+//! adapter.start_scan(ScanFilter::default()).await?;
+//! replay.run().await;
+//! # Ok(())
+//! # }
+//! ```
+
+mod btsnoop;
+mod recorder;
+mod replay;
+
+pub use self::btsnoop::Btsnoop;
+pub use self::recorder::{RecordingAdapter, RecordingPeripheral};
+pub use self::replay::Replay;
+
+use crate::{
+    api::{
+        AddressType, BDAddr, CentralEvent, Characteristic, Descriptor, PeripheralProperties,
+        Service, WriteType,
+    },
+    AttError, Error, Result,
+};
+use serde_cr::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use uuid::Uuid;
+
+/// Something observed through a [`RecordingAdapter`], with when it happened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub struct SessionEntry {
+    /// The time since recording started, in milliseconds.
+    pub elapsed_ms: u64,
+    pub event: SessionEvent,
+}
+
+/// Something observed through a [`RecordingAdapter`]. GATT operations are recorded once they
+/// complete, along with their result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub enum SessionEvent {
+    /// An event emitted by the adapter.
+    Central(CentralEvent),
+    /// The properties of a device after an advertisement was received from it. This is recorded
+    /// just before the corresponding [`CentralEvent::DeviceDiscovered`] or
+    /// [`CentralEvent::DeviceUpdated`] event.
+    Advertisement(RecordedProperties),
+    Services {
+        address: BDAddr,
+        services: Vec<Service>,
+    },
+    Characteristics {
+        address: BDAddr,
+        characteristics: Vec<Characteristic>,
+    },
+    Descriptors {
+        address: BDAddr,
+        characteristic: Characteristic,
+        descriptors: Vec<Descriptor>,
+    },
+    Read {
+        address: BDAddr,
+        characteristic: Characteristic,
+        result: std::result::Result<Vec<u8>, RecordedError>,
+    },
+    Write {
+        address: BDAddr,
+        characteristic: Characteristic,
+        value: Vec<u8>,
+        write_type: WriteType,
+        result: std::result::Result<(), RecordedError>,
+    },
+    ReadDescriptor {
+        address: BDAddr,
+        descriptor: Descriptor,
+        result: std::result::Result<Vec<u8>, RecordedError>,
+    },
+    WriteDescriptor {
+        address: BDAddr,
+        descriptor: Descriptor,
+        value: Vec<u8>,
+        result: std::result::Result<(), RecordedError>,
+    },
+    Subscribe {
+        address: BDAddr,
+        characteristic: Characteristic,
+        result: std::result::Result<(), RecordedError>,
+    },
+    Unsubscribe {
+        address: BDAddr,
+        characteristic: Characteristic,
+        result: std::result::Result<(), RecordedError>,
+    },
+    /// A notification or indication received from a connected device.
+    Notification {
+        address: BDAddr,
+        uuid: Uuid,
+        service_uuid: Uuid,
+        handle: u16,
+        value: Vec<u8>,
+    },
+}
+
+/// What a device advertised, as recorded from its [`PeripheralProperties`].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub struct RecordedProperties {
+    pub address: BDAddr,
+    pub address_type: Option<AddressType>,
+    pub local_name: Option<String>,
+    pub tx_power_level: Option<i8>,
+    pub rssi: Option<i16>,
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    pub services: Vec<Uuid>,
+}
+
+impl From<&PeripheralProperties> for RecordedProperties {
+    fn from(properties: &PeripheralProperties) -> Self {
+        RecordedProperties {
+            address: properties.address,
+            address_type: properties.address_type.clone(),
+            local_name: properties.local_name.clone(),
+            tx_power_level: properties.tx_power_level,
+            rssi: properties.rssi,
+            manufacturer_data: properties.manufacturer_data.clone(),
+            service_data: properties.service_data.clone(),
+            services: properties.services.clone(),
+        }
+    }
+}
+
+impl From<&RecordedProperties> for PeripheralProperties {
+    fn from(recorded: &RecordedProperties) -> Self {
+        PeripheralProperties {
+            address: recorded.address,
+            address_type: recorded.address_type.clone(),
+            local_name: recorded.local_name.clone(),
+            tx_power_level: recorded.tx_power_level,
+            rssi: recorded.rssi,
+            manufacturer_data: recorded.manufacturer_data.clone(),
+            service_data: recorded.service_data.clone(),
+            services: recorded.services.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The error a recorded operation failed with. Only the errors which a device can cause are kept
+/// exactly; any other error is recorded as its message.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "serde_cr")]
+pub enum RecordedError {
+    NotConnected,
+    /// The device returned the given ATT error code.
+    Att(u8),
+    Other(String),
+}
+
+impl From<&Error> for RecordedError {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::NotConnected => RecordedError::NotConnected,
+            Error::Att(error) => RecordedError::Att(error.0),
+            error => RecordedError::Other(error.to_string()),
+        }
+    }
+}
+
+impl From<RecordedError> for Error {
+    fn from(error: RecordedError) -> Self {
+        match error {
+            RecordedError::NotConnected => Error::NotConnected,
+            RecordedError::Att(code) => Error::Att(AttError(code)),
+            RecordedError::Other(message) => Error::Other(message.into()),
+        }
+    }
+}
+
+/// Where a [`RecordingAdapter`] writes the entries of a session to. By default they are written as
+/// JSON lines, which can be read back with [`read_session`]. [`Btsnoop`] writes a capture of the
+/// ATT traffic instead, and applications can implement this to send the entries elsewhere.
+pub trait SessionSink: Send + 'static {
+    /// Writes an entry, which is called as soon as it is observed. Errors are logged, and don't
+    /// stop the recording.
+    fn record(&mut self, entry: &SessionEntry) -> io::Result<()>;
+}
+
+/// Reads a session written by a [`RecordingAdapter`], one entry per line. Blank lines are skipped.
+pub fn read_session(reader: impl BufRead) -> Result<Vec<SessionEntry>> {
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(|e| Error::Other(Box::new(e)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(&line).map_err(|e| Error::Other(Box::new(e)))?);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{bleuuid::uuid_from_u16, Central, CharPropFlags, Peripheral as _, ScanFilter};
+    use crate::mock::{self, MockCharacteristic, MockDevice, MockService};
+    use bytes::Bytes;
+    use futures::stream::StreamExt;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const ADDRESS: BDAddr = BDAddr::from_be_bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn device() -> MockDevice {
+        let mut device = MockDevice::default();
+        device.properties.address = ADDRESS;
+        device.properties.local_name = Some("Sensor".to_string());
+        device.services.push(MockService {
+            uuid: uuid_from_u16(0xfff0),
+            characteristics: vec![MockCharacteristic {
+                uuid: uuid_from_u16(0xfff1),
+                properties: CharPropFlags::READ | CharPropFlags::NOTIFY,
+                value: vec![0x01],
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        device
+    }
+
+    /// Connects to the device, subscribes to its characteristic and reads it twice.
+    async fn use_device<A: Central>(adapter: &A) -> Vec<std::result::Result<Bytes, Error>> {
+        let peripheral = adapter.peripheral(ADDRESS).await.unwrap();
+        peripheral.connect().await.unwrap();
+        let characteristics = peripheral.discover_characteristics().await.unwrap();
+        peripheral.subscribe(&characteristics[0]).await.unwrap();
+        vec![
+            peripheral.read(&characteristics[0]).await,
+            peripheral.read(&characteristics[0]).await,
+        ]
+    }
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let mock_adapter = mock::Adapter::new();
+        let simulated = mock_adapter.add_device(device());
+        let buffer = SharedBuffer::default();
+        let adapter = RecordingAdapter::new(mock_adapter, buffer.clone())
+            .await
+            .unwrap();
+        adapter.start_scan(ScanFilter::default()).await.unwrap();
+        let mut recorded_events = adapter.events().await.unwrap();
+
+        let uuid = uuid_from_u16(0xfff1);
+        let peripheral = adapter.peripheral(ADDRESS).await.unwrap();
+        peripheral.connect().await.unwrap();
+        let characteristics = peripheral.discover_characteristics().await.unwrap();
+        peripheral.subscribe(&characteristics[0]).await.unwrap();
+        assert_eq!(
+            peripheral.read(&characteristics[0]).await.unwrap(),
+            &[0x01][..]
+        );
+        simulated
+            .set_error(uuid, Some(AttError::READ_NOT_PERMITTED))
+            .unwrap();
+        assert!(peripheral.read(&characteristics[0]).await.is_err());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        simulated.notify(uuid, &[0x02]).unwrap();
+        // Wait for the recording task to see the last event.
+        simulated.disconnect_remotely(crate::api::DisconnectReason::RemoteTerminated);
+        while !matches!(
+            recorded_events.next().await,
+            Some(CentralEvent::DeviceDisconnected(..))
+        ) {}
+        tokio::task::yield_now().await;
+        drop((adapter, peripheral));
+
+        let contents = buffer.0.lock().unwrap().clone();
+        let session = read_session(&contents[..]).unwrap();
+        assert!(session.iter().any(|entry| matches!(
+            entry.event,
+            SessionEvent::Notification { ref value, .. } if value == &[0x02]
+        )));
+
+        let replay = Replay::new(session);
+        let replayed = replay.adapter();
+        let mut events = replayed.events().await.unwrap();
+        replayed.start_scan(ScanFilter::default()).await.unwrap();
+        let client = async {
+            while !matches!(
+                events.next().await,
+                Some(CentralEvent::DeviceDiscovered(ADDRESS))
+            ) {}
+            let peripheral = replayed.peripheral(ADDRESS).await.unwrap();
+            let mut notifications = peripheral.notifications().await.unwrap();
+            let reads = use_device(&replayed).await;
+            assert_eq!(reads[0].as_ref().unwrap(), &[0x01][..]);
+            assert!(matches!(
+                reads[1],
+                Err(Error::Att(AttError::READ_NOT_PERMITTED))
+            ));
+            assert_eq!(notifications.next().await.unwrap().value, &[0x02][..]);
+            assert!(notifications.next().await.is_none());
+        };
+        tokio::join!(replay.run(), client);
+    }
+
+    #[test]
+    fn reject_malformed_session() {
+        assert!(read_session(&b"{\"elapsed_ms\": 0}\n"[..]).is_err());
+        assert!(read_session(&b"\n"[..]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,584 @@
+use super::{RecordedError, RecordedProperties, SessionEntry, SessionEvent, SessionSink};
+use crate::{
+    api::{
+        self, AdapterMetrics, AdapterState, Advertisement, BDAddr, Central, CentralEvent,
+        Characteristic, ConnectOptions, ConnectionParameters, ConnectionPriority, Descriptor,
+        EventFilter, IdentityResolvingKey, L2capChannel, PairingAgent, PeriodicAdvertisement,
+        Peripheral as _, PeripheralOptions, PeripheralProperties, PeripheralSummary, Phy,
+        ScanFilter, SecurityLevel, Service, ValueNotification, WriteType,
+    },
+    common::runtime,
+    Result,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{Stream, StreamExt};
+use log::warn;
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::{self, Debug, Formatter},
+    io::{self, Write},
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+type Sink = Mutex<Box<dyn SessionSink>>;
+
+/// The default sink, which writes each entry as a line of JSON, flushing it straight away so that
+/// the session is complete up to the last entry if the application crashes.
+struct JsonLines<W>(W);
+
+impl<W: Write + Send + 'static> SessionSink for JsonLines<W> {
+    fn record(&mut self, entry: &SessionEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.0.write_all(&line)?;
+        self.0.flush()
+    }
+}
+
+/// Writes the entries of a session to its sink. It is shared by a [`RecordingAdapter`] and every
+/// peripheral it returns.
+#[derive(Clone)]
+struct Recorder {
+    start: Instant,
+    sink: Arc<Sink>,
+    /// The devices whose notifications are being recorded.
+    listening: Arc<Mutex<HashSet<BDAddr>>>,
+}
+
+impl Recorder {
+    fn new(sink: impl SessionSink) -> Self {
+        Recorder {
+            start: Instant::now(),
+            sink: Arc::new(Mutex::new(Box::new(sink))),
+            listening: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    fn record(&self, event: SessionEvent) {
+        write_entry(&self.sink, self.start, event);
+    }
+
+    /// Returns a handle for background tasks, which shouldn't keep the sink open once the
+    /// adapter and its peripherals have been dropped.
+    fn downgrade(&self) -> WeakRecorder {
+        WeakRecorder {
+            start: self.start,
+            sink: Arc::downgrade(&self.sink),
+        }
+    }
+}
+
+struct WeakRecorder {
+    start: Instant,
+    sink: Weak<Sink>,
+}
+
+impl WeakRecorder {
+    /// Records the event, returning false if the recording has been dropped.
+    fn record(&self, event: SessionEvent) -> bool {
+        match self.sink.upgrade() {
+            Some(sink) => {
+                write_entry(&sink, self.start, event);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn write_entry(sink: &Sink, start: Instant, event: SessionEvent) {
+    let entry = SessionEntry {
+        elapsed_ms: start.elapsed().as_millis() as u64,
+        event,
+    };
+    if let Err(e) = sink.lock().unwrap().record(&entry) {
+        warn!("Failed to record session entry: {}", e);
+    }
+}
+
+/// Converts the result of an operation to how it is recorded.
+fn recorded<T, U>(
+    result: &Result<T>,
+    f: impl FnOnce(&T) -> U,
+) -> std::result::Result<U, RecordedError> {
+    result.as_ref().map(f).map_err(RecordedError::from)
+}
+
+/// An adapter which records everything observed through it. It implements
+/// [`Central`](crate::api::Central) by delegating to the wrapped adapter, and returns
+/// [`RecordingPeripheral`]s which record the GATT operations made through them.
+#[derive(Clone)]
+pub struct RecordingAdapter<A> {
+    adapter: A,
+    recorder: Recorder,
+}
+
+impl<A: Central + 'static> RecordingAdapter<A> {
+    /// Starts recording the events of the adapter to the given writer, such as a file, as one
+    /// JSON entry per line. With the `tokio-runtime` feature, this must be called from within a
+    /// Tokio runtime. Recording stops once the returned adapter and every peripheral it returned
+    /// have been dropped.
+    pub async fn new(adapter: A, writer: impl Write + Send + 'static) -> Result<Self> {
+        Self::with_sink(adapter, JsonLines(writer)).await
+    }
+
+    /// Starts recording the events of the adapter to the given sink, such as a [`Btsnoop`]
+    /// capture, as with [`new`](Self::new).
+    ///
+    /// [`Btsnoop`]: super::Btsnoop
+    pub async fn with_sink(adapter: A, sink: impl SessionSink) -> Result<Self> {
+        let recorder = Recorder::new(sink);
+        let mut events = adapter.events().await?;
+        let events_adapter = adapter.clone();
+        let weak_recorder = recorder.downgrade();
+        runtime::spawn(async move {
+            while let Some(event) = events.next().await {
+                let advertised = match event {
+                    CentralEvent::DeviceDiscovered(address)
+                    | CentralEvent::DeviceUpdated { address, .. } => {
+                        advertised_properties(&events_adapter, address).await
+                    }
+                    _ => None,
+                };
+                if let Some(properties) = advertised {
+                    weak_recorder.record(SessionEvent::Advertisement(RecordedProperties::from(
+                        &properties,
+                    )));
+                }
+                if !weak_recorder.record(SessionEvent::Central(event)) {
+                    break;
+                }
+            }
+        });
+        Ok(RecordingAdapter { adapter, recorder })
+    }
+
+    /// Returns the adapter being recorded.
+    pub fn inner(&self) -> &A {
+        &self.adapter
+    }
+
+    fn wrap(&self, peripheral: A::Peripheral) -> RecordingPeripheral<A::Peripheral> {
+        RecordingPeripheral {
+            peripheral,
+            recorder: self.recorder.clone(),
+        }
+    }
+}
+
+async fn advertised_properties<A: Central>(
+    adapter: &A,
+    address: BDAddr,
+) -> Option<PeripheralProperties> {
+    let peripheral = adapter.peripheral(address).await.ok()?;
+    peripheral.properties().await.ok().flatten()
+}
+
+#[async_trait]
+impl<A: Central + 'static> Central for RecordingAdapter<A> {
+    type Peripheral = RecordingPeripheral<A::Peripheral>;
+
+    async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        self.adapter.events().await
+    }
+
+    async fn events_filtered(
+        &self,
+        filter: EventFilter,
+    ) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        self.adapter.events_filtered(filter).await
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.adapter.start_scan(filter).await
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        self.adapter.stop_scan().await
+    }
+
+    async fn is_scanning(&self) -> Result<bool> {
+        self.adapter.is_scanning().await
+    }
+
+    async fn set_powered(&self, powered: bool) -> Result<()> {
+        self.adapter.set_powered(powered).await
+    }
+
+    async fn adapter_state(&self) -> Result<AdapterState> {
+        self.adapter.adapter_state().await
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Self::Peripheral>> {
+        let peripherals = self.adapter.peripherals().await?;
+        Ok(peripherals.into_iter().map(|p| self.wrap(p)).collect())
+    }
+
+    async fn peripheral(&self, address: BDAddr) -> Result<Self::Peripheral> {
+        let peripheral = self.adapter.peripheral(address).await?;
+        Ok(self.wrap(peripheral))
+    }
+
+    async fn set_retain_peripherals(&self, retain: bool) -> Result<()> {
+        self.adapter.set_retain_peripherals(retain).await
+    }
+
+    async fn set_peripheral_ttl(&self, ttl: Option<Duration>) -> Result<()> {
+        self.adapter.set_peripheral_ttl(ttl).await
+    }
+
+    async fn set_max_peripherals(&self, max: Option<usize>) -> Result<()> {
+        self.adapter.set_max_peripherals(max).await
+    }
+
+    async fn add_identity_resolving_key(
+        &self,
+        identity: BDAddr,
+        irk: IdentityResolvingKey,
+    ) -> Result<()> {
+        self.adapter.add_identity_resolving_key(identity, irk).await
+    }
+
+    async fn remove_identity_resolving_key(&self, identity: BDAddr) -> Result<()> {
+        self.adapter.remove_identity_resolving_key(identity).await
+    }
+
+    async fn add_peripheral(&self, address: BDAddr) -> Result<Self::Peripheral> {
+        let peripheral = self.adapter.add_peripheral(address).await?;
+        Ok(self.wrap(peripheral))
+    }
+
+    async fn bonded_peripherals(&self) -> Result<Vec<Self::Peripheral>> {
+        let peripherals = self.adapter.bonded_peripherals().await?;
+        Ok(peripherals.into_iter().map(|p| self.wrap(p)).collect())
+    }
+
+    async fn start_advertising(&self, advertisement: &Advertisement) -> Result<()> {
+        self.adapter.start_advertising(advertisement).await
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        self.adapter.stop_advertising().await
+    }
+
+    async fn sync_periodic_advertising(
+        &self,
+        address: BDAddr,
+        sid: u8,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeriodicAdvertisement> + Send>>> {
+        self.adapter.sync_periodic_advertising(address, sid).await
+    }
+
+    async fn metrics(&self) -> Result<AdapterMetrics> {
+        self.adapter.metrics().await
+    }
+}
+
+/// A peripheral returned by a [`RecordingAdapter`], which records the GATT operations made through
+/// it and, once connected, the notifications it receives.
+#[derive(Clone)]
+pub struct RecordingPeripheral<P> {
+    peripheral: P,
+    recorder: Recorder,
+}
+
+impl<P: api::Peripheral + 'static> RecordingPeripheral<P> {
+    /// Returns the peripheral being recorded.
+    pub fn inner(&self) -> &P {
+        &self.peripheral
+    }
+
+    /// Starts recording notifications from the device, unless they already are being. This is
+    /// done for every connection, as the notification stream ends when the device disconnects.
+    async fn record_notifications(&self) {
+        let address = self.peripheral.address();
+        if !self.recorder.listening.lock().unwrap().insert(address) {
+            return;
+        }
+        let notifications = self.peripheral.notifications().await.ok();
+        let listening = self.recorder.listening.clone();
+        let mut notifications = match notifications {
+            Some(notifications) => notifications,
+            None => {
+                listening.lock().unwrap().remove(&address);
+                return;
+            }
+        };
+        let weak_recorder = self.recorder.downgrade();
+        runtime::spawn(async move {
+            while let Some(notification) = notifications.next().await {
+                let recorded = weak_recorder.record(SessionEvent::Notification {
+                    address,
+                    uuid: notification.uuid,
+                    service_uuid: notification.service_uuid,
+                    handle: notification.handle,
+                    value: notification.value.to_vec(),
+                });
+                if !recorded {
+                    break;
+                }
+            }
+            listening.lock().unwrap().remove(&address);
+        });
+    }
+}
+
+impl<P: Debug> Debug for RecordingPeripheral<P> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("RecordingPeripheral")
+            .field("peripheral", &self.peripheral)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<P: api::Peripheral + 'static> api::Peripheral for RecordingPeripheral<P> {
+    fn address(&self) -> BDAddr {
+        self.peripheral.address()
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        self.peripheral.properties().await
+    }
+
+    async fn properties_stream(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeripheralProperties> + Send>>> {
+        self.peripheral.properties_stream().await
+    }
+
+    async fn summary(&self) -> Result<PeripheralSummary> {
+        self.peripheral.summary().await
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.peripheral.characteristics()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        self.peripheral.is_connected().await
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.peripheral.connect().await?;
+        self.record_notifications().await;
+        Ok(())
+    }
+
+    async fn connect_with_timeout(&self, timeout: Duration) -> Result<()> {
+        self.peripheral.connect_with_timeout(timeout).await?;
+        self.record_notifications().await;
+        Ok(())
+    }
+
+    async fn connect_with_options(&self, options: &ConnectOptions) -> Result<()> {
+        self.peripheral.connect_with_options(options).await?;
+        self.record_notifications().await;
+        Ok(())
+    }
+
+    fn set_options(&self, options: &PeripheralOptions) {
+        self.peripheral.set_options(options)
+    }
+
+    async fn pair(&self) -> Result<()> {
+        self.peripheral.pair().await
+    }
+
+    async fn pair_with_agent(&self, agent: Arc<dyn PairingAgent>) -> Result<()> {
+        self.peripheral.pair_with_agent(agent).await
+    }
+
+    async fn request_security(&self, level: SecurityLevel) -> Result<()> {
+        self.peripheral.request_security(level).await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.peripheral.disconnect().await
+    }
+
+    async fn mtu(&self) -> Result<u16> {
+        self.peripheral.mtu().await
+    }
+
+    async fn request_mtu(&self, mtu: u16) -> Result<()> {
+        self.peripheral.request_mtu(mtu).await
+    }
+
+    async fn read_rssi(&self) -> Result<i16> {
+        self.peripheral.read_rssi().await
+    }
+
+    async fn set_connection_parameters(&self, parameters: &ConnectionParameters) -> Result<()> {
+        self.peripheral.set_connection_parameters(parameters).await
+    }
+
+    async fn request_connection_priority(&self, priority: ConnectionPriority) -> Result<()> {
+        self.peripheral.request_connection_priority(priority).await
+    }
+
+    async fn set_preferred_phy(&self, tx: Phy, rx: Phy) -> Result<()> {
+        self.peripheral.set_preferred_phy(tx, rx).await
+    }
+
+    async fn read_phy(&self) -> Result<(Phy, Phy)> {
+        self.peripheral.read_phy().await
+    }
+
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        let characteristics = self.peripheral.discover_characteristics().await?;
+        self.recorder.record(SessionEvent::Characteristics {
+            address: self.address(),
+            characteristics: characteristics.clone(),
+        });
+        Ok(characteristics)
+    }
+
+    async fn discover_services(&self) -> Result<Vec<Service>> {
+        let services = self.peripheral.discover_services().await?;
+        self.recorder.record(SessionEvent::Services {
+            address: self.address(),
+            services: services.clone(),
+        });
+        Ok(services)
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        let result = self
+            .peripheral
+            .write(characteristic, data, write_type)
+            .await;
+        self.recorder.record(SessionEvent::Write {
+            address: self.address(),
+            characteristic: characteristic.clone(),
+            value: data.to_vec(),
+            write_type,
+            result: recorded(&result, |_| ()),
+        });
+        result
+    }
+
+    async fn wait_until_ready_to_write(&self) -> Result<()> {
+        self.peripheral.wait_until_ready_to_write().await
+    }
+
+    /// Long writes are recorded as writes of the whole value from the offset onwards.
+    async fn write_long(
+        &self,
+        characteristic: &Characteristic,
+        offset: u16,
+        data: &[u8],
+    ) -> Result<()> {
+        let result = self
+            .peripheral
+            .write_long(characteristic, offset, data)
+            .await;
+        self.recorder.record(SessionEvent::Write {
+            address: self.address(),
+            characteristic: characteristic.clone(),
+            value: data.to_vec(),
+            write_type: WriteType::WithResponse,
+            result: recorded(&result, |_| ()),
+        });
+        result
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Bytes> {
+        let result = self.peripheral.read(characteristic).await;
+        self.recorder.record(SessionEvent::Read {
+            address: self.address(),
+            characteristic: characteristic.clone(),
+            result: recorded(&result, |value| value.to_vec()),
+        });
+        result
+    }
+
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>> {
+        let descriptors = self.peripheral.discover_descriptors(characteristic).await?;
+        self.recorder.record(SessionEvent::Descriptors {
+            address: self.address(),
+            characteristic: characteristic.clone(),
+            descriptors: descriptors.clone(),
+        });
+        Ok(descriptors)
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let result = self.peripheral.read_descriptor(descriptor).await;
+        self.recorder.record(SessionEvent::ReadDescriptor {
+            address: self.address(),
+            descriptor: descriptor.clone(),
+            result: recorded(&result, |value| value.clone()),
+        });
+        result
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let result = self.peripheral.write_descriptor(descriptor, data).await;
+        self.recorder.record(SessionEvent::WriteDescriptor {
+            address: self.address(),
+            descriptor: descriptor.clone(),
+            value: data.to_vec(),
+            result: recorded(&result, |_| ()),
+        });
+        result
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let result = self.peripheral.subscribe(characteristic).await;
+        self.recorder.record(SessionEvent::Subscribe {
+            address: self.address(),
+            characteristic: characteristic.clone(),
+            result: recorded(&result, |_| ()),
+        });
+        result
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let result = self.peripheral.unsubscribe(characteristic).await;
+        self.recorder.record(SessionEvent::Unsubscribe {
+            address: self.address(),
+            characteristic: characteristic.clone(),
+            result: recorded(&result, |_| ()),
+        });
+        result
+    }
+
+    fn is_subscribed(&self, characteristic: &Characteristic) -> bool {
+        self.peripheral.is_subscribed(characteristic)
+    }
+
+    fn subscriptions(&self) -> BTreeSet<Characteristic> {
+        self.peripheral.subscriptions()
+    }
+
+    async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        self.peripheral.notifications().await
+    }
+
+    fn dropped_notifications(&self) -> u64 {
+        self.peripheral.dropped_notifications()
+    }
+
+    async fn notifications_for(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        self.peripheral.notifications_for(characteristic).await
+    }
+
+    async fn open_l2cap_channel(&self, psm: u16) -> Result<Box<dyn L2capChannel>> {
+        self.peripheral.open_l2cap_channel(psm).await
+    }
+}
@@ -0,0 +1,310 @@
+use super::{RecordedError, SessionEntry, SessionEvent};
+use crate::{
+    api::{BDAddr, CentralEvent, Characteristic, DisconnectReason, PeripheralProperties, Service},
+    common::runtime,
+    mock::{Adapter, MockCharacteristic, MockDescriptor, MockDevice, MockService},
+    AttError,
+};
+use log::debug;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// The recorded results of the operations on a characteristic, which are returned in turn. The
+/// last one is repeated once the others have been used up.
+type Responses<T> = Arc<Mutex<VecDeque<Result<T, RecordedError>>>>;
+
+fn next_response<T: Clone>(responses: &Responses<T>) -> Result<T, AttError> {
+    let mut responses = responses.lock().unwrap();
+    let response = if responses.len() > 1 {
+        responses.pop_front()
+    } else {
+        responses.front().cloned()
+    };
+    match response {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(RecordedError::Att(code))) => Err(AttError(code)),
+        // Other failures can't be reproduced by a device, so they are replayed as a generic ATT
+        // error.
+        Some(Err(_)) | None => Err(AttError::UNLIKELY_ERROR),
+    }
+}
+
+/// What was recorded about a device, from which it is simulated.
+#[derive(Default)]
+struct RecordedDevice {
+    advertised: bool,
+    services: Vec<Service>,
+    /// Every characteristic seen, in the order of their handles.
+    characteristics: BTreeMap<(u16, Uuid, Uuid), Characteristic>,
+    /// The descriptors of each characteristic, by its handle and UUID.
+    descriptors: BTreeMap<(u16, Uuid), Vec<MockDescriptor>>,
+    reads: HashMap<Uuid, Responses<Vec<u8>>>,
+    writes: HashMap<Uuid, Responses<()>>,
+}
+
+impl RecordedDevice {
+    fn descriptor(&mut self, handle: u16, characteristic: Uuid, uuid: Uuid) -> &mut MockDescriptor {
+        let descriptors = self
+            .descriptors
+            .entry((handle, characteristic))
+            .or_default();
+        match descriptors.iter().position(|d| d.uuid == uuid) {
+            Some(index) => &mut descriptors[index],
+            None => {
+                descriptors.push(MockDescriptor {
+                    uuid,
+                    value: Vec::new(),
+                });
+                descriptors.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Rebuilds the GATT database of the device, in the order the services were discovered in.
+    fn gatt(&self) -> Vec<MockService> {
+        let mut services: Vec<MockService> = self
+            .services
+            .iter()
+            .map(|service| MockService {
+                uuid: service.uuid,
+                primary: service.primary,
+                characteristics: Vec::new(),
+                included_services: service.included_services.iter().cloned().collect(),
+            })
+            .collect();
+        for characteristic in self.characteristics.values() {
+            let index = match services
+                .iter()
+                .position(|service| service.uuid == characteristic.service_uuid)
+            {
+                Some(index) => index,
+                None => {
+                    services.push(MockService {
+                        uuid: characteristic.service_uuid,
+                        ..Default::default()
+                    });
+                    services.len() - 1
+                }
+            };
+            services[index].characteristics.push(MockCharacteristic {
+                uuid: characteristic.uuid,
+                properties: characteristic.properties,
+                value: Vec::new(),
+                descriptors: self
+                    .descriptors
+                    .get(&(characteristic.handle, characteristic.uuid))
+                    .cloned()
+                    .unwrap_or_default(),
+            });
+        }
+        services
+    }
+}
+
+/// Plays a recorded session back through a [mock](crate::mock) adapter.
+///
+/// The devices of the session are simulated with the services and characteristics which were
+/// discovered on them. Reads and writes of a characteristic return the recorded results in the
+/// order they were recorded, repeating the last one once they run out. Advertisements,
+/// notifications and disconnections by the device happen at the times they were recorded, relative
+/// to when [`run`](Self::run) is called.
+pub struct Replay {
+    adapter: Adapter,
+    entries: Vec<SessionEntry>,
+    devices: HashMap<BDAddr, RecordedDevice>,
+}
+
+impl Replay {
+    /// Prepares to replay the session. Devices which were connected to without having advertised
+    /// are in range of the adapter straight away; the others come into range when they first
+    /// advertise.
+    pub fn new(entries: Vec<SessionEntry>) -> Self {
+        let mut devices: HashMap<BDAddr, RecordedDevice> = HashMap::new();
+        for entry in &entries {
+            match &entry.event {
+                SessionEvent::Advertisement(properties) => {
+                    devices.entry(properties.address).or_default().advertised = true;
+                }
+                SessionEvent::Services { address, services } => {
+                    let device = devices.entry(*address).or_default();
+                    device.services = services.clone();
+                    for characteristic in services.iter().flat_map(|s| &s.characteristics) {
+                        device
+                            .characteristics
+                            .insert(characteristic_key(characteristic), characteristic.clone());
+                    }
+                }
+                SessionEvent::Characteristics {
+                    address,
+                    characteristics,
+                } => {
+                    let device = devices.entry(*address).or_default();
+                    for characteristic in characteristics {
+                        device
+                            .characteristics
+                            .insert(characteristic_key(characteristic), characteristic.clone());
+                    }
+                }
+                SessionEvent::Descriptors {
+                    address,
+                    characteristic,
+                    descriptors,
+                } => {
+                    let device = devices.entry(*address).or_default();
+                    for descriptor in descriptors {
+                        device.descriptor(
+                            characteristic.handle,
+                            characteristic.uuid,
+                            descriptor.uuid,
+                        );
+                    }
+                }
+                SessionEvent::ReadDescriptor {
+                    address,
+                    descriptor,
+                    result,
+                } => {
+                    let device = devices.entry(*address).or_default();
+                    let mock_descriptor = device.descriptor(
+                        descriptor.characteristic_handle,
+                        descriptor.characteristic_uuid,
+                        descriptor.uuid,
+                    );
+                    if let Ok(value) = result {
+                        mock_descriptor.value = value.clone();
+                    }
+                }
+                SessionEvent::Read {
+                    address,
+                    characteristic,
+                    result,
+                } => {
+                    let device = devices.entry(*address).or_default();
+                    device
+                        .reads
+                        .entry(characteristic.uuid)
+                        .or_default()
+                        .lock()
+                        .unwrap()
+                        .push_back(result.clone());
+                }
+                SessionEvent::Write {
+                    address,
+                    characteristic,
+                    result,
+                    ..
+                } => {
+                    let device = devices.entry(*address).or_default();
+                    device
+                        .writes
+                        .entry(characteristic.uuid)
+                        .or_default()
+                        .lock()
+                        .unwrap()
+                        .push_back(result.clone());
+                }
+                _ => {}
+            }
+        }
+
+        let replay = Replay {
+            adapter: Adapter::new(),
+            entries,
+            devices,
+        };
+        for (address, device) in &replay.devices {
+            if !device.advertised {
+                let properties = PeripheralProperties {
+                    address: *address,
+                    ..Default::default()
+                };
+                replay.add_device(properties);
+            }
+        }
+        replay
+    }
+
+    /// Returns the adapter through which the session is replayed.
+    pub fn adapter(&self) -> Adapter {
+        self.adapter.clone()
+    }
+
+    /// Replays the advertisements, notifications and disconnections of the session at the times
+    /// they were recorded, returning once the last has been replayed. Devices which are lost
+    /// during the session are taken out of range of the adapter.
+    pub async fn run(&self) {
+        let start = Instant::now();
+        for entry in &self.entries {
+            let due = start + Duration::from_millis(entry.elapsed_ms);
+            runtime::sleep(due.saturating_duration_since(Instant::now())).await;
+            match &entry.event {
+                SessionEvent::Advertisement(recorded) => {
+                    let properties = PeripheralProperties::from(recorded);
+                    match self.adapter.device(recorded.address) {
+                        Some(device) => device.set_properties(properties),
+                        None => self.add_device(properties),
+                    }
+                }
+                SessionEvent::Notification {
+                    address,
+                    uuid,
+                    value,
+                    ..
+                } => {
+                    if let Some(device) = self.adapter.device(*address) {
+                        if let Err(e) = device.notify(*uuid, value) {
+                            debug!("Failed to replay notification from {}: {}", address, e);
+                        }
+                    }
+                }
+                SessionEvent::Central(CentralEvent::DeviceDisconnected(address, reason))
+                    if *reason != DisconnectReason::UserRequest =>
+                {
+                    if let Some(device) = self.adapter.device(*address) {
+                        device.disconnect_remotely(*reason);
+                    }
+                }
+                SessionEvent::Central(CentralEvent::DeviceLost(address)) => {
+                    self.adapter.remove_device(*address);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Brings a recorded device into range, answering reads and writes with its recorded results.
+    fn add_device(&self, properties: PeripheralProperties) {
+        let address = properties.address;
+        let recorded = match self.devices.get(&address) {
+            Some(recorded) => recorded,
+            None => return,
+        };
+        let device = self.adapter.add_device(MockDevice {
+            properties,
+            services: recorded.gatt(),
+            ..Default::default()
+        });
+        for (uuid, responses) in &recorded.reads {
+            let responses = responses.clone();
+            if let Err(e) = device.set_read_handler(*uuid, move |_| next_response(&responses)) {
+                debug!("Can't replay reads from {}: {}", address, e);
+            }
+        }
+        for (uuid, responses) in &recorded.writes {
+            let responses = responses.clone();
+            if let Err(e) = device.set_write_handler(*uuid, move |_, _| next_response(&responses)) {
+                debug!("Can't replay writes to {}: {}", address, e);
+            }
+        }
+    }
+}
+
+fn characteristic_key(characteristic: &Characteristic) -> (u16, Uuid, Uuid) {
+    (
+        characteristic.handle,
+        characteristic.service_uuid,
+        characteristic.uuid,
+    )
+}
@@ -0,0 +1,389 @@
+//! The implementation of the interface in `btleplug.udl`, from which
+//! [UniFFI](https://mozilla.github.io/uniffi-rs/) generates bindings for Kotlin, Swift and Python,
+//! with the `uniffi-bindings` feature.
+//!
+//! These wrap the platform [`Manager`](crate::platform::Manager),
+//! [`Adapter`](crate::platform::Adapter) and [`Peripheral`](crate::platform::Peripheral) with
+//! blocking methods, which run on a runtime owned by the manager, so that they can be called from
+//! any thread of the foreign language. Addresses are passed as strings like
+//! `"AA:BB:CC:DD:EE:FF"`, and UUIDs in their hyphenated form. Listeners are called on the
+//! runtime's threads.
+
+use crate::{
+    api::{self, BDAddr, Central as _, Manager as _, Peripheral as _, ScanFilter, WriteType},
+    platform, Error,
+};
+use futures::{
+    future::{self, AbortHandle},
+    stream::StreamExt,
+};
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+uniffi::include_scaffolding!("btleplug");
+
+#[derive(Debug, thiserror::Error)]
+pub enum BtleplugError {
+    #[error("Permission denied")]
+    PermissionDenied,
+    #[error("Device not found")]
+    DeviceNotFound,
+    #[error("Not connected")]
+    NotConnected,
+    #[error("{0}")]
+    NotSupported(String),
+    #[error("{0}")]
+    TimedOut(String),
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("{0}")]
+    Att(String),
+    #[error("{0}")]
+    InsufficientAuthentication(String),
+    #[error("{0}")]
+    DeviceUnreachable(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<Error> for BtleplugError {
+    fn from(error: Error) -> Self {
+        let message = error.to_string();
+        match error {
+            Error::PermissionDenied => BtleplugError::PermissionDenied,
+            Error::DeviceNotFound => BtleplugError::DeviceNotFound,
+            Error::NotConnected => BtleplugError::NotConnected,
+            Error::NotSupported(_) => BtleplugError::NotSupported(message),
+            Error::TimedOut(_) | Error::ConnectionTimeout(_) => BtleplugError::TimedOut(message),
+            Error::Uuid(_) | Error::InvalidBDAddr(_) => BtleplugError::InvalidArgument(message),
+            Error::Att(_) => BtleplugError::Att(message),
+            Error::InsufficientAuthentication(_) => {
+                BtleplugError::InsufficientAuthentication(message)
+            }
+            Error::DeviceUnreachable(_) => BtleplugError::DeviceUnreachable(message),
+            Error::Platform { .. } | Error::Other(_) => BtleplugError::Other(message),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, BtleplugError>;
+
+pub enum CentralEventKind {
+    DeviceDiscovered,
+    DeviceUpdated,
+    DeviceConnected,
+    DeviceDisconnected,
+    DeviceLost,
+    RssiUpdate,
+}
+
+pub struct CentralEvent {
+    pub kind: CentralEventKind,
+    pub address: String,
+    pub rssi: Option<i16>,
+}
+
+impl CentralEvent {
+    fn from_central_event(event: api::CentralEvent) -> Option<Self> {
+        let (kind, address, rssi) = match event {
+            api::CentralEvent::DeviceDiscovered(address) => {
+                (CentralEventKind::DeviceDiscovered, address, None)
+            }
+            api::CentralEvent::DeviceUpdated { address, .. } => {
+                (CentralEventKind::DeviceUpdated, address, None)
+            }
+            api::CentralEvent::DeviceConnected(address) => {
+                (CentralEventKind::DeviceConnected, address, None)
+            }
+            api::CentralEvent::DeviceDisconnected(address, _) => {
+                (CentralEventKind::DeviceDisconnected, address, None)
+            }
+            api::CentralEvent::DeviceLost(address) => (CentralEventKind::DeviceLost, address, None),
+            api::CentralEvent::RssiUpdate { address, rssi } => {
+                (CentralEventKind::RssiUpdate, address, Some(rssi))
+            }
+            _ => return None,
+        };
+        Some(CentralEvent {
+            kind,
+            address: address.to_string(),
+            rssi,
+        })
+    }
+}
+
+pub struct PeripheralProperties {
+    pub address: String,
+    pub local_name: Option<String>,
+    pub tx_power_level: Option<i8>,
+    pub rssi: Option<i16>,
+    pub services: Vec<String>,
+}
+
+impl From<api::PeripheralProperties> for PeripheralProperties {
+    fn from(properties: api::PeripheralProperties) -> Self {
+        PeripheralProperties {
+            address: properties.address.to_string(),
+            local_name: properties.local_name,
+            tx_power_level: properties.tx_power_level,
+            rssi: properties.rssi,
+            services: properties
+                .services
+                .iter()
+                .map(|service| service.to_string())
+                .collect(),
+        }
+    }
+}
+
+pub struct Characteristic {
+    pub uuid: String,
+    pub service_uuid: String,
+}
+
+pub trait EventListener: Send + Sync + Debug {
+    fn on_event(&self, event: CentralEvent);
+}
+
+pub trait NotificationListener: Send + Sync + Debug {
+    fn on_notification(&self, characteristic: String, value: Vec<u8>);
+}
+
+fn parse_uuid(uuid: &str) -> Result<Uuid> {
+    Uuid::parse_str(uuid).map_err(|e| BtleplugError::InvalidArgument(e.to_string()))
+}
+
+/// Stops the task forwarding events to a listener, if there is one.
+fn stop_listener(listener: &Mutex<Option<AbortHandle>>) {
+    if let Some(listener) = listener.lock().unwrap().take() {
+        listener.abort();
+    }
+}
+
+pub struct Manager {
+    runtime: Arc<Runtime>,
+    manager: platform::Manager,
+}
+
+impl Manager {
+    pub fn new() -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("btleplug")
+            .build()
+            .map_err(|e| BtleplugError::Other(e.to_string()))?;
+        let manager = runtime.block_on(platform::Manager::new())?;
+        Ok(Manager {
+            runtime: Arc::new(runtime),
+            manager,
+        })
+    }
+
+    pub fn adapters(&self) -> Result<Vec<Arc<Adapter>>> {
+        let adapters = self.runtime.block_on(self.manager.adapters())?;
+        Ok(adapters
+            .into_iter()
+            .map(|adapter| {
+                Arc::new(Adapter {
+                    runtime: self.runtime.clone(),
+                    adapter,
+                    listener: Mutex::new(None),
+                })
+            })
+            .collect())
+    }
+}
+
+pub struct Adapter {
+    runtime: Arc<Runtime>,
+    adapter: platform::Adapter,
+    listener: Mutex<Option<AbortHandle>>,
+}
+
+impl Adapter {
+    fn wrap(&self, peripheral: platform::Peripheral) -> Arc<Peripheral> {
+        Arc::new(Peripheral {
+            runtime: self.runtime.clone(),
+            peripheral,
+            listener: Mutex::new(None),
+        })
+    }
+
+    pub fn start_scan(&self, services: Vec<String>) -> Result<()> {
+        let filter = ScanFilter {
+            services: services
+                .iter()
+                .map(|service| parse_uuid(service))
+                .collect::<Result<_>>()?,
+            ..ScanFilter::default()
+        };
+        Ok(self.runtime.block_on(self.adapter.start_scan(filter))?)
+    }
+
+    pub fn stop_scan(&self) -> Result<()> {
+        Ok(self.runtime.block_on(self.adapter.stop_scan())?)
+    }
+
+    pub fn peripherals(&self) -> Result<Vec<Arc<Peripheral>>> {
+        let peripherals = self.runtime.block_on(self.adapter.peripherals())?;
+        Ok(peripherals
+            .into_iter()
+            .map(|peripheral| self.wrap(peripheral))
+            .collect())
+    }
+
+    pub fn peripheral(&self, address: String) -> Result<Arc<Peripheral>> {
+        let address = address.parse::<BDAddr>().map_err(Error::from)?;
+        let peripheral = self.runtime.block_on(self.adapter.peripheral(address))?;
+        Ok(self.wrap(peripheral))
+    }
+
+    /// Calls the listener with each event from the adapter, in place of any listener set before.
+    pub fn set_event_listener(&self, listener: Box<dyn EventListener>) -> Result<()> {
+        stop_listener(&self.listener);
+        let events = self.runtime.block_on(self.adapter.events())?;
+        let (forward, abort_handle) = future::abortable(events.for_each(move |event| {
+            if let Some(event) = CentralEvent::from_central_event(event) {
+                listener.on_event(event);
+            }
+            future::ready(())
+        }));
+        self.runtime.spawn(forward);
+        *self.listener.lock().unwrap() = Some(abort_handle);
+        Ok(())
+    }
+
+    pub fn clear_event_listener(&self) {
+        stop_listener(&self.listener);
+    }
+}
+
+impl Drop for Adapter {
+    fn drop(&mut self) {
+        stop_listener(&self.listener);
+    }
+}
+
+pub struct Peripheral {
+    runtime: Arc<Runtime>,
+    peripheral: platform::Peripheral,
+    listener: Mutex<Option<AbortHandle>>,
+}
+
+impl Peripheral {
+    /// Returns the first discovered characteristic with the given UUID.
+    fn characteristic(&self, uuid: &str) -> Result<api::Characteristic> {
+        let uuid = parse_uuid(uuid)?;
+        self.peripheral
+            .characteristics()
+            .into_iter()
+            .find(|characteristic| characteristic.uuid == uuid)
+            .ok_or_else(|| {
+                BtleplugError::InvalidArgument(format!(
+                    "Characteristic {} hasn't been discovered",
+                    uuid
+                ))
+            })
+    }
+
+    pub fn address(&self) -> String {
+        self.peripheral.address().to_string()
+    }
+
+    pub fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        let properties = self.runtime.block_on(self.peripheral.properties())?;
+        Ok(properties.map(PeripheralProperties::from))
+    }
+
+    pub fn connect(&self) -> Result<()> {
+        Ok(self.runtime.block_on(self.peripheral.connect())?)
+    }
+
+    pub fn disconnect(&self) -> Result<()> {
+        Ok(self.runtime.block_on(self.peripheral.disconnect())?)
+    }
+
+    pub fn is_connected(&self) -> Result<bool> {
+        Ok(self.runtime.block_on(self.peripheral.is_connected())?)
+    }
+
+    pub fn discover_services(&self) -> Result<()> {
+        self.runtime.block_on(self.peripheral.discover_services())?;
+        Ok(())
+    }
+
+    pub fn characteristics(&self) -> Vec<Characteristic> {
+        self.peripheral
+            .characteristics()
+            .into_iter()
+            .map(|characteristic| Characteristic {
+                uuid: characteristic.uuid.to_string(),
+                service_uuid: characteristic.service_uuid.to_string(),
+            })
+            .collect()
+    }
+
+    pub fn read(&self, characteristic: String) -> Result<Vec<u8>> {
+        let characteristic = self.characteristic(&characteristic)?;
+        let value = self
+            .runtime
+            .block_on(self.peripheral.read(&characteristic))?;
+        Ok(value.to_vec())
+    }
+
+    pub fn write(&self, characteristic: String, value: Vec<u8>, with_response: bool) -> Result<()> {
+        let characteristic = self.characteristic(&characteristic)?;
+        let write_type = if with_response {
+            WriteType::WithResponse
+        } else {
+            WriteType::WithoutResponse
+        };
+        Ok(self
+            .runtime
+            .block_on(self.peripheral.write(&characteristic, &value, write_type))?)
+    }
+
+    pub fn subscribe(&self, characteristic: String) -> Result<()> {
+        let characteristic = self.characteristic(&characteristic)?;
+        Ok(self
+            .runtime
+            .block_on(self.peripheral.subscribe(&characteristic))?)
+    }
+
+    pub fn unsubscribe(&self, characteristic: String) -> Result<()> {
+        let characteristic = self.characteristic(&characteristic)?;
+        Ok(self
+            .runtime
+            .block_on(self.peripheral.unsubscribe(&characteristic))?)
+    }
+
+    /// Calls the listener with each notification or indication from the peripheral, in place of
+    /// any listener set before.
+    pub fn set_notification_listener(&self, listener: Box<dyn NotificationListener>) -> Result<()> {
+        stop_listener(&self.listener);
+        let notifications = self.runtime.block_on(self.peripheral.notifications())?;
+        let (forward, abort_handle) =
+            future::abortable(notifications.for_each(move |notification| {
+                listener
+                    .on_notification(notification.uuid.to_string(), notification.value.to_vec());
+                future::ready(())
+            }));
+        self.runtime.spawn(forward);
+        *self.listener.lock().unwrap() = Some(abort_handle);
+        Ok(())
+    }
+
+    pub fn clear_notification_listener(&self) {
+        stop_listener(&self.listener);
+    }
+}
+
+impl Drop for Peripheral {
+    fn drop(&mut self) {
+        stop_listener(&self.listener);
+    }
+}
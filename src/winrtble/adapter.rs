@@ -11,31 +11,93 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use super::{ble::watcher::BLEWatcher, peripheral::Peripheral};
+use super::{
+    bindings,
+    ble::{
+        paired_watcher::BLEPairedWatcher,
+        publisher::BLEPublisher,
+        service_provider::{BLEServiceProvider, ServerEventSenders},
+        watcher::BLEWatcher,
+    },
+    peripheral::Peripheral,
+    utils::{self, DataSections},
+};
 use crate::{
-    api::{BDAddr, Central, CentralEvent},
-    common::adapter_manager::AdapterManager,
+    api::{
+        AdapterMetrics, AdapterState, Advertisement, BDAddr, Central, CentralEvent, EventFilter,
+        GattServer, GattServerEvent, IdentityResolvingKey, LocalService, PeriodicAdvertisement,
+        ScanFilter,
+    },
+    common::{adapter_manager::AdapterManager, util},
     Error, Result,
 };
 use async_trait::async_trait;
+use bindings::Windows::Devices::Bluetooth::BluetoothLEDevice;
+use bindings::Windows::Devices::Enumeration::DeviceInformation;
+use bindings::Windows::Devices::Radios::{Radio, RadioAccessStatus, RadioState};
+use bindings::Windows::Foundation::TypedEventHandler;
+use dashmap::DashMap;
+use futures::channel::mpsc;
 use futures::stream::Stream;
 use std::convert::TryInto;
 use std::fmt::{self, Debug, Formatter};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
 
 /// Implementation of [api::Central](crate::api::Central).
 #[derive(Clone)]
 pub struct Adapter {
+    radio: Radio,
     watcher: Arc<Mutex<BLEWatcher>>,
+    paired_watcher: Arc<Mutex<Option<BLEPairedWatcher>>>,
+    publisher: Arc<Mutex<Option<BLEPublisher>>>,
     manager: AdapterManager<Peripheral>,
+    local_services: Arc<DashMap<Uuid, Arc<BLEServiceProvider>>>,
+    server_event_senders: ServerEventSenders,
 }
 
 impl Adapter {
-    pub(crate) fn new() -> Self {
-        let watcher = Arc::new(Mutex::new(BLEWatcher::new()));
-        let manager = AdapterManager::default();
-        Adapter { watcher, manager }
+    pub(crate) fn new(radio: Radio) -> Self {
+        let watcher = BLEWatcher::new();
+        let manager: AdapterManager<Peripheral> = AdapterManager::default();
+        let manager_clone = manager.clone();
+        watcher
+            .on_stopped(Box::new(move || {
+                manager_clone.emit(CentralEvent::ScanStopped)
+            }))
+            .unwrap();
+        let watcher = Arc::new(Mutex::new(watcher));
+        let manager_clone = manager.clone();
+        let state_changed_handler = TypedEventHandler::new(move |sender: &Option<Radio>, _| {
+            if let Some(sender) = sender {
+                let state = utils::to_adapter_state(sender.State()?);
+                manager_clone.emit(CentralEvent::AdapterStateChanged(state));
+            }
+            Ok(())
+        });
+        radio.StateChanged(&state_changed_handler).unwrap();
+        Adapter {
+            radio,
+            watcher,
+            paired_watcher: Arc::new(Mutex::new(None)),
+            publisher: Arc::new(Mutex::new(None)),
+            manager,
+            local_services: Arc::new(DashMap::new()),
+            server_event_senders: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Returns the local service with the given UUID. It is cloned out of the map, so that the map
+    /// isn't locked while using it.
+    fn local_service(&self, service: Uuid) -> Result<Arc<BLEServiceProvider>> {
+        self.local_services
+            .get(&service)
+            .map(|provider| provider.clone())
+            .ok_or_else(|| {
+                Error::Other(format!("Local service with UUID {} not found.", service).into())
+            })
     }
 }
 
@@ -52,33 +114,120 @@ impl Central for Adapter {
     type Peripheral = Peripheral;
 
     async fn events(&self) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
-        Ok(self.manager.event_stream())
+        Ok(self.manager.event_stream(EventFilter::default()))
     }
 
-    async fn start_scan(&self) -> Result<()> {
+    async fn events_filtered(
+        &self,
+        filter: EventFilter,
+    ) -> Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>> {
+        Ok(self.manager.event_stream(filter))
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        // The filter is applied as advertisements are received, so that it behaves the same as on
+        // other platforms.
+        let scan_type = filter.scan_type;
+        self.manager.set_scan_filter(filter);
         let watcher = self.watcher.lock().unwrap();
+        let was_scanning = watcher.is_scanning()?;
         let manager = self.manager.clone();
-        watcher.start(Box::new(move |args| {
-            let bluetooth_address = args.BluetoothAddress().unwrap();
-            let address = bluetooth_address.try_into().unwrap();
-            if let Some(mut entry) = manager.peripheral_mut(address) {
-                entry.value_mut().update_properties(args);
-                manager.emit(CentralEvent::DeviceUpdated(address));
-            } else {
-                let peripheral = Peripheral::new(manager.clone(), address);
-                peripheral.update_properties(args);
-                manager.add_peripheral(address, peripheral);
-                manager.emit(CentralEvent::DeviceDiscovered(address));
-            }
-        }))
+        // Advertisements are delivered one at a time, so they share a buffer for their data.
+        let data_sections = Mutex::new(DataSections::default());
+        watcher.start(
+            scan_type,
+            Box::new(move |args| {
+                util::in_event_pump("winrt", || {
+                    let mut data_sections = data_sections.lock().unwrap();
+                    manager.metrics().advertisement_received();
+                    let address: BDAddr = match args
+                        .BluetoothAddress()
+                        .ok()
+                        .and_then(|address| address.try_into().ok())
+                    {
+                        Some(address) => address,
+                        None => return,
+                    };
+                    // The peripheral is cloned out of the map, rather than borrowed, so the map
+                    // isn't locked while the advertisement is parsed. A new one is added before
+                    // it is updated, as that may emit events for it.
+                    let (peripheral, added) = manager.peripheral_or_insert_with(address, || {
+                        Peripheral::new(manager.clone(), address)
+                    });
+                    let changes = peripheral.update_properties(args, &mut data_sections);
+                    if added {
+                        peripheral.emit_advertisement(CentralEvent::DeviceDiscovered(address));
+                    } else {
+                        peripheral
+                            .emit_advertisement(CentralEvent::DeviceUpdated { address, changes });
+                    }
+                })
+            }),
+        )?;
+        drop(watcher);
+
+        // Paired devices may not be advertising, so announce them as well.
+        let paired_watcher = BLEPairedWatcher::new()?;
+        let manager = self.manager.clone();
+        paired_watcher.start(Box::new(move |address, name| {
+            util::in_event_pump("winrt", || {
+                let (peripheral, _) = manager.peripheral_or_insert_with(address, || {
+                    Peripheral::new(manager.clone(), address)
+                });
+                peripheral.update_name(&name);
+                peripheral.emit_advertisement(CentralEvent::DeviceDiscovered(address));
+            })
+        }))?;
+        if let Some(previous) = self.paired_watcher.lock().unwrap().replace(paired_watcher) {
+            previous.stop()?;
+        }
+
+        if !was_scanning {
+            self.manager.emit(CentralEvent::ScanStarted);
+        }
+        Ok(())
     }
 
     async fn stop_scan(&self) -> Result<()> {
         let watcher = self.watcher.lock().unwrap();
-        watcher.stop().unwrap();
+        watcher.stop()?;
+        if let Some(paired_watcher) = self.paired_watcher.lock().unwrap().take() {
+            paired_watcher.stop()?;
+        }
         Ok(())
     }
 
+    async fn is_scanning(&self) -> Result<bool> {
+        self.watcher.lock().unwrap().is_scanning()
+    }
+
+    async fn set_powered(&self, powered: bool) -> Result<()> {
+        let state = if powered {
+            RadioState::On
+        } else {
+            RadioState::Off
+        };
+        let mut status = Radio::RequestAccessAsync()?.await?;
+        if status == RadioAccessStatus::Allowed {
+            status = self.radio.SetStateAsync(state)?.await?;
+        }
+        if status == RadioAccessStatus::Allowed {
+            Ok(())
+        } else if status == RadioAccessStatus::DeniedByUser
+            || status == RadioAccessStatus::DeniedBySystem
+        {
+            Err(Error::PermissionDenied)
+        } else {
+            Err(Error::Other(
+                "Failed to change the radio state".to_string().into(),
+            ))
+        }
+    }
+
+    async fn adapter_state(&self) -> Result<AdapterState> {
+        Ok(utils::to_adapter_state(self.radio.State()?))
+    }
+
     async fn peripherals(&self) -> Result<Vec<Peripheral>> {
         Ok(self.manager.peripherals())
     }
@@ -89,9 +238,115 @@ impl Central for Adapter {
             .ok_or(Error::DeviceNotFound)
     }
 
-    async fn add_peripheral(&self, _address: BDAddr) -> Result<Peripheral> {
+    async fn set_retain_peripherals(&self, retain: bool) -> Result<()> {
+        self.manager.set_retain_peripherals(retain);
+        Ok(())
+    }
+
+    async fn set_peripheral_ttl(&self, ttl: Option<Duration>) -> Result<()> {
+        self.manager.set_peripheral_ttl(ttl);
+        Ok(())
+    }
+
+    async fn set_max_peripherals(&self, max: Option<usize>) -> Result<()> {
+        self.manager.set_max_peripherals(max);
+        Ok(())
+    }
+
+    async fn add_identity_resolving_key(
+        &self,
+        _identity: BDAddr,
+        _irk: IdentityResolvingKey,
+    ) -> Result<()> {
+        Err(Error::NotSupported(
+            "Resolving private addresses is not supported on Windows".to_string(),
+        ))
+    }
+
+    async fn remove_identity_resolving_key(&self, _identity: BDAddr) -> Result<()> {
         Err(Error::NotSupported(
-            "Can't add a Peripheral from a BDAddr".to_string(),
+            "Resolving private addresses is not supported on Windows".to_string(),
         ))
     }
+
+    async fn add_peripheral(&self, address: BDAddr) -> Result<Peripheral> {
+        // The device itself is only looked up by its address when connecting.
+        let (peripheral, _) = self
+            .manager
+            .peripheral_or_insert_with(address, || Peripheral::new(self.manager.clone(), address));
+        Ok(peripheral)
+    }
+
+    async fn bonded_peripherals(&self) -> Result<Vec<Peripheral>> {
+        let selector = BluetoothLEDevice::GetDeviceSelectorFromPairingState(true)?;
+        let devices = DeviceInformation::FindAllAsyncAqsFilter(selector)?.await?;
+        let mut peripherals = Vec::new();
+        for device_information in devices {
+            let device = BluetoothLEDevice::FromIdAsync(device_information.Id()?)?.await?;
+            let address: BDAddr = device.BluetoothAddress()?.try_into()?;
+            let (peripheral, _) = self.manager.peripheral_or_insert_with(address, || {
+                Peripheral::new(self.manager.clone(), address)
+            });
+            peripherals.push(peripheral);
+        }
+        Ok(peripherals)
+    }
+
+    async fn start_advertising(&self, advertisement: &Advertisement) -> Result<()> {
+        let publisher = BLEPublisher::new(advertisement)?;
+        publisher.start()?;
+        *self.publisher.lock().unwrap() = Some(publisher);
+        Ok(())
+    }
+
+    async fn stop_advertising(&self) -> Result<()> {
+        if let Some(publisher) = self.publisher.lock().unwrap().take() {
+            publisher.stop()?;
+        }
+        Ok(())
+    }
+
+    async fn sync_periodic_advertising(
+        &self,
+        _address: BDAddr,
+        _sid: u8,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeriodicAdvertisement> + Send>>> {
+        Err(Error::NotSupported(
+            "Periodic advertising sync is not supported on Windows".to_string(),
+        ))
+    }
+
+    async fn metrics(&self) -> Result<AdapterMetrics> {
+        Ok(self.manager.metrics().snapshot())
+    }
+}
+
+#[async_trait]
+impl GattServer for Adapter {
+    async fn add_service(&self, service: &LocalService) -> Result<()> {
+        let provider = BLEServiceProvider::new(service, self.server_event_senders.clone()).await?;
+        self.local_services.insert(service.uuid, Arc::new(provider));
+        Ok(())
+    }
+
+    async fn remove_service(&self, service: Uuid) -> Result<()> {
+        self.local_services.remove(&service);
+        Ok(())
+    }
+
+    async fn set_value(&self, service: Uuid, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        self.local_service(service)?
+            .set_value(characteristic, value)
+    }
+
+    async fn notify(&self, service: Uuid, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        let provider = self.local_service(service)?;
+        provider.notify(characteristic, value).await
+    }
+
+    async fn server_events(&self) -> Result<Pin<Box<dyn Stream<Item = GattServerEvent> + Send>>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.server_event_senders.lock().unwrap().push(sender);
+        Ok(Box::pin(receiver))
+    }
 }
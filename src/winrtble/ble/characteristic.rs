@@ -11,11 +11,11 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use super::super::bindings;
+use super::{super::bindings, descriptor::BLEDescriptor};
 use crate::{
     api::{Characteristic, WriteType},
     winrtble::utils,
-    Error, Result,
+    AttError, Error, Result,
 };
 
 use bindings::Windows::Devices::Bluetooth::BluetoothCacheMode;
@@ -27,6 +27,9 @@ use bindings::Windows::Devices::Bluetooth::GenericAttributeProfile::{
 use bindings::Windows::Foundation::{EventRegistrationToken, TypedEventHandler};
 use bindings::Windows::Storage::Streams::{DataReader, DataWriter};
 use log::{debug, trace};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
 
 pub type NotifiyEventHandler = Box<dyn Fn(Vec<u8>) + Send>;
 
@@ -54,17 +57,43 @@ impl From<GattCharacteristicProperties> for GattClientCharacteristicConfiguratio
     }
 }
 
+/// The registration of the handler for a characteristic's notifications, which is removed when
+/// the last clone of the characteristic is dropped.
 #[derive(Debug)]
+struct NotifyToken {
+    characteristic: GattCharacteristic,
+    token: Mutex<Option<EventRegistrationToken>>,
+}
+
+impl Drop for NotifyToken {
+    fn drop(&mut self) {
+        if let Some(token) = self.token.get_mut().unwrap().take() {
+            let result = self.characteristic.RemoveValueChanged(&token);
+            if let Err(err) = result {
+                debug!("Drop:remove_connection_status_changed {:?}", err);
+            }
+        }
+    }
+}
+
+/// A characteristic of a connected device. Clones share the notification handler, so a clone can
+/// be subscribed without keeping the peripheral's map of characteristics locked.
+#[derive(Clone, Debug)]
 pub struct BLECharacteristic {
     characteristic: GattCharacteristic,
-    notify_token: Option<EventRegistrationToken>,
+    descriptors: HashMap<Uuid, BLEDescriptor>,
+    notify_token: Arc<NotifyToken>,
 }
 
 impl BLECharacteristic {
     pub fn new(characteristic: GattCharacteristic) -> Self {
         BLECharacteristic {
+            notify_token: Arc::new(NotifyToken {
+                characteristic: characteristic.clone(),
+                token: Mutex::new(None),
+            }),
             characteristic,
-            notify_token: None,
+            descriptors: HashMap::new(),
         }
     }
 
@@ -73,15 +102,15 @@ impl BLECharacteristic {
         writer.WriteBytes(data)?;
         let operation = self
             .characteristic
-            .WriteValueWithOptionAsync(writer.DetachBuffer()?, write_type.into())?;
+            .WriteValueWithResultAndOptionAsync(writer.DetachBuffer()?, write_type.into())?;
         let result = operation.await?;
-        if result == GattCommunicationStatus::Success {
-            Ok(())
-        } else {
-            Err(Error::Other(
-                format!("Windows UWP threw error on write: {:?}", result).into(),
-            ))
+        let status = result.Status()?;
+        if status == GattCommunicationStatus::ProtocolError {
+            if let Ok(code) = result.ProtocolError().and_then(|code| code.Value()) {
+                return Err(Error::Att(AttError(code)));
+            }
         }
+        utils::to_error(status)
     }
 
     pub async fn read_value(&self) -> Result<Vec<u8>> {
@@ -103,7 +132,7 @@ impl BLECharacteristic {
         }
     }
 
-    pub async fn subscribe(&mut self, on_value_changed: NotifiyEventHandler) -> Result<()> {
+    pub async fn subscribe(&self, on_value_changed: NotifiyEventHandler) -> Result<()> {
         {
             let value_handler = TypedEventHandler::new(
                 move |_: &Option<GattCharacteristic>, args: &Option<GattValueChangedEventArgs>| {
@@ -120,7 +149,7 @@ impl BLECharacteristic {
                 },
             );
             let token = self.characteristic.ValueChanged(&value_handler)?;
-            self.notify_token = Some(token);
+            *self.notify_token.token.lock().unwrap() = Some(token);
         }
         let config = self.characteristic.CharacteristicProperties()?.into();
         if config == GattClientCharacteristicConfigurationDescriptorValue::None {
@@ -141,11 +170,11 @@ impl BLECharacteristic {
         }
     }
 
-    pub async fn unsubscribe(&mut self) -> Result<()> {
-        if let Some(token) = &self.notify_token {
-            self.characteristic.RemoveValueChanged(token)?;
+    pub async fn unsubscribe(&self) -> Result<()> {
+        let token = self.notify_token.token.lock().unwrap().take();
+        if let Some(token) = token {
+            self.characteristic.RemoveValueChanged(&token)?;
         }
-        self.notify_token = None;
         let config = GattClientCharacteristicConfigurationDescriptorValue::None;
         let status = self
             .characteristic
@@ -161,21 +190,49 @@ impl BLECharacteristic {
         }
     }
 
+    pub async fn discover_descriptors(&mut self) -> Result<Vec<Uuid>> {
+        let result = self
+            .characteristic
+            .GetDescriptorsWithCacheModeAsync(BluetoothCacheMode::Uncached)?
+            .await?;
+        let status = result.Status()?;
+        if status != GattCommunicationStatus::Success {
+            return Err(Error::Other(
+                format!(
+                    "Windows UWP threw error on descriptor discovery: {:?}",
+                    status
+                )
+                .into(),
+            ));
+        }
+        self.descriptors = result
+            .Descriptors()?
+            .into_iter()
+            .map(|descriptor| {
+                let descriptor = BLEDescriptor::new(descriptor);
+                (descriptor.uuid(), descriptor)
+            })
+            .collect();
+        Ok(self.descriptors.keys().cloned().collect())
+    }
+
+    pub fn descriptor(&self, uuid: &Uuid) -> Result<&BLEDescriptor> {
+        self.descriptors
+            .get(uuid)
+            .ok_or_else(|| Error::Other(format!("Descriptor with UUID {} not found.", uuid).into()))
+    }
+
     pub fn to_characteristic(&self) -> Characteristic {
         let uuid = utils::to_uuid(&self.characteristic.Uuid().unwrap());
+        let service_uuid = utils::to_uuid(&self.characteristic.Service().unwrap().Uuid().unwrap());
+        let handle = self.characteristic.AttributeHandle().unwrap();
         let properties =
             utils::to_char_props(&self.characteristic.CharacteristicProperties().unwrap());
-        Characteristic { uuid, properties }
-    }
-}
-
-impl Drop for BLECharacteristic {
-    fn drop(&mut self) {
-        if let Some(token) = &self.notify_token {
-            let result = self.characteristic.RemoveValueChanged(token);
-            if let Err(err) = result {
-                debug!("Drop:remove_connection_status_changed {:?}", err);
-            }
+        Characteristic {
+            uuid,
+            service_uuid,
+            handle,
+            properties,
         }
     }
 }
@@ -0,0 +1,61 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::super::bindings;
+use crate::{winrtble::utils, Error, Result};
+
+use bindings::Windows::Devices::Bluetooth::BluetoothCacheMode;
+use bindings::Windows::Devices::Bluetooth::GenericAttributeProfile::{
+    GattCommunicationStatus, GattDescriptor,
+};
+use bindings::Windows::Storage::Streams::DataWriter;
+use uuid::Uuid;
+
+#[derive(Clone, Debug)]
+pub struct BLEDescriptor {
+    descriptor: GattDescriptor,
+}
+
+impl BLEDescriptor {
+    pub fn new(descriptor: GattDescriptor) -> Self {
+        BLEDescriptor { descriptor }
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        utils::to_uuid(&self.descriptor.Uuid().unwrap())
+    }
+
+    pub async fn write_value(&self, data: &[u8]) -> Result<()> {
+        let writer = DataWriter::new()?;
+        writer.WriteBytes(data)?;
+        let result = self
+            .descriptor
+            .WriteValueAsync(writer.DetachBuffer()?)?
+            .await?;
+        if result == GattCommunicationStatus::Success {
+            Ok(())
+        } else {
+            Err(Error::Other(
+                format!("Windows UWP threw error on descriptor write: {:?}", result).into(),
+            ))
+        }
+    }
+
+    pub async fn read_value(&self) -> Result<Vec<u8>> {
+        let result = self
+            .descriptor
+            .ReadValueWithCacheModeAsync(BluetoothCacheMode::Uncached)?
+            .await?;
+        if result.Status()? == GattCommunicationStatus::Success {
+            Ok(utils::to_vec(&result.Value()?))
+        } else {
+            Err(Error::Other(
+                format!("Windows UWP threw error on descriptor read: {:?}", result).into(),
+            ))
+        }
+    }
+}
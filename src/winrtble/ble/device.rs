@@ -12,25 +12,52 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 use super::super::bindings;
-use crate::{api::BDAddr, winrtble::utils, Error, Result};
+use crate::{
+    api::{AddressType, BDAddr},
+    winrtble::utils,
+    Error, Result,
+};
 use bindings::Windows::Devices::Bluetooth::GenericAttributeProfile::{
     GattCharacteristic, GattCommunicationStatus, GattDeviceService, GattDeviceServicesResult,
+    GattSession,
+};
+use bindings::Windows::Devices::Bluetooth::{
+    BluetoothCacheMode, BluetoothConnectionStatus, BluetoothLEDevice,
 };
-use bindings::Windows::Devices::Bluetooth::{BluetoothConnectionStatus, BluetoothLEDevice};
 use bindings::Windows::Foundation::{EventRegistrationToken, TypedEventHandler};
 use log::{debug, error, trace};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// A service found on the device, along with its characteristics and the UUIDs of the services
+/// it includes.
+pub struct DiscoveredService {
+    pub service: GattDeviceService,
+    pub primary: bool,
+    pub characteristics: Vec<GattCharacteristic>,
+    pub included_services: Vec<Uuid>,
+}
 
 pub type ConnectedEventHandler = Box<dyn Fn(bool) + Send>;
+pub type ServicesChangedEventHandler = Box<dyn Fn() + Send>;
 
 pub struct BLEDevice {
     device: BluetoothLEDevice,
     connection_token: EventRegistrationToken,
+    services_changed_token: EventRegistrationToken,
+    // Windows only maintains the connection for as long as this session is kept.
+    maintained_session: Option<GattSession>,
+    // Every service object handed out by Windows holds the connection open until it is closed.
+    services: Mutex<Vec<GattDeviceService>>,
+    cache_mode: BluetoothCacheMode,
 }
 
 impl BLEDevice {
     pub async fn new(
         address: BDAddr,
         connection_status_changed: ConnectedEventHandler,
+        services_changed: ServicesChangedEventHandler,
     ) -> Result<Self> {
         let async_op = BluetoothLEDevice::FromBluetoothAddressAsync(address.into())
             .map_err(|_| Error::DeviceNotFound)?;
@@ -51,17 +78,36 @@ impl BLEDevice {
         let connection_token = device
             .ConnectionStatusChanged(&connection_status_handler)
             .map_err(|_| Error::Other("Could not add connection status handler".into()))?;
+        // Windows handles Service Changed indications itself, and raises this once it has
+        // invalidated its cache of the device's services.
+        let services_changed_handler = TypedEventHandler::new(move |_, _| {
+            services_changed();
+            Ok(())
+        });
+        let services_changed_token = device
+            .GattServicesChanged(&services_changed_handler)
+            .map_err(|_| Error::Other("Could not add services changed handler".into()))?;
 
         Ok(BLEDevice {
             device,
             connection_token,
+            services_changed_token,
+            maintained_session: None,
+            services: Mutex::new(Vec::new()),
+            cache_mode: BluetoothCacheMode::Cached,
         })
     }
 
     async fn get_gatt_services(&self) -> Result<GattDeviceServicesResult> {
         let winrt_error = |e| Error::Other(format!("{:?}", e).into());
-        let async_op = self.device.GetGattServicesAsync().map_err(winrt_error)?;
+        let async_op = self
+            .device
+            .GetGattServicesWithCacheModeAsync(self.cache_mode)
+            .map_err(winrt_error)?;
         let service_result = async_op.await.map_err(winrt_error)?;
+        if let Ok(services) = service_result.Services() {
+            self.services.lock().unwrap().extend(services);
+        }
         Ok(service_result)
     }
 
@@ -71,11 +117,44 @@ impl BLEDevice {
         utils::to_error(status)
     }
 
+    /// Sets whether services and characteristics are discovered from the device itself, rather than
+    /// from the system's cache.
+    pub fn set_uncached(&mut self, uncached: bool) {
+        self.cache_mode = if uncached {
+            BluetoothCacheMode::Uncached
+        } else {
+            BluetoothCacheMode::Cached
+        };
+    }
+
+    /// Asks Windows to keep the connection open even while no GATT operations are in progress.
+    pub async fn maintain_connection(&mut self) -> Result<()> {
+        let session = GattSession::FromDeviceIdAsync(self.device.BluetoothDeviceId()?)?.await?;
+        session.SetMaintainConnection(true)?;
+        self.maintained_session = Some(session);
+        Ok(())
+    }
+
+    pub fn device(&self) -> &BluetoothLEDevice {
+        &self.device
+    }
+
+    pub fn address_type(&self) -> Result<Option<AddressType>> {
+        Ok(utils::to_address_type(self.device.BluetoothAddressType()?))
+    }
+
+    pub async fn mtu(&self) -> Result<u16> {
+        let session = GattSession::FromDeviceIdAsync(self.device.BluetoothDeviceId()?)?.await?;
+        Ok(session.MaxPduSize()?)
+    }
+
     async fn get_characteristics(
         &self,
         service: &GattDeviceService,
     ) -> std::result::Result<Vec<GattCharacteristic>, windows::Error> {
-        let async_result = service.GetCharacteristicsAsync()?.await?;
+        let async_result = service
+            .GetCharacteristicsWithCacheModeAsync(self.cache_mode)?
+            .await?;
         let status = async_result.Status();
         if status == Ok(GattCommunicationStatus::Success) {
             let results = async_result.Characteristics()?;
@@ -88,32 +167,88 @@ impl BLEDevice {
     }
 
     pub async fn discover_characteristics(&self) -> Result<Vec<GattCharacteristic>> {
+        let mut characteristics = Vec::new();
+        for mut service in self.discover_services().await? {
+            characteristics.append(&mut service.characteristics);
+        }
+        Ok(characteristics)
+    }
+
+    async fn get_included_services(
+        &self,
+        service: &GattDeviceService,
+    ) -> std::result::Result<Vec<GattDeviceService>, windows::Error> {
+        let result = service
+            .GetIncludedServicesWithCacheModeAsync(self.cache_mode)?
+            .await?;
+        if result.Status()? == GattCommunicationStatus::Success {
+            let services: Vec<_> = result.Services()?.into_iter().collect();
+            self.services
+                .lock()
+                .unwrap()
+                .extend(services.iter().cloned());
+            Ok(services)
+        } else {
+            trace!("get_included_services {:?}", result.Status());
+            Ok(vec![])
+        }
+    }
+
+    /// Returns the device's services, each with its characteristics and included services. Windows
+    /// only reports primary services directly, so secondary services are found by following the
+    /// includes.
+    pub async fn discover_services(&self) -> Result<Vec<DiscoveredService>> {
         let winrt_error = |e| Error::Other(format!("{:?}", e).into());
         let service_result = self.get_gatt_services().await?;
         let status = service_result.Status().map_err(winrt_error)?;
-        if status == GattCommunicationStatus::Success {
-            let mut characteristics = Vec::new();
-            // We need to convert the IVectorView to a Vec, because IVectorView is not Send and so
-            // can't be help past the await point below.
-            let services: Vec<_> = service_result
-                .Services()
-                .map_err(winrt_error)?
-                .into_iter()
-                .collect();
-            debug!("services {:?}", services.len());
-            for service in &services {
-                match self.get_characteristics(&service).await {
-                    Ok(mut service_characteristics) => {
-                        characteristics.append(&mut service_characteristics);
-                    }
-                    Err(e) => {
-                        error!("get_characteristics_async {:?}", e);
-                    }
+        if status != GattCommunicationStatus::Success {
+            return Ok(Vec::new());
+        }
+        // We need to convert the IVectorView to a Vec, because IVectorView is not Send and so
+        // can't be help past the await point below.
+        let mut pending: VecDeque<_> = service_result
+            .Services()
+            .map_err(winrt_error)?
+            .into_iter()
+            .map(|service| (service, true))
+            .collect();
+        debug!("services {:?}", pending.len());
+        let mut handles: HashSet<u16> = pending
+            .iter()
+            .filter_map(|(service, _)| service.AttributeHandle().ok())
+            .collect();
+        let mut services_result = Vec::new();
+        while let Some((service, primary)) = pending.pop_front() {
+            let characteristics = self
+                .get_characteristics(&service)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("get_characteristics_async {:?}", e);
+                    vec![]
+                });
+            let included = self
+                .get_included_services(&service)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("get_included_services_async {:?}", e);
+                    vec![]
+                });
+            let mut included_services = Vec::new();
+            for included_service in included {
+                included_services.push(utils::to_uuid(&included_service.Uuid()?));
+                // A service may be included by several others, or be a primary service too.
+                if handles.insert(included_service.AttributeHandle()?) {
+                    pending.push_back((included_service, false));
                 }
             }
-            return Ok(characteristics);
+            services_result.push(DiscoveredService {
+                service,
+                primary,
+                characteristics,
+                included_services,
+            });
         }
-        Ok(Vec::new())
+        Ok(services_result)
     }
 }
 
@@ -125,5 +260,27 @@ impl Drop for BLEDevice {
         if let Err(err) = result {
             debug!("Drop:remove_connection_status_changed {:?}", err);
         }
+        let result = self
+            .device
+            .RemoveGattServicesChanged(&self.services_changed_token);
+        if let Err(err) = result {
+            debug!("Drop:remove_gatt_services_changed {:?}", err);
+        }
+
+        // Windows only drops the link once nothing refers to the device any more, so close
+        // everything explicitly rather than waiting for the objects to be garbage collected.
+        for service in self.services.lock().unwrap().drain(..) {
+            if let Err(err) = service.Close() {
+                debug!("Drop:close_service {:?}", err);
+            }
+        }
+        if let Some(session) = self.maintained_session.take() {
+            if let Err(err) = session.Close() {
+                debug!("Drop:close_session {:?}", err);
+            }
+        }
+        if let Err(err) = self.device.Close() {
+            debug!("Drop:close_device {:?}", err);
+        }
     }
 }
@@ -12,5 +12,10 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 pub mod characteristic;
+pub mod descriptor;
 pub mod device;
+pub mod paired_watcher;
+pub mod pairing;
+pub mod publisher;
+pub mod service_provider;
 pub mod watcher;
@@ -0,0 +1,59 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::super::bindings;
+use crate::{api::BDAddr, Result};
+use bindings::Windows::Devices::Bluetooth::BluetoothLEDevice;
+use bindings::Windows::Devices::Enumeration::{
+    DeviceInformation, DeviceWatcher, DeviceWatcherStatus,
+};
+use bindings::Windows::Foundation::TypedEventHandler;
+use std::convert::TryInto;
+
+/// Called with the address and the name Windows has cached for each paired device.
+pub type PairedDeviceEventHandler = Box<dyn Fn(BDAddr, String) + Send>;
+
+/// Watches for LE devices which are paired with this computer, whether or not they are currently
+/// advertising.
+pub struct BLEPairedWatcher {
+    watcher: DeviceWatcher,
+}
+
+impl BLEPairedWatcher {
+    pub fn new() -> Result<Self> {
+        let selector = BluetoothLEDevice::GetDeviceSelectorFromPairingState(true)?;
+        let watcher = DeviceInformation::CreateWatcherAqsFilter(selector)?;
+        Ok(BLEPairedWatcher { watcher })
+    }
+
+    pub fn start(&self, on_added: PairedDeviceEventHandler) -> Result<()> {
+        let handler = TypedEventHandler::new(
+            move |_sender: &Option<DeviceWatcher>, info: &Option<DeviceInformation>| {
+                if let Some(info) = info {
+                    let device = BluetoothLEDevice::FromIdAsync(info.Id()?)?.get()?;
+                    if let Ok(address) = device.BluetoothAddress()?.try_into() {
+                        on_added(address, info.Name()?.to_string());
+                    }
+                }
+                Ok(())
+            },
+        );
+        self.watcher.Added(&handler)?;
+        self.watcher.Start()?;
+        Ok(())
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        let status = self.watcher.Status()?;
+        if status == DeviceWatcherStatus::Started
+            || status == DeviceWatcherStatus::EnumerationCompleted
+        {
+            self.watcher.Stop()?;
+        }
+        Ok(())
+    }
+}
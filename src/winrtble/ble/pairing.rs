@@ -0,0 +1,122 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::super::bindings;
+use crate::{
+    api::{BDAddr, PairingAgent, SecurityLevel},
+    Error, Result,
+};
+use bindings::Windows::Devices::Bluetooth::BluetoothLEDevice;
+use bindings::Windows::Devices::Enumeration::{
+    DeviceInformationCustomPairing, DevicePairingKinds, DevicePairingProtectionLevel,
+    DevicePairingRequestedEventArgs, DevicePairingResult, DevicePairingResultStatus,
+};
+use bindings::Windows::Foundation::TypedEventHandler;
+use log::trace;
+use std::sync::Arc;
+use windows::HSTRING;
+
+/// Pairs with the device with the given address, either through the system's pairing prompts or
+/// with the given agent.
+pub async fn pair(address: BDAddr, agent: Option<Arc<dyn PairingAgent>>) -> Result<()> {
+    let device = BluetoothLEDevice::FromBluetoothAddressAsync(address.into())?
+        .await
+        .map_err(|_| Error::DeviceNotFound)?;
+    let pairing = device.DeviceInformation()?.Pairing()?;
+    let result = match agent {
+        None => pairing.PairAsync()?.await?,
+        Some(agent) => {
+            let custom = pairing.Custom()?;
+            let handler = TypedEventHandler::new(
+                move |_sender: &Option<DeviceInformationCustomPairing>,
+                      args: &Option<DevicePairingRequestedEventArgs>| {
+                    if let Some(args) = args {
+                        on_pairing_requested(address, agent.as_ref(), args)?;
+                    }
+                    Ok(())
+                },
+            );
+            let token = custom.PairingRequested(&handler)?;
+            let kinds = DevicePairingKinds::ConfirmOnly
+                | DevicePairingKinds::DisplayPin
+                | DevicePairingKinds::ProvidePin
+                | DevicePairingKinds::ConfirmPinMatch;
+            let result = custom.PairAsync(kinds)?.await;
+            custom.RemovePairingRequested(&token)?;
+            result?
+        }
+    };
+    to_result(&result)
+}
+
+/// Pairs with the device with the given address if it isn't already paired with at least the
+/// protection the given security level needs. Windows encrypts the link with the keys from pairing.
+pub async fn request_security(address: BDAddr, level: SecurityLevel) -> Result<()> {
+    let device = BluetoothLEDevice::FromBluetoothAddressAsync(address.into())?
+        .await
+        .map_err(|_| Error::DeviceNotFound)?;
+    let pairing = device.DeviceInformation()?.Pairing()?;
+    let protection_level = match level {
+        SecurityLevel::Encrypted => DevicePairingProtectionLevel::Encryption,
+        SecurityLevel::Authenticated => DevicePairingProtectionLevel::EncryptionAndAuthentication,
+    };
+    if pairing.IsPaired()? && pairing.ProtectionLevel()?.0 >= protection_level.0 {
+        return Ok(());
+    }
+    let result = pairing
+        .PairWithProtectionLevelAsync(protection_level)?
+        .await?;
+    to_result(&result)
+}
+
+fn on_pairing_requested(
+    address: BDAddr,
+    agent: &dyn PairingAgent,
+    args: &DevicePairingRequestedEventArgs,
+) -> windows::Result<()> {
+    let kind = args.PairingKind()?;
+    trace!("pairing requested {:?}", kind);
+    let passkey = || args.Pin().ok().and_then(|pin| pin.to_string().parse().ok());
+    if kind == DevicePairingKinds::ConfirmOnly {
+        if agent.confirm(address) {
+            args.Accept()?;
+        }
+    } else if kind == DevicePairingKinds::DisplayPin {
+        if let Some(passkey) = passkey() {
+            if agent.display_passkey(address, passkey) {
+                args.Accept()?;
+            }
+        }
+    } else if kind == DevicePairingKinds::ConfirmPinMatch {
+        if let Some(passkey) = passkey() {
+            if agent.confirm_passkey(address, passkey) {
+                args.Accept()?;
+            }
+        }
+    } else if kind == DevicePairingKinds::ProvidePin {
+        if let Some(passkey) = agent.request_passkey(address) {
+            args.AcceptWithPin(HSTRING::from(format!("{:06}", passkey)))?;
+        }
+    }
+    // Not accepting the request rejects it.
+    Ok(())
+}
+
+fn to_result(result: &DevicePairingResult) -> Result<()> {
+    let status = result.Status()?;
+    if status == DevicePairingResultStatus::Paired
+        || status == DevicePairingResultStatus::AlreadyPaired
+    {
+        Ok(())
+    } else if status == DevicePairingResultStatus::AccessDenied
+        || status == DevicePairingResultStatus::RejectedByHandler
+    {
+        Err(Error::PermissionDenied)
+    } else {
+        Err(Error::Other(format!("Pairing failed: {:?}", status).into()))
+    }
+}
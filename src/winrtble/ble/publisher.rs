@@ -0,0 +1,57 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::super::bindings;
+use crate::{api::Advertisement, Error, Result};
+use bindings::Windows::Devices::Bluetooth::Advertisement::*;
+use bindings::Windows::Storage::Streams::DataWriter;
+use log::debug;
+
+pub struct BLEPublisher {
+    publisher: BluetoothLEAdvertisementPublisher,
+}
+
+impl BLEPublisher {
+    pub fn new(advertisement: &Advertisement) -> Result<Self> {
+        // The publisher rejects advertisements containing the section types Windows reserves for
+        // itself, which include the local name and the service UUID lists.
+        if advertisement.local_name.is_some() || !advertisement.services.is_empty() {
+            return Err(Error::NotSupported(
+                "Windows can only advertise manufacturer data".to_string(),
+            ));
+        }
+        let publisher = BluetoothLEAdvertisementPublisher::new()?;
+        let manufacturer_data = publisher.Advertisement()?.ManufacturerData()?;
+        for (company_id, data) in &advertisement.manufacturer_data {
+            let writer = DataWriter::new()?;
+            writer.WriteBytes(data)?;
+            let section = BluetoothLEManufacturerData::new()?;
+            section.SetCompanyId(*company_id)?;
+            section.SetData(writer.DetachBuffer()?)?;
+            manufacturer_data.Append(&section)?;
+        }
+        Ok(BLEPublisher { publisher })
+    }
+
+    pub fn start(&self) -> Result<()> {
+        self.publisher.Start()?;
+        Ok(())
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        self.publisher.Stop()?;
+        Ok(())
+    }
+}
+
+impl Drop for BLEPublisher {
+    fn drop(&mut self) {
+        if let Err(err) = self.publisher.Stop() {
+            debug!("Drop:stop_publisher {:?}", err);
+        }
+    }
+}
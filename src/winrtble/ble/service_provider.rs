@@ -0,0 +1,284 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::super::bindings;
+use crate::{
+    api::{GattServerEvent, LocalCharacteristic, LocalService},
+    common::util,
+    winrtble::utils,
+    Error, Result,
+};
+use bindings::Windows::Devices::Bluetooth::BluetoothError;
+use bindings::Windows::Devices::Bluetooth::GenericAttributeProfile::{
+    GattLocalCharacteristic, GattLocalCharacteristicParameters, GattReadRequest,
+    GattReadRequestedEventArgs, GattServiceProvider, GattServiceProviderAdvertisingParameters,
+    GattWriteOption, GattWriteRequest, GattWriteRequestedEventArgs,
+};
+use bindings::Windows::Foundation::{
+    AsyncOperationCompletedHandler, AsyncStatus, EventRegistrationToken, IAsyncOperation,
+    TypedEventHandler,
+};
+use bindings::Windows::Storage::Streams::DataWriter;
+use futures::channel::mpsc::UnboundedSender;
+use log::{debug, trace};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+pub type ServerEventSenders = Arc<Mutex<Vec<UnboundedSender<GattServerEvent>>>>;
+
+struct BLELocalCharacteristic {
+    uuid: Uuid,
+    characteristic: GattLocalCharacteristic,
+    value: Arc<Mutex<Vec<u8>>>,
+    read_token: EventRegistrationToken,
+    write_token: EventRegistrationToken,
+    subscribed_token: EventRegistrationToken,
+}
+
+impl BLELocalCharacteristic {
+    fn new(
+        service: Uuid,
+        local: &LocalCharacteristic,
+        characteristic: GattLocalCharacteristic,
+        event_senders: ServerEventSenders,
+    ) -> Result<Self> {
+        let uuid = local.uuid;
+        let value = Arc::new(Mutex::new(local.value.clone()));
+
+        let read_value = value.clone();
+        let read_senders = event_senders.clone();
+        let read_handler = TypedEventHandler::new(
+            move |_: &Option<GattLocalCharacteristic>,
+                  args: &Option<GattReadRequestedEventArgs>| {
+                if let Some(args) = args {
+                    // The request is answered once it's available, with the deferral keeping it
+                    // open until then, rather than blocking the thread raising the event.
+                    let deferral = args.GetDeferral()?;
+                    let value = read_value.clone();
+                    let senders = read_senders.clone();
+                    let completed_handler = AsyncOperationCompletedHandler::new(
+                        move |operation: &Option<IAsyncOperation<GattReadRequest>>,
+                              status: AsyncStatus| {
+                            let result = match operation {
+                                Some(operation) if status == AsyncStatus::Completed => {
+                                    operation.GetResults().and_then(|request| {
+                                        let writer = DataWriter::new()?;
+                                        writer.WriteBytes(&value.lock().unwrap())?;
+                                        request.RespondWithValue(writer.DetachBuffer()?)
+                                    })
+                                }
+                                _ => Ok(()),
+                            };
+                            deferral.Complete()?;
+                            if result.is_ok() {
+                                util::send_notification(
+                                    &senders,
+                                    &GattServerEvent::CharacteristicRead {
+                                        service,
+                                        characteristic: uuid,
+                                    },
+                                );
+                            }
+                            result
+                        },
+                    );
+                    args.GetRequestAsync()?.SetCompleted(&completed_handler)?;
+                }
+                Ok(())
+            },
+        );
+        let read_token = characteristic.ReadRequested(&read_handler)?;
+
+        let write_value = value.clone();
+        let write_senders = event_senders.clone();
+        let write_handler = TypedEventHandler::new(
+            move |_: &Option<GattLocalCharacteristic>,
+                  args: &Option<GattWriteRequestedEventArgs>| {
+                if let Some(args) = args {
+                    let deferral = args.GetDeferral()?;
+                    let stored_value = write_value.clone();
+                    let senders = write_senders.clone();
+                    let completed_handler = AsyncOperationCompletedHandler::new(
+                        move |operation: &Option<IAsyncOperation<GattWriteRequest>>,
+                              status: AsyncStatus| {
+                            let result = match operation {
+                                Some(operation) if status == AsyncStatus::Completed => {
+                                    operation.GetResults().and_then(|request| {
+                                        let value = utils::to_vec(&request.Value()?);
+                                        trace!("local characteristic {} written {:?}", uuid, value);
+                                        *stored_value.lock().unwrap() = value.clone();
+                                        if request.Option()? == GattWriteOption::WriteWithResponse {
+                                            request.Respond()?;
+                                        }
+                                        Ok(Some(value))
+                                    })
+                                }
+                                _ => Ok(None),
+                            };
+                            deferral.Complete()?;
+                            if let Ok(Some(value)) = &result {
+                                util::send_notification(
+                                    &senders,
+                                    &GattServerEvent::CharacteristicWritten {
+                                        service,
+                                        characteristic: uuid,
+                                        value: value.clone(),
+                                    },
+                                );
+                            }
+                            result.map(|_| ())
+                        },
+                    );
+                    args.GetRequestAsync()?.SetCompleted(&completed_handler)?;
+                }
+                Ok(())
+            },
+        );
+        let write_token = characteristic.WriteRequested(&write_handler)?;
+
+        let subscribed_count = Mutex::new(0);
+        let subscribed_handler =
+            TypedEventHandler::new(move |sender: &Option<GattLocalCharacteristic>, _| {
+                if let Some(sender) = sender {
+                    let count = sender.SubscribedClients()?.Size()?;
+                    let mut previous = subscribed_count.lock().unwrap();
+                    let event = if count > *previous {
+                        GattServerEvent::Subscribed {
+                            service,
+                            characteristic: uuid,
+                        }
+                    } else {
+                        GattServerEvent::Unsubscribed {
+                            service,
+                            characteristic: uuid,
+                        }
+                    };
+                    *previous = count;
+                    util::send_notification(&event_senders, &event);
+                }
+                Ok(())
+            });
+        let subscribed_token = characteristic.SubscribedClientsChanged(&subscribed_handler)?;
+
+        Ok(BLELocalCharacteristic {
+            uuid,
+            characteristic,
+            value,
+            read_token,
+            write_token,
+            subscribed_token,
+        })
+    }
+}
+
+impl Drop for BLELocalCharacteristic {
+    fn drop(&mut self) {
+        let results = [
+            self.characteristic.RemoveReadRequested(&self.read_token),
+            self.characteristic.RemoveWriteRequested(&self.write_token),
+            self.characteristic
+                .RemoveSubscribedClientsChanged(&self.subscribed_token),
+        ];
+        for result in results.iter() {
+            if let Err(err) = result {
+                debug!("Drop:remove_local_characteristic_handler {:?}", err);
+            }
+        }
+    }
+}
+
+/// A local GATT service, published through a `GattServiceProvider`.
+pub struct BLEServiceProvider {
+    provider: GattServiceProvider,
+    characteristics: Vec<BLELocalCharacteristic>,
+}
+
+impl BLEServiceProvider {
+    pub async fn new(service: &LocalService, event_senders: ServerEventSenders) -> Result<Self> {
+        let result = GattServiceProvider::CreateAsync(utils::to_guid(&service.uuid))?.await?;
+        if result.Error()? != BluetoothError::Success {
+            return Err(Error::Other(
+                format!(
+                    "Windows UWP threw error on service creation: {:?}",
+                    result.Error()?
+                )
+                .into(),
+            ));
+        }
+        let provider = result.ServiceProvider()?;
+        let local_service = provider.Service()?;
+
+        let mut characteristics = Vec::with_capacity(service.characteristics.len());
+        for local in &service.characteristics {
+            let parameters = GattLocalCharacteristicParameters::new()?;
+            parameters.SetCharacteristicProperties(utils::to_gatt_char_props(&local.properties))?;
+            let result = local_service
+                .CreateCharacteristicAsync(utils::to_guid(&local.uuid), &parameters)?
+                .await?;
+            if result.Error()? != BluetoothError::Success {
+                return Err(Error::Other(
+                    format!(
+                        "Windows UWP threw error on characteristic creation: {:?}",
+                        result.Error()?
+                    )
+                    .into(),
+                ));
+            }
+            characteristics.push(BLELocalCharacteristic::new(
+                service.uuid,
+                local,
+                result.Characteristic()?,
+                event_senders.clone(),
+            )?);
+        }
+
+        let advertising_parameters = GattServiceProviderAdvertisingParameters::new()?;
+        advertising_parameters.SetIsConnectable(true)?;
+        advertising_parameters.SetIsDiscoverable(true)?;
+        provider.StartAdvertisingWithParameters(&advertising_parameters)?;
+
+        Ok(BLEServiceProvider {
+            provider,
+            characteristics,
+        })
+    }
+
+    fn characteristic(&self, uuid: Uuid) -> Result<&BLELocalCharacteristic> {
+        self.characteristics
+            .iter()
+            .find(|c| c.uuid == uuid)
+            .ok_or_else(|| {
+                Error::Other(format!("Characteristic with UUID {} not found.", uuid).into())
+            })
+    }
+
+    pub fn set_value(&self, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        let characteristic = self.characteristic(characteristic)?;
+        *characteristic.value.lock().unwrap() = value.to_vec();
+        Ok(())
+    }
+
+    pub async fn notify(&self, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        let characteristic = self.characteristic(characteristic)?;
+        *characteristic.value.lock().unwrap() = value.to_vec();
+        let writer = DataWriter::new()?;
+        writer.WriteBytes(value)?;
+        characteristic
+            .characteristic
+            .NotifyValueAsync(writer.DetachBuffer()?)?
+            .await?;
+        Ok(())
+    }
+}
+
+impl Drop for BLEServiceProvider {
+    fn drop(&mut self) {
+        if let Err(err) = self.provider.StopAdvertising() {
+            debug!("Drop:stop_advertising {:?}", err);
+        }
+    }
+}
@@ -11,12 +11,13 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use super::super::bindings;
-use crate::{Error, Result};
+use super::super::{bindings, utils};
+use crate::{api::ScanType, Error, Result};
 use bindings::Windows::Devices::Bluetooth::Advertisement::*;
 use bindings::Windows::Foundation::TypedEventHandler;
 
 pub type AdvertismentEventHandler = Box<dyn Fn(&BluetoothLEAdvertisementReceivedEventArgs) + Send>;
+pub type StoppedEventHandler = Box<dyn Fn() + Send>;
 
 pub struct BLEWatcher {
     watcher: BluetoothLEAdvertisementWatcher,
@@ -24,7 +25,7 @@ pub struct BLEWatcher {
 
 impl From<windows::Error> for Error {
     fn from(err: windows::Error) -> Error {
-        Error::Other(format!("{:?}", err).into())
+        utils::from_hresult(err.code().0, err.message())
     }
 }
 
@@ -35,10 +36,15 @@ impl BLEWatcher {
         BLEWatcher { watcher }
     }
 
-    pub fn start(&self, on_received: AdvertismentEventHandler) -> Result<()> {
-        self.watcher
-            .SetScanningMode(BluetoothLEScanningMode::Active)
-            .unwrap();
+    pub fn start(&self, scan_type: ScanType, on_received: AdvertismentEventHandler) -> Result<()> {
+        let scanning_mode = match scan_type {
+            ScanType::Active => BluetoothLEScanningMode::Active,
+            ScanType::Passive => BluetoothLEScanningMode::Passive,
+        };
+        self.watcher.SetScanningMode(scanning_mode)?;
+        // Without this, Windows drops Bluetooth 5 extended advertisements rather than reporting
+        // them.
+        self.watcher.SetAllowExtendedAdvertisements(true)?;
         let handler: TypedEventHandler<
             BluetoothLEAdvertisementWatcher,
             BluetoothLEAdvertisementReceivedEventArgs,
@@ -60,4 +66,22 @@ impl BLEWatcher {
         self.watcher.Stop()?;
         Ok(())
     }
+
+    pub fn is_scanning(&self) -> Result<bool> {
+        Ok(self.watcher.Status()? == BluetoothLEAdvertisementWatcherStatus::Started)
+    }
+
+    /// Registers a handler to be called whenever the watcher stops, including when Windows stops
+    /// it because the radio was turned off.
+    pub fn on_stopped(&self, on_stopped: StoppedEventHandler) -> Result<()> {
+        let handler: TypedEventHandler<
+            BluetoothLEAdvertisementWatcher,
+            BluetoothLEAdvertisementWatcherStoppedEventArgs,
+        > = TypedEventHandler::new(move |_sender, _args| {
+            on_stopped();
+            Ok(())
+        });
+        self.watcher.Stopped(&handler)?;
+        Ok(())
+    }
 }
@@ -14,7 +14,20 @@
 use super::{adapter::Adapter, bindings};
 use crate::{api, Result};
 use async_trait::async_trait;
+use bindings::Windows::Devices::Enumeration::{
+    DeviceInformation, DeviceInformationUpdate, DeviceWatcher,
+};
 use bindings::Windows::Devices::Radios::{Radio, RadioKind};
+use bindings::Windows::Foundation::TypedEventHandler;
+use dashmap::DashMap;
+use futures::channel::mpsc;
+use futures::stream::{Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use windows::IInspectable;
 
 /// Implementation of [api::Manager](crate::api::Manager).
 #[derive(Clone, Debug)]
@@ -37,9 +50,79 @@ impl api::Manager for Manager {
         for radio in &radios {
             let kind = radio.Kind().unwrap();
             if kind == RadioKind::Bluetooth {
-                result.push(Adapter::new());
+                result.push(Adapter::new(radio));
             }
         }
         return Ok(result);
     }
+
+    async fn adapter_events(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = api::ManagerEvent<Adapter>> + Send>>> {
+        let (sender, receiver) = mpsc::unbounded();
+        // Radios are reported by their device ID, so keep track of which adapter each ID belongs
+        // to in order to report the right one when it goes away.
+        let adapters = Arc::new(DashMap::new());
+        // The watcher first reports the radios which are already present, which shouldn't be
+        // announced.
+        let enumerated = Arc::new(AtomicBool::new(false));
+
+        let watcher = DeviceInformation::CreateWatcherAqsFilter(Radio::GetDeviceSelector()?)?;
+
+        let added_sender = sender.clone();
+        let added_adapters = adapters.clone();
+        let added_enumerated = enumerated.clone();
+        let added_handler = TypedEventHandler::new(
+            move |_sender: &Option<DeviceWatcher>, info: &Option<DeviceInformation>| {
+                if let Some(info) = info {
+                    let id = info.Id()?;
+                    let radio = Radio::FromIdAsync(&id)?.get()?;
+                    if radio.Kind()? == RadioKind::Bluetooth {
+                        let adapter = Adapter::new(radio);
+                        added_adapters.insert(id.to_string(), adapter.clone());
+                        if added_enumerated.load(Ordering::Relaxed) {
+                            let _ = added_sender
+                                .unbounded_send(api::ManagerEvent::AdapterAdded(adapter));
+                        }
+                    }
+                }
+                Ok(())
+            },
+        );
+        let removed_handler = TypedEventHandler::new(
+            move |_sender: &Option<DeviceWatcher>, update: &Option<DeviceInformationUpdate>| {
+                if let Some(update) = update {
+                    if let Some((_, adapter)) = adapters.remove(&update.Id()?.to_string()) {
+                        let _ = sender.unbounded_send(api::ManagerEvent::AdapterRemoved(adapter));
+                    }
+                }
+                Ok(())
+            },
+        );
+        let enumeration_completed_handler = TypedEventHandler::new(
+            move |_sender: &Option<DeviceWatcher>, _: &Option<IInspectable>| {
+                enumerated.store(true, Ordering::Relaxed);
+                Ok(())
+            },
+        );
+        watcher.Added(&added_handler)?;
+        watcher.Removed(&removed_handler)?;
+        watcher.EnumerationCompleted(&enumeration_completed_handler)?;
+        watcher.Start()?;
+
+        // Stop watching once the stream is dropped.
+        let watcher = WatcherGuard(watcher);
+        Ok(Box::pin(receiver.map(move |event| {
+            let _ = &watcher;
+            event
+        })))
+    }
+}
+
+struct WatcherGuard(DeviceWatcher);
+
+impl Drop for WatcherGuard {
+    fn drop(&mut self) {
+        let _ = self.0.Stop();
+    }
 }
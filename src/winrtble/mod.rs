@@ -21,6 +21,8 @@ mod utils;
 
 /// Only some of the assigned numbers are populated here as needed from https://www.bluetooth.com/specifications/assigned-numbers/generic-access-profile/
 mod advertisement_data_type {
+    pub const TX_POWER_LEVEL: u8 = 0x0a;
+    pub const MANUFACTURER_SPECIFIC_DATA: u8 = 0xff;
     pub const SERVICE_DATA_16_BIT_UUID: u8 = 0x16;
     pub const SERVICE_DATA_32_BIT_UUID: u8 = 0x20;
     pub const SERVICE_DATA_128_BIT_UUID: u8 = 0x21;
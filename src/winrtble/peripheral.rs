@@ -12,33 +12,44 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 use super::{
-    advertisement_data_type, bindings, ble::characteristic::BLECharacteristic,
-    ble::device::BLEDevice, utils,
+    bindings,
+    ble::characteristic::{BLECharacteristic, NotifiyEventHandler},
+    ble::device::BLEDevice,
+    ble::pairing,
+    utils::{self, DataSection, DataSections},
 };
 use crate::{
     api::{
-        bleuuid::{uuid_from_u16, uuid_from_u32},
-        BDAddr, CentralEvent, Characteristic, Peripheral as ApiPeripheral, PeripheralProperties,
-        ValueNotification, WriteType,
+        BDAddr, CentralEvent, Characteristic, ConnectOptions, ConnectionParameters,
+        ConnectionPriority, Descriptor, DisconnectReason, L2capChannel, PairingAgent,
+        Peripheral as ApiPeripheral, PeripheralOptions, PeripheralProperties, PeripheralSummary,
+        Phy, PropertyChanges, SecurityLevel, Service, ValueNotification, WriteType,
+    },
+    common::{
+        adapter_manager::AdapterManager, operation_queue::OperationQueue,
+        subscriptions::SubscriptionCounts, util, watched::Watched,
     },
-    common::{adapter_manager::AdapterManager, util},
     Error, Result,
 };
 use async_trait::async_trait;
+use bytes::Bytes;
 use dashmap::DashMap;
 use futures::channel::mpsc::{self, UnboundedSender};
-use futures::stream::Stream;
+use futures::future::ready;
+use futures::stream::{Stream, StreamExt};
 use std::{
-    collections::BTreeSet,
-    convert::TryInto,
+    collections::{BTreeSet, HashMap},
     fmt::{self, Debug, Display, Formatter},
     pin::Pin,
-    sync::atomic::{AtomicBool, Ordering},
-    sync::{Arc, Mutex},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
 };
 use uuid::Uuid;
+use windows::{IInspectable, Interface};
 
 use bindings::Windows::Devices::Bluetooth::Advertisement::*;
+use bindings::Windows::Devices::Bluetooth::GenericAttributeProfile::GattCharacteristic;
 
 /// Implementation of [api::Peripheral](crate::api::Peripheral).
 #[derive(Clone)]
@@ -46,10 +57,30 @@ pub struct Peripheral {
     device: Arc<tokio::sync::Mutex<Option<BLEDevice>>>,
     adapter: AdapterManager<Self>,
     address: BDAddr,
-    properties: Arc<Mutex<Option<PeripheralProperties>>>,
+    // Snapshots of the properties, which are replaced rather than changed in place so that they
+    // can be shared with the properties stream and the scan filter without copying them.
+    properties: Arc<Mutex<Option<Arc<PeripheralProperties>>>>,
+    properties_watch: Watched<Option<Arc<PeripheralProperties>>>,
     connected: Arc<AtomicBool>,
-    ble_characteristics: Arc<DashMap<Uuid, BLECharacteristic>>,
+    ble_characteristics: Arc<DashMap<u16, BLECharacteristic>>,
     notification_senders: Arc<Mutex<Vec<UnboundedSender<ValueNotification>>>>,
+    subscriptions: Arc<Mutex<SubscriptionCounts>>,
+    operations: Arc<OperationQueue>,
+    options: Arc<Mutex<PeripheralOptions>>,
+    dropped_notifications: Arc<AtomicU64>,
+}
+
+/// Forgets the characteristics and subscriptions of a connection which has been closed, and ends
+/// the notification streams.
+fn clear_connection_state(
+    ble_characteristics: &DashMap<u16, BLECharacteristic>,
+    notification_senders: &Mutex<Vec<UnboundedSender<ValueNotification>>>,
+    subscriptions: &Mutex<SubscriptionCounts>,
+) {
+    ble_characteristics.clear();
+    subscriptions.lock().unwrap().disconnected();
+    // Dropping the senders ends the streams returned by `notifications`.
+    notification_senders.lock().unwrap().clear();
 }
 
 impl Peripheral {
@@ -64,100 +95,261 @@ impl Peripheral {
             adapter,
             address,
             properties,
+            properties_watch: Watched::new(None),
             connected,
             ble_characteristics,
             notification_senders,
+            subscriptions: Arc::new(Mutex::new(SubscriptionCounts::default())),
+            operations: Arc::new(OperationQueue::default()),
+            options: Arc::new(Mutex::new(PeripheralOptions::default())),
+            dropped_notifications: Arc::new(AtomicU64::new(0)),
         }
     }
 
-    pub(crate) fn update_properties(&self, args: &BluetoothLEAdvertisementReceivedEventArgs) {
-        let mut maybe_properties = self.properties.lock().unwrap();
-        let properties = maybe_properties.get_or_insert_with(|| {
-            let mut new_properties = PeripheralProperties::default();
-            new_properties.address = self.address;
-            new_properties
-        });
-        let advertisement = args.Advertisement().unwrap();
+    /// Returns the WinRT `BluetoothLEDevice` behind this peripheral, for operations which btleplug
+    /// doesn't wrap. It is returned as an `IInspectable`, to be cast to the application's own
+    /// bindings for `Windows.Devices.Bluetooth.BluetoothLEDevice`. The device is only opened while
+    /// connected, so this fails with [`Error::NotConnected`] otherwise.
+    pub async fn platform_handle(&self) -> Result<IInspectable> {
+        let device = self.device.lock().await;
+        let device = device.as_ref().ok_or(Error::NotConnected)?;
+        Ok(device.device().cast()?)
+    }
+
+    /// Returns a handler which sends the values notified for the characteristic to the
+    /// notification streams.
+    fn notification_handler(&self, characteristic: &Characteristic) -> NotifiyEventHandler {
+        let notification_senders = self.notification_senders.clone();
+        let metrics = self.adapter.metrics().clone();
+        let uuid = characteristic.uuid;
+        let service_uuid = characteristic.service_uuid;
+        let handle = characteristic.handle;
+        Box::new(move |value| {
+            let notification = ValueNotification {
+                uuid,
+                service_uuid,
+                handle,
+                value: value.into(),
+            };
+            metrics.notification_received();
+            util::send_notification(&notification_senders, &notification);
+        })
+    }
 
-        properties.discovery_count += 1;
+    /// Keeps a discovered characteristic, unless it was already known.
+    fn add_characteristic(&self, gatt_characteristic: GattCharacteristic) -> Characteristic {
+        let ble_characteristic = BLECharacteristic::new(gatt_characteristic);
+        let characteristic = ble_characteristic.to_characteristic();
+        self.ble_characteristics
+            .entry(characteristic.handle)
+            .or_insert_with(|| ble_characteristic);
+        characteristic
+    }
 
-        // Advertisements are cumulative: set/replace data only if it's set
-        if let Ok(name) = advertisement.LocalName() {
-            if !name.is_empty() {
-                properties.local_name = Some(name.to_string());
-            }
+    /// Enables notifications again for the characteristics whose subscriptions were restored after
+    /// reconnecting.
+    async fn resubscribe(&self, characteristics: &[Characteristic]) -> Result<()> {
+        self.discover_characteristics().await?;
+        let options = *self.options.lock().unwrap();
+        for characteristic in characteristics {
+            // Cloned so that the map isn't locked while waiting for the device.
+            let ble_characteristic = match self.ble_characteristics.get(&characteristic.handle) {
+                Some(ble_characteristic) => ble_characteristic.clone(),
+                None => continue,
+            };
+            let ble_characteristic = &ble_characteristic;
+            self.operations
+                .run(
+                    options.subscribe_timeout,
+                    options.retry,
+                    move || async move {
+                        ble_characteristic
+                            .subscribe(self.notification_handler(characteristic))
+                            .await
+                    },
+                )
+                .await?;
         }
-        if let Ok(manufacturer_data) = advertisement.ManufacturerData() {
-            properties.manufacturer_data = manufacturer_data
-                .into_iter()
-                .map(|d| {
-                    let manufacturer_id = d.CompanyId().unwrap();
-                    let data = utils::to_vec(&d.Data().unwrap());
-
-                    (manufacturer_id, data)
-                })
-                .collect();
-
-            // Emit event of newly received advertisement
-            self.adapter
-                .emit(CentralEvent::ManufacturerDataAdvertisement {
-                    address: self.address,
-                    manufacturer_data: properties.manufacturer_data.clone(),
-                });
+        Ok(())
+    }
+
+    /// Returns the properties of a peripheral which hasn't advertised anything yet.
+    fn new_properties(&self) -> PeripheralProperties {
+        PeripheralProperties {
+            address: self.address,
+            ..PeripheralProperties::default()
         }
+    }
 
-        // The Windows Runtime API (as of 19041) does not directly expose Service Data as a friendly API (like Manufacturer Data above)
-        // Instead they provide data sections for access to raw advertising data. That is processed here.
-        if let Ok(data_sections) = advertisement.DataSections() {
-            properties.service_data = data_sections
-                .into_iter()
-                .filter_map(|d| {
-                    let data = utils::to_vec(&d.Data().unwrap());
-
-                    match d.DataType().unwrap() {
-                        advertisement_data_type::SERVICE_DATA_16_BIT_UUID => {
-                            let (uuid, data) = data.split_at(2);
-                            let uuid = uuid_from_u16(u16::from_le_bytes(uuid.try_into().unwrap()));
-                            Some((uuid, data.to_owned()))
-                        }
-                        advertisement_data_type::SERVICE_DATA_32_BIT_UUID => {
-                            let (uuid, data) = data.split_at(4);
-                            let uuid = uuid_from_u32(u32::from_le_bytes(uuid.try_into().unwrap()));
-                            Some((uuid, data.to_owned()))
-                        }
-                        advertisement_data_type::SERVICE_DATA_128_BIT_UUID => {
-                            let (uuid, data) = data.split_at(16);
-                            let uuid = Uuid::from_slice(uuid).unwrap();
-                            Some((uuid, data.to_owned()))
-                        }
-                        _ => None,
-                    }
-                })
-                .collect();
-
-            // Emit event of newly received advertisement
-            self.adapter.emit(CentralEvent::ServiceDataAdvertisement {
-                address: self.address,
-                service_data: properties.service_data.clone(),
-            });
+    /// Updates the properties from an advertisement, reading its data sections into
+    /// `data_sections`, which is reused from one advertisement to the next.
+    pub(crate) fn update_properties(
+        &self,
+        args: &BluetoothLEAdvertisementReceivedEventArgs,
+        data_sections: &mut DataSections,
+    ) -> PropertyChanges {
+        // Everything is read from the WinRT collections before the properties are locked, so the
+        // lock is only held to build the new snapshot.
+        let advertisement = match args.Advertisement() {
+            Ok(advertisement) => advertisement,
+            Err(_) => return PropertyChanges::empty(),
+        };
+        let rssi = args.RawSignalStrengthInDBm().ok();
+        let last_seen = args.Timestamp().ok().map(|t| utils::to_system_time(&t));
+        let local_name = advertisement
+            .LocalName()
+            .ok()
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_string());
+        // The Windows Runtime API (as of 19041) does not directly expose Service Data as a friendly API (like Manufacturer Data)
+        // Instead they provide data sections for access to raw advertising data. Manufacturer data is
+        // also taken from these, rather than reading it from the advertisement a second time.
+        if data_sections.read(&advertisement).is_err() {
+            data_sections.clear();
+        }
+        // The sections come straight from the air, so any which are too short for their type are
+        // skipped rather than trusted.
+        let mut manufacturer_data = HashMap::new();
+        let mut service_data = HashMap::new();
+        let mut tx_power_level = None;
+        let mut malformed = Vec::new();
+        for (ad_type, data) in data_sections.iter() {
+            match utils::parse_data_section(ad_type, data) {
+                Some(DataSection::ManufacturerData(manufacturer_id, data)) => {
+                    manufacturer_data.insert(manufacturer_id, data.to_owned());
+                }
+                Some(DataSection::ServiceData(uuid, data)) => {
+                    service_data.insert(uuid, data.to_owned());
+                }
+                Some(DataSection::TxPowerLevel(level)) => tx_power_level = Some(level),
+                Some(DataSection::Other) => {}
+                None => malformed.push(ad_type),
+            }
         }
+        let services: Vec<Uuid> = advertisement
+            .ServiceUuids()
+            .map(|services| {
+                services
+                    .into_iter()
+                    .map(|uuid| utils::to_uuid(&uuid))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // The address type is only included in the advertisement event args from Windows 10 2004
+        // onwards. On older versions it's filled in from the device object once connected.
+        let address_type = args.BluetoothAddressType().ok();
 
-        if let Ok(services) = advertisement.ServiceUuids() {
-            properties.services = services
-                .into_iter()
-                .map(|uuid| utils::to_uuid(&uuid))
-                .collect();
+        let (previous, properties) = {
+            let mut current = self.properties.lock().unwrap();
+            let previous = current.clone();
+            let mut properties = match &previous {
+                Some(previous) => PeripheralProperties::clone(previous),
+                None => self.new_properties(),
+            };
+            properties.discovery_count += 1;
+            properties.rssi = rssi;
+            properties.last_seen = last_seen;
+            // Advertisements are cumulative: set/replace data only if it's set, and only if it
+            // differs, so that unchanged data isn't copied again.
+            if local_name.is_some() {
+                properties.local_name = local_name;
+            }
+            if !manufacturer_data.is_empty() && properties.manufacturer_data != manufacturer_data {
+                properties.manufacturer_data = manufacturer_data.clone();
+            }
+            // The raw data sections are only copied out of the reused buffer when they change.
+            if !data_sections.is_empty() && !data_sections.matches(&properties.ad_structures) {
+                properties.ad_structures = data_sections.to_ad_structures();
+            }
+            if !service_data.is_empty() && properties.service_data != service_data {
+                properties.service_data = service_data.clone();
+            }
+            if !services.is_empty() && properties.services != services {
+                properties.services = services.clone();
+            }
+            if let Some(address_type) = address_type {
+                properties.address_type = utils::to_address_type(address_type);
+            }
+            if tx_power_level.is_some() {
+                properties.tx_power_level = tx_power_level;
+            }
+            let properties = Arc::new(properties);
+            *current = Some(properties.clone());
+            (previous, properties)
+        };
 
-            self.adapter.emit(CentralEvent::ServicesAdvertisement {
-                address: self.address,
-                services: properties.services.clone(),
-            });
+        // Events are emitted once the lock is released, each taking the data parsed from this
+        // advertisement rather than a copy of the peripheral's.
+        if !manufacturer_data.is_empty() {
+            self.adapter.emit_advertisement(
+                &properties,
+                CentralEvent::ManufacturerDataAdvertisement {
+                    address: self.address,
+                    manufacturer_data,
+                },
+            );
+        }
+        if !service_data.is_empty() {
+            self.adapter.emit_advertisement(
+                &properties,
+                CentralEvent::ServiceDataAdvertisement {
+                    address: self.address,
+                    service_data,
+                },
+            );
+        }
+        if !services.is_empty() {
+            self.adapter.emit_advertisement(
+                &properties,
+                CentralEvent::ServicesAdvertisement {
+                    address: self.address,
+                    services,
+                },
+            );
         }
+        for ad_type in malformed {
+            self.adapter.emit_advertisement(
+                &properties,
+                CentralEvent::MalformedAdvertisement {
+                    address: self.address,
+                    ad_type,
+                },
+            );
+        }
+        if let Some(rssi) = rssi {
+            self.adapter.emit_advertisement(
+                &properties,
+                CentralEvent::RssiUpdate {
+                    address: self.address,
+                    rssi,
+                },
+            );
+        }
+        let changes = match &previous {
+            Some(previous) => PropertyChanges::between(previous, &properties),
+            None => PropertyChanges::between(&self.new_properties(), &properties),
+        };
+        self.properties_watch.publish(Some(properties));
+        changes
+    }
 
-        // windows does not provide the address type in the advertisement event args but only in the device object
-        // https://social.msdn.microsoft.com/Forums/en-US/c71d51a2-56a1-425a-9063-de44fda48766/bluetooth-address-public-or-random?forum=wdk
-        properties.address_type = None;
-        properties.tx_power_level = args.RawSignalStrengthInDBm().ok().map(|rssi| rssi as i8);
+    /// Sets the peripheral's name, if it hasn't advertised one itself.
+    pub(crate) fn update_name(&self, name: &str) {
+        let mut current = self.properties.lock().unwrap();
+        let properties = current.get_or_insert_with(|| Arc::new(self.new_properties()));
+        if properties.local_name.is_none() && !name.is_empty() {
+            // The snapshot may be shared with readers, so this changes a copy of it.
+            Arc::make_mut(properties).local_name = Some(name.to_string());
+        }
+        self.properties_watch.publish(Some(properties.clone()));
+    }
+
+    /// Emits an event about this peripheral being seen, subject to the adapter's scan filter.
+    pub(crate) fn emit_advertisement(&self, event: CentralEvent) {
+        let properties = self.properties.lock().unwrap().clone();
+        if let Some(properties) = properties {
+            self.adapter.emit_advertisement(&properties, event);
+        }
     }
 }
 
@@ -168,17 +360,21 @@ impl Display for Peripheral {
         } else {
             ""
         };
-        let properties = self.properties.lock().unwrap();
+        // Formatting mustn't panic, so this also works before the first advertisement has been
+        // received, or if an update panicked while holding the lock.
+        let properties = self
+            .properties
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let local_name = properties
+            .as_ref()
+            .and_then(|properties| properties.local_name.as_deref());
         write!(
             f,
             "{} {}{}",
             self.address,
-            properties
-                .clone()
-                .unwrap()
-                .local_name
-                .clone()
-                .unwrap_or_else(|| "(unknown)".to_string()),
+            local_name.unwrap_or("(unknown)"),
             connected
         )
     }
@@ -191,11 +387,15 @@ impl Debug for Peripheral {
         } else {
             ""
         };
-        let properties = self.properties.lock().unwrap();
+        let properties = self
+            .properties
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
         write!(
             f,
             "{} properties: {:?}, characteristics: {:?} {}",
-            self.address, *properties, self.ble_characteristics, connected
+            self.address, properties, self.ble_characteristics, connected
         )
     }
 }
@@ -210,8 +410,20 @@ impl ApiPeripheral for Peripheral {
     /// Returns the set of properties associated with the peripheral. These may be updated over time
     /// as additional advertising reports are received.
     async fn properties(&self) -> Result<Option<PeripheralProperties>> {
-        let l = self.properties.lock().unwrap();
-        Ok(l.clone())
+        let properties = self.properties.lock().unwrap();
+        Ok(properties.as_deref().cloned())
+    }
+
+    async fn properties_stream(
+        &self,
+    ) -> Result<Pin<Box<dyn Stream<Item = PeripheralProperties> + Send>>> {
+        Ok(Box::pin(self.properties_watch.stream().filter_map(
+            |properties| ready(properties.as_deref().cloned()),
+        )))
+    }
+
+    async fn summary(&self) -> Result<PeripheralSummary> {
+        util::summary(self).await
     }
 
     /// The set of characteristics we've discovered for this device. This will be empty until
@@ -232,112 +444,514 @@ impl ApiPeripheral for Peripheral {
     /// Ok there has been successful connection. Note that peripherals allow only one connection at
     /// a time. Operations that attempt to communicate with a device will fail until it is connected.
     async fn connect(&self) -> Result<()> {
+        self.connect_with_options(&ConnectOptions::default()).await
+    }
+
+    async fn connect_with_timeout(&self, timeout: Duration) -> Result<()> {
+        util::connect_with_timeout(self, timeout).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "connect",
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address),
+            err
+        )
+    )]
+    async fn connect_with_options(&self, options: &ConnectOptions) -> Result<()> {
+        let attempt = self.adapter.metrics().connect_attempt();
+        self.operations.set_concurrency(
+            options
+                .concurrent_operations
+                .map_or(1, |concurrency| concurrency.get()),
+        );
         let connected = self.connected.clone();
         let adapter_clone = self.adapter.clone();
         let address = self.address;
-        let device = BLEDevice::new(
+        let ble_characteristics = self.ble_characteristics.clone();
+        let notification_senders = self.notification_senders.clone();
+        let subscriptions = self.subscriptions.clone();
+        let services_changed_characteristics = self.ble_characteristics.clone();
+        let services_changed_adapter = self.adapter.clone();
+        let mut device = BLEDevice::new(
             self.address,
             Box::new(move |is_connected| {
                 connected.store(is_connected, Ordering::Relaxed);
                 if !is_connected {
-                    adapter_clone.emit(CentralEvent::DeviceDisconnected(address));
+                    clear_connection_state(
+                        &ble_characteristics,
+                        &notification_senders,
+                        &subscriptions,
+                    );
+                    // WinRT only reports that the connection status changed, not why.
+                    adapter_clone.emit(CentralEvent::DeviceDisconnected(
+                        address,
+                        DisconnectReason::Unknown,
+                    ));
                 }
             }),
+            Box::new(move || {
+                // The handles of the cached characteristics may no longer be valid.
+                services_changed_characteristics.clear();
+                services_changed_adapter.emit(CentralEvent::ServicesChanged(address));
+            }),
         )
         .await?;
 
-        device.connect().await?;
-        let mut d = self.device.lock().await;
-        *d = Some(device);
+        device.set_uncached(options.uncached_discovery);
+        let retry = self.options.lock().unwrap().retry;
+        util::with_retry(retry, || device.connect()).await?;
+        if options.maintain_connection {
+            device.maintain_connection().await?;
+        }
+        if let Some(properties) = &mut *self.properties.lock().unwrap() {
+            if properties.address_type.is_none() {
+                Arc::make_mut(properties).address_type = device.address_type()?;
+            }
+        }
+        *self.device.lock().await = Some(device);
+        attempt.succeeded();
         self.adapter
             .emit(CentralEvent::DeviceConnected(self.address));
+        let resubscriptions = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .reconnected(options.resubscribe);
+        if options.resubscribe {
+            self.resubscribe(&resubscriptions).await?;
+        }
         Ok(())
     }
 
+    fn set_options(&self, options: &PeripheralOptions) {
+        *self.options.lock().unwrap() = *options;
+    }
+
+    async fn pair(&self) -> Result<()> {
+        pairing::pair(self.address, None).await
+    }
+
+    async fn pair_with_agent(&self, agent: Arc<dyn PairingAgent>) -> Result<()> {
+        pairing::pair(self.address, Some(agent)).await
+    }
+
+    async fn request_security(&self, level: SecurityLevel) -> Result<()> {
+        pairing::request_security(self.address, level).await
+    }
+
     /// Terminates a connection to the device. This is a synchronous operation.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address), err)
+    )]
     async fn disconnect(&self) -> Result<()> {
         let mut device = self.device.lock().await;
+        // The characteristics keep their services open, so they must be released for the link to
+        // be closed.
+        clear_connection_state(
+            &self.ble_characteristics,
+            &self.notification_senders,
+            &self.subscriptions,
+        );
         *device = None;
-        self.adapter
-            .emit(CentralEvent::DeviceDisconnected(self.address));
+        self.adapter.emit(CentralEvent::DeviceDisconnected(
+            self.address,
+            DisconnectReason::UserRequest,
+        ));
         Ok(())
     }
 
-    /// Discovers all characteristics for the device. This is a synchronous operation.
-    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+    /// Returns the maximum PDU size of the GATT session with the device.
+    async fn mtu(&self) -> Result<u16> {
         let device = self.device.lock().await;
         if let Some(ref device) = *device {
-            let mut characteristics_result = vec![];
-            let characteristics = device.discover_characteristics().await?;
-            for gatt_characteristic in characteristics {
-                let ble_characteristic = BLECharacteristic::new(gatt_characteristic);
-                let characteristic = ble_characteristic.to_characteristic();
-                self.ble_characteristics
-                    .entry(characteristic.uuid.clone())
-                    .or_insert_with(|| ble_characteristic);
-                characteristics_result.push(characteristic);
-            }
-            return Ok(characteristics_result);
+            return device.mtu().await;
         }
         Err(Error::NotConnected)
     }
 
+    /// Windows negotiates the MTU itself when connecting, so this is not supported.
+    async fn request_mtu(&self, _mtu: u16) -> Result<()> {
+        Err(Error::NotSupported(
+            "Windows negotiates the MTU automatically".to_string(),
+        ))
+    }
+
+    /// Windows chooses connection parameters itself, so this is not supported.
+    async fn set_connection_parameters(&self, _parameters: &ConnectionParameters) -> Result<()> {
+        Err(Error::NotSupported(
+            "Setting connection parameters is not supported on Windows".to_string(),
+        ))
+    }
+
+    /// Windows chooses connection parameters itself, so this is not supported.
+    async fn request_connection_priority(&self, _priority: ConnectionPriority) -> Result<()> {
+        Err(Error::NotSupported(
+            "Setting connection parameters is not supported on Windows".to_string(),
+        ))
+    }
+
+    /// Windows doesn't allow choosing the PHY, so this is not supported.
+    async fn set_preferred_phy(&self, _tx: Phy, _rx: Phy) -> Result<()> {
+        Err(Error::NotSupported(
+            "Setting the PHY is not supported on Windows".to_string(),
+        ))
+    }
+
+    /// Windows doesn't report the PHY in use, so this is not supported.
+    async fn read_phy(&self) -> Result<(Phy, Phy)> {
+        Err(Error::NotSupported(
+            "Reading the PHY is not supported on Windows".to_string(),
+        ))
+    }
+
+    /// Windows has no API for reading the RSSI of a connection, so this is not supported.
+    async fn read_rssi(&self) -> Result<i16> {
+        Err(Error::NotSupported(
+            "Reading the RSSI of a connection is not supported on Windows".to_string(),
+        ))
+    }
+
+    /// Discovers all characteristics for the device. This is a synchronous operation.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address), err)
+    )]
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        let timeout = self.options.lock().unwrap().discovery_timeout;
+        util::with_timeout(timeout, async {
+            let device = self.device.lock().await;
+            if let Some(ref device) = *device {
+                let mut characteristics_result = vec![];
+                let characteristics = device.discover_characteristics().await?;
+                for gatt_characteristic in characteristics {
+                    characteristics_result.push(self.add_characteristic(gatt_characteristic));
+                }
+                return Ok(characteristics_result);
+            }
+            Err(Error::NotConnected)
+        })
+        .await
+    }
+
+    /// Discovers all services for the device, along with their characteristics.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(peripheral = %self.address), err)
+    )]
+    async fn discover_services(&self) -> Result<Vec<Service>> {
+        let timeout = self.options.lock().unwrap().discovery_timeout;
+        util::with_timeout(timeout, async {
+            let device = self.device.lock().await;
+            if let Some(ref device) = *device {
+                let mut services_result = vec![];
+                for discovered in device.discover_services().await? {
+                    let mut characteristics = BTreeSet::new();
+                    for gatt_characteristic in discovered.characteristics {
+                        characteristics.insert(self.add_characteristic(gatt_characteristic));
+                    }
+                    services_result.push(Service {
+                        uuid: utils::to_uuid(&discovered.service.Uuid()?),
+                        primary: discovered.primary,
+                        characteristics,
+                        included_services: discovered.included_services.into_iter().collect(),
+                    });
+                }
+                return Ok(services_result);
+            }
+            Err(Error::NotConnected)
+        })
+        .await
+    }
+
     /// Write some data to the characteristic. Returns an error if the write couldn't be send or (in
     /// the case of a write-with-response) if the device returns an error.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
     async fn write(
         &self,
         characteristic: &Characteristic,
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        if let Some(ble_characteristic) = self.ble_characteristics.get(&characteristic.uuid) {
-            ble_characteristic.write_value(data, write_type).await
-        } else {
-            Err(Error::NotSupported("write".into()))
-        }
+        let _operation = self.adapter.metrics().gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.write_timeout, options.retry, move || async move {
+                if let Some(ble_characteristic) =
+                    self.ble_characteristics.get(&characteristic.handle)
+                {
+                    ble_characteristic.write_value(data, write_type).await
+                } else {
+                    Err(Error::NotSupported("write".into()))
+                }
+            })
+            .await
     }
 
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
     /// This is a synchronous call.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
     async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        if let Some(mut ble_characteristic) = self.ble_characteristics.get_mut(&characteristic.uuid)
-        {
-            let notification_senders = self.notification_senders.clone();
-            let uuid = characteristic.uuid;
-            ble_characteristic
-                .subscribe(Box::new(move |value| {
-                    let notification = ValueNotification { uuid: uuid, value };
-                    util::send_notification(&notification_senders, &notification);
-                }))
-                .await
-        } else {
-            Err(Error::NotSupported("subscribe".into()))
-        }
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(
+                options.subscribe_timeout,
+                options.retry,
+                move || async move {
+                    if let Some(ble_characteristic) =
+                        self.ble_characteristics.get(&characteristic.handle)
+                    {
+                        if self
+                            .subscriptions
+                            .lock()
+                            .unwrap()
+                            .subscribe_if_enabled(characteristic)
+                        {
+                            return Ok(());
+                        }
+                        // Only counted once notifications are enabled, as this future is dropped
+                        // if the operation times out.
+                        ble_characteristic
+                            .subscribe(self.notification_handler(characteristic))
+                            .await?;
+                        self.subscriptions.lock().unwrap().subscribe(characteristic);
+                        Ok(())
+                    } else {
+                        Err(Error::NotSupported("subscribe".into()))
+                    }
+                },
+            )
+            .await
     }
 
     /// Disables either notify or indicate (depending on support) for the specified characteristic.
     /// This is a synchronous call.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
     async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        if let Some(mut ble_characteristic) = self.ble_characteristics.get_mut(&characteristic.uuid)
-        {
-            ble_characteristic.unsubscribe().await
-        } else {
-            Err(Error::NotSupported("unsubscribe".into()))
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(
+                options.subscribe_timeout,
+                options.retry,
+                move || async move {
+                    if let Some(ble_characteristic) =
+                        self.ble_characteristics.get(&characteristic.handle)
+                    {
+                        if !self
+                            .subscriptions
+                            .lock()
+                            .unwrap()
+                            .unsubscribe(characteristic)
+                        {
+                            return Ok(());
+                        }
+                        ble_characteristic.unsubscribe().await
+                    } else {
+                        Err(Error::NotSupported("unsubscribe".into()))
+                    }
+                },
+            )
+            .await
+    }
+
+    fn is_subscribed(&self, characteristic: &Characteristic) -> bool {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .is_subscribed(characteristic)
+    }
+
+    fn subscriptions(&self) -> BTreeSet<Characteristic> {
+        self.subscriptions.lock().unwrap().subscribed()
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn write_long(
+        &self,
+        characteristic: &Characteristic,
+        offset: u16,
+        data: &[u8],
+    ) -> Result<()> {
+        if offset != 0 {
+            return Err(Error::NotSupported(
+                "Writing at an offset is not supported on Windows".to_string(),
+            ));
         }
+        // Writes with response which don't fit in a single request are split into prepared writes
+        // automatically.
+        self.write(characteristic, data, WriteType::WithResponse)
+            .await
     }
 
-    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        if let Some(ble_characteristic) = self.ble_characteristics.get(&characteristic.uuid) {
-            ble_characteristic.read_value().await
+    async fn wait_until_ready_to_write(&self) -> Result<()> {
+        // Writes without response complete once the Bluetooth stack has queued them, so there is
+        // nothing else to wait for.
+        if self.connected.load(Ordering::Relaxed) {
+            Ok(())
         } else {
-            Err(Error::NotSupported("read".into()))
+            Err(Error::NotConnected)
         }
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(peripheral = %self.address, characteristic = %characteristic.uuid),
+            err
+        )
+    )]
+    async fn read(&self, characteristic: &Characteristic) -> Result<Bytes> {
+        let _operation = self.adapter.metrics().gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.read_timeout, options.retry, move || async move {
+                if let Some(ble_characteristic) =
+                    self.ble_characteristics.get(&characteristic.handle)
+                {
+                    Ok(ble_characteristic.read_value().await?.into())
+                } else {
+                    Err(Error::NotSupported("read".into()))
+                }
+            })
+            .await
+    }
+
+    async fn discover_descriptors(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>> {
+        let timeout = self.options.lock().unwrap().discovery_timeout;
+        util::with_timeout(timeout, async {
+            if let Some(mut ble_characteristic) =
+                self.ble_characteristics.get_mut(&characteristic.handle)
+            {
+                let uuids = ble_characteristic.discover_descriptors().await?;
+                Ok(uuids
+                    .into_iter()
+                    .map(|uuid| Descriptor {
+                        uuid,
+                        characteristic_uuid: characteristic.uuid,
+                        characteristic_handle: characteristic.handle,
+                    })
+                    .collect())
+            } else {
+                Err(Error::NotSupported("discover_descriptors".into()))
+            }
+        })
+        .await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let _operation = self.adapter.metrics().gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.read_timeout, options.retry, move || async move {
+                if let Some(ble_characteristic) = self
+                    .ble_characteristics
+                    .get(&descriptor.characteristic_handle)
+                {
+                    ble_characteristic
+                        .descriptor(&descriptor.uuid)?
+                        .read_value()
+                        .await
+                } else {
+                    Err(Error::NotSupported("read_descriptor".into()))
+                }
+            })
+            .await
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let _operation = self.adapter.metrics().gatt_operation();
+        let options = *self.options.lock().unwrap();
+        self.operations
+            .run(options.write_timeout, options.retry, move || async move {
+                if let Some(ble_characteristic) = self
+                    .ble_characteristics
+                    .get(&descriptor.characteristic_handle)
+                {
+                    ble_characteristic
+                        .descriptor(&descriptor.uuid)?
+                        .write_value(data)
+                        .await
+                } else {
+                    Err(Error::NotSupported("write_descriptor".into()))
+                }
+            })
+            .await
+    }
+
     async fn notifications(&self) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
         let (sender, receiver) = mpsc::unbounded();
-        let mut senders = self.notification_senders.lock().unwrap();
-        senders.push(sender);
-        Ok(Box::pin(receiver))
+        self.notification_senders.lock().unwrap().push(sender);
+        let notifications: Pin<Box<dyn Stream<Item = ValueNotification> + Send>> =
+            Box::pin(receiver);
+        Ok(match self.options.lock().unwrap().notification_capacity {
+            Some(capacity) => util::bounded_notifications(
+                notifications,
+                capacity,
+                self.dropped_notifications.clone(),
+                self.adapter.metrics().clone(),
+            ),
+            None => notifications,
+        })
+    }
+
+    fn dropped_notifications(&self) -> u64 {
+        self.dropped_notifications.load(Ordering::Relaxed)
+    }
+
+    async fn notifications_for(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+        Ok(util::notifications_for(
+            self.notifications().await?,
+            characteristic,
+        ))
+    }
+
+    async fn open_l2cap_channel(&self, _psm: u16) -> Result<Box<dyn L2capChannel>> {
+        Err(Error::NotSupported(
+            "L2CAP channels are not supported on Windows".to_string(),
+        ))
     }
 }
@@ -11,15 +11,28 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use super::bindings;
-use crate::{api::CharPropFlags, Error, Result};
+use super::{advertisement_data_type, bindings};
+use crate::{
+    api::{
+        bleuuid::{uuid_from_u16, uuid_from_u32},
+        AdStructure, AdapterState, AddressType, CharPropFlags,
+    },
+    AttError, Error, ErrorCode, Result,
+};
 use bindings::Windows::{
+    Devices::Bluetooth::Advertisement::BluetoothLEAdvertisement,
+    Devices::Bluetooth::BluetoothAddressType,
     Devices::Bluetooth::GenericAttributeProfile::{
         GattCharacteristicProperties, GattCommunicationStatus,
     },
+    Devices::Radios::RadioState,
+    Foundation::DateTime,
     Storage::Streams::{DataReader, IBuffer},
 };
+use std::convert::TryInto;
+use std::ops::Range;
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 use windows::Guid;
 
@@ -37,27 +50,198 @@ pub fn to_error(status: GattCommunicationStatus) -> Result<()> {
     }
 }
 
+/// The `E_BLUETOOTH_ATT_*` HRESULTs, whose low byte is the ATT error code.
+const E_BLUETOOTH_ATT_BASE: u32 = 0x8065_0000;
+const E_ACCESSDENIED: u32 = 0x8007_0005;
+/// `HRESULT_FROM_WIN32(ERROR_SEM_TIMEOUT)`
+const E_SEM_TIMEOUT: u32 = 0x8007_0079;
+/// `HRESULT_FROM_WIN32(ERROR_DEVICE_NOT_CONNECTED)`
+const E_DEVICE_NOT_CONNECTED: u32 = 0x8007_048f;
+/// `HRESULT_FROM_WIN32(ERROR_TIMEOUT)`
+const E_TIMEOUT: u32 = 0x8007_05b4;
+/// `HRESULT_FROM_WIN32(ERROR_DEVICE_NOT_AVAILABLE)`
+const E_DEVICE_NOT_AVAILABLE: u32 = 0x8007_10df;
+
+/// Converts an HRESULT from the Windows Runtime into the most specific [`Error`] which it
+/// corresponds to.
+pub fn from_hresult(hresult: u32, message: String) -> Error {
+    let code = ErrorCode::HResult(hresult as i32);
+    match hresult {
+        _ if hresult & 0xffff_ff00 == E_BLUETOOTH_ATT_BASE => Error::Att(AttError(hresult as u8)),
+        E_ACCESSDENIED => Error::PermissionDenied,
+        E_SEM_TIMEOUT | E_TIMEOUT => Error::ConnectionTimeout(code),
+        E_DEVICE_NOT_CONNECTED | E_DEVICE_NOT_AVAILABLE => Error::DeviceUnreachable(code),
+        _ => Error::Platform { code, message },
+    }
+}
+
 pub fn to_uuid(uuid: &Guid) -> Uuid {
     let guid_s = format!("{:?}", uuid);
     Uuid::from_str(&guid_s).unwrap()
 }
 
 pub fn to_vec(buffer: &IBuffer) -> Vec<u8> {
-    let reader = DataReader::FromBuffer(buffer).unwrap();
-    let len = reader.UnconsumedBufferLength().unwrap() as usize;
-    let mut data = vec![0u8; len];
-    reader.ReadBytes(&mut data).unwrap();
+    let mut data = Vec::new();
+    append_buffer(buffer, &mut data).unwrap();
     data
 }
 
+/// Appends the contents of a buffer to `data`, reading them straight into the end of it.
+pub fn append_buffer(buffer: &IBuffer, data: &mut Vec<u8>) -> windows::Result<()> {
+    let reader = DataReader::FromBuffer(buffer)?;
+    let len = reader.UnconsumedBufferLength()? as usize;
+    let start = data.len();
+    data.resize(start + len, 0);
+    reader.ReadBytes(&mut data[start..])
+}
+
+/// A data section of an advertisement, of one of the types which btleplug interprets.
+#[derive(Debug, PartialEq)]
+pub enum DataSection<'a> {
+    ManufacturerData(u16, &'a [u8]),
+    ServiceData(Uuid, &'a [u8]),
+    TxPowerLevel(i8),
+    Other,
+}
+
+/// Parses a data section of an advertisement, returning `None` if it is too short for its type.
+pub fn parse_data_section(ad_type: u8, data: &[u8]) -> Option<DataSection<'_>> {
+    let header_len = match ad_type {
+        advertisement_data_type::TX_POWER_LEVEL => 1,
+        advertisement_data_type::MANUFACTURER_SPECIFIC_DATA
+        | advertisement_data_type::SERVICE_DATA_16_BIT_UUID => 2,
+        advertisement_data_type::SERVICE_DATA_32_BIT_UUID => 4,
+        advertisement_data_type::SERVICE_DATA_128_BIT_UUID => 16,
+        _ => return Some(DataSection::Other),
+    };
+    if data.len() < header_len {
+        return None;
+    }
+    let (header, data) = data.split_at(header_len);
+    Some(match ad_type {
+        advertisement_data_type::TX_POWER_LEVEL => DataSection::TxPowerLevel(header[0] as i8),
+        advertisement_data_type::MANUFACTURER_SPECIFIC_DATA => {
+            DataSection::ManufacturerData(u16::from_le_bytes([header[0], header[1]]), data)
+        }
+        advertisement_data_type::SERVICE_DATA_16_BIT_UUID => DataSection::ServiceData(
+            uuid_from_u16(u16::from_le_bytes([header[0], header[1]])),
+            data,
+        ),
+        advertisement_data_type::SERVICE_DATA_32_BIT_UUID => DataSection::ServiceData(
+            uuid_from_u32(u32::from_le_bytes(header.try_into().ok()?)),
+            data,
+        ),
+        _ => DataSection::ServiceData(Uuid::from_bytes(header.try_into().ok()?), data),
+    })
+}
+
+/// The data sections of an advertisement, copied into a single buffer. This is reused for each
+/// advertisement, so that parsing them doesn't allocate once the buffer has grown to fit.
+#[derive(Debug, Default)]
+pub struct DataSections {
+    data: Vec<u8>,
+    sections: Vec<(u8, Range<usize>)>,
+}
+
+impl DataSections {
+    /// Replaces the contents with the data sections of an advertisement.
+    pub fn read(&mut self, advertisement: &BluetoothLEAdvertisement) -> windows::Result<()> {
+        self.clear();
+        for section in advertisement.DataSections()? {
+            let start = self.data.len();
+            append_buffer(&section.Data()?, &mut self.data)?;
+            self.sections
+                .push((section.DataType()?, start..self.data.len()));
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.sections.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// Returns the type and data of each section, in the order they were advertised.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &[u8])> + '_ {
+        self.sections
+            .iter()
+            .map(move |(ad_type, range)| (*ad_type, &self.data[range.clone()]))
+    }
+
+    /// Returns whether these are the same sections as `ad_structures`.
+    pub fn matches(&self, ad_structures: &[AdStructure]) -> bool {
+        self.sections.len() == ad_structures.len()
+            && self
+                .iter()
+                .zip(ad_structures)
+                .all(|((ad_type, data), ad_structure)| {
+                    ad_type == ad_structure.ad_type && data == ad_structure.data.as_slice()
+                })
+    }
+
+    pub fn to_ad_structures(&self) -> Vec<AdStructure> {
+        self.iter()
+            .map(|(ad_type, data)| AdStructure {
+                ad_type,
+                data: data.to_vec(),
+            })
+            .collect()
+    }
+}
+
+pub fn to_address_type(address_type: BluetoothAddressType) -> Option<AddressType> {
+    if address_type == BluetoothAddressType::Public {
+        Some(AddressType::Public)
+    } else if address_type == BluetoothAddressType::Random {
+        Some(AddressType::Random)
+    } else {
+        None
+    }
+}
+
+pub fn to_adapter_state(state: RadioState) -> AdapterState {
+    if state == RadioState::On {
+        AdapterState::PoweredOn
+    } else if state == RadioState::Off {
+        AdapterState::PoweredOff
+    } else if state == RadioState::Disabled {
+        AdapterState::Unavailable
+    } else {
+        AdapterState::Unknown
+    }
+}
+
+/// The number of 100ns ticks between the Windows epoch (1601-01-01) and the Unix epoch.
+const UNIX_EPOCH_TICKS: i64 = 116_444_736_000_000_000;
+
+pub fn to_system_time(date_time: &DateTime) -> SystemTime {
+    let ticks = date_time.UniversalTime - UNIX_EPOCH_TICKS;
+    if ticks >= 0 {
+        UNIX_EPOCH + Duration::from_nanos(ticks as u64 * 100)
+    } else {
+        UNIX_EPOCH - Duration::from_nanos(-ticks as u64 * 100)
+    }
+}
+
 #[allow(dead_code)]
 pub fn to_guid(uuid: &Uuid) -> Guid {
     let (data1, data2, data3, data4) = uuid.as_fields();
     Guid::from_values(data1, data2, data3, data4.to_owned())
 }
 
-pub fn to_char_props(_: &GattCharacteristicProperties) -> CharPropFlags {
-    CharPropFlags::from_bits_truncate(0 as u8)
+// The low byte of GattCharacteristicProperties uses the same bit layout as the characteristic
+// properties field in the GATT specification, followed by the extended properties, which Windows
+// reads itself, so the flags can be carried over directly.
+pub fn to_char_props(properties: &GattCharacteristicProperties) -> CharPropFlags {
+    CharPropFlags::from_bits_truncate(properties.0 as u16)
+}
+
+pub fn to_gatt_char_props(properties: &CharPropFlags) -> GattCharacteristicProperties {
+    GattCharacteristicProperties(properties.bits() as u32)
 }
 
 #[cfg(test)]
@@ -85,4 +269,103 @@ mod tests {
         let uuid_expected = Uuid::from_str(uuid_str).unwrap();
         assert_eq!(uuid_converted, uuid_expected);
     }
+
+    #[test]
+    fn check_hresult_to_error_conversion() {
+        assert!(matches!(
+            from_hresult(0x8065_0005, String::new()),
+            Error::Att(AttError::INSUFFICIENT_AUTHENTICATION)
+        ));
+        assert!(matches!(
+            from_hresult(E_TIMEOUT, String::new()),
+            Error::ConnectionTimeout(ErrorCode::HResult(_))
+        ));
+        assert_eq!(
+            from_hresult(0x8000_4005, String::new()).code(),
+            Some(ErrorCode::HResult(0x8000_4005_u32 as i32))
+        );
+    }
+
+    #[test]
+    fn check_datetime_to_system_time_conversion() {
+        let epoch = DateTime {
+            UniversalTime: UNIX_EPOCH_TICKS,
+        };
+        assert_eq!(to_system_time(&epoch), UNIX_EPOCH);
+
+        let later = DateTime {
+            UniversalTime: UNIX_EPOCH_TICKS + 15_000_000,
+        };
+        assert_eq!(
+            to_system_time(&later),
+            UNIX_EPOCH + Duration::from_millis(1500)
+        );
+    }
+
+    #[test]
+    fn check_data_section_parsing() {
+        assert_eq!(
+            parse_data_section(0xff, &[0x4c, 0x00, 0x02, 0x15]),
+            Some(DataSection::ManufacturerData(0x004c, &[0x02, 0x15]))
+        );
+        assert_eq!(
+            parse_data_section(0x16, &[0xaa, 0xfe, 0x10]),
+            Some(DataSection::ServiceData(uuid_from_u16(0xfeaa), &[0x10]))
+        );
+        assert_eq!(
+            parse_data_section(0x0a, &[0xf4]),
+            Some(DataSection::TxPowerLevel(-12))
+        );
+        assert_eq!(parse_data_section(0x09, &[]), Some(DataSection::Other));
+    }
+
+    #[test]
+    fn check_short_data_sections_are_rejected() {
+        assert_eq!(parse_data_section(0xff, &[0x4c]), None);
+        assert_eq!(parse_data_section(0x16, &[0xaa]), None);
+        assert_eq!(parse_data_section(0x20, &[0x01, 0x02, 0x03]), None);
+        assert_eq!(parse_data_section(0x21, &[0; 15]), None);
+        assert_eq!(parse_data_section(0x0a, &[]), None);
+    }
+
+    #[test]
+    fn check_data_sections_to_ad_structures() {
+        let data_sections = DataSections {
+            data: vec![0x06, 0x4c, 0x00, 0x02],
+            sections: vec![(0x01, 0..1), (0xff, 1..4), (0x09, 4..4)],
+        };
+        let ad_structures = data_sections.to_ad_structures();
+        assert_eq!(
+            ad_structures,
+            vec![
+                AdStructure {
+                    ad_type: 0x01,
+                    data: vec![0x06]
+                },
+                AdStructure {
+                    ad_type: 0xff,
+                    data: vec![0x4c, 0x00, 0x02]
+                },
+                AdStructure {
+                    ad_type: 0x09,
+                    data: vec![]
+                },
+            ]
+        );
+        assert!(data_sections.matches(&ad_structures));
+        assert!(!data_sections.matches(&ad_structures[1..]));
+    }
+
+    #[test]
+    fn check_char_props_conversion() {
+        // Read, Write, ExtendedProperties and ReliableWrites.
+        let properties = GattCharacteristicProperties(0x018a);
+        assert_eq!(
+            to_char_props(&properties),
+            CharPropFlags::READ
+                | CharPropFlags::WRITE
+                | CharPropFlags::EXTENDED_PROPERTIES
+                | CharPropFlags::RELIABLE_WRITE
+        );
+    }
 }